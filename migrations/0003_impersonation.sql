@@ -0,0 +1,15 @@
+ALTER TABLE {sessions} ADD COLUMN IF NOT EXISTS impersonated_user_id INTEGER REFERENCES {users} (id) ON DELETE CASCADE;
+ALTER TABLE {sessions} ADD COLUMN IF NOT EXISTS impersonation_read_only BOOLEAN NOT NULL DEFAULT true;
+
+CREATE TABLE IF NOT EXISTS {impersonation_events} (
+    id SERIAL PRIMARY KEY,
+    admin_id INTEGER REFERENCES {users} (id) ON DELETE SET NULL,
+    user_id INTEGER REFERENCES {users} (id) ON DELETE SET NULL,
+    session_id INTEGER NOT NULL,
+    action TEXT NOT NULL,
+    method TEXT,
+    path TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS impersonation_events_user_id_idx ON {impersonation_events} (user_id);
@@ -0,0 +1,20 @@
+ALTER TABLE {sessions} ADD COLUMN IF NOT EXISTS login_fingerprint TEXT;
+ALTER TABLE {sessions} ADD COLUMN IF NOT EXISTS otp_required BOOLEAN NOT NULL DEFAULT false;
+
+CREATE TABLE IF NOT EXISTS {login_fingerprints} (
+    user_id INTEGER NOT NULL REFERENCES {users} (id) ON DELETE CASCADE,
+    fingerprint TEXT NOT NULL,
+    first_seen_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_seen_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (user_id, fingerprint)
+);
+
+CREATE TABLE IF NOT EXISTS {login_alerts} (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES {users} (id) ON DELETE CASCADE,
+    session_id INTEGER NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,4 @@
+ALTER TABLE {users} ADD COLUMN IF NOT EXISTS username TEXT;
+CREATE UNIQUE INDEX IF NOT EXISTS users_username_lower_idx ON {users} (lower(username));
+
+ALTER TABLE {posts} ADD COLUMN IF NOT EXISTS author_id INTEGER REFERENCES {users} (id) ON DELETE SET NULL;
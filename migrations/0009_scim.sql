@@ -0,0 +1,2 @@
+ALTER TABLE {users} ADD COLUMN IF NOT EXISTS external_id TEXT;
+ALTER TABLE {users} ADD COLUMN IF NOT EXISTS display_name TEXT;
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS {service_keys} (
+    id SERIAL PRIMARY KEY,
+    service TEXT NOT NULL,
+    key_id TEXT NOT NULL UNIQUE,
+    -- Encrypted with SECRET_KEY, the signature check needs the secret itself.
+    secret_encr TEXT NOT NULL,
+    created_by INTEGER REFERENCES {users} (id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_used_at TIMESTAMPTZ,
+    revoked_at TIMESTAMPTZ
+);
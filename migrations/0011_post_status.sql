@@ -0,0 +1,20 @@
+-- Posts written before statuses existed were public, they stay published.
+ALTER TABLE {posts} ADD COLUMN IF NOT EXISTS status TEXT NOT NULL DEFAULT 'published'
+    CHECK (status IN ('draft', 'scheduled', 'published', 'archived'));
+ALTER TABLE {posts} ALTER COLUMN status SET DEFAULT 'draft';
+ALTER TABLE {posts} ADD COLUMN IF NOT EXISTS published_at TIMESTAMPTZ;
+UPDATE {posts} SET published_at = submitted_date
+    WHERE status = 'published' AND published_at IS NULL;
+
+CREATE INDEX IF NOT EXISTS posts_status_published_at_idx ON {posts} (status, published_at);
+
+-- Status changes, read by internal services polling GET /posts/events.
+CREATE TABLE IF NOT EXISTS {post_events} (
+    id SERIAL PRIMARY KEY,
+    post_id INTEGER NOT NULL REFERENCES {posts} (id) ON DELETE CASCADE,
+    -- The status the post moved to.
+    kind TEXT NOT NULL,
+    -- NULL when the scheduler published the post.
+    actor_id INTEGER REFERENCES {users} (id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
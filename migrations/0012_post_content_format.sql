@@ -0,0 +1,24 @@
+-- Posts keep their source in `content` and the sanitized rendering in `content_html`.
+-- Content written before formats existed is treated as plain text and rendered the way
+-- ContentFormat::Plain does: escaped, blank lines between paragraphs, other line breaks
+-- as <br>.
+ALTER TABLE {posts} ADD COLUMN IF NOT EXISTS content_format TEXT NOT NULL DEFAULT 'plain'
+    CHECK (content_format IN ('markdown', 'html', 'plain'));
+ALTER TABLE {posts} ALTER COLUMN content_format SET DEFAULT 'markdown';
+ALTER TABLE {posts} ADD COLUMN IF NOT EXISTS content_html TEXT;
+
+WITH escaped AS (
+    SELECT id, replace(replace(replace(replace(replace(
+            btrim(replace(content, E'\r\n', E'\n'), E' \t\n'),
+            '&', '&amp;'), '<', '&lt;'), '>', '&gt;'), '"', '&quot;'), '''', '&#39;') AS text
+    FROM {posts} WHERE content_html IS NULL
+)
+UPDATE {posts} p SET content_html = CASE WHEN e.text = '' THEN '' ELSE
+        '<p>' || replace(
+            replace(regexp_replace(e.text, E'\\s*\\n\\n+\\s*', E'\x01', 'g'), E'\n', '<br>'),
+            E'\x01', E'</p>\n<p>') || '</p>'
+    END
+    FROM escaped e WHERE e.id = p.id;
+
+ALTER TABLE {posts} ALTER COLUMN content_html SET DEFAULT '';
+ALTER TABLE {posts} ALTER COLUMN content_html SET NOT NULL;
@@ -0,0 +1,28 @@
+-- The revision a post is at, bumped on every update so concurrent updates get distinct
+-- revision numbers.
+ALTER TABLE {posts} ADD COLUMN IF NOT EXISTS revision INTEGER NOT NULL DEFAULT 1;
+
+-- A full snapshot of a post after each change.
+CREATE TABLE IF NOT EXISTS {post_revisions} (
+    id SERIAL PRIMARY KEY,
+    post_id INTEGER NOT NULL REFERENCES {posts} (id) ON DELETE CASCADE,
+    revision INTEGER NOT NULL,
+    title TEXT NOT NULL,
+    slug TEXT NOT NULL,
+    summary TEXT NOT NULL,
+    content TEXT NOT NULL,
+    content_format TEXT NOT NULL,
+    -- Who made the change, NULL when unknown.
+    author_id INTEGER REFERENCES {users} (id) ON DELETE SET NULL,
+    -- The revision brought back, when the change restored an older one.
+    restored_from INTEGER,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (post_id, revision)
+);
+
+-- Existing posts start their history at their current state.
+INSERT INTO {post_revisions}
+    (post_id, revision, title, slug, summary, content, content_format, author_id, created_at)
+SELECT id, revision, title, slug, summary, content, content_format, author_id, modified_date
+FROM {posts}
+ON CONFLICT (post_id, revision) DO NOTHING;
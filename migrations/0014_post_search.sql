@@ -0,0 +1,12 @@
+-- Text search configuration the post is indexed with, see pg_ts_config.
+ALTER TABLE {posts} ADD COLUMN IF NOT EXISTS language REGCONFIG NOT NULL DEFAULT 'english';
+
+-- Title ranks above summary, summary above content.
+ALTER TABLE {posts} ADD COLUMN IF NOT EXISTS search_vector TSVECTOR
+    GENERATED ALWAYS AS (
+        setweight(to_tsvector(language, coalesce(title, '')), 'A') ||
+        setweight(to_tsvector(language, coalesce(summary, '')), 'B') ||
+        setweight(to_tsvector(language, coalesce(content, '')), 'C')
+    ) STORED;
+
+CREATE INDEX IF NOT EXISTS posts_search_vector_idx ON {posts} USING GIN (search_vector);
@@ -0,0 +1,33 @@
+-- Tag names are unique regardless of case. Duplicates merge into the oldest tag.
+WITH canonical AS (
+    SELECT id, min(id) OVER (PARTITION BY lower(name)) AS keep FROM {tags}
+)
+UPDATE {posts_tags} pt SET tag_id = c.keep
+    FROM canonical c WHERE pt.tag_id = c.id AND c.id <> c.keep;
+DELETE FROM {tags} t
+    USING (SELECT id, min(id) OVER (PARTITION BY lower(name)) AS keep FROM {tags}) c
+    WHERE t.id = c.id AND c.id <> c.keep;
+CREATE UNIQUE INDEX IF NOT EXISTS tags_name_lower_idx ON {tags} (lower(name));
+
+-- A post has a tag at most once, and links go away with their post or tag.
+DELETE FROM {posts_tags} a USING {posts_tags} b
+    WHERE a.post_id = b.post_id AND a.tag_id = b.tag_id AND a.ctid > b.ctid;
+DELETE FROM {posts_tags} pt
+    WHERE NOT EXISTS (SELECT 1 FROM {posts} p WHERE p.id = pt.post_id)
+    OR NOT EXISTS (SELECT 1 FROM {tags} t WHERE t.id = pt.tag_id);
+CREATE UNIQUE INDEX IF NOT EXISTS posts_tags_post_tag_idx ON {posts_tags} (post_id, tag_id);
+CREATE INDEX IF NOT EXISTS posts_tags_tag_idx ON {posts_tags} (tag_id);
+
+DO $$
+BEGIN
+    ALTER TABLE {posts_tags} ADD CONSTRAINT posts_tags_post_id_fkey
+        FOREIGN KEY (post_id) REFERENCES {posts} (id) ON DELETE CASCADE;
+EXCEPTION WHEN duplicate_object THEN NULL;
+END $$;
+
+DO $$
+BEGIN
+    ALTER TABLE {posts_tags} ADD CONSTRAINT posts_tags_tag_id_fkey
+        FOREIGN KEY (tag_id) REFERENCES {tags} (id) ON DELETE CASCADE;
+EXCEPTION WHEN duplicate_object THEN NULL;
+END $$;
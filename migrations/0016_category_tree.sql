@@ -0,0 +1,14 @@
+-- Categories nest. Children of a deleted category move to the top level.
+ALTER TABLE {categories} ADD COLUMN IF NOT EXISTS parent_id INTEGER
+    REFERENCES {categories} (id) ON DELETE SET NULL;
+ALTER TABLE {categories} DROP CONSTRAINT IF EXISTS categories_parent_not_self;
+ALTER TABLE {categories} ADD CONSTRAINT categories_parent_not_self CHECK (parent_id <> id);
+CREATE INDEX IF NOT EXISTS categories_parent_id_idx ON {categories} (parent_id);
+
+-- Categories a post is filed under.
+CREATE TABLE IF NOT EXISTS {posts_categories} (
+    post_id INTEGER NOT NULL REFERENCES {posts} (id) ON DELETE CASCADE,
+    category_id INTEGER NOT NULL REFERENCES {categories} (id) ON DELETE CASCADE,
+    PRIMARY KEY (post_id, category_id)
+);
+CREATE INDEX IF NOT EXISTS posts_categories_category_idx ON {posts_categories} (category_id);
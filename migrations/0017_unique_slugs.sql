@@ -0,0 +1,27 @@
+-- Posts and categories without a slug get one from their title or name, repeated slugs
+-- get the row id appended, all but the oldest row keeping its slug.
+UPDATE {posts} SET slug = COALESCE(
+    NULLIF(trim(BOTH '-' FROM regexp_replace(lower(title), '[^a-z0-9]+', '-', 'g')), ''),
+    'post-' || id)
+    WHERE trim(slug) = '';
+UPDATE {posts} p SET slug = p.slug || '-' || p.id
+    FROM (SELECT id, min(id) OVER (PARTITION BY slug) AS keep FROM {posts}) d
+    WHERE p.id = d.id AND d.id <> d.keep;
+CREATE UNIQUE INDEX IF NOT EXISTS posts_slug_idx ON {posts} (slug);
+
+UPDATE {categories} SET slug = COALESCE(
+    NULLIF(trim(BOTH '-' FROM regexp_replace(lower(name), '[^a-z0-9]+', '-', 'g')), ''),
+    'category-' || id)
+    WHERE trim(slug) = '';
+UPDATE {categories} c SET slug = c.slug || '-' || c.id
+    FROM (SELECT id, min(id) OVER (PARTITION BY slug) AS keep FROM {categories}) d
+    WHERE c.id = d.id AND d.id <> d.keep;
+CREATE UNIQUE INDEX IF NOT EXISTS categories_slug_idx ON {categories} (slug);
+
+-- Slugs posts went by before, answered with a redirect to the current one.
+CREATE TABLE IF NOT EXISTS {post_slugs} (
+    slug TEXT PRIMARY KEY,
+    post_id INTEGER NOT NULL REFERENCES {posts} (id) ON DELETE CASCADE,
+    replaced_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX IF NOT EXISTS post_slugs_post_idx ON {post_slugs} (post_id);
@@ -0,0 +1,20 @@
+-- Reader comments on posts. Replies point at the comment they answer, anonymous comments
+-- carry the name and email given with them instead of an author.
+CREATE TABLE IF NOT EXISTS {comments} (
+    id SERIAL PRIMARY KEY,
+    post_id INTEGER NOT NULL REFERENCES {posts} (id) ON DELETE CASCADE,
+    parent_id INTEGER REFERENCES {comments} (id) ON DELETE CASCADE,
+    author_id INTEGER REFERENCES {users} (id) ON DELETE SET NULL,
+    author_name TEXT,
+    author_email TEXT,
+    content TEXT NOT NULL,
+    content_html TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending'
+        CHECK (status IN ('pending', 'approved', 'spam', 'deleted')),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    moderated_by INTEGER REFERENCES {users} (id) ON DELETE SET NULL,
+    moderated_at TIMESTAMPTZ
+);
+CREATE INDEX IF NOT EXISTS comments_post_status_idx ON {comments} (post_id, status);
+CREATE INDEX IF NOT EXISTS comments_status_created_idx ON {comments} (status, created_at, id);
+CREATE INDEX IF NOT EXISTS comments_parent_idx ON {comments} (parent_id);
@@ -0,0 +1,24 @@
+-- Uploaded files, stored under `storage_key` with an optional WebP thumbnail.
+CREATE TABLE IF NOT EXISTS {media} (
+    id SERIAL PRIMARY KEY,
+    storage_key TEXT NOT NULL UNIQUE,
+    thumbnail_key TEXT,
+    filename TEXT NOT NULL,
+    content_type TEXT NOT NULL,
+    size_bytes BIGINT NOT NULL,
+    width INTEGER,
+    height INTEGER,
+    alt_text TEXT NOT NULL DEFAULT '',
+    uploader_id INTEGER REFERENCES {users} (id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX IF NOT EXISTS media_uploader_idx ON {media} (uploader_id, id);
+
+-- Files attached to posts, in order.
+CREATE TABLE IF NOT EXISTS {posts_media} (
+    post_id INTEGER NOT NULL REFERENCES {posts} (id) ON DELETE CASCADE,
+    media_id INTEGER NOT NULL REFERENCES {media} (id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    PRIMARY KEY (post_id, media_id)
+);
+CREATE INDEX IF NOT EXISTS posts_media_media_idx ON {posts_media} (media_id);
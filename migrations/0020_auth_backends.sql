@@ -0,0 +1,6 @@
+-- The authentication backend that created each account. A directory only signs in to
+-- accounts it created itself, never to a local account sharing an email. Accounts an
+-- external backend created before this column existed are taken to be local; set them
+-- to their backend to keep them signing in there.
+ALTER TABLE {users} ADD COLUMN IF NOT EXISTS auth_backend TEXT NOT NULL DEFAULT 'local';
+CREATE INDEX IF NOT EXISTS users_auth_backend_email_idx ON {users} (auth_backend, lower(email));
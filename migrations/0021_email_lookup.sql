@@ -0,0 +1,2 @@
+-- Users are looked up by email address ignoring case.
+CREATE INDEX IF NOT EXISTS users_lower_email_idx ON {users} (lower(email));
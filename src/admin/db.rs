@@ -0,0 +1,154 @@
+use crate::admin::{UserDetails, UserSummary};
+use crate::auth::{delete_trusted_devices, delete_user_sessions, Role};
+use crate::configs::db;
+use crate::errors::ServiceError;
+use deadpool_postgres::Client;
+use tokio_pg_mapper::FromTokioPostgresRow;
+
+const USER_COLUMNS: &str =
+    "id, email, username, role, disabled, password_reset_required, created_at";
+
+// Both filters are optional, a NULL parameter disables it.
+const USER_FILTER: &str = "($1::text IS NULL OR strpos(lower(email), lower($1)) > 0
+        OR strpos(lower(username), lower($1)) > 0)
+    AND ($2::text IS NULL OR role = $2)";
+
+pub async fn user_list(
+    client: &Client,
+    search: Option<String>,
+    role: Option<Role>,
+    limit: i64,
+    offset: i64,
+) -> Result<(Vec<UserSummary>, i64), ServiceError> {
+    let role = role.map(|r| r.as_str());
+
+    let statement = client
+        .prepare(&format!(
+            "SELECT {} FROM {users} WHERE {} ORDER BY id LIMIT $3 OFFSET $4",
+            USER_COLUMNS,
+            USER_FILTER,
+            users = db().users
+        ))
+        .await?;
+    let users = client
+        .query(&statement, &[&search, &role, &limit, &offset])
+        .await?
+        .iter()
+        .map(|row| UserSummary::from_row_ref(row).unwrap())
+        .collect::<Vec<UserSummary>>();
+
+    let statement = client
+        .prepare(&format!(
+            "SELECT count(*) FROM {users} WHERE {}",
+            USER_FILTER,
+            users = db().users
+        ))
+        .await?;
+    let total: i64 = client
+        .query_one(&statement, &[&search, &role])
+        .await?
+        .get(0);
+
+    Ok((users, total))
+}
+
+pub async fn user_details(client: &Client, id: i32) -> Result<UserDetails, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "SELECT {},
+                (SELECT count(*) FROM {sessions} s WHERE s.user_id = u.id) AS active_sessions
+            FROM {users} u WHERE id = $1",
+            USER_COLUMNS,
+            sessions = db().sessions,
+            users = db().users
+        ))
+        .await?;
+
+    let maybe_user = client
+        .query_opt(&statement, &[&id])
+        .await?
+        .map(|row| UserDetails::from_row_ref(&row).unwrap());
+
+    match maybe_user {
+        Some(user) => Ok(user),
+        None => Err(ServiceError::NotFound("User not found".into())),
+    }
+}
+
+pub async fn user_set_role(client: &Client, id: i32, role: Role) -> Result<(), ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "UPDATE {users} SET role = $1 WHERE id = $2",
+            users = db().users
+        ))
+        .await?;
+
+    match client.execute(&statement, &[&role.as_str(), &id]).await? {
+        1 => Ok(()),
+        _ => Err(ServiceError::NotFound("User not found".into())),
+    }
+}
+
+/// Disabling also signs the user out of every session.
+pub async fn user_set_disabled(
+    client: &mut Client,
+    id: i32,
+    disabled: bool,
+) -> Result<(), ServiceError> {
+    let transaction = client.transaction().await?;
+
+    let statement = transaction
+        .prepare(&format!(
+            "UPDATE {users} SET disabled = $1 WHERE id = $2",
+            users = db().users
+        ))
+        .await?;
+    if transaction.execute(&statement, &[&disabled, &id]).await? != 1 {
+        return Err(ServiceError::NotFound("User not found".into()));
+    }
+    if disabled {
+        delete_user_sessions(&transaction, id, None).await?;
+    }
+
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Flags the account so login is refused until a new password is chosen, signs the user
+/// out of every session and forgets their trusted devices.
+pub async fn user_require_password_reset(client: &mut Client, id: i32) -> Result<(), ServiceError> {
+    let transaction = client.transaction().await?;
+
+    let statement = transaction
+        .prepare(&format!(
+            "UPDATE {users} SET password_reset_required = true WHERE id = $1",
+            users = db().users
+        ))
+        .await?;
+    if transaction.execute(&statement, &[&id]).await? != 1 {
+        return Err(ServiceError::NotFound("User not found".into()));
+    }
+    delete_user_sessions(&transaction, id, None).await?;
+    delete_trusted_devices(&transaction, id).await?;
+
+    transaction.commit().await?;
+    Ok(())
+}
+
+pub async fn user_delete(client: &mut Client, id: i32) -> Result<(), ServiceError> {
+    let transaction = client.transaction().await?;
+
+    delete_user_sessions(&transaction, id, None).await?;
+    let statement = transaction
+        .prepare(&format!(
+            "DELETE FROM {users} WHERE id = $1",
+            users = db().users
+        ))
+        .await?;
+    if transaction.execute(&statement, &[&id]).await? != 1 {
+        return Err(ServiceError::NotFound("User not found".into()));
+    }
+
+    transaction.commit().await?;
+    Ok(())
+}
@@ -0,0 +1,331 @@
+use crate::admin::db;
+use crate::admin::models::{ChangeRole, UserListQuery, UserPage};
+use crate::auth::{delete_user_sessions, send_password_reset, AdminUser, Role};
+use crate::errors::ServiceError;
+
+use actix_web::web::Query;
+use actix_web::{delete, get, patch, post, web, HttpResponse};
+use deadpool_postgres::{Client, Pool};
+
+const DEFAULT_PER_PAGE: i64 = 20;
+const MAX_PER_PAGE: i64 = 100;
+
+/// Admins may not lock themselves out by mistake.
+fn refuse_self(admin: &AdminUser, user_id: i32) -> Result<(), ServiceError> {
+    if admin.0.id == user_id {
+        return Err(ServiceError::BadRequest(
+            "Admins cannot perform this action on their own account".into(),
+        ));
+    }
+    Ok(())
+}
+
+/// Page number and size asked for, the size kept within `MAX_PER_PAGE`, and the offset
+/// of the page. Pages past the end stay past the end rather than overflowing.
+fn page_window(query: &UserListQuery) -> (i64, i64, i64) {
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query
+        .per_page
+        .unwrap_or(DEFAULT_PER_PAGE)
+        .clamp(1, MAX_PER_PAGE);
+    (page, per_page, (page - 1).saturating_mul(per_page))
+}
+
+/// List users.
+///
+/// Search by email, filter by role and page through the results.
+#[utoipa::path(
+    context_path = "/admin/users",
+    params(
+        UserListQuery
+    ),
+    responses(
+        (status = 200, description = "Page of users", body = UserPage),
+        (status = 403, description = "Admin role required", body = ServiceError)
+    )
+)]
+#[get("/")]
+pub async fn list_users(
+    _admin: AdminUser,
+    query: Query<UserListQuery>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let client: Client = db_pool.get().await?;
+
+    let (page, per_page, offset) = page_window(&query);
+    let search = query.q.clone().filter(|q| !q.trim().is_empty());
+
+    let (items, total) = db::user_list(&client, search, query.role, per_page, offset).await?;
+
+    Ok(HttpResponse::Ok().json(UserPage {
+        items,
+        total,
+        page,
+        per_page,
+    }))
+}
+
+/// Get a user by id.
+#[utoipa::path(
+    context_path = "/admin/users",
+    responses(
+        (status = 200, description = "User", body = UserDetails),
+        (status = 403, description = "Admin role required", body = ServiceError),
+        (status = 404, description = "User not found", body = ServiceError)
+    ),
+    params(
+        ("id", description = "Unique user id")
+    )
+)]
+#[get("/{id}")]
+pub async fn get_user(
+    _admin: AdminUser,
+    user_id: web::Path<(i32,)>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let client: Client = db_pool.get().await?;
+
+    let user = db::user_details(&client, user_id.0).await?;
+    Ok(HttpResponse::Ok().json(user))
+}
+
+/// Change the role of a user.
+#[utoipa::path(
+    context_path = "/admin/users",
+    request_body = ChangeRole,
+    responses(
+        (status = 200, description = "Role changed", body = UserDetails),
+        (status = 400, description = "Admins cannot change their own role", body = ServiceError),
+        (status = 403, description = "Admin role required", body = ServiceError),
+        (status = 404, description = "User not found", body = ServiceError)
+    ),
+    params(
+        ("id", description = "Unique user id")
+    )
+)]
+#[patch("/{id}/role")]
+pub async fn change_user_role(
+    admin: AdminUser,
+    user_id: web::Path<(i32,)>,
+    local_object: web::Json<ChangeRole>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    if local_object.role != Role::Admin {
+        refuse_self(&admin, user_id.0)?;
+    }
+    let client: Client = db_pool.get().await?;
+
+    db::user_set_role(&client, user_id.0, local_object.role).await?;
+    log::info!(
+        "admin {} changed role of user {} to {}",
+        admin.0.id,
+        user_id.0,
+        local_object.role
+    );
+
+    let user = db::user_details(&client, user_id.0).await?;
+    Ok(HttpResponse::Ok().json(user))
+}
+
+/// Disable a user. They are signed out and can no longer log in.
+#[utoipa::path(
+    context_path = "/admin/users",
+    responses(
+        (status = 200, description = "User disabled"),
+        (status = 400, description = "Admins cannot disable themselves", body = ServiceError),
+        (status = 403, description = "Admin role required", body = ServiceError),
+        (status = 404, description = "User not found", body = ServiceError)
+    ),
+    params(
+        ("id", description = "Unique user id")
+    )
+)]
+#[post("/{id}/disable")]
+pub async fn disable_user(
+    admin: AdminUser,
+    user_id: web::Path<(i32,)>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    refuse_self(&admin, user_id.0)?;
+    let mut client: Client = db_pool.get().await?;
+
+    db::user_set_disabled(&mut client, user_id.0, true).await?;
+    log::info!("admin {} disabled user {}", admin.0.id, user_id.0);
+
+    Ok(HttpResponse::Ok().json("User disabled"))
+}
+
+/// Enable a previously disabled user.
+#[utoipa::path(
+    context_path = "/admin/users",
+    responses(
+        (status = 200, description = "User enabled"),
+        (status = 403, description = "Admin role required", body = ServiceError),
+        (status = 404, description = "User not found", body = ServiceError)
+    ),
+    params(
+        ("id", description = "Unique user id")
+    )
+)]
+#[post("/{id}/enable")]
+pub async fn enable_user(
+    admin: AdminUser,
+    user_id: web::Path<(i32,)>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let mut client: Client = db_pool.get().await?;
+
+    db::user_set_disabled(&mut client, user_id.0, false).await?;
+    log::info!("admin {} enabled user {}", admin.0.id, user_id.0);
+
+    Ok(HttpResponse::Ok().json("User enabled"))
+}
+
+/// Force a password reset.
+///
+/// Signs the user out, refuses logins with the old password and emails a reset link.
+#[utoipa::path(
+    context_path = "/admin/users",
+    responses(
+        (status = 200, description = "Password reset sent"),
+        (status = 403, description = "Admin role required", body = ServiceError),
+        (status = 404, description = "User not found", body = ServiceError)
+    ),
+    params(
+        ("id", description = "Unique user id")
+    )
+)]
+#[post("/{id}/password-reset")]
+pub async fn force_password_reset(
+    admin: AdminUser,
+    user_id: web::Path<(i32,)>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let mut client: Client = db_pool.get().await?;
+
+    let user = db::user_details(&client, user_id.0).await?;
+    db::user_require_password_reset(&mut client, user.id).await?;
+    send_password_reset(&client, user.id, &user.email).await?;
+    log::info!(
+        "admin {} forced a password reset for user {}",
+        admin.0.id,
+        user.id
+    );
+
+    Ok(HttpResponse::Ok().json("Password reset sent"))
+}
+
+/// Revoke all sessions of a user.
+#[utoipa::path(
+    context_path = "/admin/users",
+    responses(
+        (status = 200, description = "Number of sessions revoked", body = u64),
+        (status = 403, description = "Admin role required", body = ServiceError),
+        (status = 404, description = "User not found", body = ServiceError)
+    ),
+    params(
+        ("id", description = "Unique user id")
+    )
+)]
+#[delete("/{id}/sessions")]
+pub async fn revoke_user_sessions(
+    admin: AdminUser,
+    user_id: web::Path<(i32,)>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let client: Client = db_pool.get().await?;
+
+    let user = db::user_details(&client, user_id.0).await?;
+    let revoked = delete_user_sessions(&client, user.id, None).await?;
+    log::info!(
+        "admin {} revoked {} sessions of user {}",
+        admin.0.id,
+        revoked,
+        user.id
+    );
+
+    Ok(HttpResponse::Ok().json(revoked))
+}
+
+/// Delete a user.
+#[utoipa::path(
+    context_path = "/admin/users",
+    responses(
+        (status = 200, description = "User deleted"),
+        (status = 400, description = "Admins cannot delete themselves", body = ServiceError),
+        (status = 403, description = "Admin role required", body = ServiceError),
+        (status = 404, description = "User not found", body = ServiceError)
+    ),
+    params(
+        ("id", description = "Unique user id")
+    )
+)]
+#[delete("/{id}")]
+pub async fn delete_user(
+    admin: AdminUser,
+    user_id: web::Path<(i32,)>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    refuse_self(&admin, user_id.0)?;
+    let mut client: Client = db_pool.get().await?;
+
+    db::user_delete(&mut client, user_id.0).await?;
+    log::info!("admin {} deleted user {}", admin.0.id, user_id.0);
+
+    Ok(HttpResponse::Ok().json("User deleted"))
+}
+
+pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_users);
+    cfg.service(get_user);
+    cfg.service(change_user_role);
+    cfg.service(disable_user);
+    cfg.service(enable_user);
+    cfg.service(force_password_reset);
+    cfg.service(revoke_user_sessions);
+    cfg.service(delete_user);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::AuthUser;
+
+    #[test]
+    fn test_refuse_self() {
+        let admin = AdminUser(AuthUser {
+            id: 7,
+            email: "admin@example.com".into(),
+            username: None,
+            role: Role::Admin,
+            session_id: Some(1),
+            impersonator_id: None,
+        });
+        assert!(matches!(
+            refuse_self(&admin, 7),
+            Err(ServiceError::BadRequest(_))
+        ));
+        assert!(refuse_self(&admin, 8).is_ok());
+    }
+
+    #[test]
+    fn test_page_window() {
+        let query = |page, per_page| UserListQuery {
+            q: None,
+            role: None,
+            page,
+            per_page,
+        };
+        assert_eq!(page_window(&query(None, None)), (1, DEFAULT_PER_PAGE, 0));
+        assert_eq!(page_window(&query(Some(3), Some(50))), (3, 50, 100));
+        assert_eq!(page_window(&query(Some(0), Some(0))), (1, 1, 0));
+        assert_eq!(
+            page_window(&query(Some(-2), Some(1000))),
+            (1, MAX_PER_PAGE, 0)
+        );
+        assert_eq!(
+            page_window(&query(Some(i64::MAX), Some(2))),
+            (i64::MAX, 2, i64::MAX)
+        );
+    }
+}
@@ -0,0 +1,6 @@
+pub mod db;
+pub mod handlers;
+pub mod models;
+pub use crate::admin::db::*;
+pub use crate::admin::handlers::*;
+pub use crate::admin::models::*;
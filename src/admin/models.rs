@@ -0,0 +1,58 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio_pg_mapper_derive::PostgresMapper;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::auth::Role;
+
+#[derive(Serialize, Debug, Clone, Deserialize, ToSchema, PostgresMapper)]
+#[pg_mapper(table = "users")]
+pub struct UserSummary {
+    pub id: i32,
+    pub email: String,
+    pub username: Option<String>,
+    pub role: String,
+    pub disabled: bool,
+    pub password_reset_required: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug, Clone, Deserialize, ToSchema, PostgresMapper)]
+#[pg_mapper(table = "users")]
+pub struct UserDetails {
+    pub id: i32,
+    pub email: String,
+    pub username: Option<String>,
+    pub role: String,
+    pub disabled: bool,
+    pub password_reset_required: bool,
+    pub created_at: DateTime<Utc>,
+    pub active_sessions: i64,
+}
+
+#[derive(Serialize, Debug, Clone, Deserialize, ToSchema)]
+pub struct UserPage {
+    pub items: Vec<UserSummary>,
+    pub total: i64,
+    pub page: i64,
+    pub per_page: i64,
+}
+
+/// Search and pagination for the user list.
+#[derive(Deserialize, Debug, Clone, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct UserListQuery {
+    /// Part of the email address, case insensitive.
+    pub q: Option<String>,
+    /// Only users holding this role.
+    pub role: Option<Role>,
+    /// Page number starting at 1.
+    pub page: Option<i64>,
+    /// Page size, at most 100.
+    pub per_page: Option<i64>,
+}
+
+#[derive(Serialize, Debug, Clone, Deserialize, ToSchema)]
+pub struct ChangeRole {
+    pub role: Role,
+}
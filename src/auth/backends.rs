@@ -0,0 +1,178 @@
+//! Where `process_login` checks credentials. Backends are tried in the order listed in
+//! `auth_backends` and the first one accepting the password signs the user in. Users a
+//! directory vouches for get a local account on their first login, recorded as created by
+//! that backend. A directory only ever signs in to accounts it created.
+
+use deadpool_postgres::Client;
+use futures::future::LocalBoxFuture;
+
+use crate::auth::ldap::LdapBackend;
+use crate::auth::{db, encryption, username_problem, FindUser};
+use crate::configs::SrvConfig;
+use crate::errors::ServiceError;
+
+/// A user as an external directory describes them, matched to a local account by email.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExternalUser {
+    pub email: String,
+    pub username: Option<String>,
+    pub display_name: Option<String>,
+}
+
+pub enum AuthOutcome {
+    /// The password of this local user is right.
+    Local(FindUser),
+    /// The backend vouches for a user that may not exist locally yet.
+    External(ExternalUser),
+    /// The backend knows the login but refused the password.
+    Rejected,
+    /// The backend doesn't know the login.
+    UnknownUser,
+}
+
+pub trait AuthBackend {
+    /// Name used in `auth_backends` and in logs.
+    fn name(&self) -> &'static str;
+
+    fn authenticate<'a>(
+        &'a self,
+        client: &'a Client,
+        login: &'a str,
+        password: &'a str,
+    ) -> LocalBoxFuture<'a, Result<AuthOutcome, ServiceError>>;
+}
+
+/// Checks `hashed_password` of the user found by email or username.
+pub struct LocalBackend {
+    pub use_bcrypt: bool,
+}
+
+impl AuthBackend for LocalBackend {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    fn authenticate<'a>(
+        &'a self,
+        client: &'a Client,
+        login: &'a str,
+        password: &'a str,
+    ) -> LocalBoxFuture<'a, Result<AuthOutcome, ServiceError>> {
+        Box::pin(async move {
+            let user = match db::find_user_by_login(client, login.to_owned()).await {
+                Ok(user) => user,
+                Err(_) => return Ok(AuthOutcome::UnknownUser),
+            };
+            if encryption::verify_hash(password, &user.hashed_password, self.use_bcrypt).await? {
+                Ok(AuthOutcome::Local(user))
+            } else {
+                Ok(AuthOutcome::Rejected)
+            }
+        })
+    }
+}
+
+pub fn configured_backends(cnf: &SrvConfig) -> Result<Vec<Box<dyn AuthBackend>>, ServiceError> {
+    let mut backends: Vec<Box<dyn AuthBackend>> = Vec::new();
+    for name in cnf
+        .auth_backends
+        .as_deref()
+        .unwrap_or("local")
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+    {
+        match name.to_ascii_lowercase().as_str() {
+            "local" => backends.push(Box::new(LocalBackend {
+                use_bcrypt: cnf.bcrypt_or_argon,
+            })),
+            "ldap" => backends.push(Box::new(LdapBackend::from_config(cnf)?)),
+            _ => {
+                return Err(ServiceError::FaultySetup(format!(
+                    "Unknown authentication backend {:?}",
+                    name
+                )))
+            }
+        }
+    }
+    if backends.is_empty() {
+        return Err(ServiceError::FaultySetup(
+            "auth_backends lists no backend".into(),
+        ));
+    }
+    Ok(backends)
+}
+
+/// The local account of a user a directory vouched for, created on first login with a
+/// password nobody knows. The directory's username is kept when it's valid and free.
+/// `None` when another backend's account has the email: the directory vouching for an
+/// address doesn't make it the owner of an account it didn't create.
+async fn provision(
+    client: &Client,
+    user: &ExternalUser,
+    backend: &str,
+    use_bcrypt: bool,
+) -> Result<Option<FindUser>, ServiceError> {
+    if let Some(found) = db::find_external_user(client, user, backend).await? {
+        return Ok(Some(found));
+    }
+    if db::email_taken(client, &user.email).await? {
+        log::warn!(
+            "{} backend vouched for {}, whose account it didn't create",
+            backend,
+            user.email
+        );
+        return Ok(None);
+    }
+
+    let username = match user.username.as_deref() {
+        Some(username)
+            if username_problem(username).is_none()
+                && !db::username_taken(client, username).await? =>
+        {
+            Some(username)
+        }
+        _ => None,
+    };
+    let hashed_password =
+        encryption::password_hash(&encryption::generate_token(), use_bcrypt).await?;
+    let found = db::add_external_user(client, user, backend, username, hashed_password).await?;
+    log::info!("provisioned user {} from the {} backend", found.id, backend);
+    Ok(Some(found))
+}
+
+/// Runs the configured backends in order. Returns `Local` with the user to sign in, or
+/// `Rejected` when a backend knew the login and `UnknownUser` when none did. A failing
+/// backend is skipped, its error only surfaces when no other backend knew the login.
+pub async fn authenticate(
+    client: &Client,
+    cnf: &SrvConfig,
+    login: &str,
+    password: &str,
+) -> Result<AuthOutcome, ServiceError> {
+    let mut outcome = AuthOutcome::UnknownUser;
+    let mut failure = None;
+
+    for backend in configured_backends(cnf)? {
+        match backend.authenticate(client, login, password).await {
+            Ok(AuthOutcome::Local(user)) => return Ok(AuthOutcome::Local(user)),
+            Ok(AuthOutcome::External(user)) => {
+                return match provision(client, &user, backend.name(), cnf.bcrypt_or_argon).await? {
+                    Some(user) => Ok(AuthOutcome::Local(user)),
+                    None => Ok(AuthOutcome::Rejected),
+                };
+            }
+            Ok(AuthOutcome::Rejected) => outcome = AuthOutcome::Rejected,
+            Ok(AuthOutcome::UnknownUser) => {}
+            Err(e) => {
+                log::error!("{} authentication backend failed: {:?}", backend.name(), e);
+                failure = Some(e);
+            }
+        }
+    }
+
+    match (outcome, failure) {
+        (AuthOutcome::UnknownUser, Some(e)) => Err(e),
+        (outcome, _) => Ok(outcome),
+    }
+}
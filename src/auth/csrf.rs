@@ -0,0 +1,241 @@
+use std::future::{self, Ready};
+
+use actix_identity::Identity;
+use actix_session::{Session as CookieSession, SessionExt};
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::{header, Method};
+use actix_web::{get, FromRequest, HttpMessage, HttpResponse};
+use futures::future::LocalBoxFuture;
+
+use crate::auth::{constant_time_compare, encryption, CsrfToken};
+use crate::errors::ServiceError;
+use crate::service_auth::SignedRequest;
+use crate::tls::ClientCertificate;
+
+/// Header carrying the token returned by `GET /auth/csrf`.
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+const CSRF_SESSION_KEY: &str = "csrf_token";
+
+/// CSRF Token | Top
+///
+/// Returns the synchronizer token of the current session, creating it on first use.
+/// Send it back in the `x-csrf-token` header on every POST, PUT, PATCH and DELETE made
+/// with the session cookie.
+#[utoipa::path(
+    context_path = "/auth",
+    responses(
+        (status = 200, description = "CSRF token of the session", body = CsrfToken)
+    )
+)]
+#[get("/csrf")]
+pub async fn csrf_token(session: CookieSession) -> Result<HttpResponse, ServiceError> {
+    let existing = session
+        .get::<String>(CSRF_SESSION_KEY)
+        .map_err(|e| ServiceError::InternalServerError(e.to_string()))?;
+
+    let token = match existing {
+        Some(token) => token,
+        None => {
+            let token = encryption::generate_token();
+            session
+                .insert(CSRF_SESSION_KEY, &token)
+                .map_err(|e| ServiceError::InternalServerError(e.to_string()))?;
+            token
+        }
+    };
+
+    Ok(HttpResponse::Ok().json(CsrfToken { token }))
+}
+
+/// Refuses unsafe requests made with the session cookie unless they carry the session's
+/// CSRF token. Requests without a signed in session, and service requests whose signature
+/// `ServiceSignatureGuard` checked, pass through. Other headers prove nothing: a page on
+/// another site can send any header along with the victim's cookies.
+///
+/// Needs the session and identity middleware, so it has to be registered before them, and
+/// `ServiceSignatureGuard` has to run first.
+pub struct CsrfGuard;
+
+impl<S, B> Transform<S, ServiceRequest> for CsrfGuard
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = CsrfMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        future::ready(Ok(CsrfMiddleware { service }))
+    }
+}
+
+pub struct CsrfMiddleware<S> {
+    service: S,
+}
+
+fn is_exempt(req: &ServiceRequest) -> bool {
+    if matches!(
+        *req.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+    ) {
+        return true;
+    }
+
+    if req.extensions().contains::<SignedRequest>() {
+        return true;
+    }
+    // Browsers holding a client certificate present it cross site too, so only clients
+    // sending no Origin, which browsers always do here, may rely on it alone.
+    if req.conn_data::<ClientCertificate>().is_some() && req.headers().contains_key(header::ORIGIN)
+    {
+        return false;
+    }
+
+    // Without a signed in identity the cookie grants nothing worth forging.
+    Identity::from_request(req.request(), &mut Payload::None)
+        .into_inner()
+        .is_err()
+}
+
+fn token_matches(req: &ServiceRequest) -> bool {
+    let expected = match req.get_session().get::<String>(CSRF_SESSION_KEY) {
+        Ok(Some(token)) => token,
+        _ => return false,
+    };
+
+    req.headers()
+        .get(CSRF_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(|sent| constant_time_compare(sent, &expected))
+        .unwrap_or(false)
+}
+
+impl<S, B> Service<ServiceRequest> for CsrfMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, actix_web::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        if !is_exempt(&req) && !token_matches(&req) {
+            log::debug!("CSRF check failed: {} {}", req.method(), req.path());
+            let response =
+                req.into_response(HttpResponse::Forbidden().json("Missing or invalid CSRF token"));
+            return Box::pin(async { Ok(response.map_into_right_body()) });
+        }
+
+        let future = self.service.call(req);
+        Box::pin(async move { future.await.map(ServiceResponse::map_into_left_body) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_identity::IdentityMiddleware;
+    use actix_session::{storage::CookieSessionStore, SessionMiddleware};
+    use actix_web::cookie::{Cookie, Key};
+    use actix_web::{test, web, App, HttpRequest};
+
+    async fn sign_in(req: HttpRequest) -> HttpResponse {
+        Identity::login(&req.extensions(), "1".into()).unwrap();
+        HttpResponse::Ok().finish()
+    }
+
+    async fn write() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    fn cookies<B>(res: &ServiceResponse<B>) -> Vec<Cookie<'static>> {
+        res.response()
+            .cookies()
+            .map(|cookie| cookie.into_owned())
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn test_csrf_guard() {
+        let app = test::init_service(
+            App::new()
+                .wrap(CsrfGuard)
+                // Stands in for `ServiceSignatureGuard` accepting a signature.
+                .wrap_fn(|req, srv| {
+                    if req.headers().contains_key("x-test-signed") {
+                        req.extensions_mut().insert(SignedRequest);
+                    }
+                    srv.call(req)
+                })
+                .wrap(IdentityMiddleware::default())
+                .wrap(SessionMiddleware::new(
+                    CookieSessionStore::default(),
+                    Key::generate(),
+                ))
+                .service(csrf_token)
+                .route("/sign-in", web::post().to(sign_in))
+                .route("/write", web::post().to(write)),
+        )
+        .await;
+        let post = |cookies: &[Cookie<'static>], headers: &[(&str, &str)]| {
+            let req = cookies
+                .iter()
+                .fold(test::TestRequest::post().uri("/write"), |req, cookie| {
+                    req.cookie(cookie.clone())
+                });
+            headers
+                .iter()
+                .fold(req, |req, header| req.insert_header(*header))
+                .to_request()
+        };
+
+        // Anonymous requests have nothing to forge.
+        let res = test::call_service(&app, post(&[], &[])).await;
+        assert_eq!(res.status(), 200);
+
+        let res =
+            test::call_service(&app, test::TestRequest::post().uri("/sign-in").to_request()).await;
+        let signed_in = cookies(&res);
+        assert!(!signed_in.is_empty());
+
+        let res = test::call_service(&app, post(&signed_in, &[])).await;
+        assert_eq!(res.status(), 403);
+        // Headers a page on another site can set don't lift the check.
+        let res = test::call_service(&app, post(&signed_in, &[("x-api-key", "x")])).await;
+        assert_eq!(res.status(), 403);
+        let res = test::call_service(&app, post(&signed_in, &[("x-service-key", "x")])).await;
+        assert_eq!(res.status(), 403);
+        let res =
+            test::call_service(&app, post(&signed_in, &[("authorization", "Bearer x")])).await;
+        assert_eq!(res.status(), 403);
+        // A checked signature does.
+        let res = test::call_service(&app, post(&signed_in, &[("x-test-signed", "1")])).await;
+        assert_eq!(res.status(), 200);
+
+        let req = signed_in
+            .iter()
+            .fold(test::TestRequest::get().uri("/csrf"), |req, cookie| {
+                req.cookie(cookie.clone())
+            })
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 200);
+        let with_token = cookies(&res);
+        let body: CsrfToken = test::read_body_json(res).await;
+
+        let res = test::call_service(&app, post(&with_token, &[(CSRF_HEADER, "wrong")])).await;
+        assert_eq!(res.status(), 403);
+        let res = test::call_service(&app, post(&with_token, &[(CSRF_HEADER, &body.token)])).await;
+        assert_eq!(res.status(), 200);
+    }
+}
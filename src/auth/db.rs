@@ -1,20 +1,59 @@
-use deadpool_postgres::Client;
+use chrono::{DateTime, Utc};
+use deadpool_postgres::{Client, GenericClient};
 use std::io;
 use tokio_pg_mapper::FromTokioPostgresRow;
+use tokio_postgres::error::SqlState;
 
+use crate::configs::db;
 use crate::errors::ServiceError;
 
+use super::backends::ExternalUser;
 use super::model::*;
+use super::roles::Role;
 
-pub async fn add_user(client: &Client, usr: CreateUser) -> Result<CreatedUser, ServiceError> {
+/// Unique index keeping usernames distinct regardless of case.
+const USERNAME_INDEX: &str = "users_username_lower_idx";
+
+// Turns a unique violation on the users table into a conflict naming the clashing field.
+fn user_conflict(e: tokio_postgres::Error) -> ServiceError {
+    match e.code() {
+        Some(code) if *code == SqlState::UNIQUE_VIOLATION => {
+            let constraint = e.as_db_error().and_then(|db_error| db_error.constraint());
+            if constraint == Some(USERNAME_INDEX) {
+                ServiceError::Conflict("Username is already taken".into())
+            } else {
+                ServiceError::Conflict("Account already exists".into())
+            }
+        }
+        _ => ServiceError::from(e),
+    }
+}
+
+pub async fn add_user<C: GenericClient>(
+    client: &C,
+    usr: CreateUser,
+    role: Role,
+) -> Result<CreatedUser, ServiceError> {
     let statement = client
-        .prepare(  "INSERT INTO public.users (id, username, hashed_password, email) VALUES ($0, $1 ) RETURNING id",)
-        .await
-        .unwrap();
+        .prepare(&format!(
+            "INSERT INTO {users} (email, hashed_password, role, username)
+            VALUES ($1, $2, $3, $4) RETURNING id",
+            users = db().users
+        ))
+        .await?;
 
     let result = client
-        .query_one(&statement, &[&usr.email, &usr.hashed_password])
-        .await?;
+        .query_one(
+            &statement,
+            &[
+                &usr.email,
+                &usr.hashed_password,
+                &role.as_str(),
+                &usr.username,
+            ],
+        )
+        .await
+        .map_err(user_conflict)?;
     let user = CreatedUser::from_row_ref(&result).unwrap(); // or from_row_ref(&result)
     Ok(user)
 }
@@ -24,17 +63,23 @@ pub async fn add_session(
     sess: SessionAdd,
 ) -> Result<CreatedSession, ServiceError> {
     let statement = client
-        .prepare(
-            "INSERT INTO public.sessions (user_id, session_verifier, otp_code_encrypted)
-            VALUES($1, $2, $3) RETURNING id",
-        )
+        .prepare(&format!(
+            "INSERT INTO {sessions} (user_id, session_verifier, otp_code_encrypted, login_fingerprint)
+            VALUES($1, $2, $3, $4) RETURNING id",
+            sessions = db().sessions
+        ))
         .await
         .unwrap();
 
     let result = client
         .query_one(
             &statement,
-            &[&sess.user_id, &sess.session_verifier, &sess.otp_code_encr],
+            &[
+                &sess.user_id,
+                &sess.session_verifier,
+                &sess.otp_code_encr,
+                &sess.login_fingerprint,
+            ],
         )
         .await?;
     let sess = CreatedSession::from_row_ref(&result).unwrap(); // or from_row_ref(&result)
@@ -43,7 +88,10 @@ pub async fn add_session(
 
 pub async fn delete_session(client: &Client, sess: Session) -> Result<(), io::Error> {
     let statement = client
-        .prepare("DELETE FROM public.sessions WHERE id = $1")
+        .prepare(&format!(
+            "DELETE FROM {sessions} WHERE id = $1",
+            sessions = db().sessions
+        ))
         .await
         .unwrap();
 
@@ -54,9 +102,29 @@ pub async fn delete_session(client: &Client, sess: Session) -> Result<(), io::Er
     Ok(())
 }
 
+/// Deletes every session of a user, optionally keeping one (usually the caller's own).
+/// Returns the number of sessions removed.
+pub async fn delete_user_sessions<C: GenericClient>(
+    client: &C,
+    user_id: i32,
+    except_session_id: Option<i32>,
+) -> Result<u64, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "DELETE FROM {sessions} WHERE user_id = $1 AND ($2::int IS NULL OR id <> $2)",
+            sessions = db().sessions
+        ))
+        .await?;
+
+    let removed = client
+        .execute(&statement, &[&user_id, &except_session_id])
+        .await?;
+    Ok(removed)
+}
+
 pub async fn find_user_by_session(client: &Client, session: Session) -> Option<UserSession> {
     let statement = client
-        .prepare(
+        .prepare(&format!(
             " SELECT
             id,
             user_id,
@@ -64,14 +132,18 @@ pub async fn find_user_by_session(client: &Client, session: Session) -> Option<U
             otp_code_confirmed,
             otp_code_encrypted,
             otp_code_attempts,
-            otp_code_sent FROM public.users WHERE id = $1",
-        )
+            otp_code_sent,
+            impersonated_user_id,
+            impersonation_read_only,
+            otp_required FROM {sessions} WHERE id = $1",
+            sessions = db().sessions
+        ))
         .await
         //  .map_err(|e| format!("Error preparing statement: {}", e))?;
         .unwrap();
 
     let maybe_session = client
-        .query_opt(&statement, &[&session.session_verifier])
+        .query_opt(&statement, &[&session.session_id])
         .await
         // .ok()
         .expect("Error adding session ")
@@ -90,22 +162,57 @@ pub async fn find_user_by_session(client: &Client, session: Session) -> Option<U
 
 // Constant time string compare.
 pub fn constant_time_compare(a: &str, b: &str) -> bool {
-    a.bytes()
-        .zip(b.bytes())
-        .fold(0, |acc, (a, b)| acc | (a ^ b))
-        == 0
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
 }
 
+/// Looks a user up by email address ignoring case, like `email_taken` checks addresses.
+/// Accounts from before that check may differ only in case; the exact match wins then.
 pub async fn find_user_by_mail(client: &Client, email: String) -> Result<FindUser, io::Error> {
     let statement = client
-        .prepare("SELECT id, hashed_password FROM public.users WHERE email = $1")
+        .prepare(&format!(
+            "SELECT id, hashed_password, disabled, password_reset_required
+            FROM {users} WHERE lower(email) = lower($1)
+            ORDER BY email = $1 DESC, id LIMIT 1",
+            users = db().users
+        ))
         .await
-        .unwrap();
+        .map_err(io::Error::other)?;
 
     let maybe_user = client
         .query_opt(&statement, &[&email])
         .await
-        .expect("Error adding category ")
+        .map_err(io::Error::other)?
+        .map(|row| FindUser::from_row_ref(&row).unwrap());
+
+    match maybe_user {
+        Some(user) => Ok(user),
+        None => Err(io::Error::new(io::ErrorKind::NotFound, "Not found")),
+    }
+}
+
+/// Looks a user up by email address or, when `login` has no `@`, by username ignoring case.
+pub async fn find_user_by_login(client: &Client, login: String) -> Result<FindUser, io::Error> {
+    if login.contains('@') {
+        return find_user_by_mail(client, login).await;
+    }
+
+    let statement = client
+        .prepare(&format!(
+            "SELECT id, hashed_password, disabled, password_reset_required
+            FROM {users} WHERE lower(username) = lower($1)",
+            users = db().users
+        ))
+        .await
+        .map_err(io::Error::other)?;
+
+    let maybe_user = client
+        .query_opt(&statement, &[&login])
+        .await
+        .map_err(io::Error::other)?
         .map(|row| FindUser::from_row_ref(&row).unwrap());
 
     match maybe_user {
@@ -114,9 +221,151 @@ pub async fn find_user_by_mail(client: &Client, email: String) -> Result<FindUse
     }
 }
 
+/// The account `backend` created for a directory user, matched by email ignoring case.
+/// The display name follows the directory.
+pub async fn find_external_user(
+    client: &Client,
+    user: &ExternalUser,
+    backend: &str,
+) -> Result<Option<FindUser>, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "UPDATE {users} SET display_name = COALESCE($2, display_name)
+            WHERE lower(email) = lower($1) and auth_backend = $3
+            RETURNING id, hashed_password, disabled, password_reset_required",
+            users = db().users
+        ))
+        .await?;
+
+    Ok(client
+        .query_opt(&statement, &[&user.email, &user.display_name, &backend])
+        .await?
+        .map(|row| FindUser::from_row_ref(&row).unwrap()))
+}
+
+pub async fn email_taken(client: &Client, email: &str) -> Result<bool, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "SELECT EXISTS (SELECT 1 FROM {users} WHERE lower(email) = lower($1))",
+            users = db().users
+        ))
+        .await?;
+
+    Ok(client.query_one(&statement, &[&email]).await?.get(0))
+}
+
+/// Creates the account of a directory user, recording `backend` created it.
+pub async fn add_external_user(
+    client: &Client,
+    user: &ExternalUser,
+    backend: &str,
+    username: Option<&str>,
+    hashed_password: String,
+) -> Result<FindUser, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "INSERT INTO {users} (email, hashed_password, role, username, display_name, auth_backend)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id, hashed_password, disabled, password_reset_required",
+            users = db().users
+        ))
+        .await?;
+
+    let row = client
+        .query_one(
+            &statement,
+            &[
+                &user.email,
+                &hashed_password,
+                &Role::User.as_str(),
+                &username,
+                &user.display_name,
+                &backend,
+            ],
+        )
+        .await
+        .map_err(user_conflict)?;
+    Ok(FindUser::from_row_ref(&row).unwrap())
+}
+
+pub async fn username_taken(client: &Client, username: &str) -> Result<bool, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "SELECT EXISTS (SELECT 1 FROM {users} WHERE lower(username) = lower($1))",
+            users = db().users
+        ))
+        .await?;
+
+    Ok(client.query_one(&statement, &[&username]).await?.get(0))
+}
+
+pub async fn user_set_username(
+    client: &Client,
+    id: i32,
+    username: &str,
+) -> Result<(), ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "UPDATE {users} SET username = $1 WHERE id = $2",
+            users = db().users
+        ))
+        .await?;
+
+    match client
+        .execute(&statement, &[&username, &id])
+        .await
+        .map_err(user_conflict)?
+    {
+        1 => Ok(()),
+        _ => Err(ServiceError::NotFound("User not found".into())),
+    }
+}
+
+pub async fn find_user_password_by_id(client: &Client, id: i32) -> Result<FindUser, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "SELECT id, hashed_password, disabled, password_reset_required
+            FROM {users} WHERE id = $1",
+            users = db().users
+        ))
+        .await?;
+
+    let maybe_user = client
+        .query_opt(&statement, &[&id])
+        .await?
+        .map(|row| FindUser::from_row_ref(&row).unwrap());
+
+    match maybe_user {
+        Some(user) => Ok(user),
+        None => Err(ServiceError::NotFound("User not found".into())),
+    }
+}
+
+pub async fn find_user_by_id(client: &Client, id: i32) -> Result<UserAccount, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "SELECT id, email, username, role, disabled FROM {users} WHERE id = $1",
+            users = db().users
+        ))
+        .await?;
+
+    let maybe_user = client
+        .query_opt(&statement, &[&id])
+        .await?
+        .map(|row| UserAccount::from_row_ref(&row).unwrap());
+
+    match maybe_user {
+        Some(user) => Ok(user),
+        None => Err(ServiceError::NotFound("User not found".into())),
+    }
+}
+
 pub async fn find_user_mail_by_id(client: &Client, id: i32) -> Result<UserMail, ServiceError> {
     let statement = client
-        .prepare("SELECT email FROM public.users WHERE id = $1")
+        .prepare(&format!(
+            "SELECT email FROM {users} WHERE id = $1",
+            users = db().users
+        ))
         .await
         .unwrap();
 
@@ -134,7 +383,10 @@ pub async fn find_user_mail_by_id(client: &Client, id: i32) -> Result<UserMail,
 
 pub async fn session_otp_update_true(client: &Client, id: i32) -> Result<(), io::Error> {
     let statement = client
-        .prepare("update public.sessions set otp_code_sent = true where id = $1")
+        .prepare(&format!(
+            "update {sessions} set otp_code_sent = true where id = $1",
+            sessions = db().sessions
+        ))
         .await
         .unwrap();
 
@@ -151,9 +403,10 @@ pub async fn session_otp_update_true(client: &Client, id: i32) -> Result<(), io:
 
 pub async fn session_otp_update_confirm_true(client: &Client, id: i32) -> Result<(), io::Error> {
     let statement = client
-        .prepare(
-            "update public.sessions SET otp_code_confirm = true AND otp_code_attempts = 0 WHERE id = $0",
-        )
+        .prepare(&format!(
+            "update {sessions} SET otp_code_confirmed = true, otp_code_attempts = 0 WHERE id = $1",
+            sessions = db().sessions
+        ))
         .await
         .unwrap();
 
@@ -170,9 +423,10 @@ pub async fn session_otp_update_confirm_true(client: &Client, id: i32) -> Result
 
 pub async fn session_otp_set_attempts(client: &Client, id: i32) -> Result<(), io::Error> {
     let statement = client
-        .prepare(
-            "update public.sessions SET otp_code_attempts = otp_code_attempts + 1 where id = $1",
-        )
+        .prepare(&format!(
+            "update {sessions} SET otp_code_attempts = otp_code_attempts + 1 where id = $1",
+            sessions = db().sessions
+        ))
         .await
         .unwrap();
 
@@ -186,3 +440,463 @@ pub async fn session_otp_set_attempts(client: &Client, id: i32) -> Result<(), io
         _ => Err(io::Error::new(io::ErrorKind::Other, "Failed to check list")),
     }
 }
+
+pub async fn password_reset_add(
+    client: &Client,
+    user_id: i32,
+    token_hash: &str,
+    expires_at: DateTime<Utc>,
+) -> Result<(), ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "INSERT INTO {password_resets} (user_id, token_hash, expires_at) VALUES ($1, $2, $3)",
+            password_resets = db().password_resets
+        ))
+        .await?;
+
+    client
+        .execute(&statement, &[&user_id, &token_hash, &expires_at])
+        .await?;
+    Ok(())
+}
+
+/// The reset a token was issued for, used or expired ones included.
+pub async fn password_reset_find(
+    client: &Client,
+    token_hash: &str,
+) -> Result<Option<PasswordReset>, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "SELECT id, user_id, expires_at, used_at FROM {password_resets}
+            WHERE token_hash = $1",
+            password_resets = db().password_resets
+        ))
+        .await?;
+
+    let maybe_reset = client
+        .query_opt(&statement, &[&token_hash])
+        .await?
+        .map(|row| PasswordReset::from_row_ref(&row).unwrap());
+    Ok(maybe_reset)
+}
+
+/// Stores the new password, clears the reset flag, burns the reset token and signs the
+/// user out everywhere, in one transaction.
+pub async fn password_reset_complete(
+    client: &mut Client,
+    reset: &PasswordReset,
+    hashed_password: &str,
+) -> Result<(), ServiceError> {
+    let transaction = client.transaction().await?;
+
+    let statement = transaction
+        .prepare(&format!(
+            "UPDATE {password_resets} SET used_at = now() WHERE id = $1 AND used_at IS NULL",
+            password_resets = db().password_resets
+        ))
+        .await?;
+    if transaction.execute(&statement, &[&reset.id]).await? != 1 {
+        return Err(ServiceError::NotFound(
+            "Reset link is invalid or expired".into(),
+        ));
+    }
+
+    let statement = transaction
+        .prepare(&format!("UPDATE {users} SET hashed_password = $1, password_reset_required = false WHERE id = $2", users = db().users),
+        )
+        .await?;
+    transaction
+        .execute(&statement, &[&hashed_password, &reset.user_id])
+        .await?;
+
+    delete_user_sessions(&transaction, reset.user_id, None).await?;
+    delete_trusted_devices(&transaction, reset.user_id).await?;
+
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Stores a new password and signs the user out of every other session.
+pub async fn user_password_change(
+    client: &mut Client,
+    user_id: i32,
+    hashed_password: &str,
+    keep_session_id: Option<i32>,
+) -> Result<u64, ServiceError> {
+    let transaction = client.transaction().await?;
+
+    let statement = transaction
+        .prepare(&format!(
+            "UPDATE {users} SET hashed_password = $1 WHERE id = $2",
+            users = db().users
+        ))
+        .await?;
+    if transaction
+        .execute(&statement, &[&hashed_password, &user_id])
+        .await?
+        != 1
+    {
+        return Err(ServiceError::NotFound("User not found".into()));
+    }
+    let revoked = delete_user_sessions(&transaction, user_id, keep_session_id).await?;
+
+    transaction.commit().await?;
+    Ok(revoked)
+}
+
+pub async fn email_change_add(
+    client: &Client,
+    user_id: i32,
+    new_email: &str,
+    token_hash: &str,
+    expires_at: DateTime<Utc>,
+) -> Result<(), ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "INSERT INTO {email_changes} (user_id, new_email, token_hash, expires_at)
+            VALUES ($1, $2, $3, $4)",
+            email_changes = db().email_changes
+        ))
+        .await?;
+
+    client
+        .execute(
+            &statement,
+            &[&user_id, &new_email, &token_hash, &expires_at],
+        )
+        .await?;
+    Ok(())
+}
+
+pub async fn email_change_find(
+    client: &Client,
+    token_hash: &str,
+) -> Result<Option<EmailChange>, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "SELECT id, user_id, new_email, expires_at, confirmed_at FROM {email_changes}
+            WHERE token_hash = $1",
+            email_changes = db().email_changes
+        ))
+        .await?;
+
+    let maybe_change = client
+        .query_opt(&statement, &[&token_hash])
+        .await?
+        .map(|row| EmailChange::from_row_ref(&row).unwrap());
+    Ok(maybe_change)
+}
+
+/// Switches the address of the user once the link sent to the new one is followed and
+/// burns the token. Pending requests of the same user are dropped.
+pub async fn email_change_confirm(
+    client: &mut Client,
+    change: &EmailChange,
+) -> Result<(), ServiceError> {
+    let transaction = client.transaction().await?;
+
+    let statement = transaction
+        .prepare(&format!(
+            "UPDATE {email_changes} SET confirmed_at = now() WHERE id = $1 AND confirmed_at IS NULL",
+            email_changes = db().email_changes
+        ))
+        .await?;
+    if transaction.execute(&statement, &[&change.id]).await? != 1 {
+        return Err(ServiceError::NotFound(
+            "Confirmation link is invalid or expired".into(),
+        ));
+    }
+
+    let statement = transaction
+        .prepare(&format!(
+            "UPDATE {users} SET email = $1 WHERE id = $2",
+            users = db().users
+        ))
+        .await?;
+    transaction
+        .execute(&statement, &[&change.new_email, &change.user_id])
+        .await
+        .map_err(|e| match e.code() {
+            Some(code) if *code == SqlState::UNIQUE_VIOLATION => {
+                ServiceError::Conflict("Email address already in use".into())
+            }
+            _ => ServiceError::from(e),
+        })?;
+
+    let statement = transaction
+        .prepare(&format!(
+            "DELETE FROM {email_changes} WHERE user_id = $1 AND confirmed_at IS NULL",
+            email_changes = db().email_changes
+        ))
+        .await?;
+    transaction.execute(&statement, &[&change.user_id]).await?;
+
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Replaces all recovery codes of a user with the given hashes.
+pub async fn recovery_codes_replace(
+    client: &mut Client,
+    user_id: i32,
+    code_hashes: &[String],
+) -> Result<(), ServiceError> {
+    let transaction = client.transaction().await?;
+
+    let statement = transaction
+        .prepare(&format!(
+            "DELETE FROM {recovery_codes} WHERE user_id = $1",
+            recovery_codes = db().recovery_codes
+        ))
+        .await?;
+    transaction.execute(&statement, &[&user_id]).await?;
+
+    let statement = transaction
+        .prepare(&format!(
+            "INSERT INTO {recovery_codes} (user_id, code_hash) VALUES ($1, $2)",
+            recovery_codes = db().recovery_codes
+        ))
+        .await?;
+    for code_hash in code_hashes {
+        transaction
+            .execute(&statement, &[&user_id, code_hash])
+            .await?;
+    }
+
+    transaction.commit().await?;
+    Ok(())
+}
+
+pub async fn recovery_codes_remaining(client: &Client, user_id: i32) -> Result<i64, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "SELECT count(*) FROM {recovery_codes} WHERE user_id = $1 AND used_at IS NULL",
+            recovery_codes = db().recovery_codes
+        ))
+        .await?;
+
+    Ok(client.query_one(&statement, &[&user_id]).await?.get(0))
+}
+
+/// Burns a recovery code. Returns false when it doesn't exist or was already used.
+pub async fn recovery_code_use(
+    client: &Client,
+    user_id: i32,
+    code_hash: &str,
+) -> Result<bool, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "UPDATE {recovery_codes} SET used_at = now()
+            WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL",
+            recovery_codes = db().recovery_codes
+        ))
+        .await?;
+
+    Ok(client.execute(&statement, &[&user_id, &code_hash]).await? == 1)
+}
+
+pub async fn trusted_device_add(
+    client: &Client,
+    user_id: i32,
+    token_hash: &str,
+    fingerprint: &str,
+    name: &str,
+    expires_at: DateTime<Utc>,
+) -> Result<(), ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "INSERT INTO {trusted_devices} (user_id, token_hash, fingerprint, name, expires_at)
+            VALUES ($1, $2, $3, $4, $5)",
+            trusted_devices = db().trusted_devices
+        ))
+        .await?;
+
+    client
+        .execute(
+            &statement,
+            &[&user_id, &token_hash, &fingerprint, &name, &expires_at],
+        )
+        .await?;
+    Ok(())
+}
+
+/// Marks the device as used when the token belongs to the user, was issued to the same
+/// fingerprint and hasn't expired. Returns false otherwise.
+pub async fn trusted_device_use(
+    client: &Client,
+    user_id: i32,
+    token_hash: &str,
+    fingerprint: &str,
+) -> Result<bool, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "UPDATE {trusted_devices} SET last_used_at = now()
+            WHERE user_id = $1 AND token_hash = $2 AND fingerprint = $3 AND expires_at > now()",
+            trusted_devices = db().trusted_devices
+        ))
+        .await?;
+
+    Ok(client
+        .execute(&statement, &[&user_id, &token_hash, &fingerprint])
+        .await?
+        == 1)
+}
+
+pub async fn trusted_device_list(
+    client: &Client,
+    user_id: i32,
+) -> Result<Vec<TrustedDevice>, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "SELECT id, name, expires_at, last_used_at, created_at FROM {trusted_devices}
+            WHERE user_id = $1 AND expires_at > now() ORDER BY id DESC",
+            trusted_devices = db().trusted_devices
+        ))
+        .await?;
+
+    let devices = client
+        .query(&statement, &[&user_id])
+        .await?
+        .iter()
+        .map(|row| TrustedDevice::from_row_ref(row).unwrap())
+        .collect::<Vec<TrustedDevice>>();
+    Ok(devices)
+}
+
+pub async fn trusted_device_revoke(
+    client: &Client,
+    user_id: i32,
+    id: i32,
+) -> Result<(), ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "DELETE FROM {trusted_devices} WHERE id = $1 AND user_id = $2",
+            trusted_devices = db().trusted_devices
+        ))
+        .await?;
+
+    match client.execute(&statement, &[&id, &user_id]).await? {
+        1 => Ok(()),
+        _ => Err(ServiceError::NotFound("Device not found".into())),
+    }
+}
+
+/// Forgets every trusted device of a user. Returns the number of devices removed.
+pub async fn delete_trusted_devices<C: GenericClient>(
+    client: &C,
+    user_id: i32,
+) -> Result<u64, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "DELETE FROM {trusted_devices} WHERE user_id = $1",
+            trusted_devices = db().trusted_devices
+        ))
+        .await?;
+
+    Ok(client.execute(&statement, &[&user_id]).await?)
+}
+
+/// Remembers the fingerprint of a login. Returns whether it is new for the user and how
+/// many fingerprints the user has, this one included.
+pub async fn login_fingerprint_record(
+    client: &Client,
+    user_id: i32,
+    fingerprint: &str,
+) -> Result<(bool, i64), ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "INSERT INTO {login_fingerprints} (user_id, fingerprint) VALUES ($1, $2)
+            ON CONFLICT (user_id, fingerprint) DO UPDATE SET last_seen_at = now()
+            RETURNING (xmax = 0) AS inserted",
+            login_fingerprints = db().login_fingerprints
+        ))
+        .await?;
+    let inserted: bool = client
+        .query_one(&statement, &[&user_id, &fingerprint])
+        .await?
+        .get(0);
+
+    let statement = client
+        .prepare(&format!(
+            "SELECT count(*) FROM {login_fingerprints} WHERE user_id = $1",
+            login_fingerprints = db().login_fingerprints
+        ))
+        .await?;
+    let known: i64 = client.query_one(&statement, &[&user_id]).await?.get(0);
+
+    Ok((inserted, known))
+}
+
+pub async fn session_otp_require(client: &Client, id: i32) -> Result<(), ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "UPDATE {sessions} SET otp_required = true WHERE id = $1",
+            sessions = db().sessions
+        ))
+        .await?;
+
+    client.execute(&statement, &[&id]).await?;
+    Ok(())
+}
+
+pub async fn login_alert_add(
+    client: &Client,
+    user_id: i32,
+    session_id: i32,
+    token_hash: &str,
+    expires_at: DateTime<Utc>,
+) -> Result<(), ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "INSERT INTO {login_alerts} (user_id, session_id, token_hash, expires_at)
+            VALUES ($1, $2, $3, $4)",
+            login_alerts = db().login_alerts
+        ))
+        .await?;
+
+    client
+        .execute(
+            &statement,
+            &[&user_id, &session_id, &token_hash, &expires_at],
+        )
+        .await?;
+    Ok(())
+}
+
+/// Acts on a "this wasn't me" link: burns the alert token, requires a password reset and
+/// signs the user out of every session, the reported one included, in one transaction.
+/// Returns the user id.
+pub async fn login_alert_revoke(
+    client: &mut Client,
+    token_hash: &str,
+) -> Result<i32, ServiceError> {
+    let transaction = client.transaction().await?;
+
+    let statement = transaction
+        .prepare(&format!(
+            "UPDATE {login_alerts} SET used_at = now()
+            WHERE token_hash = $1 AND used_at IS NULL AND expires_at > now()
+            RETURNING user_id",
+            login_alerts = db().login_alerts
+        ))
+        .await?;
+    let user_id: i32 = transaction
+        .query_opt(&statement, &[&token_hash])
+        .await?
+        .map(|row| row.get(0))
+        .ok_or_else(|| ServiceError::NotFound("Link is invalid or expired".into()))?;
+
+    let statement = transaction
+        .prepare(&format!(
+            "UPDATE {users} SET password_reset_required = true WHERE id = $1",
+            users = db().users
+        ))
+        .await?;
+    transaction.execute(&statement, &[&user_id]).await?;
+
+    delete_user_sessions(&transaction, user_id, None).await?;
+    delete_trusted_devices(&transaction, user_id).await?;
+
+    transaction.commit().await?;
+    Ok(user_id)
+}
@@ -10,6 +10,9 @@ use argon2::{
     Argon2,
 };
 use bcrypt::{hash, verify, DEFAULT_COST};
+use chrono::{DateTime, Duration, Utc};
+use rand::Rng;
+use sha2::{Digest, Sha256};
 use unicode_normalization::UnicodeNormalization;
 
 pub(crate) const NONCE_LEN: usize = 12;
@@ -180,6 +183,78 @@ pub async fn verify_hash(
     Ok(is_valid)
 }
 
+/// Random 32 byte token, hex encoded. Used for links and codes handed to users.
+pub fn generate_token() -> String {
+    hex::encode(rand::thread_rng().gen::<[u8; 32]>())
+}
+
+/// Hash a token before it is stored so a database leak doesn't expose usable tokens.
+pub fn hash_token(token: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(token.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+/// A token to hand out by email, with what gets stored of it.
+pub struct IssuedToken {
+    /// Sent to the user, never stored.
+    pub token: String,
+    pub hash: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// A fresh token that works for `ttl` from `now`.
+pub fn issue_token(ttl: Duration, now: DateTime<Utc>) -> IssuedToken {
+    let token = generate_token();
+    IssuedToken {
+        hash: hash_token(&token),
+        token,
+        expires_at: now + ttl,
+    }
+}
+
+/// Whether a stored single use token still works at `now`: it hasn't expired and nobody
+/// used it yet.
+pub fn token_usable(
+    expires_at: DateTime<Utc>,
+    used_at: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> bool {
+    used_at.is_none() && now < expires_at
+}
+
+pub(crate) const RECOVERY_CODE_COUNT: usize = 10;
+// No 0/O or 1/I, recovery codes get typed in from paper.
+const RECOVERY_CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+/// Fresh set of one-time recovery codes formatted as `XXXX-XXXX-XXXX-XXXX` (80 bits each).
+pub fn generate_recovery_codes() -> Vec<String> {
+    let mut rng = rand::thread_rng();
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let chars: Vec<char> = (0..16)
+                .map(|_| {
+                    RECOVERY_CODE_ALPHABET[rng.gen_range(0..RECOVERY_CODE_ALPHABET.len())] as char
+                })
+                .collect();
+            chars
+                .chunks(4)
+                .map(|group| group.iter().collect::<String>())
+                .collect::<Vec<String>>()
+                .join("-")
+        })
+        .collect()
+}
+
+/// Uppercases and drops separators so `abcd efgh-...` matches `ABCD-EFGH-...`.
+/// The result is what gets hashed with `hash_token`.
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
 pub fn hex_to_bytes(hex: &str) -> Result<Vec<u8>, std::num::ParseIntError> {
     (0..hex.len())
         .step_by(2)
@@ -189,7 +264,39 @@ pub fn hex_to_bytes(hex: &str) -> Result<Vec<u8>, std::num::ParseIntError> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use rand::Rng;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_issue_token() {
+        let now = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+        let issued = issue_token(Duration::hours(24), now);
+        assert_eq!(issued.token.len(), 64);
+        assert!(issued.token.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(issued.hash, hash_token(&issued.token));
+        assert_ne!(issued.hash, issued.token);
+        assert_eq!(
+            issued.expires_at,
+            Utc.with_ymd_and_hms(2024, 3, 2, 12, 0, 0).unwrap()
+        );
+        assert_ne!(issue_token(Duration::hours(24), now).token, issued.token);
+    }
+
+    #[test]
+    fn test_token_usable() {
+        let now = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+        let expires_at = now + Duration::hours(1);
+        assert!(token_usable(expires_at, None, now));
+        assert!(token_usable(
+            expires_at,
+            None,
+            expires_at - Duration::seconds(1)
+        ));
+        // Expired.
+        assert!(!token_usable(expires_at, None, expires_at));
+        assert!(!token_usable(expires_at, None, now + Duration::days(1)));
+        // Used once already.
+        assert!(!token_usable(expires_at, Some(now), now));
+    }
 
     #[tokio::test]
     async fn test_encryption() {
@@ -212,4 +319,16 @@ mod tests {
 
         assert_eq!(un_wrapped, "Hello World");
     }
+
+    #[test]
+    fn test_recovery_codes() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+
+        for code in &codes {
+            assert_eq!(code.len(), 19);
+            let typed = code.to_lowercase().replace('-', " ");
+            assert_eq!(normalize_recovery_code(&typed), code.replace('-', ""));
+        }
+    }
 }
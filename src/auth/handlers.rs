@@ -8,9 +8,11 @@ use serde_json::{Map, Value};
 use sha2::{Digest, Sha256};
 use std::io;
 
+use chrono::{Duration, Utc};
+
 use actix_web::{
-    delete, error, get, patch, post, web, Error, FromRequest, HttpRequest, HttpResponse, Responder,
-    Result,
+    delete, error, get, patch, post, web, Error, FromRequest, HttpMessage, HttpRequest,
+    HttpResponse, Responder, Result,
 };
 use deadpool_postgres::{Client, Pool};
 use io::ErrorKind::NotFound;
@@ -22,15 +24,23 @@ use io::ErrorKind::NotFound;
 //use crate::custom_error::CustomError;
 //use crate::layouts;
 
+use actix_web::cookie::time::Duration as CookieDuration;
+use actix_web::cookie::{Cookie, CookieJar, Key, SameSite};
 use actix_web::http;
 
 //use sqlx::PgPool;
 use std::borrow::Cow;
 use std::default::Default;
 
+use crate::auth::backends::{self, AuthOutcome};
 use crate::auth::db;
-use crate::auth::model::{CreateUser, Session, SessionAdd};
-use crate::configs;
+use crate::auth::model::{
+    ChangeEmail, ChangePassword, CompletePasswordReset, ConfirmEmailChange, CreateUser, Login,
+    LoginResult, OtpConfirmed, Profile, RecoveryCodeStatus, RecoveryCodes, Session, SessionAdd,
+    MIN_PASSWORD_LENGTH,
+};
+use crate::auth::roles::{AuthUser, Role};
+use crate::configs::{self, RegistrationMode};
 use crate::mail::model::Message;
 
 // use crate::auth::{db, UISchemaField, UserUISchema};
@@ -44,6 +54,12 @@ use super::{
 };
 // use validator::{Validate, ValidationError, ValidationErrors};
 
+const PASSWORD_RESET_TTL_HOURS: i64 = 24;
+const EMAIL_CHANGE_TTL_HOURS: i64 = 24;
+
+/// Signed cookie holding the token of a trusted device.
+const TRUSTED_DEVICE_COOKIE: &str = "trusted_device";
+
 /// Create User | Top
 
 /// Create an Account
@@ -52,6 +68,8 @@ use super::{
     request_body(content = CreateUser, description = "Create User", content_type = "application/json",  example = json!({"id": 1, "name": "bob the cat"})),
     responses(
         (status = 201, description = "User created successfully", body = CreateUser),
+        (status = 400, description = "Invalid username", body = ServiceError),
+        (status = 403, description = "Registration is by invitation only", body = ServiceError),
         (status = 409, description = "User with id already exists", body = ErrorResponse, example = json!(crate::auth::ErrorResponse::Conflict(String::from("id = 1"))))
     )
 )]
@@ -59,7 +77,19 @@ use super::{
 pub async fn register_user(
     db_pool: web::Data<Pool>,
     jsonusr: web::Json<CreateUser>,
-) -> impl Responder {
+) -> Result<HttpResponse, ServiceError> {
+    let config = configs::Config::from_env().unwrap();
+    if config.srv_cnf.registration_mode == RegistrationMode::InviteOnly {
+        return Err(ServiceError::Forbidden(
+            "Registration is by invitation only".into(),
+        ));
+    }
+
+    let username = jsonusr.username.as_deref().map(str::trim);
+    if let Some(username) = username {
+        super::username::validate_username(username)?;
+    }
+
     let client: Client = db_pool
         .get()
         .await
@@ -67,22 +97,27 @@ pub async fn register_user(
 
     let usr = CreateUser {
         email: jsonusr.email.clone(),
-        hashed_password: jsonusr.hashed_password.clone(),
+        username: username.map(str::to_owned),
+        hashed_password: encryption::password_hash(
+            &jsonusr.hashed_password,
+            config.srv_cnf.bcrypt_or_argon,
+        )
+        .await?,
     };
-    let result = db::add_user(&client, usr).await;
+    let result = db::add_user(&client, usr, Role::User).await;
 
     match result {
-        Ok(object) => HttpResponse::Ok().json(object),
-        Err(_) => HttpResponse::InternalServerError().into(),
+        Ok(object) => Ok(HttpResponse::Ok().json(object)),
+        Err(e) => Err(e),
     }
 }
 
 pub async fn session_create(
     pool: web::Data<Pool>,
-    identity: Identity,
+    req: &HttpRequest,
     user_id: i32,
     master_key_hash: Option<String>,
-) -> Result<(), ServiceError> {
+) -> Result<i32, ServiceError> {
     // We generate and OTP code and encrypt it.
     // Encryption helps secure against an attacker who has read only access to the database
 
@@ -118,6 +153,7 @@ pub async fn session_create(
         user_id,
         session_verifier: hex_hashed_session_verifier,
         otp_code_encr: otp_encrypted,
+        login_fingerprint: super::new_device::login_fingerprint(req),
     };
 
     let client: Client = pool.get().await.expect("Error connecting to the database");
@@ -133,52 +169,549 @@ pub async fn session_create(
     let serialized =
         serde_json::to_string(&session).map_err(|e| ServiceError::FaultySetup(e.to_string()))?;
 
-    // identity.remember(serialized);
+    Identity::login(&req.extensions(), serialized)
+        .map_err(|e| ServiceError::InternalServerError(e.to_string()))?;
 
-    Ok(())
+    Ok(sess_res.id)
+}
+
+fn cookie_key() -> Result<Key, ServiceError> {
+    let config = configs::Config::from_env().unwrap();
+    let secret = hex_to_bytes(&config.srv_cnf.secret_key)
+        .map_err(|e| ServiceError::FaultySetup(e.to_string()))?;
+    Ok(Key::derive_from(&secret))
+}
+
+/// Devices are told apart by their user agent, a cookie copied to another browser
+/// won't match.
+fn device_fingerprint(req: &HttpRequest) -> String {
+    encryption::hash_token(&device_name(req))
+}
+
+pub(crate) fn device_name(req: &HttpRequest) -> String {
+    req.headers()
+        .get(http::header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .map(|agent| agent.chars().take(200).collect())
+        .unwrap_or_else(|| "Unknown device".to_owned())
+}
+
+/// The trusted device token sent by the browser, if its signature is valid.
+fn trusted_device_token(req: &HttpRequest, key: &Key) -> Option<String> {
+    let mut jar = CookieJar::new();
+    jar.add_original(req.cookie(TRUSTED_DEVICE_COOKIE)?);
+    jar.signed(key)
+        .get(TRUSTED_DEVICE_COOKIE)
+        .map(|cookie| cookie.value().to_owned())
+}
+
+fn trusted_device_cookie(token: String, key: &Key, days: i64, secure: bool) -> Cookie<'static> {
+    let mut jar = CookieJar::new();
+    jar.signed_mut(key).add(
+        Cookie::build(TRUSTED_DEVICE_COOKIE, token)
+            .path("/auth")
+            .http_only(true)
+            .secure(secure)
+            .same_site(SameSite::Strict)
+            .max_age(CookieDuration::days(days))
+            .finish(),
+    );
+    jar.get(TRUSTED_DEVICE_COOKIE)
+        .cloned()
+        .expect("signed cookie was just added")
 }
 
 /// Login | Top
 ///
-/// Login your account
+/// Login your account with your email address or username
 #[utoipa::path(
     context_path = "/auth",
-    request_body = CreateUser,
+    request_body = Login,
     responses(
-        (status = 201, description = "User logged successfully", body = CreateUser),
+        (status = 202, description = "User logged successfully", body = LoginResult),
+        (status = 403, description = "Password reset required", body = ServiceError),
         (status = 409, description = "Authentication Failure", body = ErrorResponse, example = json!(crate::auth::ErrorResponse::Conflict(String::from("id = 1"))))
     )
 )]
 #[post("/login")]
 pub async fn process_login(
     pool: web::Data<Pool>,
-    identity: Identity,
-    login: web::Json<CreateUser>,
+    req: HttpRequest,
+    login: web::Json<Login>,
 ) -> Result<HttpResponse, ServiceError> {
     let client: Client = pool.get().await.expect("Error connecting to the database");
 
-    let email = &login.email;
     let config = configs::Config::from_env().unwrap();
 
-    match find_user_by_mail(&client, email.to_string()).await {
-        Ok(user) => {
-            if encryption::verify_hash(
-                &login.hashed_password,
-                &user.hashed_password,
-                config.srv_cnf.bcrypt_or_argon,
+    let user = match backends::authenticate(
+        &client,
+        &config.srv_cnf,
+        login.login.trim(),
+        &login.password,
+    )
+    .await?
+    {
+        AuthOutcome::Local(user) => user,
+        AuthOutcome::UnknownUser => {
+            return Ok(HttpResponse::NotFound().json("Account does not exist"))
+        }
+        _ => return Ok(HttpResponse::Unauthorized().json("Authentication failure")),
+    };
+
+    if user.disabled {
+        return Ok(HttpResponse::Unauthorized().json("Account disabled"));
+    }
+    if user.password_reset_required {
+        return Ok(HttpResponse::Forbidden().json("Password reset required"));
+    }
+    let session_id = session_create(pool, &req, user.id, None).await?;
+
+    // A trusted device skips the confirmation code.
+    let trusted = match trusted_device_token(&req, &cookie_key()?) {
+        Some(token) => {
+            db::trusted_device_use(
+                &client,
+                user.id,
+                &encryption::hash_token(&token),
+                &device_fingerprint(&req),
             )
             .await?
-            {
-                session_create(pool, identity, user.id.clone(), None).await?;
-                return Ok(HttpResponse::Accepted().finish());
-            } else {
-                return Ok(HttpResponse::Unauthorized().json("Authentication failure"));
-            }
-        }
-        Err(_) => {
-            return Ok(HttpResponse::NotFound().json("Account does not exist"));
         }
+        None => false,
+    };
+    let otp_forced = if trusted {
+        session_otp_update_confirm_true(&client, session_id).await?;
+        log::info!("user {} signed in from a trusted device", user.id);
+        false
+    } else {
+        super::new_device::check_new_device(&client, user.id, session_id, &req).await?
+    };
+
+    Ok(HttpResponse::Accepted().json(LoginResult {
+        otp_required: (config.srv_cnf.email_otp_enabled || otp_forced) && !trusted,
+    }))
+}
+
+/// Profile | Top
+///
+/// The signed in user.
+#[utoipa::path(
+    context_path = "/auth",
+    responses(
+        (status = 200, description = "Profile of the signed in user", body = Profile),
+        (status = 401, description = "Not signed in", body = ServiceError)
+    )
+)]
+#[get("/me")]
+pub async fn profile(user: AuthUser) -> Result<HttpResponse, ServiceError> {
+    Ok(HttpResponse::Ok().json(Profile {
+        id: user.id,
+        email: user.email,
+        username: user.username,
+        role: user.role.to_string(),
+    }))
+}
+
+/// Frontend page a password reset email links to, which posts the token with the new
+/// password to `POST /auth/password-reset`.
+pub const PASSWORD_RESET_PAGE: &str = "/reset-password";
+
+fn password_reset_message(email: &str, link: &str) -> Message {
+    Message {
+        email: email.to_owned(),
+        subject: "Reset your password".to_owned(),
+        msg: format!(
+            "<p>A password reset was requested for your account.</p>
+            <p><a href=\"{0}\">{0}</a></p>
+            <p>This link expires in {1} hours.</p>",
+            link, PASSWORD_RESET_TTL_HOURS
+        ),
+    }
+}
+
+/// Emails the user a single use link to choose a new password.
+pub async fn send_password_reset(
+    client: &Client,
+    user_id: i32,
+    email: &str,
+) -> Result<(), ServiceError> {
+    let config = configs::Config::from_env().unwrap();
+
+    let issued = encryption::issue_token(Duration::hours(PASSWORD_RESET_TTL_HOURS), Utc::now());
+    db::password_reset_add(client, user_id, &issued.hash, issued.expires_at).await?;
+
+    let link = config
+        .srv_cnf
+        .frontend_link(PASSWORD_RESET_PAGE, &issued.token);
+    send_email(password_reset_message(email, &link)).await;
+
+    Ok(())
+}
+
+/// Password Reset | Top
+///
+/// Choose a new password with the token from a reset email, which links to the frontend's
+/// `/reset-password` page. Each token works once. Signs the user out everywhere.
+#[utoipa::path(
+    context_path = "/auth",
+    request_body = CompletePasswordReset,
+    responses(
+        (status = 200, description = "Password changed"),
+        (status = 400, description = "Password is too short", body = ServiceError),
+        (status = 404, description = "Reset link is invalid or expired", body = ServiceError)
+    )
+)]
+#[post("/password-reset")]
+pub async fn complete_password_reset(
+    pool: web::Data<Pool>,
+    reset: web::Json<CompletePasswordReset>,
+) -> Result<HttpResponse, ServiceError> {
+    if reset.password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(ServiceError::BadRequest("Password is too short".into()));
+    }
+
+    let mut client: Client = pool.get().await?;
+    let config = configs::Config::from_env().unwrap();
+
+    let pending = db::password_reset_find(&client, &encryption::hash_token(&reset.token))
+        .await?
+        .filter(|pending| encryption::token_usable(pending.expires_at, pending.used_at, Utc::now()))
+        .ok_or_else(|| ServiceError::NotFound("Reset link is invalid or expired".into()))?;
+
+    let hashed_password =
+        encryption::password_hash(&reset.password, config.srv_cnf.bcrypt_or_argon).await?;
+    db::password_reset_complete(&mut client, &pending, &hashed_password).await?;
+
+    Ok(HttpResponse::Ok().json("Password changed"))
+}
+
+/// Re-authentication for sensitive account changes.
+async fn verify_current_password(
+    client: &Client,
+    user_id: i32,
+    password: &str,
+) -> Result<(), ServiceError> {
+    let config = configs::Config::from_env().unwrap();
+    let user = db::find_user_password_by_id(client, user_id).await?;
+
+    if encryption::verify_hash(
+        password,
+        &user.hashed_password,
+        config.srv_cnf.bcrypt_or_argon,
+    )
+    .await?
+    {
+        Ok(())
+    } else {
+        Err(ServiceError::Unauthorized)
+    }
+}
+
+/// Change Password | Top
+///
+/// Requires the current password. Every other session of the user is signed out.
+#[utoipa::path(
+    context_path = "/auth",
+    request_body = ChangePassword,
+    responses(
+        (status = 200, description = "Password changed"),
+        (status = 400, description = "Password is too short", body = ServiceError),
+        (status = 401, description = "Not signed in or wrong current password", body = ServiceError),
+        (status = 403, description = "Not allowed while impersonating", body = ServiceError)
+    )
+)]
+#[post("/me/password")]
+pub async fn change_password(
+    user: AuthUser,
+    pool: web::Data<Pool>,
+    change: web::Json<ChangePassword>,
+) -> Result<HttpResponse, ServiceError> {
+    if user.impersonator_id.is_some() {
+        return Err(ServiceError::Forbidden(
+            "Not allowed while impersonating".into(),
+        ));
     }
+    if change.new_password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(ServiceError::BadRequest("Password is too short".into()));
+    }
+
+    let mut client: Client = pool.get().await?;
+    let config = configs::Config::from_env().unwrap();
+
+    verify_current_password(&client, user.id, &change.current_password).await?;
+
+    let hashed_password =
+        encryption::password_hash(&change.new_password, config.srv_cnf.bcrypt_or_argon).await?;
+    let revoked =
+        db::user_password_change(&mut client, user.id, &hashed_password, user.session_id).await?;
+    log::info!(
+        "user {} changed their password, {} other sessions revoked",
+        user.id,
+        revoked
+    );
+
+    let message = Message {
+        email: user.email.clone(),
+        subject: "Your password was changed".to_owned(),
+        msg: "<p>The password of your account was just changed and your other sessions were signed out.</p>
+            <p>If this wasn't you, reset your password right away.</p>"
+            .to_owned(),
+    };
+    send_email(message).await;
+
+    Ok(HttpResponse::Ok().json("Password changed"))
+}
+
+/// Frontend page an email change confirmation links to, which posts the token to
+/// `POST /auth/me/email/confirm`.
+pub const CONFIRM_EMAIL_PAGE: &str = "/confirm-email";
+
+fn email_confirmation_message(new_email: &str, link: &str) -> Message {
+    Message {
+        email: new_email.to_owned(),
+        subject: "Confirm your new email address".to_owned(),
+        msg: format!(
+            "<p>Follow this link to use this address for your account:</p>
+            <p><a href=\"{0}\">{0}</a></p>
+            <p>This link expires in {1} hours.</p>",
+            link, EMAIL_CHANGE_TTL_HOURS
+        ),
+    }
+}
+
+/// Change Email | Top
+///
+/// Requires the current password. A confirmation link is sent to the new address and a
+/// notice to the current one, the address only changes once the link is followed.
+#[utoipa::path(
+    context_path = "/auth",
+    request_body = ChangeEmail,
+    responses(
+        (status = 202, description = "Confirmation link sent"),
+        (status = 400, description = "Invalid email address", body = ServiceError),
+        (status = 401, description = "Not signed in or wrong current password", body = ServiceError),
+        (status = 403, description = "Not allowed while impersonating", body = ServiceError),
+        (status = 409, description = "Email address already in use", body = ServiceError)
+    )
+)]
+#[post("/me/email")]
+pub async fn change_email(
+    user: AuthUser,
+    pool: web::Data<Pool>,
+    change: web::Json<ChangeEmail>,
+) -> Result<HttpResponse, ServiceError> {
+    if user.impersonator_id.is_some() {
+        return Err(ServiceError::Forbidden(
+            "Not allowed while impersonating".into(),
+        ));
+    }
+    let new_email = change.email.trim();
+    if !new_email.contains('@') || new_email == user.email {
+        return Err(ServiceError::BadRequest("Invalid email address".into()));
+    }
+
+    let client: Client = pool.get().await?;
+    let config = configs::Config::from_env().unwrap();
+
+    verify_current_password(&client, user.id, &change.current_password).await?;
+    if find_user_by_mail(&client, new_email.to_owned())
+        .await
+        .is_ok()
+    {
+        return Err(ServiceError::Conflict(
+            "Email address already in use".into(),
+        ));
+    }
+
+    let issued = encryption::issue_token(Duration::hours(EMAIL_CHANGE_TTL_HOURS), Utc::now());
+    db::email_change_add(&client, user.id, new_email, &issued.hash, issued.expires_at).await?;
+
+    let link = config
+        .srv_cnf
+        .frontend_link(CONFIRM_EMAIL_PAGE, &issued.token);
+    send_email(email_confirmation_message(new_email, &link)).await;
+
+    let notice = Message {
+        email: user.email.clone(),
+        subject: "Your email address is being changed".to_owned(),
+        msg: format!(
+            "<p>A request was made to change the email address of your account to {}.</p>
+            <p>If this wasn't you, change your password right away.</p>",
+            new_email
+        ),
+    };
+    send_email(notice).await;
+
+    Ok(HttpResponse::Accepted().json("Confirmation link sent"))
+}
+
+/// Confirm Email Change | Top
+///
+/// Switches to the new address with the token from the confirmation email, which links to
+/// the frontend's `/confirm-email` page. Each token works once.
+#[utoipa::path(
+    context_path = "/auth",
+    request_body = ConfirmEmailChange,
+    responses(
+        (status = 200, description = "Email address changed"),
+        (status = 404, description = "Confirmation link is invalid or expired", body = ServiceError),
+        (status = 409, description = "Email address already in use", body = ServiceError)
+    )
+)]
+#[post("/me/email/confirm")]
+pub async fn confirm_email_change(
+    pool: web::Data<Pool>,
+    confirm: web::Json<ConfirmEmailChange>,
+) -> Result<HttpResponse, ServiceError> {
+    let mut client: Client = pool.get().await?;
+
+    let change = db::email_change_find(&client, &encryption::hash_token(&confirm.token))
+        .await?
+        .filter(|change| {
+            encryption::token_usable(change.expires_at, change.confirmed_at, Utc::now())
+        })
+        .ok_or_else(|| ServiceError::NotFound("Confirmation link is invalid or expired".into()))?;
+    db::email_change_confirm(&mut client, &change).await?;
+    log::info!("user {} changed their email address", change.user_id);
+
+    Ok(HttpResponse::Ok().json("Email address changed"))
+}
+
+/// Generates and stores a new set of recovery codes, dropping the previous set.
+/// Returns the plain codes; only their hashes are kept.
+pub async fn issue_recovery_codes(
+    client: &mut Client,
+    user_id: i32,
+) -> Result<Vec<String>, ServiceError> {
+    let codes = encryption::generate_recovery_codes();
+    let hashes: Vec<String> = codes
+        .iter()
+        .map(|code| encryption::hash_token(&encryption::normalize_recovery_code(code)))
+        .collect();
+
+    db::recovery_codes_replace(client, user_id, &hashes).await?;
+    Ok(codes)
+}
+
+/// Recovery Codes | Top
+///
+/// Number of unused recovery codes of the signed in user.
+#[utoipa::path(
+    context_path = "/auth",
+    responses(
+        (status = 200, description = "Unused recovery codes", body = RecoveryCodeStatus),
+        (status = 401, description = "Not signed in", body = ServiceError)
+    )
+)]
+#[get("/recovery-codes")]
+pub async fn recovery_code_status(
+    user: AuthUser,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let client: Client = pool.get().await?;
+
+    let remaining = db::recovery_codes_remaining(&client, user.id).await?;
+    Ok(HttpResponse::Ok().json(RecoveryCodeStatus { remaining }))
+}
+
+/// Regenerate Recovery Codes | Top
+///
+/// Replaces the recovery codes of the signed in user. The new codes are only shown in this
+/// response.
+#[utoipa::path(
+    context_path = "/auth",
+    responses(
+        (status = 200, description = "New recovery codes", body = RecoveryCodes),
+        (status = 401, description = "Not signed in", body = ServiceError),
+        (status = 403, description = "Not allowed while impersonating", body = ServiceError)
+    )
+)]
+#[post("/recovery-codes")]
+pub async fn regenerate_recovery_codes(
+    user: AuthUser,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    if user.impersonator_id.is_some() {
+        return Err(ServiceError::Forbidden(
+            "Not allowed while impersonating".into(),
+        ));
+    }
+    let mut client: Client = pool.get().await?;
+
+    let codes = issue_recovery_codes(&mut client, user.id).await?;
+    log::info!("user {} regenerated their recovery codes", user.id);
+
+    Ok(HttpResponse::Ok().json(RecoveryCodes { codes }))
+}
+
+/// Trusted Devices | Top
+///
+/// Devices of the signed in user that currently skip the confirmation code.
+#[utoipa::path(
+    context_path = "/auth",
+    responses(
+        (status = 200, description = "Trusted devices", body = [TrustedDevice]),
+        (status = 401, description = "Not signed in", body = ServiceError)
+    )
+)]
+#[get("/devices")]
+pub async fn list_trusted_devices(
+    user: AuthUser,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let client: Client = pool.get().await?;
+
+    let devices = db::trusted_device_list(&client, user.id).await?;
+    Ok(HttpResponse::Ok().json(devices))
+}
+
+/// Revoke Trusted Device | Top
+///
+/// The device will be asked for a confirmation code again on its next login.
+#[utoipa::path(
+    context_path = "/auth",
+    responses(
+        (status = 200, description = "Device revoked"),
+        (status = 401, description = "Not signed in", body = ServiceError),
+        (status = 404, description = "Device not found", body = ServiceError)
+    ),
+    params(
+        ("id", description = "Trusted device id")
+    )
+)]
+#[delete("/devices/{id}")]
+pub async fn revoke_trusted_device(
+    user: AuthUser,
+    device_id: web::Path<(i32,)>,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let client: Client = pool.get().await?;
+
+    db::trusted_device_revoke(&client, user.id, device_id.0).await?;
+    log::info!("user {} revoked trusted device {}", user.id, device_id.0);
+
+    Ok(HttpResponse::Ok().json("Device revoked"))
+}
+
+/// Revoke All Trusted Devices | Top
+#[utoipa::path(
+    context_path = "/auth",
+    responses(
+        (status = 200, description = "Number of devices revoked", body = u64),
+        (status = 401, description = "Not signed in", body = ServiceError)
+    )
+)]
+#[delete("/devices")]
+pub async fn revoke_trusted_devices(
+    user: AuthUser,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let client: Client = pool.get().await?;
+
+    let revoked = db::delete_trusted_devices(&client, user.id).await?;
+    log::info!("user {} revoked {} trusted devices", user.id, revoked);
+
+    Ok(HttpResponse::Ok().json(revoked))
 }
 
 impl FromRequest for Session {
@@ -226,6 +759,16 @@ pub async fn logout(
     return Ok(HttpResponse::Ok().json("Logout Successfully"));
 }
 
+/// Send OTP | Top
+///
+/// Emails the confirmation code of the current session, once per session.
+#[utoipa::path(
+    context_path = "/auth",
+    responses(
+        (status = 202, description = "Code sent if the session exists")
+    )
+)]
+#[post("/otp")]
 pub async fn email_otp(
     session: Option<Session>,
     pool: web::Data<Pool>,
@@ -237,9 +780,9 @@ pub async fn email_otp(
     if let Some(session) = session {
         if let Some(user_session) = find_user_by_session(&client, session).await {
             if !user_session.otp_code_sent {
-                session_otp_update_true(&client, user_session.id);
+                session_otp_update_true(&client, user_session.id).await?;
 
-                let mail_id = find_user_mail_by_id(&client, user_session.id).await;
+                let mail_id = find_user_mail_by_id(&client, user_session.user_id).await;
                 let otp_code = encryption::decrypt(
                     &user_session.otp_code_encrypted,
                     &format!("{}", user_session.user_id),
@@ -252,7 +795,7 @@ pub async fn email_otp(
                         subject: "Your Confirmation Code".to_owned(),
                         msg: body,
                     };
-                    send_email(message);
+                    send_email(message).await;
                 } else if user_session.user_id == config.srv_cnf.user_invalid_id {
                     // Looks like the an attempt to register a duplicate user
                     // There may be a timing attack here.
@@ -284,13 +827,29 @@ pub async fn email_otp(
     Ok(HttpResponse::Accepted().finish())
 }
 
+/// Confirm OTP | Top
+///
+/// Completes the login with the emailed code or, when the mailbox is out of reach, with one
+/// of the recovery codes. The first successful confirmation returns a set of recovery codes.
+/// With `remember_device` the emailed code also sets a cookie that skips this step on later
+/// logins from the same browser.
+#[utoipa::path(
+    context_path = "/auth",
+    request_body = Otp,
+    responses(
+        (status = 202, description = "Session confirmed", body = OtpConfirmed),
+        (status = 401, description = "Invalid code or no session", body = ServiceError)
+    )
+)]
+#[post("/otp/confirm")]
 pub async fn confirm_otp(
     pool: web::Data<Pool>,
+    req: HttpRequest,
     session: Option<Session>,
     otp: web::Json<Otp>,
 ) -> Result<HttpResponse, ServiceError> {
     let config = configs::Config::from_env().unwrap();
-    let client: Client = pool.get().await.expect("Error connecting to the database");
+    let mut client: Client = pool.get().await.expect("Error connecting to the database");
     let secret = hex_to_bytes(&config.srv_cnf.secret_key).expect("SECRET_KEY could not parse");
 
     if let Some(session) = session {
@@ -328,7 +887,70 @@ pub async fn confirm_otp(
                 //         .finish());
                 // }
 
-                return Ok(HttpResponse::Accepted().json("Accepted"));
+                let recovery_codes =
+                    if db::recovery_codes_remaining(&client, user_session.user_id).await? == 0 {
+                        Some(issue_recovery_codes(&mut client, user_session.user_id).await?)
+                    } else {
+                        None
+                    };
+
+                let mut response = HttpResponse::Accepted();
+                if otp.remember_device {
+                    let days = config.srv_cnf.trusted_device_days;
+                    let issued = encryption::issue_token(Duration::days(days), Utc::now());
+                    db::trusted_device_add(
+                        &client,
+                        user_session.user_id,
+                        &issued.hash,
+                        &device_fingerprint(&req),
+                        &device_name(&req),
+                        issued.expires_at,
+                    )
+                    .await?;
+                    response.cookie(trusted_device_cookie(
+                        issued.token,
+                        &cookie_key()?,
+                        days,
+                        config.srv_cnf.public_url().starts_with("https://"),
+                    ));
+                }
+
+                return Ok(response.json(OtpConfirmed {
+                    recovery_codes,
+                    recovery_code_used: false,
+                }));
+            } else if db::recovery_code_use(
+                &client,
+                user_session.user_id,
+                &encryption::hash_token(&encryption::normalize_recovery_code(&otp.code)),
+            )
+            .await?
+            {
+                session_otp_update_confirm_true(&client, user_session.id).await?;
+                log::warn!(
+                    "user {} signed in with a recovery code",
+                    user_session.user_id
+                );
+
+                if let Ok(db_user) = find_user_mail_by_id(&client, user_session.user_id).await {
+                    let remaining =
+                        db::recovery_codes_remaining(&client, user_session.user_id).await?;
+                    let message = Message {
+                        email: db_user.email,
+                        subject: "A recovery code was used".to_owned(),
+                        msg: format!(
+                            "<p>One of your recovery codes was just used to sign in.</p>
+                            <p>{} recovery codes are left. If this wasn't you, change your password and generate new recovery codes.</p>",
+                            remaining
+                        ),
+                    };
+                    send_email(message).await;
+                }
+
+                return Ok(HttpResponse::Accepted().json(OtpConfirmed {
+                    recovery_codes: None,
+                    recovery_code_used: true,
+                }));
             } else {
                 // sqlx::query(
                 //     "
@@ -343,7 +965,7 @@ pub async fn confirm_otp(
                 // .bind(user_session.id)
                 // .execute(pool.get_ref())
                 // .await?;
-                session_otp_set_attempts(&client, user_session.id);
+                session_otp_set_attempts(&client, user_session.id).await?;
 
                 return Ok(HttpResponse::Unauthorized().json("Too Much Attempts "));
 
@@ -387,4 +1009,145 @@ pub async fn confirm_otp(
 pub fn init_routes(cfg: &mut web::ServiceConfig) {
     cfg.service(register_user);
     cfg.service(process_login);
+    cfg.service(complete_password_reset);
+    cfg.service(profile);
+    cfg.service(super::username::username_available);
+    cfg.service(super::username::set_username);
+    cfg.service(change_password);
+    cfg.service(change_email);
+    cfg.service(confirm_email_change);
+    cfg.service(super::csrf::csrf_token);
+    cfg.service(super::new_device::not_me);
+    cfg.service(email_otp);
+    cfg.service(confirm_otp);
+    cfg.service(recovery_code_status);
+    cfg.service(regenerate_recovery_codes);
+    cfg.service(list_trusted_devices);
+    cfg.service(revoke_trusted_device);
+    cfg.service(revoke_trusted_devices);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::model::EmailChange;
+
+    #[test]
+    fn test_password_reset_message() {
+        let config = configs::tests::srv_config(serde_json::json!({
+            "frontend_url": "https://example.com",
+        }));
+        let link = config.frontend_link(PASSWORD_RESET_PAGE, "f00d");
+
+        let message = password_reset_message("jane@example.com", &link);
+        assert_eq!(message.email, "jane@example.com");
+        assert!(message
+            .msg
+            .contains("<a href=\"https://example.com/reset-password?token=f00d\">"));
+        assert!(message.msg.contains("expires in 24 hours"));
+    }
+
+    #[test]
+    fn test_email_confirmation_message() {
+        let config = configs::tests::srv_config(serde_json::json!({
+            "frontend_url": "https://example.com",
+        }));
+        let link = config.frontend_link(CONFIRM_EMAIL_PAGE, "f00d");
+
+        let message = email_confirmation_message("jane@example.org", &link);
+        assert_eq!(message.email, "jane@example.org");
+        assert!(message
+            .msg
+            .contains("<a href=\"https://example.com/confirm-email?token=f00d\">"));
+        assert!(message.msg.contains("expires in 24 hours"));
+    }
+
+    /// The cookie carries the token back, signed, and only for the key it was signed with.
+    #[test]
+    fn test_trusted_device_cookie() {
+        let key = Key::generate();
+        let now = Utc::now();
+        let issued = encryption::issue_token(Duration::days(30), now);
+        assert_eq!(issued.expires_at, now + Duration::days(30));
+
+        let cookie = trusted_device_cookie(issued.token.clone(), &key, 30, true);
+        assert_eq!(cookie.name(), TRUSTED_DEVICE_COOKIE);
+        assert_ne!(cookie.value(), issued.token);
+        assert_eq!(cookie.path(), Some("/auth"));
+        assert_eq!(cookie.http_only(), Some(true));
+        assert_eq!(cookie.secure(), Some(true));
+        assert_eq!(cookie.same_site(), Some(SameSite::Strict));
+        assert_eq!(cookie.max_age(), Some(CookieDuration::days(30)));
+
+        let req = actix_web::test::TestRequest::default()
+            .cookie(cookie.clone())
+            .to_http_request();
+        let token = trusted_device_token(&req, &key).unwrap();
+        assert_eq!(encryption::hash_token(&token), issued.hash);
+        assert_eq!(trusted_device_token(&req, &Key::generate()), None);
+
+        let forged = actix_web::test::TestRequest::default()
+            .cookie(Cookie::new(TRUSTED_DEVICE_COOKIE, issued.token))
+            .to_http_request();
+        assert_eq!(trusted_device_token(&forged, &key), None);
+        assert_eq!(
+            trusted_device_token(
+                &actix_web::test::TestRequest::default().to_http_request(),
+                &key
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_device_fingerprint() {
+        let request = |agent: &str| {
+            actix_web::test::TestRequest::default()
+                .insert_header((http::header::USER_AGENT, agent))
+                .to_http_request()
+        };
+        assert_eq!(
+            device_fingerprint(&request("Firefox")),
+            device_fingerprint(&request("Firefox"))
+        );
+        assert_ne!(
+            device_fingerprint(&request("Firefox")),
+            device_fingerprint(&request("Chrome"))
+        );
+        assert_eq!(
+            device_name(&actix_web::test::TestRequest::default().to_http_request()),
+            "Unknown device"
+        );
+    }
+
+    /// The emailed token finds the stored change and works until it expires or is used.
+    #[test]
+    fn test_email_change_token() {
+        let now = Utc::now();
+        let issued = encryption::issue_token(Duration::hours(EMAIL_CHANGE_TTL_HOURS), now);
+        assert_eq!(encryption::hash_token(&issued.token), issued.hash);
+
+        let mut change = EmailChange {
+            id: 1,
+            user_id: 2,
+            new_email: "jane@example.org".into(),
+            expires_at: issued.expires_at,
+            confirmed_at: None,
+        };
+        let usable = |change: &EmailChange, at| {
+            encryption::token_usable(change.expires_at, change.confirmed_at, at)
+        };
+        assert!(usable(&change, now));
+        assert!(usable(
+            &change,
+            now + Duration::hours(EMAIL_CHANGE_TTL_HOURS - 1)
+        ));
+        assert!(!usable(
+            &change,
+            now + Duration::hours(EMAIL_CHANGE_TTL_HOURS)
+        ));
+
+        change.confirmed_at = Some(now);
+        assert!(!usable(&change, now + Duration::minutes(1)));
+    }
 }
@@ -0,0 +1,316 @@
+//! Authenticates users against a directory with the `ldap3` client: a subtree search for
+//! the entry matching a login, then a simple bind as that entry. Connections are always
+//! encrypted, either `ldaps://` or `ldap://` upgraded with StartTLS before anything is sent.
+
+use std::time::Duration;
+
+use deadpool_postgres::Client;
+use futures::future::LocalBoxFuture;
+use ldap3::{
+    ldap_escape, Ldap, LdapConnAsync, LdapConnSettings, Scope, SearchEntry, SearchOptions,
+    SearchResult,
+};
+
+use crate::auth::backends::{AuthBackend, AuthOutcome, ExternalUser};
+use crate::configs::SrvConfig;
+use crate::errors::ServiceError;
+
+pub const LDAP_SUCCESS: u32 = 0;
+pub const LDAP_SIZE_LIMIT_EXCEEDED: u32 = 4;
+pub const LDAP_INVALID_CREDENTIALS: u32 = 49;
+
+/// Checks `url` names a directory reachable over TLS. Returns whether the connection needs
+/// StartTLS to get there, which `ldap://` does and `ldaps://` doesn't.
+pub fn ldap_starttls(url: &str) -> Result<bool, ServiceError> {
+    let parsed = url::Url::parse(url)
+        .map_err(|e| ServiceError::FaultySetup(format!("{:?} is not a URL: {}", url, e)))?;
+    if parsed.host_str().unwrap_or_default().is_empty() {
+        return Err(ServiceError::FaultySetup(format!(
+            "{:?} names no LDAP host",
+            url
+        )));
+    }
+    match parsed.scheme() {
+        "ldaps" => Ok(false),
+        "ldap" => Ok(true),
+        _ => Err(ServiceError::FaultySetup(format!(
+            "{:?} is neither an ldaps:// nor an ldap:// URL",
+            url
+        ))),
+    }
+}
+
+fn ldap_error(error: impl std::fmt::Display) -> ServiceError {
+    ServiceError::InternalServerError(format!("LDAP: {}", error))
+}
+
+/// First value of `attribute`, whatever case the directory spells its name in.
+fn first<'e>(entry: &'e SearchEntry, attribute: &str) -> Option<&'e str> {
+    entry
+        .attrs
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(attribute))
+        .and_then(|(_, values)| values.first())
+        .map(String::as_str)
+}
+
+/// Authenticates against a directory: finds the entry whose login attributes match, then
+/// binds as that entry with the password. Its attributes become the local account.
+#[derive(Debug, Clone)]
+pub struct LdapBackend {
+    pub url: String,
+    /// Upgrade the connection with StartTLS, for `ldap://` URLs.
+    pub starttls: bool,
+    pub bind_dn: Option<String>,
+    pub bind_password: Option<String>,
+    pub base_dn: String,
+    pub user_object_class: String,
+    pub login_attributes: Vec<String>,
+    pub email_attribute: String,
+    pub username_attribute: String,
+    pub display_name_attribute: String,
+    pub timeout: Duration,
+}
+
+impl LdapBackend {
+    pub fn from_config(cnf: &SrvConfig) -> Result<LdapBackend, ServiceError> {
+        let url = cnf
+            .ldap_url
+            .clone()
+            .ok_or_else(|| ServiceError::FaultySetup("The ldap backend needs ldap_url".into()))?;
+        let base_dn = cnf.ldap_base_dn.clone().ok_or_else(|| {
+            ServiceError::FaultySetup("The ldap backend needs ldap_base_dn".into())
+        })?;
+        let login_attributes: Vec<String> = cnf
+            .ldap_login_attributes
+            .split(',')
+            .map(|attribute| attribute.trim().to_owned())
+            .filter(|attribute| !attribute.is_empty())
+            .collect();
+        if login_attributes.is_empty() {
+            return Err(ServiceError::FaultySetup(
+                "ldap_login_attributes lists no attribute".into(),
+            ));
+        }
+
+        Ok(LdapBackend {
+            starttls: ldap_starttls(&url)?,
+            url,
+            bind_dn: cnf.ldap_bind_dn.clone(),
+            bind_password: cnf.ldap_bind_password.clone(),
+            base_dn,
+            user_object_class: cnf.ldap_user_object_class.clone(),
+            login_attributes,
+            email_attribute: cnf.ldap_email_attribute.clone(),
+            username_attribute: cnf.ldap_username_attribute.clone(),
+            display_name_attribute: cnf.ldap_display_name_attribute.clone(),
+            timeout: Duration::from_secs(cnf.ldap_timeout_secs),
+        })
+    }
+
+    /// Entries of `user_object_class` with any login attribute equal to `login`.
+    pub fn user_filter(&self, login: &str) -> String {
+        let login = ldap_escape(login);
+        format!(
+            "(&(objectClass={})(|{}))",
+            ldap_escape(self.user_object_class.as_str()),
+            self.login_attributes
+                .iter()
+                .map(|attribute| format!("({}={})", attribute, login))
+                .collect::<String>()
+        )
+    }
+
+    pub async fn check(&self, login: &str, password: &str) -> Result<AuthOutcome, ServiceError> {
+        if login.is_empty() || password.is_empty() {
+            return Ok(AuthOutcome::Rejected);
+        }
+
+        let settings = LdapConnSettings::new()
+            .set_conn_timeout(self.timeout)
+            .set_starttls(self.starttls);
+        let (connection, mut ldap) = LdapConnAsync::with_settings(settings, &self.url)
+            .await
+            .map_err(ldap_error)?;
+        ldap3::drive!(connection);
+
+        let outcome = self.bind_as_user(&mut ldap, login, password).await;
+        ldap.unbind().await.ok();
+        outcome
+    }
+
+    async fn bind_as_user(
+        &self,
+        ldap: &mut Ldap,
+        login: &str,
+        password: &str,
+    ) -> Result<AuthOutcome, ServiceError> {
+        let bound = ldap
+            .with_timeout(self.timeout)
+            .simple_bind(
+                self.bind_dn.as_deref().unwrap_or_default(),
+                self.bind_password.as_deref().unwrap_or_default(),
+            )
+            .await
+            .map_err(ldap_error)?;
+        if bound.rc != LDAP_SUCCESS {
+            return Err(ServiceError::FaultySetup(format!(
+                "LDAP search bind failed with code {}",
+                bound.rc
+            )));
+        }
+
+        // Two are enough to tell an ambiguous login.
+        let SearchResult(entries, result) = ldap
+            .with_timeout(self.timeout)
+            .with_search_options(SearchOptions::new().sizelimit(2))
+            .search(
+                &self.base_dn,
+                Scope::Subtree,
+                &self.user_filter(login),
+                vec![
+                    &self.email_attribute,
+                    &self.username_attribute,
+                    &self.display_name_attribute,
+                ],
+            )
+            .await
+            .map_err(ldap_error)?;
+        if result.rc != LDAP_SUCCESS && result.rc != LDAP_SIZE_LIMIT_EXCEEDED {
+            return Err(ldap_error(result));
+        }
+        let mut entries: Vec<SearchEntry> = entries
+            .into_iter()
+            .filter(|entry| !entry.is_ref() && !entry.is_intermediate())
+            .map(SearchEntry::construct)
+            .collect();
+        let entry = match entries.len() {
+            0 => return Ok(AuthOutcome::UnknownUser),
+            1 => entries.remove(0),
+            _ => {
+                log::warn!("LDAP login {:?} matches several entries", login);
+                return Ok(AuthOutcome::Rejected);
+            }
+        };
+
+        let bound = ldap
+            .with_timeout(self.timeout)
+            .simple_bind(&entry.dn, password)
+            .await
+            .map_err(ldap_error)?;
+        match bound.rc {
+            LDAP_SUCCESS => {}
+            LDAP_INVALID_CREDENTIALS => return Ok(AuthOutcome::Rejected),
+            _ => return Err(ldap_error(bound)),
+        }
+
+        let email = match first(&entry, &self.email_attribute) {
+            Some(email) => email.trim().to_owned(),
+            None => {
+                log::warn!(
+                    "LDAP entry {} has no {} to sign in with",
+                    entry.dn,
+                    self.email_attribute
+                );
+                return Ok(AuthOutcome::Rejected);
+            }
+        };
+        Ok(AuthOutcome::External(ExternalUser {
+            email,
+            username: first(&entry, &self.username_attribute).map(str::to_owned),
+            display_name: first(&entry, &self.display_name_attribute).map(str::to_owned),
+        }))
+    }
+}
+
+impl AuthBackend for LdapBackend {
+    fn name(&self) -> &'static str {
+        "ldap"
+    }
+
+    fn authenticate<'a>(
+        &'a self,
+        _client: &'a Client,
+        login: &'a str,
+        password: &'a str,
+    ) -> LocalBoxFuture<'a, Result<AuthOutcome, ServiceError>> {
+        Box::pin(self.check(login, password))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    fn backend(url: String) -> LdapBackend {
+        LdapBackend {
+            starttls: ldap_starttls(&url).unwrap(),
+            url,
+            bind_dn: Some("cn=search,dc=example,dc=com".into()),
+            bind_password: Some("search-secret".into()),
+            base_dn: "dc=example,dc=com".into(),
+            user_object_class: "person".into(),
+            login_attributes: vec!["uid".into(), "mail".into()],
+            email_attribute: "mail".into(),
+            username_attribute: "uid".into(),
+            display_name_attribute: "cn".into(),
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    #[test]
+    fn test_ldap_starttls() {
+        assert!(ldap_starttls("ldap://dir.local").unwrap());
+        assert!(ldap_starttls("ldap://dir.local:10389/").unwrap());
+        assert!(!ldap_starttls("ldaps://dir.local").unwrap());
+        assert!(!ldap_starttls("ldaps://[::1]:636").unwrap());
+        assert!(ldap_starttls("ldapi://%2Frun%2Fslapd.sock").is_err());
+        assert!(ldap_starttls("http://dir.local").is_err());
+        assert!(ldap_starttls("dir.local:389").is_err());
+    }
+
+    #[test]
+    fn test_user_filter() {
+        let backend = backend("ldaps://dir.local".into());
+        assert_eq!(
+            backend.user_filter("jdoe"),
+            "(&(objectClass=person)(|(uid=jdoe)(mail=jdoe)))"
+        );
+        // A login can't widen the search.
+        assert_eq!(
+            backend.user_filter("*)(uid=*"),
+            "(&(objectClass=person)(|(uid=\\2a\\29\\28uid=\\2a)(mail=\\2a\\29\\28uid=\\2a)))"
+        );
+    }
+
+    /// A server that can't do StartTLS gets no credentials: the connection fails before
+    /// the search bind.
+    #[tokio::test]
+    async fn test_ldap_requires_tls() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ldap://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut received = Vec::new();
+            let mut buf = [0; 1024];
+            // Hangs up after the first message, the StartTLS request.
+            let n = stream.read(&mut buf).await.unwrap();
+            received.extend_from_slice(&buf[..n]);
+            received
+        });
+
+        assert!(backend(url).check("jdoe", "correct horse").await.is_err());
+        let received = server.await.unwrap();
+        // The OID of StartTLS.
+        assert!(received
+            .windows(22)
+            .any(|window| window == b"1.3.6.1.4.1.1466.20037"));
+        for secret in [&b"search-secret"[..], b"correct horse"] {
+            assert!(!received
+                .windows(secret.len())
+                .any(|window| window == secret));
+        }
+    }
+}
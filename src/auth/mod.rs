@@ -1,8 +1,20 @@
+pub mod backends;
+pub mod csrf;
 pub mod db;
 pub mod encryption;
 pub mod handlers;
+pub mod ldap;
 pub mod model;
+pub mod new_device;
+pub mod roles;
+pub mod username;
+pub use crate::auth::backends::*;
+pub use crate::auth::csrf::*;
 pub use crate::auth::db::*;
 pub use crate::auth::encryption::*;
 pub use crate::auth::handlers::*;
+pub use crate::auth::ldap::*;
 pub use crate::auth::model::*;
+pub use crate::auth::new_device::*;
+pub use crate::auth::roles::*;
+pub use crate::auth::username::*;
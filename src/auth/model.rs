@@ -1,7 +1,10 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use tokio_pg_mapper_derive::PostgresMapper;
 use utoipa::{IntoParams, ToResponse, ToSchema};
 
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+
 #[derive(
     Serialize, Debug, ToSchema, ToResponse, IntoParams, Deserialize, PostgresMapper, Default,
 )]
@@ -14,6 +17,18 @@ pub struct CreateUser {
     pub email: String,
     #[schema(example = json!({"widget": "password", "class": "email-form px-2"}))]
     pub hashed_password: String,
+    /// Optional handle, unique regardless of case.
+    #[serde(default)]
+    pub username: Option<String>,
+}
+
+/// Credentials for `process_login`. `login` is an email address or a username.
+#[derive(Serialize, Debug, Deserialize, ToSchema)]
+pub struct Login {
+    #[serde(alias = "email", alias = "username")]
+    pub login: String,
+    #[serde(alias = "hashed_password")]
+    pub password: String,
 }
 
 #[derive(Serialize, Debug, Deserialize, PostgresMapper, Default)]
@@ -21,6 +36,100 @@ pub struct CreateUser {
 pub struct FindUser {
     pub id: i32,
     pub hashed_password: String,
+    pub disabled: bool,
+    pub password_reset_required: bool,
+}
+
+#[derive(Serialize, Debug, Deserialize, PostgresMapper, Default)]
+#[pg_mapper(table = "users")]
+pub struct UserAccount {
+    pub id: i32,
+    pub email: String,
+    pub username: Option<String>,
+    pub role: String,
+    pub disabled: bool,
+}
+
+#[derive(Serialize, Debug, Deserialize, PostgresMapper)]
+#[pg_mapper(table = "password_resets")]
+pub struct PasswordReset {
+    pub id: i32,
+    pub user_id: i32,
+    pub expires_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Debug, Deserialize, PostgresMapper)]
+#[pg_mapper(table = "email_changes")]
+pub struct EmailChange {
+    pub id: i32,
+    pub user_id: i32,
+    pub new_email: String,
+    pub expires_at: DateTime<Utc>,
+    pub confirmed_at: Option<DateTime<Utc>>,
+}
+
+/// The signed in user, as returned by `GET /auth/me`.
+#[derive(Serialize, Debug, Deserialize, ToSchema)]
+pub struct Profile {
+    pub id: i32,
+    pub email: String,
+    pub username: Option<String>,
+    pub role: String,
+}
+
+#[derive(Serialize, Debug, Deserialize, ToSchema)]
+pub struct SetUsername {
+    pub username: String,
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+pub struct UsernameQuery {
+    pub username: String,
+}
+
+#[derive(Serialize, Debug, Deserialize, ToSchema)]
+pub struct UsernameAvailability {
+    pub username: String,
+    pub available: bool,
+    /// Why the username can't be used.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+#[derive(Serialize, Debug, Deserialize, ToSchema)]
+pub struct NotMe {
+    /// Token from the new device email.
+    pub token: String,
+}
+
+#[derive(Serialize, Debug, Deserialize, ToSchema)]
+pub struct ChangePassword {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Serialize, Debug, Deserialize, ToSchema)]
+pub struct ChangeEmail {
+    /// The new address, it only replaces the current one once confirmed.
+    pub email: String,
+    pub current_password: String,
+}
+
+#[derive(Serialize, Debug, Deserialize, ToSchema)]
+pub struct ConfirmEmailChange {
+    pub token: String,
+}
+
+#[derive(Serialize, Debug, Deserialize, ToSchema)]
+pub struct CsrfToken {
+    pub token: String,
+}
+
+#[derive(Serialize, Debug, Deserialize, ToSchema)]
+pub struct CompletePasswordReset {
+    pub token: String,
+    pub password: String,
 }
 
 #[derive(Serialize, Debug, Deserialize, PostgresMapper, Default)]
@@ -33,6 +142,11 @@ pub struct UserSession {
     pub otp_code_encrypted: String,
     pub otp_code_attempts: i32,
     pub otp_code_sent: bool,
+    /// Set while an admin is acting as another user, `user_id` stays the admin.
+    pub impersonated_user_id: Option<i32>,
+    pub impersonation_read_only: bool,
+    /// The emailed code is required for this session regardless of `email_otp_enabled`.
+    pub otp_required: bool,
 }
 
 #[derive(Serialize, Debug, Deserialize, Default)]
@@ -40,9 +154,10 @@ pub struct SessionAdd {
     pub user_id: i32,
     pub session_verifier: String,
     pub otp_code_encr: String,
+    pub login_fingerprint: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Session {
     pub session_id: i32,
     pub session_verifier: String,
@@ -92,7 +207,48 @@ pub struct CreatedUser {
 pub struct CreatedSession {
     pub id: i32,
 }
-#[derive(Serialize, Deserialize, Default)]
+#[derive(Serialize, Deserialize, Default, ToSchema)]
 pub struct Otp {
+    /// The emailed confirmation code, or one of the recovery codes.
     pub code: String,
+    /// Skip the code on later logins from this device. Ignored for recovery codes.
+    #[serde(default)]
+    pub remember_device: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, ToSchema)]
+pub struct OtpConfirmed {
+    /// Handed out the first time the second factor is completed. They are not shown again.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recovery_codes: Option<Vec<String>>,
+    /// A recovery code was used instead of the emailed code.
+    pub recovery_code_used: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct RecoveryCodes {
+    pub codes: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct LoginResult {
+    /// A confirmation code must be sent and confirmed before the session can be used.
+    pub otp_required: bool,
+}
+
+/// A device that may skip the confirmation code until `expires_at`.
+#[derive(Serialize, Deserialize, Debug, ToSchema, PostgresMapper)]
+#[pg_mapper(table = "trusted_devices")]
+pub struct TrustedDevice {
+    pub id: i32,
+    /// User agent of the browser that was trusted.
+    pub name: String,
+    pub expires_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct RecoveryCodeStatus {
+    pub remaining: i64,
 }
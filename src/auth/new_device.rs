@@ -0,0 +1,191 @@
+use std::net::{IpAddr, SocketAddr};
+
+use actix_web::{post, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Duration, Utc};
+use deadpool_postgres::{Client, Pool};
+
+use crate::auth::{db, encryption, find_user_mail_by_id, send_password_reset, NotMe};
+use crate::configs;
+use crate::errors::ServiceError;
+use crate::mail::model::Message;
+use crate::mail::send_email;
+use crate::posts::escape_html;
+
+const LOGIN_ALERT_TTL_DAYS: i64 = 7;
+
+/// The network a login came from: the /24 of an IPv4 address or the /48 of an IPv6 one,
+/// so a new lease from the same provider doesn't look like a new device.
+pub fn ip_prefix(addr: &str) -> String {
+    let ip = addr
+        .parse::<IpAddr>()
+        .or_else(|_| addr.parse::<SocketAddr>().map(|socket| socket.ip()));
+
+    match ip {
+        Ok(IpAddr::V4(ip)) => {
+            let [a, b, c, _] = ip.octets();
+            format!("{}.{}.{}.0/24", a, b, c)
+        }
+        Ok(IpAddr::V6(ip)) => {
+            let segments = ip.segments();
+            format!("{:x}:{:x}:{:x}::/48", segments[0], segments[1], segments[2])
+        }
+        Err(_) => "unknown".to_owned(),
+    }
+}
+
+fn remote_prefix(req: &HttpRequest) -> String {
+    ip_prefix(
+        req.connection_info()
+            .realip_remote_addr()
+            .unwrap_or_default(),
+    )
+}
+
+/// Frontend page a new sign-in alert links to, which posts the token to `POST /auth/not-me`.
+pub const NOT_ME_PAGE: &str = "/not-me";
+
+fn new_device_message(
+    email: &str,
+    device: &str,
+    network: &str,
+    at: DateTime<Utc>,
+    link: &str,
+) -> Message {
+    Message {
+        email: email.to_owned(),
+        subject: "New sign-in to your account".to_owned(),
+        msg: format!(
+            "<p>Your account was just accessed from a new device.</p>
+            <p>Device: {}<br>Network: {}<br>Time: {}</p>
+            <p>If this wasn't you, follow this link to sign out everywhere and reset your password:</p>
+            <p><a href=\"{3}\">{3}</a></p>",
+            // The user agent is chosen by the client, keep it from injecting markup.
+            escape_html(device),
+            network,
+            at.format("%Y-%m-%d %H:%M UTC"),
+            link
+        ),
+    }
+}
+
+/// Hash of the network prefix and user agent of a request, stored with each session.
+pub fn login_fingerprint(req: &HttpRequest) -> String {
+    encryption::hash_token(&format!(
+        "{}|{}",
+        remote_prefix(req),
+        super::handlers::device_name(req)
+    ))
+}
+
+/// Records the fingerprint of a fresh login. The first time a known account signs in from
+/// an unseen one, the owner gets an email with a "this wasn't me" link and, when
+/// `new_device_otp` is on, the session has to be confirmed with the emailed code.
+///
+/// Returns whether the confirmation code was forced for the session.
+pub async fn check_new_device(
+    client: &Client,
+    user_id: i32,
+    session_id: i32,
+    req: &HttpRequest,
+) -> Result<bool, ServiceError> {
+    let config = configs::Config::from_env().unwrap();
+
+    let (is_new, known) =
+        db::login_fingerprint_record(client, user_id, &login_fingerprint(req)).await?;
+    // The very first login of an account is not news.
+    if !is_new || known < 2 {
+        return Ok(false);
+    }
+    log::info!("user {} signed in from a new device", user_id);
+
+    let force_otp = config.srv_cnf.new_device_otp;
+    if force_otp {
+        db::session_otp_require(client, session_id).await?;
+    }
+
+    let now = Utc::now();
+    let issued = encryption::issue_token(Duration::days(LOGIN_ALERT_TTL_DAYS), now);
+    db::login_alert_add(client, user_id, session_id, &issued.hash, issued.expires_at).await?;
+
+    let user = find_user_mail_by_id(client, user_id).await?;
+    let link = config.srv_cnf.frontend_link(NOT_ME_PAGE, &issued.token);
+    send_email(new_device_message(
+        &user.email,
+        &super::handlers::device_name(req),
+        &remote_prefix(req),
+        now,
+        &link,
+    ))
+    .await;
+
+    Ok(force_otp)
+}
+
+/// This Wasn't Me | Top
+///
+/// Reports a sign-in from a new device email as unauthorized, with the token from the
+/// frontend's `/not-me` page the email links to. Every session of the user is revoked and
+/// a password reset is required; a reset link is emailed.
+#[utoipa::path(
+    context_path = "/auth",
+    request_body = NotMe,
+    responses(
+        (status = 200, description = "Sessions revoked, password reset emailed"),
+        (status = 404, description = "Link is invalid or expired", body = ServiceError)
+    )
+)]
+#[post("/not-me")]
+pub async fn not_me(
+    pool: web::Data<Pool>,
+    report: web::Json<NotMe>,
+) -> Result<HttpResponse, ServiceError> {
+    let mut client: Client = pool.get().await?;
+
+    let user_id =
+        db::login_alert_revoke(&mut client, &encryption::hash_token(&report.token)).await?;
+    log::warn!("user {} reported a sign-in as not theirs", user_id);
+
+    let user = find_user_mail_by_id(&client, user_id).await?;
+    send_password_reset(&client, user_id, &user.email).await?;
+
+    Ok(HttpResponse::Ok().json("Sessions revoked, check your email to reset your password"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_ip_prefix() {
+        assert_eq!(ip_prefix("203.0.113.7"), "203.0.113.0/24");
+        assert_eq!(ip_prefix("203.0.113.7:51234"), "203.0.113.0/24");
+        assert_eq!(ip_prefix("2001:db8:85a3:1::8a2e"), "2001:db8:85a3::/48");
+        assert_eq!(ip_prefix("[2001:db8:85a3::1]:443"), "2001:db8:85a3::/48");
+        assert_eq!(ip_prefix(""), "unknown");
+    }
+
+    #[test]
+    fn test_new_device_message() {
+        let config = configs::tests::srv_config(serde_json::json!({
+            "frontend_url": "https://example.com",
+        }));
+        let link = config.frontend_link(NOT_ME_PAGE, "f00d");
+
+        let message = new_device_message(
+            "jane@example.com",
+            "<script>alert(1)</script>",
+            "203.0.113.0/24",
+            Utc.with_ymd_and_hms(2024, 5, 1, 12, 30, 0).unwrap(),
+            &link,
+        );
+        assert_eq!(message.email, "jane@example.com");
+        assert!(message
+            .msg
+            .contains("<a href=\"https://example.com/not-me?token=f00d\">"));
+        assert!(message
+            .msg
+            .contains("Device: &lt;script&gt;alert(1)&lt;/script&gt;<br>Network: 203.0.113.0/24"));
+        assert!(message.msg.contains("Time: 2024-05-01 12:30 UTC"));
+    }
+}
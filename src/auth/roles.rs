@@ -0,0 +1,211 @@
+use std::fmt;
+use std::str::FromStr;
+
+use actix_web::{web, FromRequest, HttpMessage, HttpRequest};
+use deadpool_postgres::{Client, Pool};
+use futures::future::LocalBoxFuture;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::auth::db::{find_user_by_id, find_user_by_session};
+use crate::auth::model::{Session, UserAccount};
+use crate::configs;
+use crate::errors::ServiceError;
+use crate::tls::CertificateUser;
+
+/// Role held by a user, stored as text in the `role` column of the users table.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    User,
+    Editor,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Editor => "editor",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = ServiceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "user" => Ok(Role::User),
+            "editor" => Ok(Role::Editor),
+            "admin" => Ok(Role::Admin),
+            other => Err(ServiceError::BadRequest(format!("Unknown role: {}", other))),
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Whether the admin behind an impersonation may still act as someone else. Checked on
+/// every request, so demoting or disabling the admin ends it right away.
+fn may_impersonate(impersonator: &UserAccount) -> bool {
+    !impersonator.disabled && impersonator.role == Role::Admin.as_str()
+}
+
+/// The user behind the session cookie of the current request or, without a session,
+/// behind the client certificate the TLS connection was authenticated with.
+///
+/// While an admin impersonates someone, `id`, `email` and `role` are those of the
+/// impersonated user and `impersonator_id` holds the admin's id.
+///
+/// Rejects the request with 401 when there is no session, the session is unknown,
+/// the account is disabled or the email OTP step is enabled but hasn't been completed yet,
+/// and while impersonating when the admin is no longer an active admin.
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    pub id: i32,
+    pub email: String,
+    pub username: Option<String>,
+    pub role: Role,
+    /// `None` when signed in with a client certificate.
+    pub session_id: Option<i32>,
+    pub impersonator_id: Option<i32>,
+}
+
+impl FromRequest for AuthUser {
+    type Error = ServiceError;
+    type Future = LocalBoxFuture<'static, Result<AuthUser, ServiceError>>;
+
+    fn from_request(req: &HttpRequest, pl: &mut actix_web::dev::Payload) -> Self::Future {
+        let session = Session::from_request(req, pl).into_inner();
+        let certificate_user = req.extensions().get::<CertificateUser>().copied();
+        let pool = req.app_data::<web::Data<Pool>>().cloned();
+
+        Box::pin(async move {
+            let pool =
+                pool.ok_or_else(|| ServiceError::FaultySetup("database pool missing".into()))?;
+            let client: Client = pool.get().await?;
+
+            let (user_id, session_id, impersonator_id) = match (session, certificate_user) {
+                (Ok(session), _) => {
+                    let session_id = session.session_id;
+                    let config = configs::Config::from_env().unwrap();
+
+                    let user_session = find_user_by_session(&client, session)
+                        .await
+                        .ok_or(ServiceError::Unauthorized)?;
+                    if (config.srv_cnf.email_otp_enabled || user_session.otp_required)
+                        && !user_session.otp_code_confirmed
+                    {
+                        return Err(ServiceError::Unauthorized);
+                    }
+
+                    match user_session.impersonated_user_id {
+                        Some(impersonated) => {
+                            (impersonated, Some(session_id), Some(user_session.user_id))
+                        }
+                        None => (user_session.user_id, Some(session_id), None),
+                    }
+                }
+                (Err(_), Some(CertificateUser(user_id))) => (user_id, None, None),
+                (Err(e), None) => return Err(e),
+            };
+
+            if let Some(impersonator_id) = impersonator_id {
+                let impersonator = find_user_by_id(&client, impersonator_id)
+                    .await
+                    .map_err(|_| ServiceError::Unauthorized)?;
+                if !may_impersonate(&impersonator) {
+                    log::warn!(
+                        "user {} is no longer an active admin, refusing their impersonation of user {}",
+                        impersonator_id,
+                        user_id
+                    );
+                    return Err(ServiceError::Unauthorized);
+                }
+            }
+
+            let user = find_user_by_id(&client, user_id)
+                .await
+                .map_err(|_| ServiceError::Unauthorized)?;
+            if user.disabled {
+                return Err(ServiceError::Unauthorized);
+            }
+
+            Ok(AuthUser {
+                id: user.id,
+                email: user.email,
+                username: user.username,
+                role: user.role.parse()?,
+                session_id,
+                impersonator_id,
+            })
+        })
+    }
+}
+
+/// An `AuthUser` holding the admin role. Other users get a 403.
+#[derive(Debug, Clone)]
+pub struct AdminUser(pub AuthUser);
+
+fn admin_only(user: AuthUser) -> Result<AdminUser, ServiceError> {
+    if user.role != Role::Admin {
+        return Err(ServiceError::Forbidden("Admin role required".into()));
+    }
+    Ok(AdminUser(user))
+}
+
+impl FromRequest for AdminUser {
+    type Error = ServiceError;
+    type Future = LocalBoxFuture<'static, Result<AdminUser, ServiceError>>;
+
+    fn from_request(req: &HttpRequest, pl: &mut actix_web::dev::Payload) -> Self::Future {
+        let user = AuthUser::from_request(req, pl);
+
+        Box::pin(async move { admin_only(user.await?) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_may_impersonate() {
+        let account = |role: Role, disabled| UserAccount {
+            id: 1,
+            role: role.to_string(),
+            disabled,
+            ..Default::default()
+        };
+        assert!(may_impersonate(&account(Role::Admin, false)));
+        assert!(!may_impersonate(&account(Role::Admin, true)));
+        assert!(!may_impersonate(&account(Role::Editor, false)));
+        assert!(!may_impersonate(&account(Role::User, false)));
+    }
+
+    #[test]
+    fn test_admin_only() {
+        let user = |role: Role| AuthUser {
+            id: 1,
+            email: "jane@example.com".into(),
+            username: None,
+            role,
+            session_id: Some(1),
+            impersonator_id: None,
+        };
+        assert_eq!(admin_only(user(Role::Admin)).unwrap().0.id, 1);
+        for role in [Role::Editor, Role::User] {
+            assert!(matches!(
+                admin_only(user(role)),
+                Err(ServiceError::Forbidden(_))
+            ));
+        }
+    }
+}
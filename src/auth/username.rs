@@ -0,0 +1,162 @@
+use actix_web::web::Query;
+use actix_web::{get, put, web, HttpResponse};
+use deadpool_postgres::{Client, Pool};
+
+use crate::auth::{db, AuthUser, SetUsername, UsernameAvailability, UsernameQuery};
+use crate::errors::ServiceError;
+
+pub const USERNAME_MIN_LENGTH: usize = 3;
+pub const USERNAME_MAX_LENGTH: usize = 30;
+
+/// Names that could pass for the service itself or clash with routes. Compared
+/// case-insensitively.
+const RESERVED_USERNAMES: &[&str] = &[
+    "abuse",
+    "admin",
+    "administrator",
+    "anonymous",
+    "api",
+    "auth",
+    "categories",
+    "feed",
+    "help",
+    "invites",
+    "me",
+    "moderator",
+    "noreply",
+    "null",
+    "posts",
+    "postmaster",
+    "root",
+    "scim",
+    "security",
+    "staff",
+    "support",
+    "system",
+    "tags",
+    "undefined",
+    "webmaster",
+    "www",
+];
+
+/// Why a username can't be used, `None` when its format is fine. Availability is a
+/// separate, database backed check.
+pub fn username_problem(username: &str) -> Option<&'static str> {
+    let length = username.chars().count();
+    if length < USERNAME_MIN_LENGTH {
+        return Some("Username is too short");
+    }
+    if length > USERNAME_MAX_LENGTH {
+        return Some("Username is too long");
+    }
+    if !username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return Some("Username may only contain letters, digits, '_' and '-'");
+    }
+    if !username.starts_with(|c: char| c.is_ascii_alphanumeric()) {
+        return Some("Username must start with a letter or digit");
+    }
+    // All digit names would be mistaken for user ids.
+    if username.chars().all(|c| c.is_ascii_digit()) {
+        return Some("Username must contain a letter");
+    }
+    let lower = username.to_ascii_lowercase();
+    if RESERVED_USERNAMES.contains(&lower.as_str()) {
+        return Some("Username is reserved");
+    }
+    None
+}
+
+pub fn validate_username(username: &str) -> Result<(), ServiceError> {
+    match username_problem(username) {
+        Some(problem) => Err(ServiceError::BadRequest(problem.into())),
+        None => Ok(()),
+    }
+}
+
+/// Username Availability | Top
+///
+/// Tells whether a username is valid and not taken, ignoring case.
+#[utoipa::path(
+    context_path = "/auth",
+    params(
+        UsernameQuery
+    ),
+    responses(
+        (status = 200, description = "Availability of the username", body = UsernameAvailability)
+    )
+)]
+#[get("/username-available")]
+pub async fn username_available(
+    query: Query<UsernameQuery>,
+    pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let username = query.username.trim();
+
+    let reason = match username_problem(username) {
+        Some(problem) => Some(problem.to_owned()),
+        None => {
+            let client: Client = pool.get().await?;
+            if db::username_taken(&client, username).await? {
+                Some("Username is already taken".to_owned())
+            } else {
+                None
+            }
+        }
+    };
+
+    Ok(HttpResponse::Ok().json(UsernameAvailability {
+        username: username.to_owned(),
+        available: reason.is_none(),
+        reason,
+    }))
+}
+
+/// Set Username | Top
+///
+/// Chooses or changes the username of the signed in user.
+#[utoipa::path(
+    context_path = "/auth",
+    request_body = SetUsername,
+    responses(
+        (status = 200, description = "Username changed"),
+        (status = 400, description = "Invalid username", body = ServiceError),
+        (status = 401, description = "Not signed in", body = ServiceError),
+        (status = 409, description = "Username is already taken", body = ServiceError)
+    )
+)]
+#[put("/me/username")]
+pub async fn set_username(
+    user: AuthUser,
+    pool: web::Data<Pool>,
+    change: web::Json<SetUsername>,
+) -> Result<HttpResponse, ServiceError> {
+    let username = change.username.trim();
+    validate_username(username)?;
+
+    let client: Client = pool.get().await?;
+    db::user_set_username(&client, user.id, username).await?;
+    log::info!("user {} is now known as {}", user.id, username);
+
+    Ok(HttpResponse::Ok().json("Username changed"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_username_problem() {
+        assert_eq!(username_problem("jane_doe"), None);
+        assert_eq!(username_problem("J-4"), None);
+        assert!(username_problem("jo").is_some());
+        assert!(username_problem(&"a".repeat(31)).is_some());
+        assert!(username_problem("jane doe").is_some());
+        assert!(username_problem("jané").is_some());
+        assert!(username_problem("_jane").is_some());
+        assert!(username_problem("12345").is_some());
+        assert!(username_problem("Admin").is_some());
+    }
+}
@@ -1,40 +1,90 @@
-use crate::category::{Category, CreateCategory};
-use deadpool_postgres::{Client, PoolError};
+use crate::category::{Category, CategoryRef, CreateCategory};
+use crate::configs::db;
+use crate::errors::ServiceError;
+use crate::pagination::{Keyed, Keyset};
+use deadpool_postgres::Client;
 use std::io;
 use tokio_pg_mapper::FromTokioPostgresRow;
+use tokio_postgres::error::SqlState;
 
-// CORE CRUD
+/// Ids of the category `category_id` (an SQL expression) and of every category nested in
+/// it, at any depth. `UNION` rather than `UNION ALL` keeps the walk finite should a cycle
+/// ever slip in.
+pub fn category_subtree(category_id: &str) -> String {
+    format!(
+        "(WITH RECURSIVE subtree AS (
+            SELECT {} AS id
+            UNION
+            SELECT child.id FROM {categories} child JOIN subtree ON child.parent_id = subtree.id
+        ) SELECT id FROM subtree)",
+        category_id,
+        categories = db().categories
+    )
+}
+
+/// Columns of a `Category` selected from `categories c`, with post counts.
+fn category_columns() -> String {
+    format!(
+        "c.id, c.name, c.slug, c.description, c.parent_id,
+        (SELECT count(*) FROM {posts_categories} pc JOIN {posts} p ON p.id = pc.post_id
+            WHERE pc.category_id = c.id AND p.status = 'published') AS post_count,
+        (SELECT count(DISTINCT pc.post_id) FROM {posts_categories} pc
+            JOIN {posts} p ON p.id = pc.post_id
+            WHERE pc.category_id IN {} AND p.status = 'published') AS total_post_count",
+        category_subtree("c.id"),
+        posts_categories = db().posts_categories,
+        posts = db().posts
+    )
+}
+
+fn category_error(e: tokio_postgres::Error) -> ServiceError {
+    match e.code() {
+        Some(code) if *code == SqlState::UNIQUE_VIOLATION => {
+            ServiceError::Conflict("A category with this slug already exists".into())
+        }
+        Some(code) if *code == SqlState::FOREIGN_KEY_VIOLATION => {
+            ServiceError::BadRequest("Parent category not found".into())
+        }
+        Some(code) if *code == SqlState::CHECK_VIOLATION => {
+            ServiceError::BadRequest("A category can't be its own parent".into())
+        }
+        _ => ServiceError::from(e),
+    }
+}
 
-//TODO configure .env for db shema name
+// CORE CRUD
 
 // Decide wether to return id or return all fields from insert sql query . if return ID, insert id in function argument.
 // shift id in db tables to the top so we can skip it when not needed
 
-pub async fn category_add(client: &Client, selfobj: CreateCategory) -> Result<Category, io::Error> {
+pub async fn category_add(
+    client: &Client,
+    selfobj: CreateCategory,
+) -> Result<Category, ServiceError> {
     let statement = client
-        .prepare(
-            "INSERT INTO public.categories
-   (id, name, slug, description)
-    VALUES ($1, $2, $3) RETURNING  name, slug, description",
-        )
-        .await
-        .unwrap();
+        .prepare(&format!(
+            "WITH c AS (INSERT INTO {categories}
+   (name, slug, description, parent_id)
+    VALUES ($1, $2, $3, $4) RETURNING *)
+            SELECT c.id, c.name, c.slug, c.description, c.parent_id,
+            0::bigint AS post_count, 0::bigint AS total_post_count FROM c",
+            categories = db().categories
+        ))
+        .await?;
 
-    client
-        .query(
+    let row = client
+        .query_one(
             &statement,
-            &[&selfobj.name, &selfobj.slug, &selfobj.description],
+            &[
+                &selfobj.name,
+                &selfobj.slug,
+                &selfobj.description,
+                &selfobj.parent_id,
+            ],
         )
         .await
-        .expect("Error creating category")
-        .iter()
-        .map(|row| Category::from_row_ref(row).unwrap())
-        .collect::<Vec<Category>>()
-        .pop()
-        .ok_or(io::Error::new(
-            io::ErrorKind::Other,
-            "Error creating category tables",
-        ))
+        .map_err(category_error)?;
+    Ok(Category::from_row_ref(&row).unwrap())
 }
 
 // TODO populate fields
@@ -43,27 +93,64 @@ pub async fn category_add(client: &Client, selfobj: CreateCategory) -> Result<Ca
 
 //NE
 
-pub async fn category_list(client: &Client) -> Result<Vec<Category>, PoolError> {
+/// A page of the categories whose name contains `q`.
+pub async fn category_list(
+    client: &Client,
+    q: Option<&str>,
+    keyset: &Keyset,
+) -> Result<Vec<Keyed<Category>>, ServiceError> {
     let statement = client
-        .prepare_cached("select * from public.categories ")
+        .prepare(&format!(
+            "SELECT {}, {} FROM {categories} c
+            WHERE ($1::text IS NULL OR c.name ILIKE '%' || $1 || '%')
+            and {}
+            {}",
+            category_columns(),
+            keyset.key_column(),
+            keyset.condition("c.id", 2, 3),
+            keyset.order_by("c.id"),
+            categories = db().categories
+        ))
         .await?;
 
-    // .unwrap();
+    let categories = client
+        .query(&statement, &[&q, &keyset.key(), &keyset.id()])
+        .await?
+        .iter()
+        .map(|row| {
+            let category = Category::from_row_ref(row).unwrap();
+            Keyed::new(row, category.id, category)
+        })
+        .collect::<Vec<Keyed<Category>>>();
+    Ok(categories)
+}
+
+/// Every category, ordered by name, for building the tree.
+pub async fn category_all(client: &Client) -> Result<Vec<Category>, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "SELECT {} FROM {categories} c ORDER BY c.name, c.id",
+            category_columns(),
+            categories = db().categories
+        ))
+        .await?;
 
-    let category_list = client
+    let categories = client
         .query(&statement, &[])
-        .await
-        .expect("Error getting author lists")
+        .await?
         .iter()
         .map(|row| Category::from_row_ref(row).unwrap())
         .collect::<Vec<Category>>();
-
-    Ok(category_list)
+    Ok(categories)
 }
 
 pub async fn category_id(client: &Client, id_category: i32) -> Result<Category, io::Error> {
     let statement = client
-        .prepare("select * from public.categories where id = $1")
+        .prepare(&format!(
+            "select {} from {categories} c where c.id = $1",
+            category_columns(),
+            categories = db().categories
+        ))
         .await
         .unwrap();
 
@@ -81,7 +168,11 @@ pub async fn category_id(client: &Client, id_category: i32) -> Result<Category,
 
 pub async fn category_search(client: &Client, category: &String) -> Result<Category, io::Error> {
     let statement = client
-        .prepare("select * from public.categories where name = $1")
+        .prepare(&format!(
+            "select {} from {categories} c where c.name = $1",
+            category_columns(),
+            categories = db().categories
+        ))
         .await
         .unwrap();
 
@@ -99,37 +190,171 @@ pub async fn category_search(client: &Client, category: &String) -> Result<Categ
 
 //TODO take into account ID position
 
+/// Updates the category, refusing to nest it in itself or in one of its subcategories.
 pub async fn category_update(
-    client: &Client,
+    client: &mut Client,
     id: i32,
     mdl: CreateCategory,
-) -> Result<(), io::Error> {
-    let statement = client
-        .prepare(
-            "update public.categories set ( name, slug, description) = ($1, $2, $3) where id = $3",
+) -> Result<(), ServiceError> {
+    let transaction = client.transaction().await?;
+
+    // Two moves checked at once could each be fine alone and still close a loop together,
+    // so moves of any category wait for each other.
+    transaction
+        .execute(
+            "SELECT pg_advisory_xact_lock(hashtext('categories_tree'))",
+            &[],
         )
-        .await
-        .unwrap();
+        .await?;
 
-    let result = client
-        .execute(&statement, &[&mdl.name, &mdl.slug, &mdl.description, &id])
-        .await
-        .expect("Error getting todo lists");
+    if let Some(parent_id) = mdl.parent_id {
+        let statement = transaction
+            .prepare(&format!("SELECT $2 IN {}", category_subtree("$1::integer")))
+            .await?;
+        let cycle: bool = transaction
+            .query_one(&statement, &[&id, &parent_id])
+            .await?
+            .get(0);
+        if cycle {
+            return Err(ServiceError::BadRequest(
+                "A category can't be nested in itself or its subcategories".into(),
+            ));
+        }
+    }
+
+    let statement = transaction
+        .prepare(&format!(
+            "update {categories} set (name, slug, description, parent_id)
+                = ($1, COALESCE($2, slug), $3, $4)
+            where id = $5",
+            categories = db().categories
+        ))
+        .await?;
 
-    match result {
-        ref updated if *updated == 1 => Ok(()),
-        _ => Err(io::Error::new(io::ErrorKind::Other, "Failed to check list")),
+    let updated = transaction
+        .execute(
+            &statement,
+            &[&mdl.name, &mdl.slug, &mdl.description, &mdl.parent_id, &id],
+        )
+        .await
+        .map_err(category_error)?;
+    if updated != 1 {
+        return Err(ServiceError::NotFound("Category not found".into()));
     }
+
+    transaction.commit().await?;
+    Ok(())
 }
 
-pub async fn category_delete(client: &Client, category_id: i32) -> Result<(), io::Error> {
+pub async fn category_delete(client: &Client, category_id: i32) -> Result<(), ServiceError> {
     let statement = client
-        .prepare("DELETE FROM public.categories WHERE id = $1")
-        .await
-        .unwrap();
+        .prepare(&format!(
+            "DELETE FROM {categories} WHERE id = $1",
+            categories = db().categories
+        ))
+        .await?;
 
-    client.execute(&statement, &[&category_id]).await.unwrap();
-    Ok(())
+    match client.execute(&statement, &[&category_id]).await? {
+        1 => Ok(()),
+        _ => Err(ServiceError::NotFound("Category not found".into())),
+    }
 }
 
 // END OF CORE CRUD
+
+/// Slugs starting with `prefix` that categories other than `exclude_id` go by.
+pub async fn category_slugs_taken(
+    client: &Client,
+    prefix: &str,
+    exclude_id: Option<i32>,
+) -> Result<Vec<String>, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "SELECT slug FROM {categories} WHERE slug LIKE $1 || '%' AND id IS DISTINCT FROM $2",
+            categories = db().categories
+        ))
+        .await?;
+
+    let slugs = client
+        .query(&statement, &[&prefix, &exclude_id])
+        .await?
+        .iter()
+        .map(|row| row.get(0))
+        .collect::<Vec<String>>();
+    Ok(slugs)
+}
+
+/// Files the post under exactly `category_ids`, all at once.
+pub async fn post_categories_set(
+    client: &mut Client,
+    post_id: i32,
+    category_ids: &[i32],
+) -> Result<Vec<CategoryRef>, ServiceError> {
+    let transaction = client.transaction().await?;
+
+    // Serializes concurrent changes to the categories of the same post.
+    let statement = transaction
+        .prepare(&format!(
+            "SELECT id FROM {posts} WHERE id = $1 FOR UPDATE",
+            posts = db().posts
+        ))
+        .await?;
+    if transaction
+        .query_opt(&statement, &[&post_id])
+        .await?
+        .is_none()
+    {
+        return Err(ServiceError::NotFound("Post not found".into()));
+    }
+
+    let statement = transaction
+        .prepare(&format!(
+            "SELECT id, name, slug FROM {categories} WHERE id = ANY ($1) ORDER BY name",
+            categories = db().categories
+        ))
+        .await?;
+    let categories = transaction
+        .query(&statement, &[&category_ids])
+        .await?
+        .iter()
+        .map(|row| CategoryRef {
+            id: row.get("id"),
+            name: row.get("name"),
+            slug: row.get("slug"),
+        })
+        .collect::<Vec<CategoryRef>>();
+    if let Some(missing) = category_ids
+        .iter()
+        .find(|id| !categories.iter().any(|category| category.id == **id))
+    {
+        return Err(ServiceError::BadRequest(format!(
+            "Category {} not found",
+            missing
+        )));
+    }
+
+    let statement = transaction
+        .prepare(&format!(
+            "DELETE FROM {posts_categories} WHERE post_id = $1 AND category_id <> ALL ($2)",
+            posts_categories = db().posts_categories
+        ))
+        .await?;
+    transaction
+        .execute(&statement, &[&post_id, &category_ids])
+        .await?;
+
+    let statement = transaction
+        .prepare(&format!(
+            "INSERT INTO {posts_categories} (post_id, category_id)
+            SELECT $1, unnest($2::integer[])
+            ON CONFLICT DO NOTHING",
+            posts_categories = db().posts_categories
+        ))
+        .await?;
+    transaction
+        .execute(&statement, &[&post_id, &category_ids])
+        .await?;
+
+    transaction.commit().await?;
+    Ok(categories)
+}
@@ -1,65 +1,101 @@
-use crate::category::models::CreateCategory;
-use crate::category::{db, SearchCategory};
+use crate::auth::AuthUser;
+use crate::category::models::{build_tree, CreateCategory};
+use crate::category::{db, CategoryListQuery, SearchCategory};
 use crate::errors::ServiceError;
+use crate::pagination::{Keyset, SortOrder};
+use crate::posts::handlers::is_editor;
+use crate::posts::{self, PostListQuery};
+use crate::slugs::SlugChoice;
 use std::io;
 
 use actix_web::web::Query;
-use actix_web::{delete, get, patch, post, web, HttpResponse, Responder};
+use actix_web::{delete, get, patch, post, web, HttpRequest, HttpResponse, Responder};
 use deadpool_postgres::{Client, Pool};
 use io::ErrorKind::NotFound;
 
+/// Categories file the posts and nest, only editors and admins change them.
+fn category_editor(user: &AuthUser) -> Result<(), ServiceError> {
+    if is_editor(user) {
+        Ok(())
+    } else {
+        Err(ServiceError::Forbidden(
+            "Only editors and admins change categories".into(),
+        ))
+    }
+}
+
+/// Get a page of categories, by name unless sorted otherwise.
 #[utoipa::path(
     responses(
-        (status = 200, description = "Category lists", body = [Category]),
-    )
+        (status = 200, description = "Category lists", body = CategoryPage),
+        (status = 400, description = "Invalid cursor", body = ServiceError)
+    ),
+    params(CategoryListQuery)
 )]
 #[get("/")]
-pub async fn category(db_pool: web::Data<Pool>) -> impl Responder {
-    let client: Client = db_pool
-        .get()
-        .await
-        .expect("Error connecting to the database");
-
-    let result = db::category_list(&client).await;
+pub async fn category(
+    req: HttpRequest,
+    query: Query<CategoryListQuery>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let keyset = Keyset::new(
+        query.sort.unwrap_or_default().field(),
+        query.order.unwrap_or(SortOrder::Asc),
+        query.cursor.as_deref(),
+        query.limit,
+    )?;
+    let client: Client = db_pool.get().await?;
 
-    match result {
-        Ok(object) => HttpResponse::Ok().json(object),
-        Err(_) => HttpResponse::InternalServerError().into(),
-    }
+    let categories = db::category_list(&client, query.q.as_deref(), &keyset).await?;
+    Ok(HttpResponse::Ok().json(keyset.page(&req, categories)))
 }
 
-/// Create new Todo to shared in-memory storage.
+/// Create a category, at the top level or under `parent_id`.
 ///
-/// Post a new `Todo` in request body as json to store it. Api will return
-/// created `Todo` on success or `ErrorResponse::Conflict` if todo with same id already exists.
-///
-/// One could call the api with.
-/// ```text
-/// curl localhost:8080/todo -d '{"id": 1, "value": "Buy movie ticket", "checked": false}'
-/// ```
+/// Open to editors and admins.
 #[utoipa::path(
     request_body = CreateCategory,
     responses(
         (status = 201, description = "Category Successfully added", body = Category),
-        (status = 409, description = "Category with id already exists", body = ServiceError)
+        (status = 400, description = "Parent category not found or invalid slug", body = ServiceError),
+        (status = 401, description = "Not signed in", body = ServiceError),
+        (status = 403, description = "Not an editor", body = ServiceError),
+        (status = 409, description = "A category with this slug already exists", body = ServiceError)
     )
 )]
 #[post("/")]
 pub async fn add_category(
+    user: AuthUser,
     local_object: web::Json<CreateCategory>,
     db_pool: web::Data<Pool>,
-) -> impl Responder {
-    let client: Client = db_pool
-        .get()
-        .await
-        .expect("Error connecting to the database");
+) -> Result<HttpResponse, ServiceError> {
+    category_editor(&user)?;
+    let mut new_category = local_object.into_inner();
+    let choice = SlugChoice::new(new_category.slug.as_deref(), &new_category.name, "category")?;
+    let client: Client = db_pool.get().await?;
 
-    let result = db::category_add(&client, local_object.clone()).await;
+    let taken = db::category_slugs_taken(&client, choice.prefix(), None).await?;
+    new_category.slug = Some(choice.resolve(&taken)?);
+    let added = db::category_add(&client, new_category).await?;
+    Ok(HttpResponse::Created().json(added))
+}
 
-    match result {
-        Ok(object) => HttpResponse::Ok().json(object),
-        Err(_) => HttpResponse::InternalServerError().into(),
-    }
+/// Every category, nested under its parent.
+///
+/// Top level categories and the children of each category are ordered by name. Post counts
+/// are those of `GET /categories`.
+#[utoipa::path(
+    context_path = "/categories",
+    responses(
+        (status = 200, description = "The category tree", body = [CategoryNode])
+    )
+)]
+#[get("/tree")]
+pub async fn category_tree(db_pool: web::Data<Pool>) -> Result<HttpResponse, ServiceError> {
+    let client: Client = db_pool.get().await?;
+
+    let categories = db::category_all(&client).await?;
+    Ok(HttpResponse::Ok().json(build_tree(categories)))
 }
 
 /// Get Category by given todo id.
@@ -125,82 +161,127 @@ pub async fn search_category(
     }
 }
 
-/// Delete Category by given path variable id.
-///
-/// This endpoint needs `api_key` authentication in order to call. Api key can be found from README.md.
+/// Delete a category.
 ///
-/// Api will delete todo from shared in-memory storage by the provided id and return success 200.
-/// If storage does not contain `Todo` with given id 404 not found will be returned.
+/// Open to editors and admins. Its posts are no longer filed under it and its
+/// subcategories move to the top level.
 #[utoipa::path(
     responses(
         (status = 200, description = "Category deleted successfully"),
-        (status = 401, description = "Unauthorized to delete Category", body = ServiceError),
+        (status = 401, description = "Not signed in", body = ServiceError),
+        (status = 403, description = "Not an editor", body = ServiceError),
         (status = 404, description = "Category not found by id", body = ServiceError)
     ),
     params(
-        ("id", description = "Unique storage id of Category")
+        ("id", description = "Unique Category Id")
     ))]
 #[delete("/{id}")]
 pub async fn delete_category(
+    user: AuthUser,
     category_id: web::Path<(i32,)>,
     db_pool: web::Data<Pool>,
-) -> impl Responder {
-    let client: Client = db_pool
-        .get()
-        .await
-        .expect("Error connecting to the database");
+) -> Result<HttpResponse, ServiceError> {
+    category_editor(&user)?;
+    let client: Client = db_pool.get().await?;
 
-    let result = db::category_delete(&client, category_id.0).await;
-
-    match result {
-        Ok(object) => HttpResponse::Ok().json(object),
-        Err(ref e) if e.kind() == NotFound => HttpResponse::NotFound().into(),
-        Err(_) => HttpResponse::InternalServerError().into(),
-    }
+    db::category_delete(&client, category_id.0).await?;
+    Ok(HttpResponse::Ok().json(()))
 }
 
-/// Update Todo with given id.
-///
-/// This endpoint supports optional authentication.
+/// Update a category.
 ///
-/// Tries to update `Todo` by given id as path variable. If todo is found by id values are
-/// updated according `TodoUpdateRequest` and updated `Todo` is returned with status 200.
-/// If todo is not found then 404 not found is returned.
+/// Replaces the name, description and parent, and the slug when given. A category can't move under
+/// itself or one of its subcategories. Open to editors and admins.
 #[utoipa::path(
-    request_body = TodoUpdateRequest,
+    request_body = CreateCategory,
     responses(
-        (status = 200, description = "Category updated successfully", body = CreateCategory),
-        (status = 404, description = "Category not found by id", body = ServiceError)
+        (status = 200, description = "Category updated successfully"),
+        (status = 400, description = "Parent category not found, or nested in the category", body = ServiceError),
+        (status = 401, description = "Not signed in", body = ServiceError),
+        (status = 403, description = "Not an editor", body = ServiceError),
+        (status = 404, description = "Category not found by id", body = ServiceError),
+        (status = 409, description = "A category with this slug already exists", body = ServiceError)
     ),
     params(
-        ("id", description = "Unique storage id of Category")
+        ("id", description = "Unique Category Id")
     )
 )]
 #[patch("/{id}")]
 pub async fn update_category(
+    user: AuthUser,
     id_category: web::Path<(i32,)>,
     local_object: web::Json<CreateCategory>,
     db_pool: web::Data<Pool>,
-) -> impl Responder {
-    let client: Client = db_pool
-        .get()
-        .await
-        .expect("Error connecting to the database");
+) -> Result<HttpResponse, ServiceError> {
+    category_editor(&user)?;
+    let mut changes = local_object.into_inner();
+    let mut client: Client = db_pool.get().await?;
 
-    let result = db::category_update(&client, id_category.0, local_object.clone()).await;
-
-    match result {
-        Ok(object) => HttpResponse::Ok().json(object),
-        Err(ref e) if e.kind() == NotFound => HttpResponse::NotFound().into(),
-        Err(_) => HttpResponse::InternalServerError().into(),
+    if let Some(given) = changes.slug.as_deref() {
+        let choice = SlugChoice::new(Some(given), &changes.name, "category")?;
+        let taken = db::category_slugs_taken(&client, choice.prefix(), Some(id_category.0)).await?;
+        changes.slug = Some(choice.resolve(&taken)?);
     }
+    db::category_update(&mut client, id_category.0, changes).await?;
+    Ok(HttpResponse::Ok().json(()))
+}
+
+/// Posts filed under a category or any of its subcategories.
+///
+/// Takes the same filters, sorting and cursors as `GET /posts`, and shows the same posts.
+#[utoipa::path(
+    context_path = "/categories",
+    responses(
+        (status = 200, description = "Posts in the category", body = PostPage),
+        (status = 400, description = "Invalid cursor", body = ServiceError),
+        (status = 404, description = "Category not found", body = ServiceError)
+    ),
+    params(
+        ("id", description = "Unique Category Id"),
+        PostListQuery
+    )
+)]
+#[get("/{id}/posts")]
+pub async fn category_posts(
+    req: HttpRequest,
+    viewer: Option<AuthUser>,
+    id_category: web::Path<(i32,)>,
+    query: Query<PostListQuery>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let keyset = Keyset::new(
+        query.sort.unwrap_or_default().field(),
+        query.order.unwrap_or_default(),
+        query.cursor.as_deref(),
+        query.limit,
+    )?;
+    let client: Client = db_pool.get().await?;
+
+    let parent = db::category_id(&client, id_category.0)
+        .await
+        .map_err(|_| ServiceError::NotFound("Category not found".into()))?;
+    let query = PostListQuery {
+        category: Some(parent.id),
+        ..query.into_inner()
+    };
+    let posts = posts::db::post_list(
+        &client,
+        viewer.as_ref().map(|user| user.id),
+        viewer.as_ref().is_some_and(is_editor),
+        &query,
+        &keyset,
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(keyset.page(&req, posts)))
 }
 
 pub fn init_routes(cfg: &mut web::ServiceConfig) {
     cfg.service(category);
+    cfg.service(category_tree);
     cfg.service(add_category);
     cfg.service(update_category);
     cfg.service(get_category);
+    cfg.service(category_posts);
     cfg.service(delete_category);
 }
 
@@ -210,3 +291,27 @@ pub fn init_routes(cfg: &mut web::ServiceConfig) {
 //     println!("{:#?}", res);
 //     res
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Role;
+
+    #[test]
+    fn test_category_editor() {
+        let user = |role: Role| AuthUser {
+            id: 1,
+            email: "jane@example.com".into(),
+            username: None,
+            role,
+            session_id: Some(1),
+            impersonator_id: None,
+        };
+        assert!(category_editor(&user(Role::Admin)).is_ok());
+        assert!(category_editor(&user(Role::Editor)).is_ok());
+        assert!(matches!(
+            category_editor(&user(Role::User)),
+            Err(ServiceError::Forbidden(_))
+        ));
+    }
+}
@@ -3,6 +3,13 @@ use tokio_pg_mapper_derive::PostgresMapper;
 extern crate chrono;
 use utoipa::{IntoParams, ToResponse, ToSchema};
 
+use std::collections::HashMap;
+use std::error::Error;
+
+use tokio_postgres::types::{FromSql, Type};
+
+use crate::pagination::{SortField, SortOrder};
+
 //To be added based on special query
 #[derive(
     Serialize, Debug, ToSchema, Clone, ToResponse, IntoParams, Deserialize, PostgresMapper, Default,
@@ -15,6 +22,77 @@ pub struct Category {
     pub name: String,
     pub slug: String,
     pub description: String,
+    /// The category this one is nested in, `None` at the top level.
+    pub parent_id: Option<i32>,
+    /// Published posts filed directly under the category.
+    pub post_count: i64,
+    /// Published posts filed under the category or any of its subcategories, each
+    /// counted once.
+    pub total_post_count: i64,
+}
+
+/// A category with its subcategories, as served by `GET /categories/tree`.
+#[derive(Serialize, Debug, ToSchema, Clone, Deserialize)]
+pub struct CategoryNode {
+    #[serde(flatten)]
+    pub category: Category,
+    /// Ordered by name.
+    pub children: Vec<CategoryNode>,
+}
+
+/// Nests `categories` under their parents, keeping their order among siblings. Categories
+/// whose parent isn't among them end up at the top level.
+pub fn build_tree(categories: Vec<Category>) -> Vec<CategoryNode> {
+    let ids = categories
+        .iter()
+        .map(|category| category.id)
+        .collect::<Vec<i32>>();
+    let mut children: HashMap<Option<i32>, Vec<Category>> = HashMap::new();
+    for category in categories {
+        let parent = category.parent_id.filter(|parent| ids.contains(parent));
+        children.entry(parent).or_default().push(category);
+    }
+
+    fn nest(
+        parent: Option<i32>,
+        children: &mut HashMap<Option<i32>, Vec<Category>>,
+    ) -> Vec<CategoryNode> {
+        children
+            .remove(&parent)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|category| CategoryNode {
+                children: nest(Some(category.id), children),
+                category,
+            })
+            .collect()
+    }
+    nest(None, &mut children)
+}
+
+/// What a post lists of each of its categories.
+#[derive(Serialize, Debug, ToSchema, Clone, Deserialize, Default, PartialEq, Eq)]
+pub struct CategoryRef {
+    pub id: i32,
+    pub name: String,
+    pub slug: String,
+}
+
+/// The categories of a post, ordered by name. Read from a column holding them as a JSON
+/// array.
+#[derive(Serialize, Debug, ToSchema, Clone, Deserialize, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct CategoryList(pub Vec<CategoryRef>);
+
+impl<'a> FromSql<'a> for CategoryList {
+    fn from_sql(ty: &Type, raw: &'a [u8]) -> Result<CategoryList, Box<dyn Error + Sync + Send>> {
+        let json = <&str as FromSql>::from_sql(ty, raw)?;
+        Ok(CategoryList(serde_json::from_str(json)?))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        <&str as FromSql>::accepts(ty)
+    }
 }
 
 #[derive(
@@ -25,8 +103,22 @@ pub struct Category {
 #[pg_mapper(table = "categories")]
 pub struct CreateCategory {
     pub name: String,
-    pub slug: String,
+    /// Made from the name when left out, or kept as it is when updating.
+    #[serde(default)]
+    pub slug: Option<String>,
     pub description: String,
+    /// Category to nest this one in. Leaving it out puts the category at the top level,
+    /// also when updating.
+    #[serde(default)]
+    pub parent_id: Option<i32>,
+}
+
+/// The complete set of categories a post should be filed under.
+#[derive(Serialize, Debug, Clone, Deserialize, ToSchema)]
+#[schema(example = json!({"categories": [1, 4]}))]
+pub struct SetPostCategories {
+    /// Ids of existing categories. An empty list leaves the post uncategorized.
+    pub categories: Vec<i32>,
 }
 
 /// Search todos Query
@@ -37,3 +129,98 @@ pub struct SearchCategory {
     /// Content that should be found from Todo's value field
     pub name: String,
 }
+
+/// What the category list can be sorted on.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CategorySort {
+    #[default]
+    Name,
+    Id,
+}
+
+impl CategorySort {
+    pub fn field(&self) -> SortField {
+        match self {
+            CategorySort::Name => SortField {
+                name: "name",
+                expr: "c.name",
+                sql_type: "text",
+            },
+            CategorySort::Id => SortField {
+                name: "id",
+                expr: "c.id",
+                sql_type: "integer",
+            },
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CategoryListQuery {
+    /// Only categories whose name contains this, ignoring case.
+    pub q: Option<String>,
+    /// Field to sort on, `name` by default.
+    pub sort: Option<CategorySort>,
+    /// `asc` by default.
+    pub order: Option<SortOrder>,
+    /// `next_cursor` or `prev_cursor` of the page before.
+    pub cursor: Option<String>,
+    /// At most this many categories, 20 by default and at most 100.
+    pub limit: Option<i64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn category(id: i32, parent_id: Option<i32>) -> Category {
+        Category {
+            id,
+            name: format!("c{}", id),
+            parent_id,
+            ..Default::default()
+        }
+    }
+
+    fn ids(nodes: &[CategoryNode]) -> Vec<(i32, Vec<i32>)> {
+        nodes
+            .iter()
+            .map(|node| {
+                (
+                    node.category.id,
+                    node.children
+                        .iter()
+                        .map(|child| child.category.id)
+                        .collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_build_tree() {
+        let tree = build_tree(vec![
+            category(1, None),
+            category(2, Some(1)),
+            category(3, Some(2)),
+            category(4, Some(1)),
+            category(5, None),
+            // Parent not in the list.
+            category(6, Some(99)),
+        ]);
+        assert_eq!(ids(&tree), vec![(1, vec![2, 4]), (5, vec![]), (6, vec![])]);
+        assert_eq!(ids(&tree[0].children), vec![(2, vec![3]), (4, vec![])]);
+    }
+
+    #[test]
+    fn test_category_list_from_sql() {
+        let categories = CategoryList::from_sql(
+            &Type::TEXT,
+            br#"[{"id": 2, "name": "Rust", "slug": "rust"}]"#,
+        )
+        .unwrap();
+        assert_eq!(categories.0[0].slug, "rust");
+    }
+}
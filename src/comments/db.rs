@@ -0,0 +1,185 @@
+use crate::comments::{Comment, CommentListQuery, CommentStatus, ModeratedComment};
+use crate::configs::db;
+use crate::errors::ServiceError;
+use crate::pagination::{Keyed, Keyset};
+use deadpool_postgres::Client;
+use tokio_pg_mapper::FromTokioPostgresRow;
+
+// Comments of signed in users go by the username, anonymous ones by the name given.
+fn comment_columns() -> &'static str {
+    "c.id, c.post_id, c.parent_id, c.author_id,
+    COALESCE(u.username, c.author_name, 'anonymous') AS author_name,
+    c.content, c.content_html, c.status, c.created_at"
+}
+
+fn moderated_columns() -> String {
+    format!(
+        "{}, c.author_email, c.moderated_by, c.moderated_at",
+        comment_columns()
+    )
+}
+
+/// Adds the comment to post `post_id`. A reply is only taken when `parent_id` is an
+/// approved comment of the same post.
+#[allow(clippy::too_many_arguments)]
+pub async fn comment_add(
+    client: &Client,
+    post_id: i32,
+    parent_id: Option<i32>,
+    author_id: Option<i32>,
+    author_name: Option<&str>,
+    author_email: Option<&str>,
+    content: &str,
+    content_html: &str,
+    status: CommentStatus,
+) -> Result<Comment, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "WITH c AS (INSERT INTO {comments}
+                (post_id, parent_id, author_id, author_name, author_email, content,
+                content_html, status)
+                SELECT $1, $2, $3, $4, $5, $6, $7, $8
+                WHERE $2::integer IS NULL OR EXISTS (SELECT 1 FROM {comments}
+                    WHERE id = $2 AND post_id = $1 AND status = 'approved')
+                RETURNING *)
+            SELECT {} FROM c LEFT JOIN {users} u ON u.id = c.author_id",
+            comment_columns(),
+            comments = db().comments,
+            users = db().users
+        ))
+        .await?;
+
+    client
+        .query_opt(
+            &statement,
+            &[
+                &post_id,
+                &parent_id,
+                &author_id,
+                &author_name,
+                &author_email,
+                &content,
+                &content_html,
+                &status.as_str(),
+            ],
+        )
+        .await?
+        .map(|row| Comment::from_row_ref(&row).unwrap())
+        .ok_or_else(|| {
+            ServiceError::BadRequest("Replies must be to a comment shown on the same post".into())
+        })
+}
+
+/// The approved comments of a post, oldest first.
+pub async fn post_comments(client: &Client, post_id: i32) -> Result<Vec<Comment>, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "SELECT {} FROM {comments} c LEFT JOIN {users} u ON u.id = c.author_id
+            WHERE c.post_id = $1 AND c.status = 'approved'
+            ORDER BY c.created_at, c.id",
+            comment_columns(),
+            comments = db().comments,
+            users = db().users
+        ))
+        .await?;
+
+    let comments = client
+        .query(&statement, &[&post_id])
+        .await?
+        .iter()
+        .map(|row| Comment::from_row_ref(row).unwrap())
+        .collect::<Vec<Comment>>();
+    Ok(comments)
+}
+
+pub async fn comment_get(client: &Client, id: i32) -> Result<ModeratedComment, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "SELECT {} FROM {comments} c LEFT JOIN {users} u ON u.id = c.author_id
+            WHERE c.id = $1",
+            moderated_columns(),
+            comments = db().comments,
+            users = db().users
+        ))
+        .await?;
+
+    client
+        .query_opt(&statement, &[&id])
+        .await?
+        .map(|row| ModeratedComment::from_row_ref(&row).unwrap())
+        .ok_or_else(|| ServiceError::NotFound("Comment not found".into()))
+}
+
+/// A page of the comments in `status` (pending when not given), for moderators.
+pub async fn comment_list(
+    client: &Client,
+    query: &CommentListQuery,
+    keyset: &Keyset,
+) -> Result<Vec<Keyed<ModeratedComment>>, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "SELECT {}, {} FROM {comments} c LEFT JOIN {users} u ON u.id = c.author_id
+            WHERE c.status = $1
+            and ($2::integer IS NULL OR c.post_id = $2)
+            and {}
+            {}",
+            moderated_columns(),
+            keyset.key_column(),
+            keyset.condition("c.id", 3, 4),
+            keyset.order_by("c.id"),
+            comments = db().comments,
+            users = db().users
+        ))
+        .await?;
+
+    let comments = client
+        .query(
+            &statement,
+            &[
+                &query.status.unwrap_or_default().as_str(),
+                &query.post,
+                &keyset.key(),
+                &keyset.id(),
+            ],
+        )
+        .await?
+        .iter()
+        .map(|row| {
+            let comment = ModeratedComment::from_row_ref(row).unwrap();
+            Keyed::new(row, comment.id, comment)
+        })
+        .collect::<Vec<Keyed<ModeratedComment>>>();
+    Ok(comments)
+}
+
+/// Moves the comment to `status` on behalf of `moderator_id`. Returns the comment and the
+/// status it had before.
+pub async fn comment_status_set(
+    client: &Client,
+    id: i32,
+    status: CommentStatus,
+    moderator_id: i32,
+) -> Result<(ModeratedComment, CommentStatus), ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "WITH old AS (SELECT id, status FROM {comments} WHERE id = $1 FOR UPDATE),
+            c AS (UPDATE {comments} SET status = $2, moderated_by = $3, moderated_at = now()
+                WHERE id = $1 RETURNING *)
+            SELECT {}, old.status AS previous_status
+            FROM c JOIN old ON old.id = c.id LEFT JOIN {users} u ON u.id = c.author_id",
+            moderated_columns(),
+            comments = db().comments,
+            users = db().users
+        ))
+        .await?;
+
+    let row = client
+        .query_opt(&statement, &[&id, &status.as_str(), &moderator_id])
+        .await?
+        .ok_or_else(|| ServiceError::NotFound("Comment not found".into()))?;
+    let previous: String = row.get("previous_status");
+    Ok((
+        ModeratedComment::from_row_ref(&row).unwrap(),
+        previous.parse()?,
+    ))
+}
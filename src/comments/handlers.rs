@@ -0,0 +1,318 @@
+use crate::auth::{find_user_mail_by_id, AuthUser};
+use crate::comments::db;
+use crate::comments::models::{
+    build_thread, CommentListQuery, CommentStatus, CommentStatusChange, CreateComment,
+    COMMENT_ORDER, COMMENT_SORT,
+};
+use crate::configs;
+use crate::errors::ServiceError;
+use crate::mail::{send_email, Message};
+use crate::pagination::Keyset;
+use crate::posts::handlers::{can_see, is_editor};
+use crate::posts::{self, escape_html, ContentFormat, Post};
+
+use actix_web::web::Query;
+use actix_web::{delete, get, patch, post, web, HttpRequest, HttpResponse};
+use deadpool_postgres::{Client, Pool};
+
+/// The post, when `viewer` may see it.
+async fn visible_post(
+    client: &Client,
+    id: i32,
+    viewer: Option<&AuthUser>,
+) -> Result<Post, ServiceError> {
+    posts::db::post_id(client, id)
+        .await
+        .ok()
+        .filter(|post| can_see(post, viewer))
+        .ok_or_else(|| ServiceError::NotFound("Post not found".into()))
+}
+
+fn moderator(user: &AuthUser) -> Result<(), ServiceError> {
+    if is_editor(user) {
+        Ok(())
+    } else {
+        Err(ServiceError::Forbidden(
+            "Only editors and admins moderate comments".into(),
+        ))
+    }
+}
+
+/// Emails the author of `post` about a comment just shown on it, unless they wrote it.
+async fn notify_post_author(
+    client: &Client,
+    post: &Post,
+    author_id: Option<i32>,
+    author_name: &str,
+    content: &str,
+) -> Result<(), ServiceError> {
+    let post_author = match post.author_id {
+        Some(id) if author_id != Some(id) => id,
+        _ => return Ok(()),
+    };
+    let recipient = find_user_mail_by_id(client, post_author).await?;
+    let link = format!(
+        "{}/posts/by-slug/{}",
+        configs::Config::from_env().unwrap().srv_cnf.public_url(),
+        post.slug
+    );
+    let message = Message {
+        email: recipient.email,
+        subject: format!("New comment on \"{}\"", post.title),
+        msg: format!(
+            "<p>{} commented on your post <a href=\"{}\">{}</a>:</p>
+            <blockquote>{}</blockquote>",
+            escape_html(author_name),
+            link,
+            escape_html(&post.title),
+            ContentFormat::Plain.render(content)
+        ),
+    };
+    send_email(message).await;
+    Ok(())
+}
+
+/// Comments on a post, as threads.
+///
+/// Only approved comments are shown, oldest first, with their replies nested in them.
+#[utoipa::path(
+    context_path = "/posts",
+    responses(
+        (status = 200, description = "Approved comments of the post", body = [CommentNode]),
+        (status = 404, description = "Post not found by id", body = ServiceError)
+    ),
+    params(
+        ("id", description = "Unique Post Id")
+    )
+)]
+#[get("/{id}/comments")]
+pub async fn comment_thread(
+    viewer: Option<AuthUser>,
+    id_posts: web::Path<(i32,)>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let client: Client = db_pool.get().await?;
+
+    let post = visible_post(&client, id_posts.0, viewer.as_ref()).await?;
+    let comments = db::post_comments(&client, post.id).await?;
+    Ok(HttpResponse::Ok().json(build_thread(comments)))
+}
+
+/// Comment on a post.
+///
+/// Anyone may comment on a published post. Comments of signed in users are shown right
+/// away, anonymous ones need a name and email and wait for an editor to approve them. The
+/// author of the post gets an email once a comment is shown.
+#[utoipa::path(
+    context_path = "/posts",
+    request_body = CreateComment,
+    responses(
+        (status = 201, description = "The new comment", body = Comment),
+        (status = 400, description = "Invalid comment, or the post takes no comments", body = ServiceError),
+        (status = 404, description = "Post not found by id", body = ServiceError)
+    ),
+    params(
+        ("id", description = "Unique Post Id")
+    )
+)]
+#[post("/{id}/comments")]
+pub async fn add_comment(
+    author: Option<AuthUser>,
+    id_posts: web::Path<(i32,)>,
+    local_object: web::Json<CreateComment>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let (content, name, email) = local_object.validate(author.is_some())?;
+    let client: Client = db_pool.get().await?;
+
+    let post = visible_post(&client, id_posts.0, author.as_ref()).await?;
+    if post.status != "published" {
+        return Err(ServiceError::BadRequest(
+            "Only published posts take comments".into(),
+        ));
+    }
+    let status = match author {
+        Some(_) => CommentStatus::Approved,
+        None => CommentStatus::Pending,
+    };
+    let comment = db::comment_add(
+        &client,
+        post.id,
+        local_object.parent_id,
+        author.as_ref().map(|user| user.id),
+        name.as_deref(),
+        email.as_deref(),
+        &content,
+        &ContentFormat::Plain.render(&content),
+        status,
+    )
+    .await?;
+
+    if status == CommentStatus::Approved {
+        notify_post_author(
+            &client,
+            &post,
+            comment.author_id,
+            &comment.author_name,
+            &comment.content,
+        )
+        .await?;
+    }
+    Ok(HttpResponse::Created().json(comment))
+}
+
+/// The moderation queue.
+///
+/// A page of the comments in a status, `pending` unless asked otherwise, oldest first.
+/// Editors and admins only.
+#[utoipa::path(
+    context_path = "/comments",
+    responses(
+        (status = 200, description = "Comments to moderate", body = ModeratedCommentPage),
+        (status = 400, description = "Invalid cursor", body = ServiceError),
+        (status = 401, description = "Not signed in", body = ServiceError),
+        (status = 403, description = "Not an editor", body = ServiceError)
+    ),
+    params(CommentListQuery)
+)]
+#[get("/")]
+pub async fn moderation_queue(
+    req: HttpRequest,
+    user: AuthUser,
+    query: Query<CommentListQuery>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    moderator(&user)?;
+    let keyset = Keyset::new(
+        COMMENT_SORT,
+        COMMENT_ORDER,
+        query.cursor.as_deref(),
+        query.limit,
+    )?;
+    let client: Client = db_pool.get().await?;
+
+    let comments = db::comment_list(&client, &query, &keyset).await?;
+    Ok(HttpResponse::Ok().json(keyset.page(&req, comments)))
+}
+
+/// Get a comment in any status, with the email of anonymous commenters. Editors and
+/// admins only.
+#[utoipa::path(
+    context_path = "/comments",
+    responses(
+        (status = 200, description = "The comment", body = ModeratedComment),
+        (status = 401, description = "Not signed in", body = ServiceError),
+        (status = 403, description = "Not an editor", body = ServiceError),
+        (status = 404, description = "Comment not found by id", body = ServiceError)
+    ),
+    params(
+        ("id", description = "Unique Comment Id")
+    )
+)]
+#[get("/{id}")]
+pub async fn get_comment(
+    user: AuthUser,
+    id_comment: web::Path<(i32,)>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    moderator(&user)?;
+    let client: Client = db_pool.get().await?;
+
+    let comment = db::comment_get(&client, id_comment.0).await?;
+    Ok(HttpResponse::Ok().json(comment))
+}
+
+/// Moderate a comment.
+///
+/// Approving shows the comment, and emails the author of the post the first time.
+/// Marking it as spam or deleted hides it along with the replies to it. Editors and admins
+/// only.
+#[utoipa::path(
+    context_path = "/comments",
+    request_body = CommentStatusChange,
+    responses(
+        (status = 200, description = "The moderated comment", body = ModeratedComment),
+        (status = 401, description = "Not signed in", body = ServiceError),
+        (status = 403, description = "Not an editor", body = ServiceError),
+        (status = 404, description = "Comment not found by id", body = ServiceError)
+    ),
+    params(
+        ("id", description = "Unique Comment Id")
+    )
+)]
+#[patch("/{id}/status")]
+pub async fn moderate_comment(
+    user: AuthUser,
+    id_comment: web::Path<(i32,)>,
+    local_object: web::Json<CommentStatusChange>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    moderator(&user)?;
+    let client: Client = db_pool.get().await?;
+
+    let status = local_object.status;
+    let (comment, previous) =
+        db::comment_status_set(&client, id_comment.0, status, user.id).await?;
+
+    // Comments held back when posted are news to the post author once let through.
+    if status == CommentStatus::Approved && previous == CommentStatus::Pending {
+        if let Ok(post) = posts::db::post_id(&client, comment.post_id).await {
+            notify_post_author(
+                &client,
+                &post,
+                comment.author_id,
+                &comment.author_name,
+                &comment.content,
+            )
+            .await?;
+        }
+    }
+    Ok(HttpResponse::Ok().json(comment))
+}
+
+/// Delete a comment.
+///
+/// Open to the commenter and to editors and admins. The comment is kept, marked as
+/// deleted, and hidden along with the replies to it.
+#[utoipa::path(
+    context_path = "/comments",
+    responses(
+        (status = 204, description = "Comment deleted"),
+        (status = 401, description = "Not signed in", body = ServiceError),
+        (status = 403, description = "Not the commenter", body = ServiceError),
+        (status = 404, description = "Comment not found by id", body = ServiceError)
+    ),
+    params(
+        ("id", description = "Unique Comment Id")
+    )
+)]
+#[delete("/{id}")]
+pub async fn delete_comment(
+    user: AuthUser,
+    id_comment: web::Path<(i32,)>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let client: Client = db_pool.get().await?;
+
+    let comment = db::comment_get(&client, id_comment.0).await?;
+    if !is_editor(&user) && comment.author_id != Some(user.id) {
+        return Err(ServiceError::Forbidden(
+            "Only the commenter, editors and admins delete comments".into(),
+        ));
+    }
+    db::comment_status_set(&client, comment.id, CommentStatus::Deleted, user.id).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Routes under `/posts`.
+pub fn init_post_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(comment_thread);
+    cfg.service(add_comment);
+}
+
+pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(moderation_queue);
+    cfg.service(get_comment);
+    cfg.service(moderate_comment);
+    cfg.service(delete_comment);
+}
@@ -0,0 +1,6 @@
+pub mod db;
+pub mod handlers;
+pub mod models;
+pub use crate::comments::db::*;
+pub use crate::comments::handlers::*;
+pub use crate::comments::models::*;
@@ -0,0 +1,288 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use tokio_pg_mapper_derive::PostgresMapper;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::errors::ServiceError;
+use crate::pagination::{SortField, SortOrder};
+
+pub const MAX_COMMENT_LEN: usize = 10_000;
+pub const MAX_COMMENTER_NAME: usize = 64;
+pub const MAX_COMMENTER_EMAIL: usize = 254;
+
+/// Where a comment is in moderation, stored as text in the `status` column. Only
+/// `approved` comments are public.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CommentStatus {
+    #[default]
+    Pending,
+    Approved,
+    Spam,
+    Deleted,
+}
+
+impl CommentStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CommentStatus::Pending => "pending",
+            CommentStatus::Approved => "approved",
+            CommentStatus::Spam => "spam",
+            CommentStatus::Deleted => "deleted",
+        }
+    }
+}
+
+impl FromStr for CommentStatus {
+    type Err = ServiceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(CommentStatus::Pending),
+            "approved" => Ok(CommentStatus::Approved),
+            "spam" => Ok(CommentStatus::Spam),
+            "deleted" => Ok(CommentStatus::Deleted),
+            other => Err(ServiceError::BadRequest(format!(
+                "Unknown comment status: {}",
+                other
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for CommentStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A comment as shown to readers. The email of anonymous commenters is never served.
+#[derive(Serialize, Debug, Clone, Deserialize, ToSchema, PostgresMapper, Default)]
+#[pg_mapper(table = "comments")]
+pub struct Comment {
+    pub id: i32,
+    pub post_id: i32,
+    /// The comment this one replies to.
+    pub parent_id: Option<i32>,
+    /// `None` for anonymous comments.
+    pub author_id: Option<i32>,
+    /// Username of the author, or the name an anonymous commenter gave.
+    pub author_name: String,
+    pub content: String,
+    /// `content` as escaped HTML paragraphs.
+    pub content_html: String,
+    /// One of `pending`, `approved`, `spam` or `deleted`.
+    pub status: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A comment with the replies to it, as served by `GET /posts/{id}/comments`.
+#[derive(Serialize, Debug, Clone, Deserialize, ToSchema)]
+pub struct CommentNode {
+    #[serde(flatten)]
+    pub comment: Comment,
+    /// Oldest first.
+    pub replies: Vec<CommentNode>,
+}
+
+/// Nests `comments` under the comments they reply to, keeping their order among siblings.
+/// Replies to a comment that isn't among them are left out, with their own replies.
+pub fn build_thread(comments: Vec<Comment>) -> Vec<CommentNode> {
+    let mut replies: HashMap<Option<i32>, Vec<Comment>> = HashMap::new();
+    for comment in comments {
+        replies.entry(comment.parent_id).or_default().push(comment);
+    }
+
+    fn nest(
+        parent: Option<i32>,
+        replies: &mut HashMap<Option<i32>, Vec<Comment>>,
+    ) -> Vec<CommentNode> {
+        replies
+            .remove(&parent)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|comment| CommentNode {
+                replies: nest(Some(comment.id), replies),
+                comment,
+            })
+            .collect()
+    }
+    nest(None, &mut replies)
+}
+
+#[derive(Serialize, Debug, Clone, Deserialize, ToSchema)]
+#[schema(example = json!({"content": "Nice post!", "author_name": "Ann", "author_email": "ann@example.com"}))]
+pub struct CreateComment {
+    /// Plain text, blank lines separate paragraphs.
+    pub content: String,
+    /// The comment this one replies to, on the same post.
+    #[serde(default)]
+    pub parent_id: Option<i32>,
+    /// Required when not signed in, ignored otherwise.
+    #[serde(default)]
+    pub author_name: Option<String>,
+    /// Required when not signed in, ignored otherwise. Only moderators see it.
+    #[serde(default)]
+    pub author_email: Option<String>,
+}
+
+impl CreateComment {
+    /// The trimmed content, and the name and email for anonymous comments.
+    pub fn validate(
+        &self,
+        signed_in: bool,
+    ) -> Result<(String, Option<String>, Option<String>), ServiceError> {
+        let content = self.content.trim();
+        if content.is_empty() || content.chars().count() > MAX_COMMENT_LEN {
+            return Err(ServiceError::BadRequest(format!(
+                "Comments must have 1 to {} characters",
+                MAX_COMMENT_LEN
+            )));
+        }
+        if signed_in {
+            return Ok((content.to_string(), None, None));
+        }
+
+        let name = self.author_name.as_deref().map(str::trim).unwrap_or("");
+        if name.is_empty() || name.chars().count() > MAX_COMMENTER_NAME {
+            return Err(ServiceError::BadRequest(format!(
+                "Anonymous comments need a name of 1 to {} characters",
+                MAX_COMMENTER_NAME
+            )));
+        }
+        let email = self.author_email.as_deref().map(str::trim).unwrap_or("");
+        let valid_email = email.len() <= MAX_COMMENTER_EMAIL
+            && email
+                .split_once('@')
+                .is_some_and(|(local, domain)| !local.is_empty() && domain.contains('.'));
+        if !valid_email {
+            return Err(ServiceError::BadRequest(
+                "Anonymous comments need a valid email".into(),
+            ));
+        }
+        Ok((
+            content.to_string(),
+            Some(name.to_string()),
+            Some(email.to_string()),
+        ))
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, Deserialize, ToSchema)]
+#[schema(example = json!({"status": "approved"}))]
+pub struct CommentStatusChange {
+    pub status: CommentStatus,
+}
+
+/// A comment with what moderators get to see of it.
+#[derive(Serialize, Debug, Clone, Deserialize, ToSchema, PostgresMapper)]
+#[pg_mapper(table = "comments")]
+pub struct ModeratedComment {
+    pub id: i32,
+    pub post_id: i32,
+    pub parent_id: Option<i32>,
+    pub author_id: Option<i32>,
+    pub author_name: String,
+    /// Email an anonymous commenter gave.
+    pub author_email: Option<String>,
+    pub content: String,
+    pub content_html: String,
+    pub status: String,
+    pub created_at: DateTime<Utc>,
+    /// The editor who last changed the status.
+    pub moderated_by: Option<i32>,
+    pub moderated_at: Option<DateTime<Utc>>,
+}
+
+/// The moderation queue is sorted by age, oldest first.
+pub const COMMENT_SORT: SortField = SortField {
+    name: "created",
+    expr: "c.created_at",
+    sql_type: "timestamptz",
+};
+pub const COMMENT_ORDER: SortOrder = SortOrder::Asc;
+
+#[derive(Deserialize, Debug, Clone, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CommentListQuery {
+    /// Only comments in this status, `pending` by default.
+    pub status: Option<CommentStatus>,
+    /// Only comments on this post.
+    pub post: Option<i32>,
+    /// `next_cursor` or `prev_cursor` of the page before.
+    pub cursor: Option<String>,
+    /// At most this many comments, 20 by default and at most 100.
+    pub limit: Option<i64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comment(id: i32, parent_id: Option<i32>) -> Comment {
+        Comment {
+            id,
+            parent_id,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_build_thread() {
+        let thread = build_thread(vec![
+            comment(1, None),
+            comment(2, Some(1)),
+            comment(3, None),
+            comment(4, Some(2)),
+            comment(5, Some(1)),
+            // Replies to a hidden comment, 6.
+            comment(7, Some(6)),
+            comment(8, Some(7)),
+        ]);
+        let ids = |nodes: &[CommentNode]| {
+            nodes
+                .iter()
+                .map(|node| node.comment.id)
+                .collect::<Vec<i32>>()
+        };
+        assert_eq!(ids(&thread), vec![1, 3]);
+        assert_eq!(ids(&thread[0].replies), vec![2, 5]);
+        assert_eq!(ids(&thread[0].replies[0].replies), vec![4]);
+        assert!(thread[1].replies.is_empty());
+    }
+
+    #[test]
+    fn test_validate() {
+        let anonymous = CreateComment {
+            content: "  Nice post!  ".into(),
+            parent_id: None,
+            author_name: Some(" Ann ".into()),
+            author_email: Some("ann@example.com".into()),
+        };
+        assert_eq!(
+            anonymous.validate(false).unwrap(),
+            (
+                "Nice post!".to_string(),
+                Some("Ann".to_string()),
+                Some("ann@example.com".to_string())
+            )
+        );
+        assert_eq!(anonymous.validate(true).unwrap().1, None);
+
+        let no_email = CreateComment {
+            author_email: Some("ann".into()),
+            ..anonymous.clone()
+        };
+        assert!(no_email.validate(false).is_err());
+        assert!(no_email.validate(true).is_ok());
+
+        let empty = CreateComment {
+            content: " \n ".into(),
+            ..anonymous
+        };
+        assert!(empty.validate(true).is_err());
+    }
+}
@@ -1,5 +1,6 @@
 use deadpool_postgres::{Config as PgConfig, PoolConfig};
 use std::env;
+use std::sync::OnceLock;
 
 use config::ConfigError;
 use serde::Deserialize;
@@ -12,6 +13,15 @@ pub struct SrvConfig {
     pub bcrypt_or_argon: bool,
     pub email_otp_enabled: bool,
     pub user_table_name: String,
+    #[serde(default = "default_session_table_name")]
+    pub session_table_name: String,
+    /// Schema holding every table of the service.
+    #[serde(default = "default_db_schema")]
+    pub db_schema: String,
+    /// Don't apply pending `migrations/` at startup, for deployments that apply them with
+    /// their own tooling. Render them with `migrate::render` first.
+    #[serde(default)]
+    pub skip_migrations: bool,
     pub smtp_host: String,
     pub smtp_port: u16,
     pub smtp_username: String,
@@ -19,6 +29,189 @@ pub struct SrvConfig {
     pub smtp_tls_off: bool,
     pub user_invalid_id: i32,
     pub max_otp_attempts: i32,
+    /// Base URL used when building links sent out by email.
+    /// Falls back to `http://{host}:{port}` when unset.
+    pub public_url: Option<String>,
+    /// Base URL of the frontend that links sent out by email lead to, with the token in a
+    /// `token` query parameter. The frontend shows a form and posts the token back to the
+    /// api. Pages linked to:
+    /// - `/accept-invite`, for `POST /invites/accept`
+    /// - `/reset-password`, for `POST /auth/password-reset`
+    /// - `/confirm-email`, for `POST /auth/me/email/confirm`
+    /// - `/not-me`, for `POST /auth/not-me`
+    ///
+    /// Falls back to `public_url`, for a frontend served from the same origin as the api.
+    pub frontend_url: Option<String>,
+    #[serde(default)]
+    pub registration_mode: RegistrationMode,
+    /// Comma separated origins allowed to make credentialed cross origin requests.
+    /// When unset every origin is allowed, which defeats CSRF protection: development only.
+    pub cors_allowed_origins: Option<String>,
+    /// How long a device stays trusted after "remember this device", in days.
+    #[serde(default = "default_trusted_device_days")]
+    pub trusted_device_days: i64,
+    /// Require the emailed code for logins from an unseen device, even when
+    /// `email_otp_enabled` is off.
+    #[serde(default)]
+    pub new_device_otp: bool,
+    /// Bearer token of the identity provider calling `/scim/v2`. SCIM is off when unset.
+    pub scim_token: Option<String>,
+    /// Comma separated authentication backends `process_login` tries in order: `local`
+    /// checks `hashed_password`, `ldap` binds to the directory below. Defaults to `local`.
+    pub auth_backends: Option<String>,
+    /// `ldaps://host:port` of the directory, or `ldap://host:port` to upgrade the
+    /// connection with StartTLS. Plain LDAP is never spoken. The directory's certificate
+    /// must chain to a CA in the system trust store.
+    pub ldap_url: Option<String>,
+    /// Account used to search for users, anonymous when unset.
+    pub ldap_bind_dn: Option<String>,
+    pub ldap_bind_password: Option<String>,
+    /// Subtree searched for user entries.
+    pub ldap_base_dn: Option<String>,
+    #[serde(default = "default_ldap_user_object_class")]
+    pub ldap_user_object_class: String,
+    /// Comma separated attributes compared to the login, any of them may match.
+    #[serde(default = "default_ldap_login_attributes")]
+    pub ldap_login_attributes: String,
+    #[serde(default = "default_ldap_email_attribute")]
+    pub ldap_email_attribute: String,
+    #[serde(default = "default_ldap_username_attribute")]
+    pub ldap_username_attribute: String,
+    #[serde(default = "default_ldap_display_name_attribute")]
+    pub ldap_display_name_attribute: String,
+    #[serde(default = "default_ldap_timeout_secs")]
+    pub ldap_timeout_secs: u64,
+    /// How far a signed service request's timestamp may be from now, in seconds.
+    #[serde(default = "default_signature_window_secs")]
+    pub signature_window_secs: i64,
+    /// PEM certificate chain and private key. With both set the server speaks HTTPS only.
+    pub tls_cert_file: Option<String>,
+    pub tls_key_file: Option<String>,
+    /// PEM bundle of the CAs client certificates are checked against. A certificate with
+    /// an email address signs in the user with that email, any other the service its
+    /// DNS name or common name is the name of.
+    pub tls_client_ca_file: Option<String>,
+    /// Refuse TLS connections without a valid client certificate.
+    #[serde(default)]
+    pub tls_client_cert_required: bool,
+    /// How often scheduled posts due for publishing are looked for, in seconds.
+    #[serde(default = "default_post_scheduler_interval_secs")]
+    pub post_scheduler_interval_secs: u64,
+    /// Text search configuration new posts are indexed with and searches use, unless the
+    /// post or the search names another one.
+    #[serde(default = "default_search_language")]
+    pub search_language: String,
+    /// Directory uploaded media and their thumbnails are stored in.
+    #[serde(default = "default_media_dir")]
+    pub media_dir: String,
+    /// Largest upload accepted, in bytes.
+    #[serde(default = "default_media_max_bytes")]
+    pub media_max_bytes: usize,
+    /// Title of the feeds, the tag or category is added to it in theirs.
+    #[serde(default = "default_feed_title")]
+    pub feed_title: String,
+}
+
+/// Whether anyone may call `register_user` or an invite token is required.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RegistrationMode {
+    #[default]
+    Open,
+    InviteOnly,
+}
+
+fn default_session_table_name() -> String {
+    "sessions".into()
+}
+
+fn default_db_schema() -> String {
+    "public".into()
+}
+
+fn default_trusted_device_days() -> i64 {
+    30
+}
+
+fn default_ldap_user_object_class() -> String {
+    "person".into()
+}
+
+fn default_ldap_login_attributes() -> String {
+    "uid,mail".into()
+}
+
+fn default_ldap_email_attribute() -> String {
+    "mail".into()
+}
+
+fn default_ldap_username_attribute() -> String {
+    "uid".into()
+}
+
+fn default_ldap_display_name_attribute() -> String {
+    "cn".into()
+}
+
+fn default_ldap_timeout_secs() -> u64 {
+    5
+}
+
+fn default_signature_window_secs() -> i64 {
+    300
+}
+
+fn default_post_scheduler_interval_secs() -> u64 {
+    30
+}
+
+fn default_search_language() -> String {
+    "english".into()
+}
+
+fn default_media_dir() -> String {
+    "media".into()
+}
+
+fn default_media_max_bytes() -> usize {
+    10 * 1024 * 1024
+}
+
+fn default_feed_title() -> String {
+    "Blog".into()
+}
+
+impl SrvConfig {
+    pub fn cors_allowed_origins(&self) -> Option<Vec<String>> {
+        self.cors_allowed_origins.as_ref().map(|origins| {
+            origins
+                .split(',')
+                .map(|origin| origin.trim().to_owned())
+                .filter(|origin| !origin.is_empty())
+                .collect()
+        })
+    }
+
+    pub fn public_url(&self) -> String {
+        match &self.public_url {
+            Some(url) => url.trim_end_matches('/').to_owned(),
+            None => format!("http://{}:{}", self.host, self.port),
+        }
+    }
+
+    /// Link to `page` of the frontend, carrying `token`.
+    pub fn frontend_link(&self, page: &str, token: &str) -> String {
+        let base = match &self.frontend_url {
+            Some(url) => url.trim_end_matches('/').to_owned(),
+            None => self.public_url(),
+        };
+        format!(
+            "{}{}?token={}",
+            base,
+            page,
+            url::form_urlencoded::byte_serialize(token.as_bytes()).collect::<String>()
+        )
+    }
 }
 
 #[derive(Deserialize, Clone)]
@@ -37,6 +230,132 @@ impl Config {
     }
 }
 
+/// Schema qualified table names used to build every query.
+///
+/// Names end up inside the SQL text, so they are checked by `valid_identifier` before use.
+#[derive(Debug, Clone)]
+pub struct DbNames {
+    pub users: String,
+    pub sessions: String,
+    pub posts: String,
+    pub categories: String,
+    pub tags: String,
+    pub posts_tags: String,
+    pub posts_categories: String,
+    pub invites: String,
+    pub password_resets: String,
+    pub impersonation_events: String,
+    pub recovery_codes: String,
+    pub trusted_devices: String,
+    pub email_changes: String,
+    pub login_fingerprints: String,
+    pub login_alerts: String,
+    pub service_keys: String,
+    pub post_events: String,
+    pub post_revisions: String,
+    pub post_slugs: String,
+    pub comments: String,
+    pub media: String,
+    pub posts_media: String,
+    /// Migrations applied so far, see `migrate`.
+    pub schema_migrations: String,
+}
+
+impl DbNames {
+    pub fn from_config(cnf: &SrvConfig) -> Result<DbNames, ConfigError> {
+        for name in [
+            &cnf.db_schema,
+            &cnf.user_table_name,
+            &cnf.session_table_name,
+        ] {
+            if !valid_identifier(name) {
+                return Err(ConfigError::Message(format!(
+                    "{:?} is not a valid schema or table name",
+                    name
+                )));
+            }
+        }
+
+        let table = |name: &str| format!("{}.{}", cnf.db_schema, name);
+        Ok(DbNames {
+            users: table(&cnf.user_table_name),
+            sessions: table(&cnf.session_table_name),
+            posts: table("posts"),
+            categories: table("categories"),
+            tags: table("tags"),
+            posts_tags: table("posts_tags"),
+            posts_categories: table("posts_categories"),
+            invites: table("invites"),
+            password_resets: table("password_resets"),
+            impersonation_events: table("impersonation_events"),
+            recovery_codes: table("recovery_codes"),
+            trusted_devices: table("trusted_devices"),
+            email_changes: table("email_changes"),
+            login_fingerprints: table("login_fingerprints"),
+            login_alerts: table("login_alerts"),
+            service_keys: table("service_keys"),
+            post_events: table("post_events"),
+            post_revisions: table("post_revisions"),
+            post_slugs: table("post_slugs"),
+            comments: table("comments"),
+            media: table("media"),
+            posts_media: table("posts_media"),
+            schema_migrations: table("schema_migrations"),
+        })
+    }
+
+    /// Every name by its field, the placeholders of the migrations.
+    pub fn tables(&self) -> Vec<(&'static str, &str)> {
+        vec![
+            ("users", &self.users),
+            ("sessions", &self.sessions),
+            ("posts", &self.posts),
+            ("categories", &self.categories),
+            ("tags", &self.tags),
+            ("posts_tags", &self.posts_tags),
+            ("posts_categories", &self.posts_categories),
+            ("invites", &self.invites),
+            ("password_resets", &self.password_resets),
+            ("impersonation_events", &self.impersonation_events),
+            ("recovery_codes", &self.recovery_codes),
+            ("trusted_devices", &self.trusted_devices),
+            ("email_changes", &self.email_changes),
+            ("login_fingerprints", &self.login_fingerprints),
+            ("login_alerts", &self.login_alerts),
+            ("service_keys", &self.service_keys),
+            ("post_events", &self.post_events),
+            ("post_revisions", &self.post_revisions),
+            ("post_slugs", &self.post_slugs),
+            ("comments", &self.comments),
+            ("media", &self.media),
+            ("posts_media", &self.posts_media),
+            ("schema_migrations", &self.schema_migrations),
+        ]
+    }
+}
+
+/// A plain, unquoted SQL identifier: a letter or underscore followed by letters, digits
+/// or underscores, at most 63 bytes like Postgres allows.
+pub fn valid_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    name.len() <= 63 && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+static DB_NAMES: OnceLock<DbNames> = OnceLock::new();
+
+/// Table names for the running configuration. `main` calls this at startup so that invalid
+/// names stop the server before it binds.
+pub fn db() -> &'static DbNames {
+    DB_NAMES.get_or_init(|| {
+        let config = Config::from_env().unwrap();
+        DbNames::from_config(&config.srv_cnf).expect("Invalid database configuration")
+    })
+}
+
 // impl Config {
 //     pub fn new() -> Config {
 //         let hex = env::var("SRV_CNF.SECRET_KEY").expect("SECRET_KEY not set");
@@ -113,3 +432,61 @@ impl Config {
 //         }
 //     }
 // }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A configuration with only the required settings, overridden by `settings`.
+    pub(crate) fn srv_config(settings: serde_json::Value) -> SrvConfig {
+        let mut config = serde_json::json!({
+            "host": "127.0.0.1",
+            "port": 8080,
+            "secret_key": "secret",
+            "bcrypt_or_argon": false,
+            "email_otp_enabled": false,
+            "user_table_name": "users",
+            "smtp_host": "localhost",
+            "smtp_port": 25,
+            "smtp_username": "",
+            "smtp_password": "",
+            "smtp_tls_off": true,
+            "user_invalid_id": -1,
+            "max_otp_attempts": 3,
+        });
+        config
+            .as_object_mut()
+            .unwrap()
+            .extend(settings.as_object().unwrap().clone());
+        serde_json::from_value(config).unwrap()
+    }
+
+    #[test]
+    fn test_frontend_link() {
+        let config = srv_config(serde_json::json!({}));
+        assert_eq!(
+            config.frontend_link("/accept-invite", "abc"),
+            "http://127.0.0.1:8080/accept-invite?token=abc"
+        );
+        let config = srv_config(serde_json::json!({
+            "public_url": "https://api.example.com/",
+            "frontend_url": "https://example.com/app/",
+        }));
+        assert_eq!(
+            config.frontend_link("/accept-invite", "a b&c"),
+            "https://example.com/app/accept-invite?token=a+b%26c"
+        );
+    }
+
+    #[test]
+    fn test_valid_identifier() {
+        assert!(valid_identifier("users"));
+        assert!(valid_identifier("_app_users2"));
+        assert!(!valid_identifier(""));
+        assert!(!valid_identifier("2users"));
+        assert!(!valid_identifier("users; DROP TABLE users"));
+        assert!(!valid_identifier("public.users"));
+        assert!(!valid_identifier("\"users\""));
+        assert!(!valid_identifier(&"a".repeat(64)));
+    }
+}
@@ -74,6 +74,9 @@ pub enum ServiceError {
     FaultySetup(String),
     DatabaseError(String),
     Unauthorized,
+    Forbidden(String),
+    PayloadTooLarge(String),
+    UnsupportedMediaType(String),
 }
 
 impl ResponseError for ServiceError {
@@ -83,7 +86,7 @@ impl ResponseError for ServiceError {
 
             ServiceError::NotFound(ref message) => HttpResponse::NotFound().json(message),
             ServiceError::BadRequest(ref message) => HttpResponse::BadRequest().json(message),
-            ServiceError::Conflict(ref message) => HttpResponse::BadRequest().json(message),
+            ServiceError::Conflict(ref message) => HttpResponse::Conflict().json(message),
 
             ServiceError::ProcessError(ref message) => {
                 HttpResponse::InternalServerError().json(message)
@@ -121,6 +124,13 @@ impl ResponseError for ServiceError {
                 HttpResponse::InternalServerError().json(err.to_string())
             }
             ServiceError::Unauthorized => HttpResponse::Unauthorized().json("UnAuthorized"),
+            ServiceError::Forbidden(ref message) => HttpResponse::Forbidden().json(message),
+            ServiceError::PayloadTooLarge(ref message) => {
+                HttpResponse::PayloadTooLarge().json(message)
+            }
+            ServiceError::UnsupportedMediaType(ref message) => {
+                HttpResponse::UnsupportedMediaType().json(message)
+            }
         }
     }
 }
@@ -245,6 +255,9 @@ impl fmt::Display for ServiceError {
             ServiceError::FaultySetup(ref cause) => write!(f, "Setup Error: {}", cause),
             ServiceError::DatabaseError(ref cause) => write!(f, "Setup Error: {}", cause),
             ServiceError::Unauthorized => write!(f, "User doesn't have access"),
+            ServiceError::Forbidden(ref err) => err.fmt(f),
+            ServiceError::PayloadTooLarge(ref err) => err.fmt(f),
+            ServiceError::UnsupportedMediaType(ref err) => err.fmt(f),
         }
     }
 }
@@ -271,6 +284,9 @@ impl StdError for ServiceError {
             ServiceError::PoolError(_) => "Pool Error",
             ServiceError::FaultySetup(_) => "Faulty Setup Error",
             ServiceError::DatabaseError(_) => "Database Error",
+            ServiceError::Forbidden(_) => "Forbidden",
+            ServiceError::PayloadTooLarge(_) => "Payload Too Large",
+            ServiceError::UnsupportedMediaType(_) => "Unsupported Media Type",
         }
     }
 }
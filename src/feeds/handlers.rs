@@ -0,0 +1,238 @@
+use crate::category;
+use crate::configs;
+use crate::errors::ServiceError;
+use crate::feeds::models::{Entry, Feed, FeedQuery, FEED_LENGTH};
+use crate::posts;
+use crate::tags;
+
+use actix_web::http::header::{
+    ContentType, ETag, EntityTag, HttpDate, IfModifiedSince, IfNoneMatch, LastModified,
+};
+use actix_web::web::Query;
+use actix_web::{get, web, HttpMessage, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use deadpool_postgres::{Client, Pool};
+use sha2::{Digest, Sha256};
+use std::time::SystemTime;
+
+/// Whether the copy the client has, going by the conditional headers it sent, is still
+/// current. `If-None-Match` wins over `If-Modified-Since` when both are sent.
+fn not_modified(req: &HttpRequest, etag: &EntityTag, last_modified: Option<DateTime<Utc>>) -> bool {
+    if let Some(if_none_match) = req.get_header::<IfNoneMatch>() {
+        return match if_none_match {
+            IfNoneMatch::Any => true,
+            IfNoneMatch::Items(tags) => tags.iter().any(|tag| tag.weak_eq(etag)),
+        };
+    }
+    match (req.get_header::<IfModifiedSince>(), last_modified) {
+        // HTTP dates have no fractions of seconds.
+        (Some(IfModifiedSince(since)), Some(modified)) => {
+            DateTime::<Utc>::from(SystemTime::from(since)).timestamp() >= modified.timestamp()
+        }
+        _ => false,
+    }
+}
+
+/// Renders the feed of `posts`, or answers 304 when the client has it already.
+fn feed_response(
+    req: &HttpRequest,
+    query: &FeedQuery,
+    title: String,
+    link: String,
+    posts: &[posts::Post],
+) -> HttpResponse {
+    let base_url = configs::Config::from_env().unwrap().srv_cnf.public_url();
+    let format = query.format.unwrap_or_default();
+    let feed = Feed {
+        title,
+        link: format!("{}{}", base_url, link),
+        self_link: format!("{}{}", base_url, req.uri()),
+        entries: posts
+            .iter()
+            .map(|post| Entry::new(post, &base_url))
+            .collect(),
+    };
+    let body = feed.render(format);
+    let etag = EntityTag::new_strong(hex::encode(Sha256::digest(body.as_bytes())));
+    let last_modified = feed.updated();
+
+    let fresh = not_modified(req, &etag, last_modified);
+    let mut response = if fresh {
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+    response.insert_header(ETag(etag));
+    if let Some(updated) = last_modified {
+        response.insert_header(LastModified(HttpDate::from(SystemTime::from(updated))));
+    }
+    if fresh {
+        response.finish()
+    } else {
+        response
+            .insert_header(ContentType(format.content_type().parse().unwrap()))
+            .body(body)
+    }
+}
+
+fn feed_title(topic: Option<&str>) -> String {
+    let title = configs::Config::from_env().unwrap().srv_cnf.feed_title;
+    match topic {
+        Some(topic) => format!("{}: {}", title, topic),
+        None => title,
+    }
+}
+
+/// Feed of the latest published posts.
+///
+/// RSS 2.0 unless `format=atom` asks for Atom 1.0. Answers conditional requests with
+/// `ETag` and `Last-Modified`, the latest `modified_date` of the posts listed.
+#[utoipa::path(
+    responses(
+        (status = 200, description = "The feed", body = String, content_type = "application/rss+xml"),
+        (status = 304, description = "The cached copy is current")
+    ),
+    params(FeedQuery)
+)]
+#[get("/feed.xml")]
+pub async fn site_feed(
+    req: HttpRequest,
+    query: Query<FeedQuery>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let client: Client = db_pool.get().await?;
+
+    let posts = posts::db::post_feed(&client, None, None, FEED_LENGTH).await?;
+    Ok(feed_response(
+        &req,
+        &query,
+        feed_title(None),
+        "/posts".into(),
+        &posts,
+    ))
+}
+
+/// Feed of the latest published posts with a tag.
+///
+/// Takes the same formats and conditional requests as `GET /feed.xml`.
+#[utoipa::path(
+    context_path = "/tags",
+    responses(
+        (status = 200, description = "The feed", body = String, content_type = "application/rss+xml"),
+        (status = 304, description = "The cached copy is current"),
+        (status = 404, description = "Tag not found", body = ServiceError)
+    ),
+    params(
+        ("id", description = "Unique Tag Id"),
+        FeedQuery
+    )
+)]
+#[get("/{id}/feed.xml")]
+pub async fn tag_feed(
+    req: HttpRequest,
+    id_tags: web::Path<(i32,)>,
+    query: Query<FeedQuery>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let client: Client = db_pool.get().await?;
+
+    let tag = tags::db::tags_id(&client, id_tags.0)
+        .await
+        .map_err(|_| ServiceError::NotFound("Tag not found".into()))?;
+    let posts = posts::db::post_feed(&client, Some(tag.id), None, FEED_LENGTH).await?;
+    Ok(feed_response(
+        &req,
+        &query,
+        feed_title(Some(&tag.name)),
+        format!("/tags/{}/posts", tag.id),
+        &posts,
+    ))
+}
+
+/// Feed of the latest published posts filed under a category or its subcategories.
+///
+/// Takes the same formats and conditional requests as `GET /feed.xml`.
+#[utoipa::path(
+    context_path = "/categories",
+    responses(
+        (status = 200, description = "The feed", body = String, content_type = "application/rss+xml"),
+        (status = 304, description = "The cached copy is current"),
+        (status = 404, description = "Category not found", body = ServiceError)
+    ),
+    params(
+        ("id", description = "Unique Category Id"),
+        FeedQuery
+    )
+)]
+#[get("/{id}/feed.xml")]
+pub async fn category_feed(
+    req: HttpRequest,
+    id_category: web::Path<(i32,)>,
+    query: Query<FeedQuery>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let client: Client = db_pool.get().await?;
+
+    let category = category::db::category_id(&client, id_category.0)
+        .await
+        .map_err(|_| ServiceError::NotFound("Category not found".into()))?;
+    let posts = posts::db::post_feed(&client, None, Some(category.id), FEED_LENGTH).await?;
+    Ok(feed_response(
+        &req,
+        &query,
+        feed_title(Some(&category.name)),
+        format!("/categories/{}/posts", category.id),
+        &posts,
+    ))
+}
+
+/// Routes under `/tags`.
+pub fn init_tag_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(tag_feed);
+}
+
+/// Routes under `/categories`.
+pub fn init_category_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(category_feed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_not_modified() {
+        let etag = EntityTag::new_strong("abc".into());
+        let modified = Utc.with_ymd_and_hms(2024, 3, 3, 12, 0, 0).unwrap();
+        let check = |headers: &[(&str, &str)]| {
+            let req = headers
+                .iter()
+                .fold(TestRequest::default(), |req, header| {
+                    req.insert_header(*header)
+                })
+                .to_http_request();
+            not_modified(&req, &etag, Some(modified))
+        };
+
+        assert!(!check(&[]));
+        assert!(check(&[("If-None-Match", "\"abc\"")]));
+        assert!(check(&[("If-None-Match", "W/\"abc\", \"def\"")]));
+        assert!(check(&[("If-None-Match", "*")]));
+        assert!(!check(&[("If-None-Match", "\"def\"")]));
+        assert!(check(&[(
+            "If-Modified-Since",
+            "Sun, 03 Mar 2024 12:00:00 GMT"
+        )]));
+        assert!(!check(&[(
+            "If-Modified-Since",
+            "Sun, 03 Mar 2024 11:59:59 GMT"
+        )]));
+        // A stale tag means changed, whatever the date says.
+        assert!(!check(&[
+            ("If-None-Match", "\"def\""),
+            ("If-Modified-Since", "Sun, 03 Mar 2024 12:00:00 GMT")
+        ]));
+    }
+}
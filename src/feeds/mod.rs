@@ -0,0 +1,4 @@
+pub mod handlers;
+pub mod models;
+pub use crate::feeds::handlers::*;
+pub use crate::feeds::models::*;
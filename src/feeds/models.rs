@@ -0,0 +1,325 @@
+use chrono::{DateTime, SecondsFormat, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::posts::Post;
+
+/// Posts listed in a feed, the latest published first.
+pub const FEED_LENGTH: i64 = 20;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedFormat {
+    /// RSS 2.0
+    #[default]
+    Rss,
+    /// Atom 1.0
+    Atom,
+}
+
+impl FeedFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            FeedFormat::Rss => "application/rss+xml; charset=utf-8",
+            FeedFormat::Atom => "application/atom+xml; charset=utf-8",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FeedQuery {
+    /// `rss` unless asked otherwise.
+    pub format: Option<FeedFormat>,
+}
+
+/// A post as listed in a feed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Stable however the post is renamed.
+    pub id: String,
+    pub title: String,
+    pub link: String,
+    pub author: Option<String>,
+    pub published: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+    /// Names of its tags and categories.
+    pub categories: Vec<String>,
+    pub summary: String,
+    /// Rendered HTML.
+    pub content: String,
+}
+
+impl Entry {
+    /// The entry of `post`, with links under `base_url`.
+    pub fn new(post: &Post, base_url: &str) -> Entry {
+        Entry {
+            id: format!("{}/posts/{}", base_url, post.id),
+            title: post.title.clone(),
+            link: format!("{}/posts/by-slug/{}", base_url, post.slug),
+            author: post.author_username.clone(),
+            published: post.published_at.unwrap_or(post.submitted_date),
+            updated: post.modified_date,
+            categories: post
+                .tags
+                .0
+                .iter()
+                .map(|tag| tag.name.clone())
+                .chain(
+                    post.categories
+                        .0
+                        .iter()
+                        .map(|category| category.name.clone()),
+                )
+                .collect(),
+            summary: post.summary.clone(),
+            content: post.content_html.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Feed {
+    pub title: String,
+    /// Where the posts are listed.
+    pub link: String,
+    /// Where the feed itself is, in the format it is rendered in.
+    pub self_link: String,
+    pub entries: Vec<Entry>,
+}
+
+impl Feed {
+    /// When a post of the feed last changed, `None` for an empty feed.
+    pub fn updated(&self) -> Option<DateTime<Utc>> {
+        self.entries.iter().map(|entry| entry.updated).max()
+    }
+
+    pub fn render(&self, format: FeedFormat) -> String {
+        match format {
+            FeedFormat::Rss => self.rss(),
+            FeedFormat::Atom => self.atom(),
+        }
+    }
+
+    fn rss(&self) -> String {
+        let mut xml = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+            <rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\" \
+            xmlns:content=\"http://purl.org/rss/1.0/modules/content/\" \
+            xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n<channel>\n",
+        );
+        let _ = write!(
+            xml,
+            "<title>{title}</title>\n<link>{}</link>\n<description>{title}</description>\n\
+            <atom:link href=\"{}\" rel=\"self\" type=\"application/rss+xml\"/>\n",
+            escape_xml(&self.link),
+            escape_xml(&self.self_link),
+            title = escape_xml(&self.title)
+        );
+        if let Some(updated) = self.updated() {
+            let _ = writeln!(
+                xml,
+                "<lastBuildDate>{}</lastBuildDate>",
+                updated.to_rfc2822()
+            );
+        }
+        for entry in &self.entries {
+            let _ = write!(
+                xml,
+                "<item>\n<title>{}</title>\n<link>{}</link>\n\
+                <guid isPermaLink=\"false\">{}</guid>\n<pubDate>{}</pubDate>\n",
+                escape_xml(&entry.title),
+                escape_xml(&entry.link),
+                escape_xml(&entry.id),
+                entry.published.to_rfc2822()
+            );
+            if let Some(author) = &entry.author {
+                let _ = writeln!(xml, "<dc:creator>{}</dc:creator>", escape_xml(author));
+            }
+            for category in &entry.categories {
+                let _ = writeln!(xml, "<category>{}</category>", escape_xml(category));
+            }
+            // Readers show the description when they don't take the full content.
+            let description = match entry.summary.trim() {
+                "" => escape_xml(&entry.content),
+                summary => escape_xml(summary),
+            };
+            let _ = write!(
+                xml,
+                "<description>{}</description>\n\
+                <content:encoded>{}</content:encoded>\n</item>\n",
+                description,
+                escape_xml(&entry.content)
+            );
+        }
+        xml.push_str("</channel>\n</rss>\n");
+        xml
+    }
+
+    fn atom(&self) -> String {
+        let mut xml = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+            <feed xmlns=\"http://www.w3.org/2005/Atom\">\n",
+        );
+        // Atom wants a date even without entries.
+        let updated = self
+            .updated()
+            .unwrap_or_else(|| Utc.timestamp_opt(0, 0).unwrap());
+        // Entries without an author fall back on the feed's.
+        let _ = write!(
+            xml,
+            "<title>{title}</title>\n<id>{self_link}</id>\n\
+            <link rel=\"self\" type=\"application/atom+xml\" href=\"{self_link}\"/>\n\
+            <link rel=\"alternate\" href=\"{}\"/>\n<updated>{}</updated>\n\
+            <author><name>{title}</name></author>\n",
+            escape_xml(&self.link),
+            atom_date(updated),
+            title = escape_xml(&self.title),
+            self_link = escape_xml(&self.self_link)
+        );
+        for entry in &self.entries {
+            let _ = write!(
+                xml,
+                "<entry>\n<title>{}</title>\n<id>{}</id>\n\
+                <link rel=\"alternate\" href=\"{}\"/>\n\
+                <published>{}</published>\n<updated>{}</updated>\n",
+                escape_xml(&entry.title),
+                escape_xml(&entry.id),
+                escape_xml(&entry.link),
+                atom_date(entry.published),
+                atom_date(entry.updated)
+            );
+            if let Some(author) = &entry.author {
+                let _ = writeln!(xml, "<author><name>{}</name></author>", escape_xml(author));
+            }
+            for category in &entry.categories {
+                let _ = writeln!(xml, "<category term=\"{}\"/>", escape_xml(category));
+            }
+            if !entry.summary.trim().is_empty() {
+                let _ = writeln!(
+                    xml,
+                    "<summary type=\"text\">{}</summary>",
+                    escape_xml(entry.summary.trim())
+                );
+            }
+            let _ = write!(
+                xml,
+                "<content type=\"html\">{}</content>\n</entry>\n",
+                escape_xml(&entry.content)
+            );
+        }
+        xml.push_str("</feed>\n");
+        xml
+    }
+}
+
+fn atom_date(date: DateTime<Utc>) -> String {
+    date.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// `text` safe in XML text and attribute values. Characters XML 1.0 doesn't allow at all,
+/// such as most control characters, are dropped.
+pub fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            '\u{0}'..='\u{1f}' | '\u{fffe}' | '\u{ffff}' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed() -> Feed {
+        let date = |day| Utc.with_ymd_and_hms(2024, 3, day, 12, 0, 0).unwrap();
+        Feed {
+            title: "Tom & Jerry's".into(),
+            link: "https://example.com/posts".into(),
+            self_link: "https://example.com/feed.xml?format=atom&x=1".into(),
+            entries: vec![
+                Entry {
+                    id: "https://example.com/posts/2".into(),
+                    title: "<b>Bold</b> claims".into(),
+                    link: "https://example.com/posts/by-slug/bold".into(),
+                    author: Some("ann".into()),
+                    published: date(1),
+                    updated: date(3),
+                    categories: vec!["rust".into()],
+                    summary: "".into(),
+                    content: "<p>Hi & bye</p>".into(),
+                },
+                Entry {
+                    id: "https://example.com/posts/1".into(),
+                    title: "Older".into(),
+                    link: "https://example.com/posts/by-slug/older".into(),
+                    author: None,
+                    published: date(2),
+                    updated: date(2),
+                    categories: vec![],
+                    summary: "Short".into(),
+                    content: "<p>Long</p>".into(),
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_escape_xml() {
+        assert_eq!(
+            escape_xml("<a href=\"x\">Tom & 'Jerry'</a>"),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; &apos;Jerry&apos;&lt;/a&gt;"
+        );
+        assert_eq!(escape_xml("a\u{0}b\u{1b}c\td\u{ffff}"), "abc\td");
+        assert_eq!(escape_xml("héllo 🦀"), "héllo 🦀");
+    }
+
+    #[test]
+    fn test_rss() {
+        let xml = feed().render(FeedFormat::Rss);
+        assert!(xml.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<rss version=\"2.0\""));
+        assert!(xml.contains("<title>Tom &amp; Jerry&apos;s</title>"));
+        assert!(xml.contains("href=\"https://example.com/feed.xml?format=atom&amp;x=1\""));
+        assert!(xml.contains("<lastBuildDate>Sun, 03 Mar 2024 12:00:00 +0000</lastBuildDate>"));
+        assert!(xml.contains("<title>&lt;b&gt;Bold&lt;/b&gt; claims</title>"));
+        assert!(xml.contains("<pubDate>Fri, 01 Mar 2024 12:00:00 +0000</pubDate>"));
+        assert!(xml.contains("<dc:creator>ann</dc:creator>"));
+        // Without a summary the content stands in for it.
+        assert!(xml.contains("<description>&lt;p&gt;Hi &amp; bye&lt;/p&gt;</description>"));
+        assert!(xml.contains("<description>Short</description>"));
+        assert_eq!(xml.matches("<item>").count(), 2);
+        assert!(xml.ends_with("</channel>\n</rss>\n"));
+    }
+
+    #[test]
+    fn test_atom() {
+        let xml = feed().render(FeedFormat::Atom);
+        assert!(xml.contains("<feed xmlns=\"http://www.w3.org/2005/Atom\">"));
+        assert!(xml.contains("<updated>2024-03-03T12:00:00Z</updated>"));
+        assert!(xml.contains("<published>2024-03-01T12:00:00Z</published>"));
+        assert!(xml.contains("<category term=\"rust\"/>"));
+        assert!(xml.contains("<content type=\"html\">&lt;p&gt;Hi &amp; bye&lt;/p&gt;</content>"));
+        assert!(xml.contains("<summary type=\"text\">Short</summary>"));
+        assert_eq!(xml.matches("<entry>").count(), 2);
+        assert_eq!(xml.matches("<author>").count(), 2);
+
+        let empty = Feed {
+            entries: vec![],
+            ..feed()
+        };
+        assert_eq!(empty.updated(), None);
+        assert!(empty
+            .render(FeedFormat::Atom)
+            .contains("<updated>1970-01-01T00:00:00Z</updated>"));
+    }
+}
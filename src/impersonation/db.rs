@@ -0,0 +1,77 @@
+use crate::configs::db;
+use crate::errors::ServiceError;
+use crate::impersonation::ImpersonationEvent;
+use deadpool_postgres::Client;
+use tokio_pg_mapper::FromTokioPostgresRow;
+
+pub async fn session_impersonation_set(
+    client: &Client,
+    session_id: i32,
+    user_id: Option<i32>,
+    read_only: bool,
+) -> Result<(), ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "UPDATE {sessions} SET impersonated_user_id = $1, impersonation_read_only = $2
+            WHERE id = $3",
+            sessions = db().sessions
+        ))
+        .await?;
+
+    match client
+        .execute(&statement, &[&user_id, &read_only, &session_id])
+        .await?
+    {
+        1 => Ok(()),
+        _ => Err(ServiceError::Unauthorized),
+    }
+}
+
+pub async fn impersonation_event_add(
+    client: &Client,
+    admin_id: i32,
+    user_id: i32,
+    session_id: i32,
+    action: &str,
+    method: Option<&str>,
+    path: Option<&str>,
+) -> Result<(), ServiceError> {
+    let statement = client
+        .prepare(&format!("INSERT INTO {impersonation_events} (admin_id, user_id, session_id, action, method, path)
+            VALUES ($1, $2, $3, $4, $5, $6)", impersonation_events = db().impersonation_events),
+        )
+        .await?;
+
+    client
+        .execute(
+            &statement,
+            &[&admin_id, &user_id, &session_id, &action, &method, &path],
+        )
+        .await?;
+    Ok(())
+}
+
+pub async fn impersonation_event_list(
+    client: &Client,
+    user_id: Option<i32>,
+    admin_id: Option<i32>,
+) -> Result<Vec<ImpersonationEvent>, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "SELECT id, admin_id, user_id, session_id, action, method, path, created_at
+            FROM {impersonation_events}
+            WHERE ($1::int IS NULL OR user_id = $1) AND ($2::int IS NULL OR admin_id = $2)
+            ORDER BY id DESC LIMIT 500",
+            impersonation_events = db().impersonation_events
+        ))
+        .await?;
+
+    let events = client
+        .query(&statement, &[&user_id, &admin_id])
+        .await?
+        .iter()
+        .map(|row| ImpersonationEvent::from_row_ref(row).unwrap())
+        .collect::<Vec<ImpersonationEvent>>();
+
+    Ok(events)
+}
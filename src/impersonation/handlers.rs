@@ -0,0 +1,128 @@
+use crate::auth::{find_user_by_id, find_user_by_session, AdminUser, Role, Session};
+use crate::errors::ServiceError;
+use crate::impersonation::db;
+use crate::impersonation::models::{ImpersonationEventQuery, StartImpersonation};
+
+use actix_web::web::Query;
+use actix_web::{get, post, web, HttpResponse};
+use deadpool_postgres::{Client, Pool};
+
+/// Start impersonating a user.
+///
+/// Until stopped, the admin's session acts as the given user. Admins and disabled users
+/// can't be impersonated. Every start, stop and write is recorded in the audit trail.
+#[utoipa::path(
+    context_path = "/impersonation",
+    request_body = StartImpersonation,
+    responses(
+        (status = 200, description = "Now acting as the user"),
+        (status = 400, description = "User can't be impersonated", body = ServiceError),
+        (status = 403, description = "Admin role required", body = ServiceError),
+        (status = 404, description = "User not found", body = ServiceError)
+    )
+)]
+#[post("/start")]
+pub async fn start_impersonation(
+    admin: AdminUser,
+    local_object: web::Json<StartImpersonation>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let session_id = admin.0.session_id.ok_or_else(|| {
+        ServiceError::BadRequest("Impersonation needs a signed in session".into())
+    })?;
+    let client: Client = db_pool.get().await?;
+
+    let user = find_user_by_id(&client, local_object.user_id).await?;
+    if user.id == admin.0.id || user.role.parse::<Role>()? == Role::Admin || user.disabled {
+        return Err(ServiceError::BadRequest(
+            "This user can't be impersonated".into(),
+        ));
+    }
+
+    let read_only = local_object.block_writes.unwrap_or(true);
+    db::session_impersonation_set(&client, session_id, Some(user.id), read_only).await?;
+    db::impersonation_event_add(
+        &client, admin.0.id, user.id, session_id, "start", None, None,
+    )
+    .await?;
+    log::warn!(
+        "admin {} started impersonating user {} (writes blocked: {})",
+        admin.0.id,
+        user.id,
+        read_only
+    );
+
+    Ok(HttpResponse::Ok().json(user))
+}
+
+/// Stop impersonating and return to the admin's own identity.
+#[utoipa::path(
+    context_path = "/impersonation",
+    responses(
+        (status = 200, description = "Impersonation stopped"),
+        (status = 400, description = "Not impersonating anyone", body = ServiceError),
+        (status = 401, description = "Not signed in", body = ServiceError)
+    )
+)]
+#[post("/stop")]
+pub async fn stop_impersonation(
+    session: Session,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let client: Client = db_pool.get().await?;
+
+    let user_session = find_user_by_session(&client, session)
+        .await
+        .ok_or(ServiceError::Unauthorized)?;
+    let user_id = user_session
+        .impersonated_user_id
+        .ok_or_else(|| ServiceError::BadRequest("Not impersonating anyone".into()))?;
+
+    db::session_impersonation_set(&client, user_session.id, None, true).await?;
+    db::impersonation_event_add(
+        &client,
+        user_session.user_id,
+        user_id,
+        user_session.id,
+        "stop",
+        None,
+        None,
+    )
+    .await?;
+    log::warn!(
+        "admin {} stopped impersonating user {}",
+        user_session.user_id,
+        user_id
+    );
+
+    Ok(HttpResponse::Ok().json("Impersonation stopped"))
+}
+
+/// Impersonation audit trail, newest first.
+#[utoipa::path(
+    context_path = "/impersonation",
+    params(
+        ImpersonationEventQuery
+    ),
+    responses(
+        (status = 200, description = "Audit trail", body = [ImpersonationEvent]),
+        (status = 403, description = "Admin role required", body = ServiceError)
+    )
+)]
+#[get("/events")]
+pub async fn impersonation_events(
+    _admin: AdminUser,
+    query: Query<ImpersonationEventQuery>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let client: Client = db_pool.get().await?;
+
+    let events = db::impersonation_event_list(&client, query.user_id, query.admin_id).await?;
+    Ok(HttpResponse::Ok().json(events))
+}
+
+pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(start_impersonation);
+    cfg.service(stop_impersonation);
+    cfg.service(impersonation_events);
+}
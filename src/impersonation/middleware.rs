@@ -0,0 +1,227 @@
+use std::future::{self, Ready};
+use std::rc::Rc;
+
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::http::Method;
+use actix_web::{web, FromRequest, HttpResponse};
+use deadpool_postgres::Pool;
+use futures::future::LocalBoxFuture;
+
+use crate::auth::{find_user_by_session, Session, UserSession};
+use crate::impersonation::db;
+
+/// Set on every response while impersonating, holds the impersonated user's id.
+pub const IMPERSONATING_HEADER: &str = "x-impersonating-user";
+/// Set on every response while impersonating, holds the admin's id.
+pub const IMPERSONATOR_HEADER: &str = "x-impersonator";
+
+/// Must stay reachable even when writes are blocked.
+const STOP_PATH: &str = "/impersonation/stop";
+
+/// Tags responses of impersonated sessions, logs their requests and refuses writes when the
+/// impersonation was started with `block_writes`.
+///
+/// Needs the identity middleware, so it has to be registered before it.
+pub struct ImpersonationGuard;
+
+impl<S, B> Transform<S, ServiceRequest> for ImpersonationGuard
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = ImpersonationMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        future::ready(Ok(ImpersonationMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct ImpersonationMiddleware<S> {
+    service: Rc<S>,
+}
+
+async fn active_impersonation(pool: &Pool, session: Session) -> Option<UserSession> {
+    let client = pool.get().await.ok()?;
+    find_user_by_session(&client, session)
+        .await
+        .filter(|user_session| user_session.impersonated_user_id.is_some())
+}
+
+/// What an impersonated request is recorded as: nothing for reads and for stopping,
+/// otherwise `blocked` when the impersonation is read only and `write` when it isn't.
+fn write_action(method: &Method, path: &str, read_only: bool) -> Option<&'static str> {
+    let is_write = !matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS);
+    match (is_write && path != STOP_PATH, read_only) {
+        (false, _) => None,
+        (true, true) => Some("blocked"),
+        (true, false) => Some("write"),
+    }
+}
+
+fn tag_response<B>(response: &mut ServiceResponse<B>, user_session: &UserSession) {
+    let headers = response.headers_mut();
+    if let Some(user_id) = user_session.impersonated_user_id {
+        headers.insert(
+            HeaderName::from_static(IMPERSONATING_HEADER),
+            HeaderValue::from(user_id),
+        );
+    }
+    headers.insert(
+        HeaderName::from_static(IMPERSONATOR_HEADER),
+        HeaderValue::from(user_session.user_id),
+    );
+}
+
+impl<S, B> Service<ServiceRequest> for ImpersonationMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, actix_web::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let session = Session::from_request(req.request(), &mut Payload::None)
+            .into_inner()
+            .ok();
+        let pool = req.app_data::<web::Data<Pool>>().cloned();
+
+        Box::pin(async move {
+            let user_session = match (session, pool.as_ref()) {
+                (Some(session), Some(pool)) => active_impersonation(pool, session).await,
+                _ => None,
+            };
+            let (user_session, pool) = match (user_session, pool) {
+                (Some(user_session), Some(pool)) => (user_session, pool),
+                _ => {
+                    return service
+                        .call(req)
+                        .await
+                        .map(ServiceResponse::map_into_left_body)
+                }
+            };
+            let user_id = user_session.impersonated_user_id.unwrap_or_default();
+
+            let method = req.method().clone();
+            let path = req.path().to_owned();
+            log::info!(
+                "admin {} as user {}: {} {}",
+                user_session.user_id,
+                user_id,
+                method,
+                path
+            );
+
+            if let Some(action) = write_action(&method, &path, user_session.impersonation_read_only)
+            {
+                if let Ok(client) = pool.get().await {
+                    if let Err(e) = db::impersonation_event_add(
+                        &client,
+                        user_session.user_id,
+                        user_id,
+                        user_session.id,
+                        action,
+                        Some(method.as_str()),
+                        Some(&path),
+                    )
+                    .await
+                    {
+                        log::error!("could not record impersonation event: {}", e);
+                    }
+                }
+
+                if action == "blocked" {
+                    let mut response = req.into_response(
+                        HttpResponse::Forbidden()
+                            .json("Write operations are blocked while impersonating"),
+                    );
+                    tag_response(&mut response, &user_session);
+                    return Ok(response.map_into_right_body());
+                }
+            }
+
+            let mut response = service.call(req).await?;
+            tag_response(&mut response, &user_session);
+            Ok(response.map_into_left_body())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_identity::IdentityMiddleware;
+    use actix_session::{storage::CookieSessionStore, SessionMiddleware};
+    use actix_web::cookie::Key;
+    use actix_web::test::{self, TestRequest};
+    use actix_web::App;
+
+    #[test]
+    fn test_write_action() {
+        assert_eq!(write_action(&Method::GET, "/posts/", true), None);
+        assert_eq!(write_action(&Method::HEAD, "/posts/", true), None);
+        assert_eq!(write_action(&Method::OPTIONS, "/posts/", false), None);
+        assert_eq!(
+            write_action(&Method::POST, "/posts/", true),
+            Some("blocked")
+        );
+        assert_eq!(
+            write_action(&Method::DELETE, "/posts/1", true),
+            Some("blocked")
+        );
+        assert_eq!(
+            write_action(&Method::PATCH, "/posts/1", false),
+            Some("write")
+        );
+        // Stopping always goes through and is recorded by the handler.
+        assert_eq!(write_action(&Method::POST, STOP_PATH, true), None);
+    }
+
+    #[test]
+    fn test_tag_response() {
+        let user_session = UserSession {
+            user_id: 1,
+            impersonated_user_id: Some(2),
+            ..Default::default()
+        };
+        let mut response = TestRequest::default().to_srv_response(HttpResponse::Ok().finish());
+        tag_response(&mut response, &user_session);
+        assert_eq!(response.headers().get(IMPERSONATING_HEADER).unwrap(), "2");
+        assert_eq!(response.headers().get(IMPERSONATOR_HEADER).unwrap(), "1");
+    }
+
+    /// Requests outside an impersonated session pass untouched, writes included.
+    #[actix_web::test]
+    async fn test_guard_without_impersonation() {
+        let app = test::init_service(
+            App::new()
+                .wrap(ImpersonationGuard)
+                .wrap(IdentityMiddleware::default())
+                .wrap(SessionMiddleware::new(
+                    CookieSessionStore::default(),
+                    Key::generate(),
+                ))
+                .route("/write", web::post().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let response =
+            test::call_service(&app, TestRequest::post().uri("/write").to_request()).await;
+        assert!(response.status().is_success());
+        assert!(response.headers().get(IMPERSONATOR_HEADER).is_none());
+    }
+}
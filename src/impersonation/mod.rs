@@ -0,0 +1,8 @@
+pub mod db;
+pub mod handlers;
+pub mod middleware;
+pub mod models;
+pub use crate::impersonation::db::*;
+pub use crate::impersonation::handlers::*;
+pub use crate::impersonation::middleware::*;
+pub use crate::impersonation::models::*;
@@ -0,0 +1,36 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio_pg_mapper_derive::PostgresMapper;
+use utoipa::{IntoParams, ToSchema};
+
+#[derive(Serialize, Debug, Clone, Deserialize, ToSchema)]
+#[schema(example = json!({"user_id": 42, "block_writes": true}))]
+pub struct StartImpersonation {
+    pub user_id: i32,
+    /// Refuse POST, PUT, PATCH and DELETE requests while impersonating. Defaults to true.
+    pub block_writes: Option<bool>,
+}
+
+/// One entry of the impersonation audit trail.
+#[derive(Serialize, Debug, Clone, Deserialize, ToSchema, PostgresMapper)]
+#[pg_mapper(table = "impersonation_events")]
+pub struct ImpersonationEvent {
+    pub id: i32,
+    pub admin_id: Option<i32>,
+    pub user_id: Option<i32>,
+    pub session_id: i32,
+    /// One of `start`, `stop`, `write` or `blocked`.
+    pub action: String,
+    pub method: Option<String>,
+    pub path: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Debug, Clone, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImpersonationEventQuery {
+    /// Only events where this user was impersonated.
+    pub user_id: Option<i32>,
+    /// Only events caused by this admin.
+    pub admin_id: Option<i32>,
+}
@@ -0,0 +1,129 @@
+use crate::auth::{add_user, CreateUser, CreatedUser, Role};
+use crate::configs::db;
+use crate::errors::ServiceError;
+use crate::invites::Invite;
+use chrono::{DateTime, Utc};
+use deadpool_postgres::Client;
+use tokio_pg_mapper::FromTokioPostgresRow;
+
+const INVITE_COLUMNS: &str =
+    "id, email, role, invited_by, expires_at, accepted_at, revoked_at, created_at";
+
+pub async fn invite_add(
+    client: &Client,
+    email: &str,
+    role: Role,
+    invited_by: i32,
+    token_hash: &str,
+    expires_at: DateTime<Utc>,
+) -> Result<Invite, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "INSERT INTO {invites} (email, role, invited_by, token_hash, expires_at)
+            VALUES ($1, $2, $3, $4, $5) RETURNING {}",
+            INVITE_COLUMNS,
+            invites = db().invites
+        ))
+        .await?;
+
+    let row = client
+        .query_one(
+            &statement,
+            &[
+                &email,
+                &role.as_str(),
+                &invited_by,
+                &token_hash,
+                &expires_at,
+            ],
+        )
+        .await?;
+    Ok(Invite::from_row_ref(&row).unwrap())
+}
+
+pub async fn invite_list(client: &Client) -> Result<Vec<Invite>, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "SELECT {} FROM {invites} ORDER BY id DESC",
+            INVITE_COLUMNS,
+            invites = db().invites
+        ))
+        .await?;
+
+    let invites = client
+        .query(&statement, &[])
+        .await?
+        .iter()
+        .map(|row| Invite::from_row_ref(row).unwrap())
+        .collect::<Vec<Invite>>();
+
+    Ok(invites)
+}
+
+/// Finds an invite that can still be accepted: not expired, revoked or already used.
+pub async fn invite_find_pending(
+    client: &Client,
+    token_hash: &str,
+) -> Result<Option<Invite>, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "SELECT {} FROM {invites}
+            WHERE token_hash = $1 AND accepted_at IS NULL AND revoked_at IS NULL AND expires_at > now()",
+            INVITE_COLUMNS, invites = db().invites))
+        .await?;
+
+    let maybe_invite = client
+        .query_opt(&statement, &[&token_hash])
+        .await?
+        .map(|row| Invite::from_row_ref(&row).unwrap());
+
+    Ok(maybe_invite)
+}
+
+pub async fn invite_revoke(client: &Client, id: i32) -> Result<(), ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "UPDATE {invites} SET revoked_at = now()
+            WHERE id = $1 AND accepted_at IS NULL AND revoked_at IS NULL",
+            invites = db().invites
+        ))
+        .await?;
+
+    match client.execute(&statement, &[&id]).await? {
+        1 => Ok(()),
+        _ => Err(ServiceError::NotFound(
+            "No pending invite with this id".into(),
+        )),
+    }
+}
+
+/// Creates the invited user with the role of the invite and marks the invite
+/// as accepted, in one transaction.
+pub async fn invite_accept(
+    client: &mut Client,
+    invite: &Invite,
+    hashed_password: String,
+    username: Option<String>,
+) -> Result<CreatedUser, ServiceError> {
+    let transaction = client.transaction().await?;
+
+    let usr = CreateUser {
+        email: invite.email.clone(),
+        hashed_password,
+        username,
+    };
+    let user = add_user(&transaction, usr, invite.role.parse()?).await?;
+
+    let statement = transaction
+        .prepare(&format!(
+            "UPDATE {invites} SET accepted_at = now() WHERE id = $1 AND accepted_at IS NULL",
+            invites = db().invites
+        ))
+        .await?;
+    if transaction.execute(&statement, &[&invite.id]).await? != 1 {
+        return Err(ServiceError::Conflict("Invite already used".into()));
+    }
+
+    transaction.commit().await?;
+    Ok(user)
+}
@@ -0,0 +1,240 @@
+use crate::auth::{
+    encryption, find_user_by_mail, session_create, validate_username, AdminUser,
+    MIN_PASSWORD_LENGTH,
+};
+use crate::configs;
+use crate::errors::ServiceError;
+use crate::invites::db;
+use crate::invites::models::{AcceptInvite, CreateInvite, Invite};
+use crate::mail::{send_email, Message};
+
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Duration, Utc};
+use deadpool_postgres::{Client, Pool};
+
+const DEFAULT_INVITE_TTL_HOURS: i64 = 72;
+/// Frontend page the invitee is sent to, which posts the token to `POST /invites/accept`.
+pub const ACCEPT_INVITE_PAGE: &str = "/accept-invite";
+
+/// When an invite sent `now` expires, `DEFAULT_INVITE_TTL_HOURS` later unless `hours` says.
+fn invite_expiry(hours: Option<i64>, now: DateTime<Utc>) -> Result<DateTime<Utc>, ServiceError> {
+    match hours.unwrap_or(DEFAULT_INVITE_TTL_HOURS) {
+        hours if hours > 0 => Ok(now + Duration::hours(hours)),
+        _ => Err(ServiceError::BadRequest(
+            "expires_in_hours must be positive".into(),
+        )),
+    }
+}
+
+fn invite_message(invite: &Invite, link: &str) -> Message {
+    Message {
+        email: invite.email.clone(),
+        subject: "You have been invited".to_owned(),
+        msg: format!(
+            "<p>You have been invited to create an account.</p>
+            <p><a href=\"{0}\">{0}</a></p>
+            <p>This invitation expires on {1}.</p>",
+            link,
+            invite.expires_at.format("%Y-%m-%d %H:%M UTC")
+        ),
+    }
+}
+
+/// Invite someone to register.
+///
+/// Admin only. The invite token is emailed to the invitee and never returned by the api.
+/// The email links to the frontend's `/accept-invite` page, see `frontend_url`.
+#[utoipa::path(
+    context_path = "/invites",
+    request_body = CreateInvite,
+    responses(
+        (status = 201, description = "Invite created and sent", body = Invite),
+        (status = 403, description = "Admin role required", body = ServiceError),
+        (status = 409, description = "An account with this email already exists", body = ServiceError)
+    )
+)]
+#[post("/")]
+pub async fn create_invite(
+    admin: AdminUser,
+    local_object: web::Json<CreateInvite>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let client: Client = db_pool.get().await?;
+
+    let email = local_object.email.trim().to_owned();
+    if email.is_empty() {
+        return Err(ServiceError::BadRequest("Email is required".into()));
+    }
+    if find_user_by_mail(&client, email.clone()).await.is_ok() {
+        return Err(ServiceError::Conflict(
+            "An account with this email already exists".into(),
+        ));
+    }
+
+    let expires_at = invite_expiry(local_object.expires_in_hours, Utc::now())?;
+
+    let token = encryption::generate_token();
+    let invite = db::invite_add(
+        &client,
+        &email,
+        local_object.role,
+        admin.0.id,
+        &encryption::hash_token(&token),
+        expires_at,
+    )
+    .await?;
+
+    let config = configs::Config::from_env().unwrap();
+    let link = config.srv_cnf.frontend_link(ACCEPT_INVITE_PAGE, &token);
+    let message = invite_message(&invite, &link);
+    send_email(message).await;
+
+    Ok(HttpResponse::Created().json(invite))
+}
+
+/// List all invites, newest first. Admin only.
+#[utoipa::path(
+    context_path = "/invites",
+    responses(
+        (status = 200, description = "Invite list", body = [Invite]),
+        (status = 403, description = "Admin role required", body = ServiceError)
+    )
+)]
+#[get("/")]
+pub async fn list_invites(
+    _admin: AdminUser,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let client: Client = db_pool.get().await?;
+
+    let invites = db::invite_list(&client).await?;
+    Ok(HttpResponse::Ok().json(invites))
+}
+
+/// Revoke a pending invite. Admin only.
+#[utoipa::path(
+    context_path = "/invites",
+    responses(
+        (status = 200, description = "Invite revoked"),
+        (status = 403, description = "Admin role required", body = ServiceError),
+        (status = 404, description = "No pending invite with this id", body = ServiceError)
+    ),
+    params(
+        ("id", description = "Unique invite id")
+    )
+)]
+#[delete("/{id}")]
+pub async fn revoke_invite(
+    _admin: AdminUser,
+    invite_id: web::Path<(i32,)>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let client: Client = db_pool.get().await?;
+
+    db::invite_revoke(&client, invite_id.0).await?;
+    Ok(HttpResponse::Ok().json("Invite revoked"))
+}
+
+/// Accept an invite.
+///
+/// Creates the account with the role chosen by the admin who sent the invite and signs the
+/// new user in.
+#[utoipa::path(
+    context_path = "/invites",
+    request_body = AcceptInvite,
+    responses(
+        (status = 201, description = "Account created"),
+        (status = 400, description = "Password is too short", body = ServiceError),
+        (status = 404, description = "Invite is invalid or expired", body = ServiceError),
+        (status = 409, description = "Account already exists", body = ServiceError)
+    )
+)]
+#[post("/accept")]
+pub async fn accept_invite(
+    req: HttpRequest,
+    local_object: web::Json<AcceptInvite>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    if local_object.password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(ServiceError::BadRequest("Password is too short".into()));
+    }
+    let username = local_object.username.as_deref().map(str::trim);
+    if let Some(username) = username {
+        validate_username(username)?;
+    }
+
+    let mut client: Client = db_pool.get().await?;
+
+    let invite = db::invite_find_pending(&client, &encryption::hash_token(&local_object.token))
+        .await?
+        .ok_or_else(|| ServiceError::NotFound("Invite is invalid or expired".into()))?;
+
+    let config = configs::Config::from_env().unwrap();
+    let hashed_password =
+        encryption::password_hash(&local_object.password, config.srv_cnf.bcrypt_or_argon).await?;
+
+    let user = db::invite_accept(
+        &mut client,
+        &invite,
+        hashed_password,
+        username.map(str::to_owned),
+    )
+    .await?;
+    session_create(db_pool, &req, user.id, None).await?;
+
+    Ok(HttpResponse::Created().json(user))
+}
+
+pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(create_invite);
+    cfg.service(list_invites);
+    cfg.service(revoke_invite);
+    cfg.service(accept_invite);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_invite_expiry() {
+        let now = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+        assert_eq!(
+            invite_expiry(None, now).unwrap(),
+            Utc.with_ymd_and_hms(2024, 3, 4, 12, 0, 0).unwrap()
+        );
+        assert_eq!(
+            invite_expiry(Some(1), now).unwrap(),
+            Utc.with_ymd_and_hms(2024, 3, 1, 13, 0, 0).unwrap()
+        );
+        assert!(invite_expiry(Some(0), now).is_err());
+        assert!(invite_expiry(Some(-5), now).is_err());
+    }
+
+    #[test]
+    fn test_invite_message() {
+        let now = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+        let invite = Invite {
+            id: 1,
+            email: "jane@example.com".into(),
+            role: "editor".into(),
+            invited_by: Some(2),
+            expires_at: invite_expiry(None, now).unwrap(),
+            accepted_at: None,
+            revoked_at: None,
+            created_at: now,
+        };
+        let config = configs::tests::srv_config(serde_json::json!({
+            "frontend_url": "https://example.com",
+        }));
+        let link = config.frontend_link(ACCEPT_INVITE_PAGE, "f00d");
+
+        let message = invite_message(&invite, &link);
+        assert_eq!(message.email, "jane@example.com");
+        assert!(message
+            .msg
+            .contains("<a href=\"https://example.com/accept-invite?token=f00d\">"));
+        assert!(message.msg.contains("expires on 2024-03-04 12:00 UTC"));
+    }
+}
@@ -0,0 +1,6 @@
+pub mod db;
+pub mod handlers;
+pub mod models;
+pub use crate::invites::db::*;
+pub use crate::invites::handlers::*;
+pub use crate::invites::models::*;
@@ -0,0 +1,39 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio_pg_mapper_derive::PostgresMapper;
+use utoipa::ToSchema;
+
+use crate::auth::Role;
+
+/// An invitation to register. The token itself is only ever sent by email,
+/// the table keeps its hash.
+#[derive(Serialize, Debug, Clone, Deserialize, ToSchema, PostgresMapper)]
+#[pg_mapper(table = "invites")]
+pub struct Invite {
+    pub id: i32,
+    pub email: String,
+    pub role: String,
+    pub invited_by: Option<i32>,
+    pub expires_at: DateTime<Utc>,
+    pub accepted_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug, Clone, Deserialize, ToSchema)]
+#[schema(example = json!({"email": "jane@example.com", "role": "editor", "expires_in_hours": 48}))]
+pub struct CreateInvite {
+    pub email: String,
+    #[serde(default)]
+    pub role: Role,
+    /// Defaults to 72 hours.
+    pub expires_in_hours: Option<i64>,
+}
+
+#[derive(Serialize, Debug, Clone, Deserialize, ToSchema)]
+pub struct AcceptInvite {
+    pub token: String,
+    pub password: String,
+    #[serde(default)]
+    pub username: Option<String>,
+}
@@ -1,4 +1,7 @@
 use actix_cors::Cors;
+use actix_identity::IdentityMiddleware;
+use actix_session::{storage::CookieSessionStore, SessionMiddleware};
+use actix_web::cookie::Key;
 use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
 use actix_web::{middleware, web, App, HttpResponse, HttpServer};
 use futures::future::LocalBoxFuture;
@@ -8,16 +11,29 @@ use std::{
     error::Error,
     future::{self, Ready},
     net::Ipv4Addr,
+    sync::Arc,
 };
 
+pub mod admin;
 pub mod auth;
 pub mod category;
+pub mod comments;
 pub mod configs;
 pub mod errors;
+pub mod feeds;
+pub mod impersonation;
+pub mod invites;
 pub mod mail;
+pub mod media;
+pub mod migrate;
+pub mod pagination;
 pub mod posts;
 pub mod posts_tags;
+pub mod scim;
+pub mod service_auth;
+pub mod slugs;
 pub mod tags;
+pub mod tls;
 use deadpool_postgres::{Runtime, Pool};
 use dotenv::dotenv;
 use utoipa::OpenApi;
@@ -165,24 +181,101 @@ async fn main() -> std::io::Result<()> {
         paths(
             auth::register_user,
             auth::process_login,
+            auth::profile,
+            auth::username_available,
+            auth::set_username,
+            auth::complete_password_reset,
+            auth::change_password,
+            auth::change_email,
+            auth::confirm_email_change,
+            auth::csrf_token,
+            auth::not_me,
+            auth::email_otp,
+            auth::confirm_otp,
+            auth::recovery_code_status,
+            auth::regenerate_recovery_codes,
+            auth::list_trusted_devices,
+            auth::revoke_trusted_device,
+            auth::revoke_trusted_devices,
             category::category,
+            category::category_tree,
             category::add_category,
             category::update_category,
             category::get_category,
             category::delete_category,
+            category::category_posts,
             tags::tags,
             tags::add_tags,
             tags::update_tags,
             tags::get_tags,
             tags::delete_tags,
+            tags::tag_posts,
+            posts_tags::posts_tags,
+            posts_tags::add_posts_tags,
+            posts_tags::delete_posts_tags,
             posts::posts,
             posts::add_posts,
             posts::update_posts,
             posts::get_posts,
             posts::delete_posts,
+            posts::change_post_status,
+            posts::post_events,
+            posts::search_posts,
+            posts::set_post_tags,
+            posts::get_post_by_slug,
+            posts::set_post_categories,
+            posts::set_post_media,
+            posts::post_revisions,
+            posts::post_revision_diff,
+            posts::post_revision,
+            posts::restore_post_revision,
+            comments::comment_thread,
+            comments::add_comment,
+            comments::moderation_queue,
+            comments::get_comment,
+            comments::moderate_comment,
+            comments::delete_comment,
+            media::upload_media,
+            media::media_library,
+            media::get_media,
+            media::update_media,
+            media::delete_media,
+            media::media_file,
+            feeds::site_feed,
+            feeds::tag_feed,
+            feeds::category_feed,
+            invites::create_invite,
+            invites::list_invites,
+            invites::revoke_invite,
+            invites::accept_invite,
+            admin::list_users,
+            admin::get_user,
+            admin::change_user_role,
+            admin::disable_user,
+            admin::enable_user,
+            admin::force_password_reset,
+            admin::revoke_user_sessions,
+            admin::delete_user,
+            impersonation::start_impersonation,
+            impersonation::stop_impersonation,
+            impersonation::impersonation_events,
+            scim::list_users,
+            scim::create_user,
+            scim::get_user,
+            scim::replace_user,
+            scim::patch_user,
+            scim::delete_user,
+            scim::list_groups,
+            scim::get_group,
+            scim::replace_group,
+            scim::patch_group,
+            scim::service_provider_config,
+            service_auth::issue_service_key,
+            service_auth::list_service_keys,
+            service_auth::revoke_service_key,
         ),
         components(
-            schemas(auth::CreateUser, errors::ServiceError, category::Category, category::CreateCategory, tags::Tags, tags::CreateTags, posts::Post, posts::CreatePost)
+            schemas(auth::CreateUser, auth::Login, auth::Profile, auth::SetUsername, auth::UsernameAvailability, auth::Role, errors::ServiceError, category::Category, category::CreateCategory, category::CategoryNode, category::CategoryRef, category::CategoryList, category::SetPostCategories, tags::Tags, tags::CreateTags, tags::TagList, posts_tags::PostsTags, posts_tags::CreatePostsTags, posts_tags::SetPostTags, posts::Post, posts::CreatePost, posts::PostStatus, posts::ContentFormat, posts::PostStatusChange, posts::PostEvent, posts::PostRevision, posts::PostSearchHit, comments::Comment, comments::CommentNode, comments::CreateComment, comments::CommentStatus, comments::CommentStatusChange, comments::ModeratedComment, media::Media, media::MediaRef, media::MediaList, media::UpdateMedia, media::SetPostMedia, feeds::FeedFormat, posts::PostSort, tags::TagSort, category::CategorySort, pagination::SortOrder, pagination::PostPage, pagination::TagPage, pagination::CategoryPage, pagination::ModeratedCommentPage, pagination::MediaPage, invites::Invite, invites::CreateInvite, invites::AcceptInvite, auth::CompletePasswordReset, auth::ChangePassword, auth::ChangeEmail, auth::ConfirmEmailChange, auth::CsrfToken, auth::NotMe, auth::Otp, auth::OtpConfirmed, auth::RecoveryCodes, auth::RecoveryCodeStatus, auth::LoginResult, auth::TrustedDevice, admin::UserSummary, admin::UserDetails, admin::UserPage, admin::ChangeRole, impersonation::StartImpersonation, impersonation::ImpersonationEvent, scim::ScimUser, scim::ScimUserInput, scim::ScimGroup, scim::ScimGroupInput, scim::ScimEmail, scim::ScimMember, scim::ScimMeta, scim::PatchOp, scim::PatchOperation, scim::ScimError, service_auth::ServiceKey, service_auth::CreateServiceKey, service_auth::IssuedServiceKey)
         )
            //  ,
         // tags(
@@ -199,10 +292,14 @@ async fn main() -> std::io::Result<()> {
     }
 
     let config = Config::from_env().unwrap();
+    // Fail fast on schema or table names that aren't plain identifiers.
+    configs::db();
     // let config = configs::Config::new();
     let bind_addr = format!("{}:{}", config.srv_cnf.host, config.srv_cnf.port);
+    let tls_config = tls::server_config(&config.srv_cnf)?;
     println!(
-        "Starting server at http://{}:{}",
+        "Starting server at {}://{}:{}",
+        if tls_config.is_some() { "https" } else { "http" },
         config.srv_cnf.host, config.srv_cnf.port
     );
 
@@ -211,20 +308,84 @@ async fn main() -> std::io::Result<()> {
         .create_pool(Some(Runtime::Tokio1), tokio_postgres::NoTls)
         .unwrap();
 
+    if !config.srv_cnf.skip_migrations {
+        let mut client = pool.get().await.map_err(std::io::Error::other)?;
+        migrate::run(&mut client)
+            .await
+            .map_err(|e| std::io::Error::other(format!("Migrations failed: {:?}", e)))?;
+    }
+
+    actix_web::rt::spawn(posts::run_scheduler(pool.clone()));
+
+    let storage: Arc<dyn media::Storage> = Arc::new(
+        media::LocalStorage::new(&config.srv_cnf.media_dir)
+            .expect("MEDIA_DIR could not be created"),
+    );
+
+    // Session cookies are signed and encrypted with a key derived from SECRET_KEY.
+    let secret_key = Key::derive_from(
+        &auth::hex_to_bytes(&config.srv_cnf.secret_key).expect("SECRET_KEY could not parse"),
+    );
+
+    let allowed_origins = config.srv_cnf.cors_allowed_origins();
+    if allowed_origins.is_none() {
+        log::warn!("cors_allowed_origins is not set, allowing every origin");
+    }
+
     let server = HttpServer::new(move || {
-        let cors = Cors::permissive();
+        let cors = match &allowed_origins {
+            Some(origins) => origins.iter().fold(
+                Cors::default()
+                    .allow_any_method()
+                    .allow_any_header()
+                    .expose_any_header()
+                    .supports_credentials(),
+                |cors, origin| cors.allowed_origin(origin),
+            ),
+            None => Cors::permissive(),
+        };
 
         App::new()
             .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::from(storage.clone()))
+            .wrap(impersonation::ImpersonationGuard)
+            .wrap(auth::CsrfGuard)
+            .wrap(service_auth::ServiceSignatureGuard)
+            .wrap(tls::ClientCertificateGuard)
+            .wrap(IdentityMiddleware::default())
+            .wrap(SessionMiddleware::new(
+                CookieSessionStore::default(),
+                secret_key.clone(),
+            ))
             .wrap(middleware::Logger::default())
             .wrap(middleware::Logger::new("%% |Origin: %a |Time: %t |Method: %r |Status: %s |Size: %b |ReqTime: %D |RemoteIP: %{r}a |Request URL: %U %{User-Agent}i"))
             .wrap(cors)
             // .service(web::scope("/categories").configure(category::init_routes))
             .service(web::scope("/auth").configure(auth::init_routes))
-            .service(web::scope("/posts").configure(posts::init_routes))
-            .service(web::scope("/categories").configure(category::init_routes))
-            // .service(web::scope("/posts_tags").configure(posts_tags::init_routes))
-            .service(web::scope("/tags").configure(tags::init_routes))
+            .service(
+                web::scope("/posts")
+                    .configure(posts::init_routes)
+                    .configure(comments::init_post_routes),
+            )
+            .service(web::scope("/comments").configure(comments::init_routes))
+            .service(web::scope("/media").configure(media::init_routes))
+            .service(
+                web::scope("/categories")
+                    .configure(category::init_routes)
+                    .configure(feeds::init_category_routes),
+            )
+            .service(web::scope("/posts_tags").configure(posts_tags::init_routes))
+            .service(
+                web::scope("/tags")
+                    .configure(tags::init_routes)
+                    .configure(feeds::init_tag_routes),
+            )
+            .service(feeds::site_feed)
+            .service(web::scope("/invites").configure(invites::init_routes))
+            .service(web::scope("/admin/users").configure(admin::init_routes))
+            .service(web::scope("/impersonation").configure(impersonation::init_routes))
+            .service(web::scope("/scim/v2").configure(scim::init_routes))
+            .service(web::scope("/admin/services").configure(service_auth::init_routes))
             .service(
                 web::resource("/api.json").route(web::get().to(|oapi: web::Data<Pool>| async move {
                     // let json_api = oapi.as_ref().api.clone();
@@ -239,7 +400,11 @@ async fn main() -> std::io::Result<()> {
                 SwaggerUi::new("/swagger-ui/{_:.*}").url("/api-docs/openapi.json", openapi.clone()),
             )
     })
-    .bind(bind_addr)?
+    .on_connect(tls::on_connect);
+    let server = match tls_config {
+        Some(tls_config) => server.bind_rustls(bind_addr, tls_config)?,
+        None => server.bind(bind_addr)?,
+    }
     // .bind_uds("/tmp/auth-uds.socket")?
     .run();
 
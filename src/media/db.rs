@@ -0,0 +1,214 @@
+use crate::configs::db;
+use crate::errors::ServiceError;
+use crate::media::{Media, MediaListQuery, FILES_PATH};
+use crate::pagination::{Keyed, Keyset};
+use deadpool_postgres::Client;
+use tokio_pg_mapper::FromTokioPostgresRow;
+
+/// Columns of a `Media` selected from `media m`, with the keys turned into URLs.
+fn media_columns() -> String {
+    format!(
+        "m.id, '{files}' || m.storage_key AS url,
+        '{files}' || m.thumbnail_key AS thumbnail_url,
+        m.filename, m.content_type, m.size_bytes, m.width, m.height, m.alt_text,
+        m.uploader_id, m.created_at",
+        files = FILES_PATH
+    )
+}
+
+/// Records a stored file.
+#[allow(clippy::too_many_arguments)]
+pub async fn media_add(
+    client: &Client,
+    storage_key: &str,
+    thumbnail_key: Option<&str>,
+    filename: &str,
+    content_type: &str,
+    size_bytes: i64,
+    dimensions: Option<(i32, i32)>,
+    alt_text: &str,
+    uploader_id: i32,
+) -> Result<Media, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "WITH m AS (INSERT INTO {media}
+                (storage_key, thumbnail_key, filename, content_type, size_bytes, width, height,
+                alt_text, uploader_id)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING *)
+            SELECT {} FROM m",
+            media_columns(),
+            media = db().media
+        ))
+        .await?;
+
+    let row = client
+        .query_one(
+            &statement,
+            &[
+                &storage_key,
+                &thumbnail_key,
+                &filename,
+                &content_type,
+                &size_bytes,
+                &dimensions.map(|(width, _)| width),
+                &dimensions.map(|(_, height)| height),
+                &alt_text,
+                &uploader_id,
+            ],
+        )
+        .await?;
+    Ok(Media::from_row_ref(&row).unwrap())
+}
+
+pub async fn media_get(client: &Client, id: i32) -> Result<Media, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "SELECT {} FROM {media} m WHERE m.id = $1",
+            media_columns(),
+            media = db().media
+        ))
+        .await?;
+
+    client
+        .query_opt(&statement, &[&id])
+        .await?
+        .map(|row| Media::from_row_ref(&row).unwrap())
+        .ok_or_else(|| ServiceError::NotFound("Media not found".into()))
+}
+
+/// A page of the media library, newest first.
+pub async fn media_list(
+    client: &Client,
+    query: &MediaListQuery,
+    keyset: &Keyset,
+) -> Result<Vec<Keyed<Media>>, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "SELECT {}, {} FROM {media} m
+            WHERE ($1::integer IS NULL OR m.uploader_id = $1)
+            and ($2::boolean IS NULL OR (m.content_type LIKE 'image/%') = $2)
+            and {}
+            {}",
+            media_columns(),
+            keyset.key_column(),
+            keyset.condition("m.id", 3, 4),
+            keyset.order_by("m.id"),
+            media = db().media
+        ))
+        .await?;
+
+    let media = client
+        .query(
+            &statement,
+            &[&query.uploader, &query.images, &keyset.key(), &keyset.id()],
+        )
+        .await?
+        .iter()
+        .map(|row| {
+            let media = Media::from_row_ref(row).unwrap();
+            Keyed::new(row, media.id, media)
+        })
+        .collect::<Vec<Keyed<Media>>>();
+    Ok(media)
+}
+
+pub async fn media_update(client: &Client, id: i32, alt_text: &str) -> Result<Media, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "WITH m AS (UPDATE {media} SET alt_text = $2 WHERE id = $1 RETURNING *)
+            SELECT {} FROM m",
+            media_columns(),
+            media = db().media
+        ))
+        .await?;
+
+    client
+        .query_opt(&statement, &[&id, &alt_text])
+        .await?
+        .map(|row| Media::from_row_ref(&row).unwrap())
+        .ok_or_else(|| ServiceError::NotFound("Media not found".into()))
+}
+
+/// Forgets the file, detaching it from posts. Returns the keys of what was stored for it.
+pub async fn media_delete(
+    client: &Client,
+    id: i32,
+) -> Result<(String, Option<String>), ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "DELETE FROM {media} WHERE id = $1 RETURNING storage_key, thumbnail_key",
+            media = db().media
+        ))
+        .await?;
+
+    client
+        .query_opt(&statement, &[&id])
+        .await?
+        .map(|row| (row.get("storage_key"), row.get("thumbnail_key")))
+        .ok_or_else(|| ServiceError::NotFound("Media not found".into()))
+}
+
+/// Attaches exactly `media_ids` to the post, in that order, all at once.
+pub async fn post_media_set(
+    client: &mut Client,
+    post_id: i32,
+    media_ids: &[i32],
+) -> Result<(), ServiceError> {
+    let transaction = client.transaction().await?;
+
+    // Serializes concurrent changes to the media of the same post.
+    let statement = transaction
+        .prepare(&format!(
+            "SELECT id FROM {posts} WHERE id = $1 FOR UPDATE",
+            posts = db().posts
+        ))
+        .await?;
+    if transaction
+        .query_opt(&statement, &[&post_id])
+        .await?
+        .is_none()
+    {
+        return Err(ServiceError::NotFound("Post not found".into()));
+    }
+
+    let statement = transaction
+        .prepare(&format!(
+            "SELECT id FROM {media} WHERE id = ANY ($1)",
+            media = db().media
+        ))
+        .await?;
+    let found = transaction
+        .query(&statement, &[&media_ids])
+        .await?
+        .iter()
+        .map(|row| row.get("id"))
+        .collect::<Vec<i32>>();
+    if let Some(missing) = media_ids.iter().find(|id| !found.contains(id)) {
+        return Err(ServiceError::BadRequest(format!(
+            "Media {} not found",
+            missing
+        )));
+    }
+
+    let statement = transaction
+        .prepare(&format!(
+            "DELETE FROM {posts_media} WHERE post_id = $1",
+            posts_media = db().posts_media
+        ))
+        .await?;
+    transaction.execute(&statement, &[&post_id]).await?;
+
+    let statement = transaction
+        .prepare(&format!(
+            "INSERT INTO {posts_media} (post_id, media_id, position)
+            SELECT $1, id, position::integer FROM unnest($2::integer[]) WITH ORDINALITY AS a(id, position)",
+            posts_media = db().posts_media
+        ))
+        .await?;
+    transaction
+        .execute(&statement, &[&post_id, &media_ids])
+        .await?;
+
+    transaction.commit().await?;
+    Ok(())
+}
@@ -0,0 +1,347 @@
+use crate::auth::AuthUser;
+use crate::configs;
+use crate::errors::ServiceError;
+use crate::media::db;
+use crate::media::images::{derive, sniff};
+use crate::media::models::{
+    alt_text, display_filename, MediaListQuery, UpdateMedia, MAX_ALT_TEXT, MEDIA_ORDER, MEDIA_SORT,
+};
+use crate::media::storage::{valid_key, Storage};
+use crate::pagination::Keyset;
+use crate::posts::handlers::is_editor;
+
+use actix_files::NamedFile;
+use actix_multipart::{Field, Multipart, MultipartError};
+use actix_web::http::header::{self, CacheControl, CacheDirective};
+use actix_web::web::Query;
+use actix_web::{delete, get, patch, post, web, HttpRequest, HttpResponse};
+use deadpool_postgres::{Client, Pool};
+use futures::TryStreamExt;
+use rand::Rng;
+
+fn multipart_error(e: MultipartError) -> ServiceError {
+    ServiceError::BadRequest(format!("Invalid multipart body: {}", e))
+}
+
+/// Reads the whole field, refusing it once it grows past `limit` bytes.
+async fn read_field(field: &mut Field, limit: usize) -> Result<Vec<u8>, ServiceError> {
+    let mut bytes = Vec::new();
+    while let Some(chunk) = field.try_next().await.map_err(multipart_error)? {
+        if bytes.len() + chunk.len() > limit {
+            return Err(ServiceError::PayloadTooLarge(format!(
+                "Files can have at most {} bytes",
+                limit
+            )));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
+
+/// The uploader of `media`, or an editor, may change it.
+fn may_change(user: &AuthUser, uploader_id: Option<i32>) -> Result<(), ServiceError> {
+    if is_editor(user) || uploader_id == Some(user.id) {
+        Ok(())
+    } else {
+        Err(ServiceError::Forbidden(
+            "Only the uploader, editors and admins change media".into(),
+        ))
+    }
+}
+
+/// Upload a file.
+///
+/// Takes a `multipart/form-data` body with the file in a `file` field and optional alt
+/// text in an `alt` field. JPEG, PNG, GIF, WebP and PDF files are taken, judged by their
+/// content rather than by the type or name sent. Images get their dimensions recorded and a
+/// WebP thumbnail. Any signed in user may upload.
+#[utoipa::path(
+    context_path = "/media",
+    request_body(content = String, content_type = "multipart/form-data"),
+    responses(
+        (status = 201, description = "The uploaded file", body = Media),
+        (status = 400, description = "No file, or an unreadable image", body = ServiceError),
+        (status = 401, description = "Not signed in", body = ServiceError),
+        (status = 413, description = "The file is too large", body = ServiceError),
+        (status = 415, description = "Not a type of file taken", body = ServiceError)
+    )
+)]
+#[post("/")]
+pub async fn upload_media(
+    user: AuthUser,
+    mut payload: Multipart,
+    storage: web::Data<dyn Storage>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let limit = configs::Config::from_env().unwrap().srv_cnf.media_max_bytes;
+
+    let mut file = None;
+    let mut alt = String::new();
+    while let Some(mut field) = payload.try_next().await.map_err(multipart_error)? {
+        match field.name() {
+            Some("file") if file.is_none() => {
+                let filename = display_filename(
+                    field
+                        .content_disposition()
+                        .and_then(|disposition| disposition.get_filename()),
+                );
+                file = Some((filename, read_field(&mut field, limit).await?));
+            }
+            Some("alt") => {
+                // Room for the longest alt text in four byte characters.
+                let bytes = read_field(&mut field, MAX_ALT_TEXT * 4).await?;
+                alt = String::from_utf8(bytes)
+                    .map_err(|_| ServiceError::BadRequest("Alt text must be UTF-8".into()))?;
+            }
+            _ => {
+                return Err(ServiceError::BadRequest(
+                    "Only `file` and `alt` fields are taken".into(),
+                ))
+            }
+        }
+    }
+    let (filename, bytes) =
+        file.ok_or_else(|| ServiceError::BadRequest("No `file` field in the body".into()))?;
+    let alt = alt_text(&alt)?;
+
+    let kind = sniff(&bytes)?;
+    let (bytes, derived) = if kind.image {
+        let (bytes, derived) = web::block(move || {
+            let derived = derive(&bytes);
+            (bytes, derived)
+        })
+        .await
+        .map_err(|e| ServiceError::InternalServerError(e.to_string()))?;
+        (bytes, Some(derived?))
+    } else {
+        (bytes, None)
+    };
+
+    let stem = hex::encode(rand::thread_rng().gen::<[u8; 16]>());
+    let key = format!("{}.{}", stem, kind.extension);
+    let size = bytes.len() as i64;
+    storage.put(&key, bytes).await?;
+    let thumbnail_key = match derived.as_ref() {
+        Some(derived) => {
+            let thumbnail_key = format!("{}-thumb.webp", stem);
+            if let Err(e) = storage.put(&thumbnail_key, derived.thumbnail.clone()).await {
+                storage.delete(&key).await.ok();
+                return Err(e.into());
+            }
+            Some(thumbnail_key)
+        }
+        None => None,
+    };
+
+    let added = match db_pool.get().await {
+        Ok(client) => {
+            db::media_add(
+                &client,
+                &key,
+                thumbnail_key.as_deref(),
+                &filename,
+                kind.content_type,
+                size,
+                derived
+                    .as_ref()
+                    .map(|derived| (derived.width as i32, derived.height as i32)),
+                &alt,
+                user.id,
+            )
+            .await
+        }
+        Err(e) => Err(e.into()),
+    };
+    // Files nobody knows about would never be cleaned up.
+    if added.is_err() {
+        storage.delete(&key).await.ok();
+        if let Some(thumbnail_key) = thumbnail_key.as_deref() {
+            storage.delete(thumbnail_key).await.ok();
+        }
+    }
+    Ok(HttpResponse::Created().json(added?))
+}
+
+/// The media library.
+///
+/// A page of uploaded files, newest first. Users other than editors and admins only see
+/// their own uploads.
+#[utoipa::path(
+    context_path = "/media",
+    responses(
+        (status = 200, description = "Uploaded files", body = MediaPage),
+        (status = 400, description = "Invalid cursor", body = ServiceError),
+        (status = 401, description = "Not signed in", body = ServiceError)
+    ),
+    params(MediaListQuery)
+)]
+#[get("/")]
+pub async fn media_library(
+    req: HttpRequest,
+    user: AuthUser,
+    query: Query<MediaListQuery>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let mut query = query.into_inner();
+    if !is_editor(&user) {
+        query.uploader = Some(user.id);
+    }
+    let keyset = Keyset::new(
+        MEDIA_SORT,
+        MEDIA_ORDER,
+        query.cursor.as_deref(),
+        query.limit,
+    )?;
+    let client: Client = db_pool.get().await?;
+
+    let media = db::media_list(&client, &query, &keyset).await?;
+    Ok(HttpResponse::Ok().json(keyset.page(&req, media)))
+}
+
+/// Get the details of an uploaded file.
+#[utoipa::path(
+    context_path = "/media",
+    responses(
+        (status = 200, description = "The file", body = Media),
+        (status = 404, description = "Media not found by id", body = ServiceError)
+    ),
+    params(
+        ("id", description = "Unique Media Id")
+    )
+)]
+#[get("/{id}")]
+pub async fn get_media(
+    id_media: web::Path<(i32,)>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let client: Client = db_pool.get().await?;
+
+    let media = db::media_get(&client, id_media.0).await?;
+    Ok(HttpResponse::Ok().json(media))
+}
+
+/// Change the alt text of an uploaded file. Open to the uploader, editors and admins.
+#[utoipa::path(
+    context_path = "/media",
+    request_body = UpdateMedia,
+    responses(
+        (status = 200, description = "The file", body = Media),
+        (status = 400, description = "Alt text too long", body = ServiceError),
+        (status = 401, description = "Not signed in", body = ServiceError),
+        (status = 403, description = "Not the uploader", body = ServiceError),
+        (status = 404, description = "Media not found by id", body = ServiceError)
+    ),
+    params(
+        ("id", description = "Unique Media Id")
+    )
+)]
+#[patch("/{id}")]
+pub async fn update_media(
+    user: AuthUser,
+    id_media: web::Path<(i32,)>,
+    local_object: web::Json<UpdateMedia>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let alt = alt_text(&local_object.alt_text)?;
+    let client: Client = db_pool.get().await?;
+
+    let media = db::media_get(&client, id_media.0).await?;
+    may_change(&user, media.uploader_id)?;
+    let media = db::media_update(&client, media.id, &alt).await?;
+    Ok(HttpResponse::Ok().json(media))
+}
+
+/// Delete an uploaded file.
+///
+/// The file and its thumbnail are removed and it is detached from every post. Open to the
+/// uploader, editors and admins.
+#[utoipa::path(
+    context_path = "/media",
+    responses(
+        (status = 204, description = "Media deleted"),
+        (status = 401, description = "Not signed in", body = ServiceError),
+        (status = 403, description = "Not the uploader", body = ServiceError),
+        (status = 404, description = "Media not found by id", body = ServiceError)
+    ),
+    params(
+        ("id", description = "Unique Media Id")
+    )
+)]
+#[delete("/{id}")]
+pub async fn delete_media(
+    user: AuthUser,
+    id_media: web::Path<(i32,)>,
+    storage: web::Data<dyn Storage>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let client: Client = db_pool.get().await?;
+
+    let media = db::media_get(&client, id_media.0).await?;
+    may_change(&user, media.uploader_id)?;
+    let (key, thumbnail_key) = db::media_delete(&client, media.id).await?;
+    storage.delete(&key).await?;
+    if let Some(thumbnail_key) = thumbnail_key {
+        storage.delete(&thumbnail_key).await?;
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Download a stored file or thumbnail.
+///
+/// Keys are never reused, so files may be cached for good. Answers conditional requests
+/// with `ETag` and `Last-Modified`, and ranges.
+#[utoipa::path(
+    context_path = "/media",
+    responses(
+        (status = 200, description = "The file"),
+        (status = 304, description = "The cached copy is current"),
+        (status = 404, description = "No file under the key", body = ServiceError)
+    ),
+    params(
+        ("key", description = "Key of the file, the last part of its url")
+    )
+)]
+#[get("/files/{key}")]
+pub async fn media_file(
+    req: HttpRequest,
+    key: web::Path<(String,)>,
+    storage: web::Data<dyn Storage>,
+) -> Result<HttpResponse, ServiceError> {
+    let not_found = || ServiceError::NotFound("File not found".into());
+    let path = Some(&key.0)
+        .filter(|key| valid_key(key))
+        .and_then(|key| storage.local_path(key))
+        .ok_or_else(not_found)?;
+    let file = NamedFile::open_async(path).await.map_err(|_| not_found())?;
+
+    let mut response = file
+        .use_etag(true)
+        .use_last_modified(true)
+        .into_response(&req);
+    let headers = response.headers_mut();
+    headers.insert(
+        header::CACHE_CONTROL,
+        CacheControl(vec![
+            CacheDirective::Public,
+            CacheDirective::MaxAge(31_536_000),
+            CacheDirective::Extension("immutable".into(), None),
+        ])
+        .to_string()
+        .parse()
+        .unwrap(),
+    );
+    headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        header::HeaderValue::from_static("nosniff"),
+    );
+    Ok(response)
+}
+
+pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(upload_media);
+    cfg.service(media_library);
+    cfg.service(media_file);
+    cfg.service(get_media);
+    cfg.service(update_media);
+    cfg.service(delete_media);
+}
@@ -0,0 +1,147 @@
+use std::io::Cursor;
+
+use image::codecs::webp::WebPEncoder;
+use image::{DynamicImage, ImageReader, Limits};
+
+use crate::errors::ServiceError;
+
+/// Longest side of a thumbnail, in pixels.
+pub const THUMBNAIL_SIZE: u32 = 320;
+/// Longest side of an image that gets decoded for a thumbnail, in pixels.
+pub const MAX_IMAGE_SIDE: u32 = 12_000;
+
+/// A type of file the media library takes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MediaKind {
+    pub content_type: &'static str,
+    pub extension: &'static str,
+    /// Whether thumbnails and dimensions can be had from it.
+    pub image: bool,
+}
+
+const ACCEPTED: &[MediaKind] = &[
+    MediaKind {
+        content_type: "image/jpeg",
+        extension: "jpg",
+        image: true,
+    },
+    MediaKind {
+        content_type: "image/png",
+        extension: "png",
+        image: true,
+    },
+    MediaKind {
+        content_type: "image/gif",
+        extension: "gif",
+        image: true,
+    },
+    MediaKind {
+        content_type: "image/webp",
+        extension: "webp",
+        image: true,
+    },
+    MediaKind {
+        content_type: "application/pdf",
+        extension: "pdf",
+        image: false,
+    },
+];
+
+/// The kind of file `bytes` hold, judging by their content alone, when it is one the
+/// media library takes.
+pub fn sniff(bytes: &[u8]) -> Result<MediaKind, ServiceError> {
+    let found = infer::get(bytes).map(|kind| kind.mime_type());
+    ACCEPTED
+        .iter()
+        .find(|kind| Some(kind.content_type) == found)
+        .copied()
+        .ok_or_else(|| {
+            ServiceError::UnsupportedMediaType(format!(
+                "Only {} files are accepted",
+                ACCEPTED
+                    .iter()
+                    .map(|kind| kind.extension)
+                    .collect::<Vec<&str>>()
+                    .join(", ")
+            ))
+        })
+}
+
+/// What is made from an uploaded image.
+#[derive(Debug, Clone)]
+pub struct Derived {
+    pub width: u32,
+    pub height: u32,
+    /// WebP, at most `THUMBNAIL_SIZE` pixels on each side.
+    pub thumbnail: Vec<u8>,
+}
+
+/// Decodes the image in `bytes` to measure it and make its thumbnail. Slow, run it off the
+/// async workers.
+pub fn derive(bytes: &[u8]) -> Result<Derived, ServiceError> {
+    let invalid =
+        |e: image::ImageError| ServiceError::BadRequest(format!("Unreadable image: {}", e));
+
+    let mut reader = ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|e| ServiceError::BadRequest(e.to_string()))?;
+    // Small files can claim huge dimensions, refuse them before allocating.
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_IMAGE_SIDE);
+    limits.max_image_height = Some(MAX_IMAGE_SIDE);
+    reader.limits(limits);
+    let image = reader.decode().map_err(invalid)?;
+
+    let thumbnail =
+        DynamicImage::ImageRgba8(image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE).into_rgba8());
+    let mut encoded = Vec::new();
+    thumbnail
+        .write_with_encoder(WebPEncoder::new_lossless(&mut encoded))
+        .map_err(|e| ServiceError::InternalServerError(e.to_string()))?;
+
+    Ok(Derived {
+        width: image.width(),
+        height: image.height(),
+        thumbnail: encoded,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageFormat, RgbImage};
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        RgbImage::new(width, height)
+            .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)
+            .unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_sniff() {
+        assert_eq!(sniff(&png(2, 2)).unwrap().content_type, "image/png");
+        assert_eq!(sniff(b"%PDF-1.7\n").unwrap().extension, "pdf");
+        assert!(matches!(
+            sniff(b"<svg onload=alert(1)>"),
+            Err(ServiceError::UnsupportedMediaType(_))
+        ));
+        assert!(sniff(b"").is_err());
+    }
+
+    #[test]
+    fn test_derive() {
+        let derived = derive(&png(640, 480)).unwrap();
+        assert_eq!((derived.width, derived.height), (640, 480));
+
+        let thumbnail = image::load_from_memory(&derived.thumbnail).unwrap();
+        assert_eq!(
+            sniff(&derived.thumbnail).unwrap().content_type,
+            "image/webp"
+        );
+        assert_eq!((thumbnail.width(), thumbnail.height()), (320, 240));
+
+        assert!(derive(b"%PDF-1.7\n").is_err());
+    }
+}
@@ -0,0 +1,9 @@
+pub mod db;
+pub mod handlers;
+pub mod images;
+pub mod models;
+pub mod storage;
+pub use crate::media::db::*;
+pub use crate::media::handlers::*;
+pub use crate::media::models::*;
+pub use crate::media::storage::*;
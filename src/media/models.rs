@@ -0,0 +1,151 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use tokio_pg_mapper_derive::PostgresMapper;
+use tokio_postgres::types::{FromSql, Type};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::errors::ServiceError;
+use crate::pagination::{SortField, SortOrder};
+
+/// Path stored files are served under, followed by their key.
+pub const FILES_PATH: &str = "/media/files/";
+
+pub const MAX_ALT_TEXT: usize = 1000;
+pub const MAX_FILENAME: usize = 255;
+
+/// An uploaded file.
+#[derive(Serialize, Debug, Clone, Deserialize, ToSchema, PostgresMapper)]
+#[pg_mapper(table = "media")]
+pub struct Media {
+    pub id: i32,
+    /// Where the file is served.
+    pub url: String,
+    /// A WebP preview at most 320 pixels on each side, for images.
+    pub thumbnail_url: Option<String>,
+    /// Name of the file as uploaded.
+    pub filename: String,
+    /// Type found from the content, whatever the upload claimed.
+    pub content_type: String,
+    pub size_bytes: i64,
+    /// Pixels, for images.
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub alt_text: String,
+    pub uploader_id: Option<i32>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// What a post lists of each file attached to it.
+#[derive(Serialize, Debug, ToSchema, Clone, Deserialize, Default, PartialEq, Eq)]
+pub struct MediaRef {
+    pub id: i32,
+    pub url: String,
+    pub thumbnail_url: Option<String>,
+    pub content_type: String,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub alt_text: String,
+}
+
+/// The files attached to a post, in the order they were given. Read from a column holding
+/// them as a JSON array.
+#[derive(Serialize, Debug, ToSchema, Clone, Deserialize, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct MediaList(pub Vec<MediaRef>);
+
+impl<'a> FromSql<'a> for MediaList {
+    fn from_sql(ty: &Type, raw: &'a [u8]) -> Result<MediaList, Box<dyn Error + Sync + Send>> {
+        let json = <&str as FromSql>::from_sql(ty, raw)?;
+        Ok(MediaList(serde_json::from_str(json)?))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        <&str as FromSql>::accepts(ty)
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Deserialize, ToSchema)]
+#[schema(example = json!({"alt_text": "A cat asleep on a keyboard"}))]
+pub struct UpdateMedia {
+    pub alt_text: String,
+}
+
+/// The complete list of files attached to a post, in order.
+#[derive(Serialize, Debug, Clone, Deserialize, ToSchema)]
+#[schema(example = json!({"media": [3, 1]}))]
+pub struct SetPostMedia {
+    pub media: Vec<i32>,
+}
+
+/// Alt text, trimmed and checked for length.
+pub fn alt_text(text: &str) -> Result<String, ServiceError> {
+    let text = text.trim();
+    if text.chars().count() > MAX_ALT_TEXT {
+        return Err(ServiceError::BadRequest(format!(
+            "Alt text can have at most {} characters",
+            MAX_ALT_TEXT
+        )));
+    }
+    Ok(text.to_string())
+}
+
+/// The name of an uploaded file as shown back, without any directories the client sent
+/// and without control characters.
+pub fn display_filename(name: Option<&str>) -> String {
+    let name = name
+        .unwrap_or_default()
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_FILENAME)
+        .collect::<String>();
+    match name.trim() {
+        "" => "upload".to_string(),
+        name => name.to_string(),
+    }
+}
+
+/// The media library lists the newest uploads first.
+pub const MEDIA_SORT: SortField = SortField {
+    name: "id",
+    expr: "m.id",
+    sql_type: "integer",
+};
+pub const MEDIA_ORDER: SortOrder = SortOrder::Desc;
+
+#[derive(Deserialize, Debug, Clone, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct MediaListQuery {
+    /// Only files uploaded by this user. Users other than editors and admins only see
+    /// their own uploads.
+    pub uploader: Option<i32>,
+    /// Only images.
+    pub images: Option<bool>,
+    /// `next_cursor` or `prev_cursor` of the page before.
+    pub cursor: Option<String>,
+    /// At most this many files, 20 by default and at most 100.
+    pub limit: Option<i64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_filename() {
+        assert_eq!(display_filename(Some("C:\\Users\\me\\cat.jpg")), "cat.jpg");
+        assert_eq!(display_filename(Some("../../etc/passwd")), "passwd");
+        assert_eq!(display_filename(Some("a\u{0}b\nc.png")), "abc.png");
+        assert_eq!(display_filename(Some("dir/")), "upload");
+        assert_eq!(display_filename(None), "upload");
+    }
+
+    #[test]
+    fn test_alt_text() {
+        assert_eq!(alt_text("  a cat ").unwrap(), "a cat");
+        assert!(alt_text(&"x".repeat(MAX_ALT_TEXT + 1)).is_err());
+    }
+}
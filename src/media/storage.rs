@@ -0,0 +1,132 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use actix_web::web;
+use futures::future::BoxFuture;
+
+/// Where uploaded files are kept, by key. Keys are made by the server, see `valid_key`.
+pub trait Storage: Send + Sync {
+    /// Stores `bytes` under `key`, replacing what was there.
+    fn put<'a>(&'a self, key: &'a str, bytes: Vec<u8>) -> BoxFuture<'a, io::Result<()>>;
+
+    /// Removes the file under `key`. Removing a missing file is not an error.
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<()>>;
+
+    /// The file under `key` on the local disk, for serving it. `None` for stores that
+    /// aren't local or keys that aren't valid.
+    fn local_path(&self, key: &str) -> Option<PathBuf>;
+}
+
+/// Whether `key` could have been made by the server: lowercase letters, digits, dashes and
+/// one dot before the extension. Nothing that could leave the storage directory.
+pub fn valid_key(key: &str) -> bool {
+    let mut parts = key.split('.');
+    let (Some(stem), Some(extension), None) = (parts.next(), parts.next(), parts.next()) else {
+        return false;
+    };
+    let allowed = |part: &str| {
+        !part.is_empty()
+            && part
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    };
+    key.len() <= 100 && allowed(stem) && allowed(extension)
+}
+
+/// Files kept in a directory of the local filesystem.
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    /// Storage in `root`, created if missing.
+    pub fn new(root: impl AsRef<Path>) -> io::Result<LocalStorage> {
+        fs::create_dir_all(root.as_ref())?;
+        Ok(LocalStorage {
+            root: root.as_ref().to_path_buf(),
+        })
+    }
+
+    fn path(&self, key: &str) -> io::Result<PathBuf> {
+        if valid_key(key) {
+            Ok(self.root.join(key))
+        } else {
+            Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid key"))
+        }
+    }
+}
+
+fn blocking_error(e: actix_web::error::BlockingError) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+impl Storage for LocalStorage {
+    fn put<'a>(&'a self, key: &'a str, bytes: Vec<u8>) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            let path = self.path(key)?;
+            // Written aside and moved in place, so a file is never served half written. The
+            // leading dot keeps the partial file from being a valid key itself.
+            let partial = self.root.join(format!(".{}.part", key));
+            web::block(move || {
+                fs::write(&partial, bytes)?;
+                fs::rename(&partial, &path)
+            })
+            .await
+            .map_err(blocking_error)?
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            let path = self.path(key)?;
+            web::block(move || match fs::remove_file(path) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+                result => result,
+            })
+            .await
+            .map_err(blocking_error)?
+        })
+    }
+
+    fn local_path(&self, key: &str) -> Option<PathBuf> {
+        self.path(key).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_key() {
+        assert!(valid_key("0f3a9c.png"));
+        assert!(valid_key("0f3a9c-thumb.webp"));
+        assert!(!valid_key("../secret.png"));
+        assert!(!valid_key("a/b.png"));
+        assert!(!valid_key(".png"));
+        assert!(!valid_key("noextension"));
+        assert!(!valid_key("a.tar.gz"));
+        assert!(!valid_key("A.PNG"));
+    }
+
+    #[actix_web::test]
+    async fn test_local_storage() {
+        let root = std::env::temp_dir().join(format!("media-test-{}", std::process::id()));
+        let storage = LocalStorage::new(&root).unwrap();
+
+        storage.put("abc.txt", b"hello".to_vec()).await.unwrap();
+        let path = storage.local_path("abc.txt").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"hello");
+        // Nothing left aside, and the name it was written under can't be served.
+        assert_eq!(fs::read_dir(&root).unwrap().count(), 1);
+        assert!(storage.local_path(".abc.txt.part").is_none());
+
+        storage.delete("abc.txt").await.unwrap();
+        assert!(!path.exists());
+        storage.delete("abc.txt").await.unwrap();
+        assert!(storage.put("../abc.txt", vec![]).await.is_err());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,207 @@
+//! Schema changes on top of the base tables, kept in `migrations/` and applied at startup.
+//! The base tables, users, sessions, posts, categories, tags and posts_tags, have to
+//! exist already.
+//!
+//! Migrations name tables by placeholder, `{users}` or `{posts}`, which `render` replaces
+//! with the schema qualified names of `DbNames`, so they follow `db_schema`,
+//! `user_table_name` and `session_table_name` like every query does. They are written to be
+//! safe to apply again, so databases migrated by hand before the runner existed catch up.
+
+use deadpool_postgres::Client;
+
+use crate::configs::{db, DbNames};
+use crate::errors::ServiceError;
+
+/// Every migration by version, oldest first. Applied ones never change, new ones go last.
+pub const MIGRATIONS: &[(&str, &str)] = &[
+    (
+        "0001_roles_and_invites",
+        include_str!("../migrations/0001_roles_and_invites.sql"),
+    ),
+    (
+        "0002_admin_users",
+        include_str!("../migrations/0002_admin_users.sql"),
+    ),
+    (
+        "0003_impersonation",
+        include_str!("../migrations/0003_impersonation.sql"),
+    ),
+    (
+        "0004_recovery_codes",
+        include_str!("../migrations/0004_recovery_codes.sql"),
+    ),
+    (
+        "0005_trusted_devices",
+        include_str!("../migrations/0005_trusted_devices.sql"),
+    ),
+    (
+        "0006_email_changes",
+        include_str!("../migrations/0006_email_changes.sql"),
+    ),
+    (
+        "0007_login_alerts",
+        include_str!("../migrations/0007_login_alerts.sql"),
+    ),
+    (
+        "0008_usernames",
+        include_str!("../migrations/0008_usernames.sql"),
+    ),
+    ("0009_scim", include_str!("../migrations/0009_scim.sql")),
+    (
+        "0010_service_keys",
+        include_str!("../migrations/0010_service_keys.sql"),
+    ),
+    (
+        "0011_post_status",
+        include_str!("../migrations/0011_post_status.sql"),
+    ),
+    (
+        "0012_post_content_format",
+        include_str!("../migrations/0012_post_content_format.sql"),
+    ),
+    (
+        "0013_post_revisions",
+        include_str!("../migrations/0013_post_revisions.sql"),
+    ),
+    (
+        "0014_post_search",
+        include_str!("../migrations/0014_post_search.sql"),
+    ),
+    (
+        "0015_post_tags",
+        include_str!("../migrations/0015_post_tags.sql"),
+    ),
+    (
+        "0016_category_tree",
+        include_str!("../migrations/0016_category_tree.sql"),
+    ),
+    (
+        "0017_unique_slugs",
+        include_str!("../migrations/0017_unique_slugs.sql"),
+    ),
+    (
+        "0018_comments",
+        include_str!("../migrations/0018_comments.sql"),
+    ),
+    ("0019_media", include_str!("../migrations/0019_media.sql")),
+    (
+        "0020_auth_backends",
+        include_str!("../migrations/0020_auth_backends.sql"),
+    ),
+    (
+        "0021_email_lookup",
+        include_str!("../migrations/0021_email_lookup.sql"),
+    ),
+];
+
+/// Any number taken for the advisory lock, the same for every instance of the service.
+const MIGRATION_LOCK: i64 = 0x006d_6967_7261_7465;
+
+/// `sql` with its table placeholders replaced by the names in `names`.
+pub fn render(sql: &str, names: &DbNames) -> String {
+    names
+        .tables()
+        .into_iter()
+        .fold(sql.to_owned(), |sql, (placeholder, name)| {
+            sql.replace(&format!("{{{}}}", placeholder), name)
+        })
+}
+
+/// Applies the migrations the database hasn't seen yet, each in its own transaction along
+/// with its entry in `schema_migrations`. Instances starting together take turns.
+pub async fn run(client: &mut Client) -> Result<(), ServiceError> {
+    let names = db();
+    client
+        .batch_execute(&format!(
+            "CREATE TABLE IF NOT EXISTS {schema_migrations} (
+                version TEXT PRIMARY KEY,
+                applied_at TIMESTAMPTZ NOT NULL DEFAULT now()
+            )",
+            schema_migrations = names.schema_migrations
+        ))
+        .await?;
+
+    for (version, sql) in MIGRATIONS {
+        let transaction = client.transaction().await?;
+        transaction
+            .execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK])
+            .await?;
+
+        let statement = transaction
+            .prepare(&format!(
+                "SELECT EXISTS (SELECT 1 FROM {schema_migrations} WHERE version = $1)",
+                schema_migrations = names.schema_migrations
+            ))
+            .await?;
+        let applied: bool = transaction.query_one(&statement, &[version]).await?.get(0);
+        if applied {
+            continue;
+        }
+
+        transaction.batch_execute(&render(sql, names)).await?;
+        let statement = transaction
+            .prepare(&format!(
+                "INSERT INTO {schema_migrations} (version) VALUES ($1)",
+                schema_migrations = names.schema_migrations
+            ))
+            .await?;
+        transaction.execute(&statement, &[version]).await?;
+        transaction.commit().await?;
+        log::info!("applied migration {}", version);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configs::tests::srv_config;
+
+    #[test]
+    fn test_render() {
+        let names = DbNames::from_config(&srv_config(serde_json::json!({
+            "db_schema": "blog",
+            "user_table_name": "accounts",
+        })))
+        .unwrap();
+        assert_eq!(
+            render(
+                "ALTER TABLE {users} ADD COLUMN x INTEGER REFERENCES {posts} (id);",
+                &names
+            ),
+            "ALTER TABLE blog.accounts ADD COLUMN x INTEGER REFERENCES blog.posts (id);"
+        );
+    }
+
+    /// Every table is named by a known placeholder, never by a fixed schema.
+    #[test]
+    fn test_migrations() {
+        let names = DbNames::from_config(&srv_config(serde_json::json!({}))).unwrap();
+        let mut versions = Vec::new();
+        for (version, sql) in MIGRATIONS {
+            assert!(!sql.contains("public."), "{} names a schema", version);
+            let rendered = render(sql, &names);
+            assert!(
+                !rendered.contains('{') && !rendered.contains('}'),
+                "{} has an unknown placeholder",
+                version
+            );
+            versions.push(*version);
+        }
+        let mut sorted = versions.clone();
+        sorted.sort_unstable();
+        sorted.dedup();
+        assert_eq!(versions, sorted);
+
+        let mut files = std::fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/migrations"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<String>>();
+        files.sort_unstable();
+        let listed = versions
+            .iter()
+            .map(|version| format!("{}.sql", version))
+            .collect::<Vec<String>>();
+        assert_eq!(files, listed, "every file in migrations/ is listed");
+    }
+}
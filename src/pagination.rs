@@ -0,0 +1,419 @@
+//! Keyset pagination shared by the list endpoints.
+//!
+//! A list query sorts on one field plus the row id as a tie breaker and asks for one row
+//! more than the page holds, to know whether another page follows. Pages are addressed by
+//! opaque cursors naming the sort key and id of the row they start after (or before, going
+//! back), so deep pages cost no more than the first one and rows added meanwhile don't
+//! shift them.
+
+use actix_web::HttpRequest;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use tokio_postgres::Row;
+use utoipa::ToSchema;
+
+use crate::category::Category;
+use crate::comments::ModeratedComment;
+use crate::errors::ServiceError;
+use crate::media::Media;
+use crate::posts::Post;
+use crate::tags::Tags;
+
+pub const DEFAULT_LIMIT: i64 = 20;
+pub const MAX_LIMIT: i64 = 100;
+
+/// The column a list query selects its sort key into, as text.
+pub const SORT_KEY_COLUMN: &str = "sort_key";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+impl SortOrder {
+    fn reversed(self) -> SortOrder {
+        match self {
+            SortOrder::Asc => SortOrder::Desc,
+            SortOrder::Desc => SortOrder::Asc,
+        }
+    }
+
+    fn as_sql(self) -> &'static str {
+        match self {
+            SortOrder::Asc => "ASC",
+            SortOrder::Desc => "DESC",
+        }
+    }
+}
+
+/// A field a list can be sorted on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SortField {
+    /// Name used in cursors.
+    pub name: &'static str,
+    /// SQL expression of the sort key, never NULL.
+    pub expr: &'static str,
+    /// SQL type of `expr`, cursors carry the key as text cast back to it.
+    pub sql_type: &'static str,
+}
+
+impl SortField {
+    /// Whether `key` casts to `sql_type`, as `expr::text` of some row would. Cursors are
+    /// handed to clients, a key that doesn't would only fail in the query.
+    fn accepts(&self, key: &str) -> bool {
+        match self.sql_type {
+            "integer" => key.parse::<i32>().is_ok(),
+            "timestamptz" => {
+                chrono::DateTime::parse_from_str(key, "%Y-%m-%d %H:%M:%S%.f%#z").is_ok()
+            }
+            _ => true,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+struct Cursor {
+    #[serde(rename = "s")]
+    sort: String,
+    #[serde(rename = "o")]
+    order: SortOrder,
+    #[serde(rename = "k")]
+    key: String,
+    #[serde(rename = "i")]
+    id: i32,
+    /// Whether the page before the row is wanted rather than the one after.
+    #[serde(rename = "b", default)]
+    backwards: bool,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap())
+    }
+
+    fn decode(cursor: &str) -> Result<Cursor, ServiceError> {
+        URL_SAFE_NO_PAD
+            .decode(cursor)
+            .ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .ok_or_else(|| ServiceError::BadRequest("Invalid cursor".into()))
+    }
+}
+
+/// One page of a list, with cursors and links to the pages around it.
+#[derive(Serialize, Debug, Clone, ToSchema)]
+#[aliases(
+    PostPage = Page<Post>,
+    TagPage = Page<Tags>,
+    CategoryPage = Page<Category>,
+    ModeratedCommentPage = Page<ModeratedComment>,
+    MediaPage = Page<Media>
+)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Most items a page holds.
+    pub limit: i64,
+    /// Cursor of the following page, `None` on the last one.
+    pub next_cursor: Option<String>,
+    /// Cursor of the preceding page, `None` on the first one.
+    pub prev_cursor: Option<String>,
+    /// This request with `cursor` set to `next_cursor`.
+    pub next: Option<String>,
+    /// This request with `cursor` set to `prev_cursor`.
+    pub prev: Option<String>,
+}
+
+/// A row of a list query along with what a cursor pointing at it needs.
+#[derive(Debug, Clone)]
+pub struct Keyed<T> {
+    pub key: String,
+    pub id: i32,
+    pub item: T,
+}
+
+impl<T> Keyed<T> {
+    /// `item`, read from a row that selected `Keyset::key_column`.
+    pub fn new(row: &Row, id: i32, item: T) -> Keyed<T> {
+        Keyed {
+            key: row.get(SORT_KEY_COLUMN),
+            id,
+            item,
+        }
+    }
+}
+
+/// The keyset part of a list query: which rows come after the cursor, in which order and
+/// how many.
+#[derive(Debug, Clone)]
+pub struct Keyset {
+    sort: SortField,
+    order: SortOrder,
+    cursor: Option<Cursor>,
+    limit: i64,
+}
+
+impl Keyset {
+    /// Reads `cursor` and `limit` from a request sorted on `sort` in `order`. A cursor from
+    /// a differently sorted list, or with a key `sort` can't hold, is refused.
+    pub fn new(
+        sort: SortField,
+        order: SortOrder,
+        cursor: Option<&str>,
+        limit: Option<i64>,
+    ) -> Result<Keyset, ServiceError> {
+        let cursor = match cursor.filter(|cursor| !cursor.is_empty()) {
+            Some(cursor) => {
+                let cursor = Cursor::decode(cursor)?;
+                if cursor.sort != sort.name || cursor.order != order || !sort.accepts(&cursor.key) {
+                    return Err(ServiceError::BadRequest("Invalid cursor".into()));
+                }
+                Some(cursor)
+            }
+            None => None,
+        };
+        Ok(Keyset {
+            sort,
+            order,
+            cursor,
+            limit: limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT),
+        })
+    }
+
+    fn backwards(&self) -> bool {
+        self.cursor.as_ref().is_some_and(|cursor| cursor.backwards)
+    }
+
+    /// The order rows are fetched in, reversed when paging back.
+    fn fetch_order(&self) -> SortOrder {
+        if self.backwards() {
+            self.order.reversed()
+        } else {
+            self.order
+        }
+    }
+
+    /// `<sort expr>::text AS sort_key`, for the select list.
+    pub fn key_column(&self) -> String {
+        format!("({})::text AS {}", self.sort.expr, SORT_KEY_COLUMN)
+    }
+
+    /// Condition keeping the rows past the cursor, bound to `key_param` and `id_param`
+    /// (see `key` and `id`). Always true without a cursor.
+    pub fn condition(&self, id_expr: &str, key_param: usize, id_param: usize) -> String {
+        let op = match self.fetch_order() {
+            SortOrder::Asc => ">",
+            SortOrder::Desc => "<",
+        };
+        format!(
+            "(${k}::text IS NULL OR ({expr}, {id}) {op} (${k}::text::{ty}, ${i}::integer))",
+            k = key_param,
+            i = id_param,
+            expr = self.sort.expr,
+            id = id_expr,
+            op = op,
+            ty = self.sort.sql_type
+        )
+    }
+
+    /// `ORDER BY ... LIMIT ...`, fetching one row more than the page holds.
+    pub fn order_by(&self, id_expr: &str) -> String {
+        let order = self.fetch_order().as_sql();
+        format!(
+            "ORDER BY {expr} {order}, {id} {order} LIMIT {limit}",
+            expr = self.sort.expr,
+            id = id_expr,
+            order = order,
+            limit = self.limit + 1
+        )
+    }
+
+    /// Sort key of the cursor, for `key_param`.
+    pub fn key(&self) -> Option<String> {
+        self.cursor.as_ref().map(|cursor| cursor.key.clone())
+    }
+
+    /// Row id of the cursor, for `id_param`.
+    pub fn id(&self) -> Option<i32> {
+        self.cursor.as_ref().map(|cursor| cursor.id)
+    }
+
+    fn cursor_at(&self, key: String, id: i32, backwards: bool) -> String {
+        Cursor {
+            sort: self.sort.name.into(),
+            order: self.order,
+            key,
+            id,
+            backwards,
+        }
+        .encode()
+    }
+
+    /// Turns the rows of the query into a page, with links built from `req`.
+    pub fn page<T>(&self, req: &HttpRequest, rows: Vec<Keyed<T>>) -> Page<T> {
+        let mut rows = rows;
+        let more = rows.len() as i64 > self.limit;
+        rows.truncate(self.limit as usize);
+        if self.backwards() {
+            rows.reverse();
+        }
+
+        // Going forward, rows before the page exist when a cursor was followed. Going back,
+        // rows after it always do, those the cursor came from.
+        let (has_prev, has_next) = if self.backwards() {
+            (more, true)
+        } else {
+            (self.cursor.is_some(), more)
+        };
+        let prev_cursor = rows
+            .first()
+            .filter(|_| has_prev)
+            .map(|row| self.cursor_at(row.key.clone(), row.id, true));
+        let next_cursor = rows
+            .last()
+            .filter(|_| has_next)
+            .map(|row| self.cursor_at(row.key.clone(), row.id, false));
+
+        Page {
+            items: rows.into_iter().map(|row| row.item).collect(),
+            limit: self.limit,
+            next: next_cursor.as_deref().map(|cursor| link(req, cursor)),
+            prev: prev_cursor.as_deref().map(|cursor| link(req, cursor)),
+            next_cursor,
+            prev_cursor,
+        }
+    }
+}
+
+/// The path and query of `req` with `cursor` replaced.
+fn link(req: &HttpRequest, cursor: &str) -> String {
+    let mut query = url::form_urlencoded::Serializer::new(String::new());
+    for (name, value) in url::form_urlencoded::parse(req.query_string().as_bytes()) {
+        if name != "cursor" {
+            query.append_pair(&name, &value);
+        }
+    }
+    query.append_pair("cursor", cursor);
+    format!("{}?{}", req.path(), query.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: SortField = SortField {
+        name: "id",
+        expr: "t.id",
+        sql_type: "integer",
+    };
+
+    #[test]
+    fn test_cursor_roundtrip() {
+        let keyset = Keyset::new(ID, SortOrder::Desc, None, Some(1000)).unwrap();
+        assert_eq!(keyset.limit, MAX_LIMIT);
+        assert_eq!(keyset.key(), None);
+
+        let cursor = keyset.cursor_at("42".into(), 42, true);
+        let keyset = Keyset::new(ID, SortOrder::Desc, Some(&cursor), None).unwrap();
+        assert_eq!(keyset.limit, DEFAULT_LIMIT);
+        assert_eq!((keyset.key(), keyset.id()), (Some("42".into()), Some(42)));
+        assert_eq!(keyset.fetch_order(), SortOrder::Asc);
+        assert_eq!(
+            keyset.condition("t.id", 1, 2),
+            "($1::text IS NULL OR (t.id, t.id) > ($1::text::integer, $2::integer))"
+        );
+        assert_eq!(
+            keyset.order_by("t.id"),
+            "ORDER BY t.id ASC, t.id ASC LIMIT 21"
+        );
+
+        assert!(Keyset::new(ID, SortOrder::Asc, Some(&cursor), None).is_err());
+        assert!(Keyset::new(ID, SortOrder::Desc, Some("not a cursor"), None).is_err());
+    }
+
+    /// A cursor carrying a key its sort field can't hold never reaches the query.
+    #[test]
+    fn test_cursor_key() {
+        const CREATED: SortField = SortField {
+            name: "created",
+            expr: "t.created_at",
+            sql_type: "timestamptz",
+        };
+        const NAME: SortField = SortField {
+            name: "name",
+            expr: "t.name",
+            sql_type: "text",
+        };
+        let cursor = |sort: SortField, key: &str| {
+            Keyset::new(sort, SortOrder::Desc, None, None)
+                .unwrap()
+                .cursor_at(key.into(), 1, false)
+        };
+        let keyset =
+            |sort: SortField, cursor: &str| Keyset::new(sort, SortOrder::Desc, Some(cursor), None);
+
+        for key in [
+            "2024-05-01 12:30:00+00",
+            "2024-05-01 12:30:00.123456+00",
+            "2024-05-01 18:00:00.5+05:30",
+        ] {
+            assert!(keyset(CREATED, &cursor(CREATED, key)).is_ok(), "{}", key);
+        }
+        for key in ["Rust", "2024-05-01", "", "42x"] {
+            assert!(matches!(
+                keyset(CREATED, &cursor(CREATED, key)),
+                Err(ServiceError::BadRequest(_))
+            ));
+        }
+        assert!(keyset(ID, &cursor(ID, "42")).is_ok());
+        assert!(keyset(ID, &cursor(ID, "Rust")).is_err());
+        assert!(keyset(ID, &cursor(ID, "99999999999")).is_err());
+        assert!(keyset(NAME, &cursor(NAME, "anything")).is_ok());
+        // A name sorted cursor replayed on another sort.
+        assert!(keyset(CREATED, &cursor(NAME, "Rust")).is_err());
+    }
+
+    #[test]
+    fn test_page() {
+        let req = actix_web::test::TestRequest::get()
+            .uri("/tags/?q=rust&cursor=old&limit=2")
+            .to_http_request();
+        let rows = |ids: &[i32]| {
+            ids.iter()
+                .map(|id| Keyed {
+                    key: id.to_string(),
+                    id: *id,
+                    item: *id,
+                })
+                .collect::<Vec<Keyed<i32>>>()
+        };
+
+        let first = Keyset::new(ID, SortOrder::Desc, None, Some(2)).unwrap();
+        let page = first.page(&req, rows(&[9, 8, 7]));
+        assert_eq!(page.items, vec![9, 8]);
+        assert_eq!(page.prev_cursor, None);
+        let next = page.next_cursor.unwrap();
+        assert_eq!(
+            page.next.unwrap(),
+            format!("/tags/?q=rust&limit=2&cursor={}", next)
+        );
+
+        let second = Keyset::new(ID, SortOrder::Desc, Some(&next), Some(2)).unwrap();
+        assert_eq!((second.key(), second.id()), (Some("8".into()), Some(8)));
+        let page = second.page(&req, rows(&[7]));
+        assert_eq!(page.items, vec![7]);
+        assert_eq!(page.next_cursor, None);
+        let prev = page.prev_cursor.unwrap();
+
+        // Paging back fetches in reverse from 7 and finds 8 and 9, the first page.
+        let back = Keyset::new(ID, SortOrder::Desc, Some(&prev), Some(2)).unwrap();
+        assert_eq!(back.fetch_order(), SortOrder::Asc);
+        let page = back.page(&req, rows(&[8, 9]));
+        assert_eq!(page.items, vec![9, 8]);
+        assert_eq!(page.prev_cursor, None);
+        assert!(page.next_cursor.is_some());
+    }
+}
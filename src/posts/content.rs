@@ -0,0 +1,214 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+
+use pulldown_cmark::{html, Options, Parser};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::errors::ServiceError;
+
+/// How the `content` of a post is written, stored as text in the `content_format` column.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentFormat {
+    #[default]
+    Markdown,
+    Html,
+    Plain,
+}
+
+impl ContentFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentFormat::Markdown => "markdown",
+            ContentFormat::Html => "html",
+            ContentFormat::Plain => "plain",
+        }
+    }
+
+    /// The HTML served as `content_html` for `source`. Whatever the format, the result
+    /// only holds the tags and attributes `sanitize` lets through.
+    pub fn render(&self, source: &str) -> String {
+        match self {
+            ContentFormat::Markdown => {
+                let mut rendered = String::with_capacity(source.len() * 3 / 2);
+                html::push_html(&mut rendered, Parser::new_ext(source, markdown_options()));
+                sanitize(&rendered)
+            }
+            ContentFormat::Html => sanitize(source),
+            ContentFormat::Plain => render_plain(source),
+        }
+    }
+}
+
+impl FromStr for ContentFormat {
+    type Err = ServiceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "markdown" => Ok(ContentFormat::Markdown),
+            "html" => Ok(ContentFormat::Html),
+            "plain" => Ok(ContentFormat::Plain),
+            other => Err(ServiceError::BadRequest(format!(
+                "Unknown content format: {}",
+                other
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for ContentFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+fn markdown_options() -> Options {
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_TABLES);
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+    options.insert(Options::ENABLE_FOOTNOTES);
+    options
+}
+
+const ALLOWED_TAGS: &[&str] = &[
+    "a",
+    "abbr",
+    "b",
+    "blockquote",
+    "br",
+    "code",
+    "del",
+    "div",
+    "em",
+    "figcaption",
+    "figure",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "hr",
+    "i",
+    "img",
+    "li",
+    "ol",
+    "p",
+    "pre",
+    "s",
+    "span",
+    "strong",
+    "sub",
+    "sup",
+    "table",
+    "tbody",
+    "td",
+    "th",
+    "thead",
+    "tr",
+    "u",
+    "ul",
+];
+
+const ALLOWED_URL_SCHEMES: &[&str] = &["http", "https", "mailto"];
+
+/// Cleans untrusted HTML down to the tags post content needs. Scripts, styles, event
+/// handler attributes and `javascript:` links are dropped, links get `rel="noopener
+/// noreferrer nofollow"`.
+pub fn sanitize(untrusted: &str) -> String {
+    let tag_attributes = HashMap::from([
+        ("a", HashSet::from(["href", "title"])),
+        ("abbr", HashSet::from(["title"])),
+        (
+            "img",
+            HashSet::from(["src", "alt", "title", "width", "height"]),
+        ),
+        ("ol", HashSet::from(["start"])),
+        ("td", HashSet::from(["align", "colspan", "rowspan"])),
+        ("th", HashSet::from(["align", "colspan", "rowspan"])),
+        // Language hints from fenced code blocks.
+        ("code", HashSet::from(["class"])),
+        // Footnote anchors.
+        ("sup", HashSet::from(["id", "class"])),
+        ("div", HashSet::from(["id", "class"])),
+    ]);
+
+    ammonia::Builder::default()
+        .tags(ALLOWED_TAGS.iter().copied().collect())
+        .tag_attributes(tag_attributes)
+        .generic_attributes(HashSet::new())
+        .url_schemes(ALLOWED_URL_SCHEMES.iter().copied().collect())
+        .link_rel(Some("noopener noreferrer nofollow"))
+        .strip_comments(true)
+        .clean(untrusted)
+        .to_string()
+}
+
+/// Escapes text for use in HTML, inside elements and quoted attributes alike.
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Blank lines separate paragraphs, other line breaks are kept as `<br>`.
+fn render_plain(source: &str) -> String {
+    let normalized = source.replace("\r\n", "\n");
+    normalized
+        .split("\n\n")
+        .map(str::trim)
+        .filter(|paragraph| !paragraph.is_empty())
+        .map(|paragraph| format!("<p>{}</p>", escape_html(paragraph).replace('\n', "<br>")))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_markdown() {
+        let html = ContentFormat::Markdown
+            .render("# Title\n\nSome *text* and [a link](https://example.com).");
+        assert!(html.contains("<h1>Title</h1>"));
+        assert!(html.contains("<em>text</em>"));
+        assert!(html.contains(
+            r#"<a href="https://example.com" rel="noopener noreferrer nofollow">a link</a>"#
+        ));
+    }
+
+    #[test]
+    fn test_sanitize_drops_scripts() {
+        let markdown = ContentFormat::Markdown.render(
+            "Hi <script>alert(1)</script>\n\n[x](javascript:alert(1)) <img src=x onerror=alert(1)>",
+        );
+        assert!(!markdown.contains("script"));
+        assert!(!markdown.contains("javascript"));
+        assert!(!markdown.contains("onerror"));
+
+        let html = ContentFormat::Html.render(
+            r#"<p style="color:red" onclick="steal()">ok</p><iframe src="//evil"></iframe>"#,
+        );
+        assert_eq!(html, "<p>ok</p>");
+    }
+
+    #[test]
+    fn test_render_plain() {
+        assert_eq!(
+            ContentFormat::Plain.render("a <b>\nline\r\n\r\nnext & last"),
+            "<p>a &lt;b&gt;<br>line</p>\n<p>next &amp; last</p>"
+        );
+    }
+}
@@ -1,69 +1,244 @@
-use crate::posts::{CreatePost, Post};
+use crate::category::category_subtree;
+use crate::configs::db;
+use crate::errors::ServiceError;
+use crate::media::FILES_PATH;
+use crate::pagination::{Keyed, Keyset};
+use crate::posts::search::headline_options;
+use crate::posts::{
+    ContentFormat, CreatePost, Post, PostEvent, PostListQuery, PostRevision, PostSearchHit,
+    PostStatus,
+};
+use chrono::{DateTime, Utc};
 use deadpool_postgres::Client;
 use std::io;
 use tokio_pg_mapper::FromTokioPostgresRow;
+use tokio_postgres::error::SqlState;
 
-// CORE CRUD
+// Every post query returns the author's username, tags, categories, attached media and
+// comment count with the post.
+fn post_columns() -> String {
+    format!(
+        "p.id, p.title, p.slug, p.summary, p.content, p.content_format,
+        p.content_html, p.submitted_date, p.modified_date,
+        p.author_id, u.username AS author_username, p.status, p.published_at,
+        p.revision, p.language::text AS language,
+        COALESCE((SELECT json_agg(json_build_object('id', t.id, 'name', t.name) ORDER BY t.name)
+            FROM {posts_tags} pt JOIN {tags} t ON t.id = pt.tag_id
+            WHERE pt.post_id = p.id), '[]')::text AS tags,
+        COALESCE((SELECT json_agg(json_build_object('id', c.id, 'name', c.name, 'slug', c.slug)
+                ORDER BY c.name)
+            FROM {posts_categories} pc JOIN {categories} c ON c.id = pc.category_id
+            WHERE pc.post_id = p.id), '[]')::text AS categories,
+        COALESCE((SELECT json_agg(json_build_object('id', m.id,
+                'url', '{files}' || m.storage_key,
+                'thumbnail_url', '{files}' || m.thumbnail_key,
+                'content_type', m.content_type, 'width', m.width, 'height', m.height,
+                'alt_text', m.alt_text) ORDER BY pm.position)
+            FROM {posts_media} pm JOIN {media} m ON m.id = pm.media_id
+            WHERE pm.post_id = p.id), '[]')::text AS media,
+        (SELECT count(*) FROM {comments} cm
+            WHERE cm.post_id = p.id AND cm.status = 'approved') AS comment_count",
+        comments = db().comments,
+        posts_tags = db().posts_tags,
+        tags = db().tags,
+        posts_categories = db().posts_categories,
+        categories = db().categories,
+        posts_media = db().posts_media,
+        media = db().media,
+        files = FILES_PATH
+    )
+}
+
+fn post_select(from: &str) -> String {
+    format!(
+        "SELECT {} FROM {} p LEFT JOIN {users} u ON u.id = p.author_id",
+        post_columns(),
+        from,
+        users = db().users
+    )
+}
 
-//TODO configure .env for db shema name
+/// Records the post rows of the `changed` CTE as new revisions made by `$author_id`.
+fn revision_insert(changed: &str, author_id: &str, restored_from: &str) -> String {
+    format!(
+        "INSERT INTO {post_revisions} (post_id, revision, title, slug, summary, content,
+            content_format, author_id, restored_from)
+        SELECT id, revision, title, slug, summary, content, content_format, {}, {}
+        FROM {}",
+        author_id,
+        restored_from,
+        changed,
+        post_revisions = db().post_revisions
+    )
+}
+
+fn slug_conflict(e: tokio_postgres::Error) -> ServiceError {
+    match e.code() {
+        Some(code) if *code == SqlState::UNIQUE_VIOLATION => {
+            ServiceError::Conflict("A post with this slug already exists".into())
+        }
+        _ => ServiceError::from(e),
+    }
+}
+
+// CORE CRUD
 
 // Decide wether to return id or return all fields from insert sql query . if return ID, insert id in function argument.
 // shift id in db tables to the top so we can skip it when not needed
 
-pub async fn post_add(client: &Client, selfobj: CreatePost) -> Result<Post, io::Error> {
+/// Adds the post as its first revision, recording an event unless it starts as a draft.
+/// The content is stored along with its rendering in `format`.
+pub async fn post_add(
+    client: &Client,
+    selfobj: CreatePost,
+    format: ContentFormat,
+    author_id: Option<i32>,
+    status: PostStatus,
+    published_at: Option<DateTime<Utc>>,
+    language: &str,
+) -> Result<Post, ServiceError> {
     let statement = client
-        .prepare(
-            "INSERT INTO public.posts
-   (title, slug, summary, content)
-    VALUES ($0, $1, $2, $3) RETURNING slug, title, summary, content",
-        )
-        .await
-        .unwrap();
+        .prepare(&format!(
+            "WITH inserted AS (INSERT INTO {posts}
+   (title, slug, summary, content, author_id, status, published_at, content_format,
+    content_html, language)
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10::text::regconfig) RETURNING *),
+    event AS (INSERT INTO {post_events} (post_id, kind, actor_id)
+        SELECT id, status, author_id FROM inserted WHERE status <> 'draft'),
+    revision AS ({})
+    {}",
+            revision_insert("inserted", "author_id", "NULL"),
+            post_select("inserted"),
+            posts = db().posts,
+            post_events = db().post_events
+        ))
+        .await?;
 
-    client
-        .query(
+    let row = client
+        .query_one(
             &statement,
             &[
-                &selfobj.slug,
                 &selfobj.title,
+                &selfobj.slug,
                 &selfobj.summary,
                 &selfobj.content,
+                &author_id,
+                &status.as_str(),
+                &published_at,
+                &format.as_str(),
+                &format.render(&selfobj.content),
+                &language,
             ],
         )
         .await
-        .expect("Error creating post")
+        .map_err(slug_conflict)?;
+    Ok(Post::from_row_ref(&row).unwrap())
+}
+
+/// The latest published posts, optionally only those with `tag` or filed under `category`
+/// or one of its subcategories, for feeds.
+pub async fn post_feed(
+    client: &Client,
+    tag: Option<i32>,
+    category: Option<i32>,
+    limit: i64,
+) -> Result<Vec<Post>, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "SELECT {} FROM {posts} p LEFT JOIN {users} u ON u.id = p.author_id
+            WHERE p.status = 'published'
+            and ($1::integer IS NULL OR EXISTS (SELECT 1 FROM {posts_tags} pt
+                WHERE pt.post_id = p.id AND pt.tag_id = $1))
+            and ($2::integer IS NULL OR EXISTS (SELECT 1 FROM {posts_categories} pc
+                WHERE pc.post_id = p.id AND pc.category_id IN {category_subtree}))
+            ORDER BY p.published_at DESC, p.id DESC
+            LIMIT $3",
+            post_columns(),
+            category_subtree = category_subtree("$2::integer"),
+            posts = db().posts,
+            users = db().users,
+            posts_tags = db().posts_tags,
+            posts_categories = db().posts_categories
+        ))
+        .await?;
+
+    let posts = client
+        .query(&statement, &[&tag, &category, &limit])
+        .await?
         .iter()
         .map(|row| Post::from_row_ref(row).unwrap())
-        .collect::<Vec<Post>>()
-        .pop()
-        .ok_or(io::Error::new(
-            io::ErrorKind::Other,
-            "Error creating post tables",
-        ))
+        .collect::<Vec<Post>>();
+    Ok(posts)
 }
 
 // TODO populate fields
 
-pub async fn post_list(client: &Client) -> Result<Vec<Post>, io::Error> {
+/// A page of the published posts, plus every post of `viewer_id` or, with `see_all`,
+/// every post, matching the filters of `query`.
+pub async fn post_list(
+    client: &Client,
+    viewer_id: Option<i32>,
+    see_all: bool,
+    query: &PostListQuery,
+    keyset: &Keyset,
+) -> Result<Vec<Keyed<Post>>, ServiceError> {
+    let since_until = "COALESCE(p.published_at, p.submitted_date)";
     let statement = client
-        .prepare("select * from public.posts order by id desc")
-        .await
-        .unwrap();
+        .prepare(&format!(
+            "SELECT {}, {} FROM {posts} p LEFT JOIN {users} u ON u.id = p.author_id
+            where (p.status = 'published' OR p.author_id = $1 OR $2)
+            and ($3::text IS NULL OR p.status = $3)
+            and ($4::integer IS NULL OR EXISTS (SELECT 1 FROM {posts_tags} pt
+                WHERE pt.post_id = p.id AND pt.tag_id = $4))
+            and ($5::integer IS NULL OR EXISTS (SELECT 1 FROM {posts_categories} pc
+                WHERE pc.post_id = p.id AND pc.category_id IN {category_subtree}))
+            and ($6::integer IS NULL OR p.author_id = $6)
+            and ($7::timestamptz IS NULL OR {since_until} >= $7)
+            and ($8::timestamptz IS NULL OR {since_until} < $8)
+            and {}
+            {}",
+            post_columns(),
+            keyset.key_column(),
+            keyset.condition("p.id", 9, 10),
+            keyset.order_by("p.id"),
+            since_until = since_until,
+            category_subtree = category_subtree("$5::integer"),
+            posts = db().posts,
+            users = db().users,
+            posts_tags = db().posts_tags,
+            posts_categories = db().posts_categories
+        ))
+        .await?;
 
-    let content_list = client
-        .query(&statement, &[])
-        .await
-        .expect("Error getting author lists")
+    let posts = client
+        .query(
+            &statement,
+            &[
+                &viewer_id,
+                &see_all,
+                &query.status.map(|status| status.as_str()),
+                &query.tag,
+                &query.category,
+                &query.author,
+                &query.since,
+                &query.until,
+                &keyset.key(),
+                &keyset.id(),
+            ],
+        )
+        .await?
         .iter()
-        .map(|row| Post::from_row_ref(row).unwrap())
-        .collect::<Vec<Post>>();
-
-    Ok(content_list)
+        .map(|row| {
+            let post = Post::from_row_ref(row).unwrap();
+            Keyed::new(row, post.id, post)
+        })
+        .collect::<Vec<Keyed<Post>>>();
+    Ok(posts)
 }
 
 pub async fn post_id(client: &Client, id_post: i32) -> Result<Post, io::Error> {
     let statement = client
-        .prepare("select * from public.posts where id = $1")
+        .prepare(&format!("{} where p.id = $1", post_select(&db().posts)))
         .await
         .unwrap();
 
@@ -79,49 +254,327 @@ pub async fn post_id(client: &Client, id_post: i32) -> Result<Post, io::Error> {
     }
 }
 
-pub async fn post_search(client: &Client, post_search: String) -> Result<Vec<Post>, io::Error> {
+/// Published posts, and those `viewer_id` or an editor (`see_all`) may see, matching the
+/// tsquery text `query`, best matches first.
+#[allow(clippy::too_many_arguments)]
+pub async fn post_search(
+    client: &Client,
+    query: &str,
+    language: &str,
+    viewer_id: Option<i32>,
+    see_all: bool,
+    tag_id: Option<i32>,
+    category_id: Option<i32>,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<PostSearchHit>, ServiceError> {
     let statement = client
-        .prepare("select * from public.posts where title LIKE %$1%")
-        .await
-        .unwrap();
+        .prepare(&format!(
+            "WITH q AS (SELECT to_tsquery($1::text::regconfig, $2) AS query),
+            hits AS (
+                SELECT p.id, ts_rank(p.search_vector, q.query) AS rank
+                FROM {posts} p, q
+                WHERE p.search_vector @@ q.query
+                and (p.status = 'published' OR p.author_id = $3 OR $4)
+                and ($5::integer IS NULL OR EXISTS (SELECT 1 FROM {posts_tags} pt
+                    WHERE pt.post_id = p.id AND pt.tag_id = $5))
+                and ($6::integer IS NULL OR EXISTS (SELECT 1 FROM {posts_categories} pc
+                    WHERE pc.post_id = p.id AND pc.category_id IN {category_subtree}))
+                ORDER BY rank DESC, p.id DESC
+                LIMIT $7 OFFSET $8
+            )
+            SELECT {}, h.rank, ts_headline(p.language, p.content, q.query, $9) AS snippet
+            FROM hits h JOIN {posts} p ON p.id = h.id
+            LEFT JOIN {users} u ON u.id = p.author_id, q
+            ORDER BY h.rank DESC, p.id DESC",
+            post_columns(),
+            category_subtree = category_subtree("$6::integer"),
+            posts = db().posts,
+            posts_tags = db().posts_tags,
+            posts_categories = db().posts_categories,
+            users = db().users
+        ))
+        .await?;
 
-    let maybe_content = client
-        .query(&statement, &[&post_search])
-        .await
-        .expect("Error fetching content ")
+    let hits = client
+        .query(
+            &statement,
+            &[
+                &language,
+                &query,
+                &viewer_id,
+                &see_all,
+                &tag_id,
+                &category_id,
+                &limit,
+                &offset,
+                &headline_options(),
+            ],
+        )
+        .await?
         .iter()
-        .map(|row| Post::from_row_ref(&row).unwrap())
-        .collect::<Vec<Post>>();
-    Ok(maybe_content)
+        .map(PostSearchHit::from_row)
+        .collect::<Vec<PostSearchHit>>();
+    Ok(hits)
+}
+
+/// Whether Postgres has a text search configuration of this name.
+pub async fn search_language_exists(client: &Client, language: &str) -> Result<bool, ServiceError> {
+    let statement = client
+        .prepare("SELECT EXISTS (SELECT 1 FROM pg_catalog.pg_ts_config WHERE cfgname = $1)")
+        .await?;
+
+    Ok(client.query_one(&statement, &[&language]).await?.get(0))
 }
 
 //TODO take into account ID position
 
-pub async fn post_update(client: &Client, id: i32, mdl: CreatePost) -> Result<(), io::Error> {
-    let statement = client.prepare("update public.posts set (slug, title, summary, content) = ($1, $2, $3, $4) where id = $5").await.unwrap();
+/// Updates the post and records the result as its next revision, made by `author_id`.
+pub async fn post_update(
+    client: &Client,
+    id: i32,
+    mdl: CreatePost,
+    format: ContentFormat,
+    author_id: Option<i32>,
+    restored_from: Option<i32>,
+) -> Result<(), ServiceError> {
+    // The slug the post leaves is kept for redirects, one it takes back is no longer needed.
+    let statement = client
+        .prepare(&format!(
+            "WITH old AS (SELECT id, slug FROM {posts} WHERE id = $5),
+            updated AS (update {posts} set (slug, title, summary, content, content_format,
+                content_html, modified_date, revision, language)
+                = (COALESCE($1, slug), $2, $3, $4, $6, $7, now(), revision + 1,
+                    COALESCE($10::text::regconfig, language)) where id = $5 RETURNING *),
+            moved AS (INSERT INTO {post_slugs} (slug, post_id)
+                SELECT old.slug, old.id FROM old JOIN updated ON updated.id = old.id
+                WHERE old.slug <> updated.slug
+                ON CONFLICT (slug) DO UPDATE SET post_id = EXCLUDED.post_id, replaced_at = now()),
+            reclaimed AS (DELETE FROM {post_slugs}
+                WHERE post_id = $5 AND slug IN (SELECT slug FROM updated))
+            {}",
+            revision_insert("updated", "$8::integer", "$9::integer"),
+            posts = db().posts,
+            post_slugs = db().post_slugs
+        ))
+        .await?;
 
     let result = client
         .execute(
             &statement,
-            &[&mdl.slug, &mdl.title, &mdl.summary, &mdl.content],
+            &[
+                &mdl.slug,
+                &mdl.title,
+                &mdl.summary,
+                &mdl.content,
+                &id,
+                &format.as_str(),
+                &format.render(&mdl.content),
+                &author_id,
+                &restored_from,
+                &mdl.language,
+            ],
         )
         .await
-        .expect("Error updating post");
+        .map_err(slug_conflict)?;
 
     match result {
-        ref updated if *updated == 1 => Ok(()),
-        _ => Err(io::Error::new(io::ErrorKind::Other, "Failed to check list")),
+        1 => Ok(()),
+        _ => Err(ServiceError::NotFound("Post not found".into())),
     }
 }
 
-pub async fn post_delete(client: &Client, post_id: i32) -> Result<(), io::Error> {
+pub async fn post_delete(client: &Client, post_id: i32) -> Result<(), ServiceError> {
     let statement = client
-        .prepare("DELETE FROM public.posts WHERE id = $1")
-        .await
-        .unwrap();
+        .prepare(&format!(
+            "DELETE FROM {posts} WHERE id = $1",
+            posts = db().posts
+        ))
+        .await?;
 
-    client.execute(&statement, &[&post_id]).await.unwrap();
-    Ok(())
+    match client.execute(&statement, &[&post_id]).await? {
+        1 => Ok(()),
+        _ => Err(ServiceError::NotFound("Post not found".into())),
+    }
 }
 
 // END OF CORE CRUD
+
+/// Slugs starting with `prefix` that posts other than `exclude_id` go by or went by.
+pub async fn post_slugs_taken(
+    client: &Client,
+    prefix: &str,
+    exclude_id: Option<i32>,
+) -> Result<Vec<String>, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "SELECT slug FROM {posts} WHERE slug LIKE $1 || '%' AND id IS DISTINCT FROM $2
+            UNION
+            SELECT slug FROM {post_slugs} WHERE slug LIKE $1 || '%' AND post_id IS DISTINCT FROM $2",
+            posts = db().posts,
+            post_slugs = db().post_slugs
+        ))
+        .await?;
+
+    let slugs = client
+        .query(&statement, &[&prefix, &exclude_id])
+        .await?
+        .iter()
+        .map(|row| row.get(0))
+        .collect::<Vec<String>>();
+    Ok(slugs)
+}
+
+pub async fn post_by_slug(client: &Client, slug: &str) -> Result<Option<Post>, ServiceError> {
+    let statement = client
+        .prepare(&format!("{} where p.slug = $1", post_select(&db().posts)))
+        .await?;
+
+    let post = client
+        .query_opt(&statement, &[&slug])
+        .await?
+        .map(|row| Post::from_row_ref(&row).unwrap());
+    Ok(post)
+}
+
+/// The post that went by `slug` before, if any.
+pub async fn post_by_old_slug(client: &Client, slug: &str) -> Result<Option<Post>, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "{} JOIN {post_slugs} s ON s.post_id = p.id where s.slug = $1",
+            post_select(&db().posts),
+            post_slugs = db().post_slugs
+        ))
+        .await?;
+
+    let post = client
+        .query_opt(&statement, &[&slug])
+        .await?
+        .map(|row| Post::from_row_ref(&row).unwrap());
+    Ok(post)
+}
+
+/// Moves the post to `status` and records the change for `GET /posts/events`.
+pub async fn post_status_set(
+    client: &Client,
+    id: i32,
+    status: PostStatus,
+    published_at: Option<DateTime<Utc>>,
+    actor_id: Option<i32>,
+) -> Result<Post, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "WITH updated AS (UPDATE {posts} SET status = $2, published_at = $3,
+                modified_date = now() WHERE id = $1 RETURNING *),
+            event AS (INSERT INTO {post_events} (post_id, kind, actor_id)
+                SELECT id, status, $4 FROM updated)
+            {}",
+            post_select("updated"),
+            posts = db().posts,
+            post_events = db().post_events
+        ))
+        .await?;
+
+    client
+        .query_opt(
+            &statement,
+            &[&id, &status.as_str(), &published_at, &actor_id],
+        )
+        .await?
+        .map(|row| Post::from_row_ref(&row).unwrap())
+        .ok_or_else(|| ServiceError::NotFound("Post not found".into()))
+}
+
+/// Publishes the scheduled posts whose time has come, returning their ids. Safe to run
+/// from several instances at once.
+pub async fn posts_publish_due(client: &Client) -> Result<Vec<i32>, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "WITH due AS (UPDATE {posts} SET status = 'published', modified_date = now()
+                WHERE status = 'scheduled' AND published_at <= now() RETURNING id)
+            INSERT INTO {post_events} (post_id, kind) SELECT id, 'published' FROM due
+            RETURNING post_id",
+            posts = db().posts,
+            post_events = db().post_events
+        ))
+        .await?;
+
+    Ok(client
+        .query(&statement, &[])
+        .await?
+        .iter()
+        .map(|row| row.get(0))
+        .collect())
+}
+
+fn revision_select() -> String {
+    format!(
+        "SELECT r.post_id, r.revision, r.title, r.slug, r.summary, r.content, r.content_format,
+            r.author_id, u.username AS author_username, r.restored_from, r.created_at
+        FROM {post_revisions} r LEFT JOIN {users} u ON u.id = r.author_id",
+        post_revisions = db().post_revisions,
+        users = db().users
+    )
+}
+
+/// Revisions of a post, newest first.
+pub async fn post_revision_list(
+    client: &Client,
+    post_id: i32,
+) -> Result<Vec<PostRevision>, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "{} WHERE r.post_id = $1 ORDER BY r.revision DESC",
+            revision_select()
+        ))
+        .await?;
+
+    let revisions = client
+        .query(&statement, &[&post_id])
+        .await?
+        .iter()
+        .map(|row| PostRevision::from_row_ref(row).unwrap())
+        .collect::<Vec<PostRevision>>();
+    Ok(revisions)
+}
+
+pub async fn post_revision_get(
+    client: &Client,
+    post_id: i32,
+    revision: i32,
+) -> Result<PostRevision, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "{} WHERE r.post_id = $1 AND r.revision = $2",
+            revision_select()
+        ))
+        .await?;
+
+    client
+        .query_opt(&statement, &[&post_id, &revision])
+        .await?
+        .map(|row| PostRevision::from_row_ref(&row).unwrap())
+        .ok_or_else(|| ServiceError::NotFound(format!("Revision {} not found", revision)))
+}
+
+pub async fn post_event_list(
+    client: &Client,
+    after: i32,
+    limit: i64,
+) -> Result<Vec<PostEvent>, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "SELECT id, post_id, kind, actor_id, created_at FROM {post_events}
+            WHERE id > $1 ORDER BY id LIMIT $2",
+            post_events = db().post_events
+        ))
+        .await?;
+
+    let events = client
+        .query(&statement, &[&after, &limit])
+        .await?
+        .iter()
+        .map(|row| PostEvent::from_row_ref(row).unwrap())
+        .collect::<Vec<PostEvent>>();
+    Ok(events)
+}
@@ -1,14 +1,86 @@
+use crate::auth::{AuthUser, Role};
+use crate::category::{self, SetPostCategories};
+use crate::configs;
+use crate::errors::ServiceError;
+use crate::media::{self, SetPostMedia};
+use crate::pagination::Keyset;
 use crate::posts::db;
-use crate::posts::models::CreatePost;
+use crate::posts::models::{
+    CreatePost, Post, PostEventQuery, PostListQuery, PostSearchQuery, PostStatusChange,
+    RevisionDiffQuery,
+};
+use crate::posts::search::parse_search;
+use crate::posts_tags::{self, tag_names, SetPostTags};
+use crate::service_auth::ServiceIdentity;
+use crate::slugs::SlugChoice;
 use std::io;
 
-use actix_web::{delete, get, patch, post, web, HttpResponse, Responder};
+use actix_web::http::header;
+use actix_web::web::Query;
+use actix_web::{delete, get, patch, post, put, web, HttpRequest, HttpResponse, Responder};
+use chrono::Utc;
 use deadpool_postgres::{Client, Pool};
 use io::ErrorKind::NotFound;
 
+const DEFAULT_EVENT_LIMIT: i64 = 100;
+const MAX_EVENT_LIMIT: i64 = 500;
+const DEFAULT_SEARCH_LIMIT: i64 = 20;
+const MAX_SEARCH_LIMIT: i64 = 100;
+
+/// Editors and admins see and manage every post, not only their own.
+pub(crate) fn is_editor(user: &AuthUser) -> bool {
+    matches!(user.role, Role::Editor | Role::Admin)
+}
+
+/// Published posts are public, the others only visible to their author and editors.
+pub(crate) fn can_see(post: &Post, viewer: Option<&AuthUser>) -> bool {
+    post.status == "published"
+        || viewer.is_some_and(|user| is_editor(user) || post.author_id == Some(user.id))
+}
+
+/// `post`, unless `viewer` may not see it, which is answered as if it didn't exist.
+pub(crate) fn visible_post(post: Post, viewer: Option<&AuthUser>) -> Result<Post, ServiceError> {
+    if can_see(&post, viewer) {
+        Ok(post)
+    } else {
+        Err(ServiceError::NotFound("Post not found".into()))
+    }
+}
+
+/// The post, when `user` may change it: its author, editors and admins.
+pub(crate) async fn editable_post(
+    client: &Client,
+    id: i32,
+    user: &AuthUser,
+) -> Result<Post, ServiceError> {
+    let post = db::post_id(client, id)
+        .await
+        .map_err(|_| ServiceError::NotFound("Post not found".into()))?;
+    let post = visible_post(post, Some(user))?;
+    if !is_editor(user) && post.author_id != Some(user.id) {
+        return Err(ServiceError::Forbidden(
+            "Only the author or an editor can change this post".into(),
+        ));
+    }
+    Ok(post)
+}
+
+/// Refuses text search configurations Postgres doesn't have.
+async fn check_language(client: &Client, language: &str) -> Result<(), ServiceError> {
+    if db::search_language_exists(client, language).await? {
+        Ok(())
+    } else {
+        Err(ServiceError::BadRequest(format!(
+            "Unknown search language: {}",
+            language
+        )))
+    }
+}
+
 /// Get list of posts.
 ///
-/// List postss from in-memory post store.
+/// Anonymous callers only get published posts. Signed in users also get their own posts
+/// in any status, editors and admins every post. Follow `next` for the next page.
 ///
 /// One could call the api endpoint with following curl.
 /// ```text
@@ -16,26 +88,45 @@ use io::ErrorKind::NotFound;
 /// ```
 #[utoipa::path(
     responses(
-        (status = 200, description = "Post lists", body = [Post]),
-    )
+        (status = 200, description = "Post lists", body = PostPage),
+        (status = 400, description = "Invalid cursor", body = ServiceError)
+    ),
+    params(PostListQuery)
 )]
 #[get("/")]
-pub async fn posts(db_pool: web::Data<Pool>) -> impl Responder {
-    let client: Client = db_pool
-        .get()
-        .await
-        .expect("Error connecting to the database");
-
-    let result = db::post_list(&client).await;
+pub async fn posts(
+    req: HttpRequest,
+    viewer: Option<AuthUser>,
+    query: Query<PostListQuery>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let keyset = Keyset::new(
+        query.sort.unwrap_or_default().field(),
+        query.order.unwrap_or_default(),
+        query.cursor.as_deref(),
+        query.limit,
+    )?;
+    let client: Client = db_pool.get().await?;
 
-    match result {
-        Ok(object) => HttpResponse::Ok().json(object),
-        Err(_) => HttpResponse::InternalServerError().into(),
-    }
+    let posts = db::post_list(
+        &client,
+        viewer.as_ref().map(|user| user.id),
+        viewer.as_ref().is_some_and(is_editor),
+        &query,
+        &keyset,
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(keyset.page(&req, posts)))
 }
 
 /// Create new Post to shared in-memory storage.
 ///
+/// Needs a signed in user, who becomes the author. `content` is Markdown unless
+/// `content_format` says otherwise, and is served rendered and sanitized as
+/// `content_html`. Posts start as drafts unless `status` says otherwise, scheduled posts
+/// need a future `published_at`. Without a `slug` one is made from the title, with a
+/// number appended if needed to keep it unique.
+///
 /// Post a new `Todo` in request body as json to store it. Api will return
 /// created `Todo` on success or `ErrorResponse::Conflict` if todo with same id already exists.
 ///
@@ -47,30 +138,104 @@ pub async fn posts(db_pool: web::Data<Pool>) -> impl Responder {
     request_body = CreatePost,
     responses(
         (status = 201, description = "Category Successfully added", body = Post),
-        (status = 409, description = "Category with id already exists", body = ServiceError)
+        (status = 400, description = "Invalid status, published_at or slug", body = ServiceError),
+        (status = 401, description = "Not signed in", body = ServiceError),
+        (status = 409, description = "The slug is already in use", body = ServiceError)
     )
 )]
 #[post("/")]
 pub async fn add_posts(
+    author: AuthUser,
     local_object: web::Json<CreatePost>,
     db_pool: web::Data<Pool>,
-) -> impl Responder {
-    let client: Client = db_pool
-        .get()
-        .await
-        .expect("Error connecting to the database");
+) -> Result<HttpResponse, ServiceError> {
+    let mut new_post = local_object.into_inner();
+    let status = new_post.status.unwrap_or_default();
+    let published_at = status.published_at(new_post.published_at, None, Utc::now())?;
+    let choice = SlugChoice::new(new_post.slug.as_deref(), &new_post.title, "post")?;
+    let client: Client = db_pool.get().await?;
 
-    let result = db::post_add(&client, local_object.clone()).await;
+    let language = new_post
+        .language
+        .clone()
+        .unwrap_or_else(|| configs::Config::from_env().unwrap().srv_cnf.search_language);
+    check_language(&client, &language).await?;
+    new_post.slug = Some(free_slug(&client, &choice, None).await?);
 
-    match result {
-        Ok(object) => HttpResponse::Ok().json(object),
-        Err(_) => HttpResponse::InternalServerError().into(),
-    }
+    let format = new_post.content_format.unwrap_or_default();
+    let post = db::post_add(
+        &client,
+        new_post,
+        format,
+        Some(author.id),
+        status,
+        published_at,
+        &language,
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(post))
+}
+
+/// The slug `choice` ends up as for post `id`, or a new post.
+async fn free_slug(
+    client: &Client,
+    choice: &SlugChoice,
+    id: Option<i32>,
+) -> Result<String, ServiceError> {
+    let taken = db::post_slugs_taken(client, choice.prefix(), id).await?;
+    choice.resolve(&taken)
+}
+
+/// Search posts.
+///
+/// Matches the title, summary and content, best matches first, with excerpts of the
+/// content highlighting the matches. Visibility is the same as for the post list.
+#[utoipa::path(
+    context_path = "/posts",
+    responses(
+        (status = 200, description = "Matching posts", body = [PostSearchHit]),
+        (status = 400, description = "Nothing to search for or unknown language", body = ServiceError)
+    ),
+    params(PostSearchQuery)
+)]
+#[get("/search")]
+pub async fn search_posts(
+    viewer: Option<AuthUser>,
+    query: Query<PostSearchQuery>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let tsquery = parse_search(&query.q)
+        .ok_or_else(|| ServiceError::BadRequest("Nothing to search for".into()))?;
+    let client: Client = db_pool.get().await?;
+
+    let language = query
+        .lang
+        .clone()
+        .unwrap_or_else(|| configs::Config::from_env().unwrap().srv_cnf.search_language);
+    check_language(&client, &language).await?;
+
+    let hits = db::post_search(
+        &client,
+        &tsquery,
+        &language,
+        viewer.as_ref().map(|user| user.id),
+        viewer.as_ref().is_some_and(is_editor),
+        query.tag,
+        query.category,
+        query
+            .limit
+            .unwrap_or(DEFAULT_SEARCH_LIMIT)
+            .clamp(1, MAX_SEARCH_LIMIT),
+        query.offset.unwrap_or(0).max(0),
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(hits))
 }
 
 /// Get Category by given todo id.
 ///
 /// Return found `Category` with status 200 or 404 not found if `Category` is not found from db.
+/// Unpublished posts are only found by their author, editors and admins.
 #[utoipa::path(
     responses(
         (status = 200, description = "Post", body = Post),
@@ -81,7 +246,11 @@ pub async fn add_posts(
     )
 )]
 #[get("/{id}")]
-pub async fn get_posts(id_posts: web::Path<(i32,)>, db_pool: web::Data<Pool>) -> impl Responder {
+pub async fn get_posts(
+    viewer: Option<AuthUser>,
+    id_posts: web::Path<(i32,)>,
+    db_pool: web::Data<Pool>,
+) -> impl Responder {
     let client: Client = db_pool
         .get()
         .await
@@ -90,91 +259,479 @@ pub async fn get_posts(id_posts: web::Path<(i32,)>, db_pool: web::Data<Pool>) ->
     let result = db::post_id(&client, id_posts.0).await;
 
     match result {
+        Ok(object) if !can_see(&object, viewer.as_ref()) => HttpResponse::NotFound().into(),
         Ok(object) => HttpResponse::Ok().json(object),
         Err(ref e) if e.kind() == NotFound => HttpResponse::NotFound().into(),
         Err(_) => HttpResponse::InternalServerError().into(),
     }
 }
 
-/// Delete Post by given path variable id.
+/// Get a post by its slug.
 ///
-/// This endpoint needs `api_key` authentication in order to call. Api key can be found from README.md.
+/// A slug the post went by before answers with a 301 to its current one. Unpublished posts
+/// are only found by their author, editors and admins.
+#[utoipa::path(
+    context_path = "/posts",
+    responses(
+        (status = 200, description = "Post", body = Post),
+        (status = 301, description = "The post goes by another slug now, see Location"),
+        (status = 404, description = "No post goes or went by this slug", body = ServiceError)
+    ),
+    params(
+        ("slug", description = "Current or former slug of the post")
+    )
+)]
+#[get("/by-slug/{slug}", name = "post_by_slug")]
+pub async fn get_post_by_slug(
+    req: HttpRequest,
+    viewer: Option<AuthUser>,
+    slug: web::Path<(String,)>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let not_found = || ServiceError::NotFound("Post not found".into());
+    let client: Client = db_pool.get().await?;
+
+    if let Some(post) = db::post_by_slug(&client, &slug.0).await? {
+        return Ok(HttpResponse::Ok().json(visible_post(post, viewer.as_ref())?));
+    }
+    match db::post_by_old_slug(&client, &slug.0).await? {
+        Some(post) if can_see(&post, viewer.as_ref()) => {
+            let location = req
+                .url_for("post_by_slug", [&post.slug])
+                .map_err(|e| ServiceError::InternalServerError(e.to_string()))?;
+            Ok(HttpResponse::MovedPermanently()
+                .insert_header((header::LOCATION, location.as_str()))
+                .finish())
+        }
+        _ => Err(not_found()),
+    }
+}
+
+/// Delete a post.
 ///
-/// Api will delete todo from shared in-memory storage by the provided id and return success 200.
-/// If storage does not contain `Todo` with given id 404 not found will be returned.
+/// Open to the author, editors and admins.
 #[utoipa::path(
     responses(
         (status = 200, description = "Post deleted successfully"),
-        (status = 401, description = "Unauthorized to delete Post", body = ServiceError),
+        (status = 401, description = "Not signed in", body = ServiceError),
+        (status = 403, description = "Not the author of the post", body = ServiceError),
         (status = 404, description = "Post not found by id", body = ServiceError)
     ),
     params(
-        ("id", description = "Unique storage id of Category")
-    ),
-    security(
-        ("api_key" = [])
+        ("id", description = "Unique Post Id")
     )
 )]
 #[delete("/{id}")]
-pub async fn delete_posts(posts_id: web::Path<(i32,)>, db_pool: web::Data<Pool>) -> impl Responder {
-    let client: Client = db_pool
-        .get()
-        .await
-        .expect("Error connecting to the database");
-
-    let result = db::post_delete(&client, posts_id.0).await;
+pub async fn delete_posts(
+    user: AuthUser,
+    posts_id: web::Path<(i32,)>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let client: Client = db_pool.get().await?;
 
-    match result {
-        Ok(object) => HttpResponse::Ok().json(object),
-        Err(ref e) if e.kind() == NotFound => HttpResponse::NotFound().into(),
-        Err(_) => HttpResponse::InternalServerError().into(),
-    }
+    let post = editable_post(&client, posts_id.0, &user).await?;
+    db::post_delete(&client, post.id).await?;
+    Ok(HttpResponse::Ok().json(()))
 }
 
-/// Update Todo with given id.
+/// Update a post.
 ///
-/// This endpoint supports optional authentication.
-///
-/// Tries to update `Todo` by given id as path variable. If todo is found by id values are
-/// updated according `TodoUpdateRequest` and updated `Todo` is returned with status 200.
-/// If todo is not found then 404 not found is returned.
+/// Replaces the title, summary and content, and the slug and language when given. Open
+/// to the author, editors and admins. Each update is recorded as a new revision,
+/// attributed to the caller.
 #[utoipa::path(
     request_body = CreatePost,
     responses(
-        (status = 200, description = "Category updated successfully", body = Post),
-        (status = 404, description = "Category not found by id", body = ServiceError)
+        (status = 200, description = "Post updated successfully", body = Post),
+        (status = 400, description = "Invalid slug", body = ServiceError),
+        (status = 401, description = "Not signed in", body = ServiceError),
+        (status = 403, description = "Not the author of the post", body = ServiceError),
+        (status = 404, description = "Post not found by id", body = ServiceError),
+        (status = 409, description = "The slug is already in use", body = ServiceError)
     ),
     params(
-        ("id", description = "Unique storage id of Category")
-    ),
-    security(
-        (),
-        ("api_key" = [])
+        ("id", description = "Unique Post Id")
     )
 )]
 #[patch("/{id}")]
 pub async fn update_posts(
+    editor: AuthUser,
     id_posts: web::Path<(i32,)>,
     local_object: web::Json<CreatePost>,
     db_pool: web::Data<Pool>,
-) -> impl Responder {
-    let client: Client = db_pool
-        .get()
-        .await
-        .expect("Error connecting to the database");
+) -> Result<HttpResponse, ServiceError> {
+    let mut changes = local_object.into_inner();
+    let client: Client = db_pool.get().await?;
 
-    let result = db::post_update(&client, id_posts.0, local_object.clone()).await;
+    let post = editable_post(&client, id_posts.0, &editor).await?;
+    if let Some(language) = &changes.language {
+        check_language(&client, language).await?;
+    }
 
-    match result {
-        Ok(object) => HttpResponse::Ok().json(object),
-        Err(ref e) if e.kind() == NotFound => HttpResponse::NotFound().into(),
-        Err(_) => HttpResponse::InternalServerError().into(),
+    // Without a format the post keeps the one it was written in.
+    let format = match changes.content_format {
+        Some(format) => format,
+        None => post.content_format.parse().unwrap_or_default(),
+    };
+    if let Some(given) = changes.slug.as_deref() {
+        let choice = SlugChoice::new(Some(given), &changes.title, "post")?;
+        changes.slug = Some(free_slug(&client, &choice, Some(post.id)).await?);
+    }
+
+    db::post_update(&client, post.id, changes, format, Some(editor.id), None).await?;
+
+    let post = db::post_id(&client, post.id).await?;
+    Ok(HttpResponse::Ok().json(visible_post(post, Some(&editor))?))
+}
+
+/// Change the status of a post.
+///
+/// Drafts can be scheduled, published right away or archived, and published posts taken
+/// back to draft. Only the author, editors and admins may change the status. Every change
+/// is recorded as a post event.
+#[utoipa::path(
+    context_path = "/posts",
+    request_body = PostStatusChange,
+    responses(
+        (status = 200, description = "Status changed", body = Post),
+        (status = 400, description = "Invalid published_at for the status", body = ServiceError),
+        (status = 401, description = "Not signed in", body = ServiceError),
+        (status = 403, description = "Not the author of the post", body = ServiceError),
+        (status = 404, description = "Post not found by id", body = ServiceError)
+    ),
+    params(
+        ("id", description = "Unique Post Id")
+    )
+)]
+#[patch("/{id}/status")]
+pub async fn change_post_status(
+    user: AuthUser,
+    id_posts: web::Path<(i32,)>,
+    local_object: web::Json<PostStatusChange>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let client: Client = db_pool.get().await?;
+
+    let post = editable_post(&client, id_posts.0, &user).await?;
+    let published_at = local_object.status.published_at(
+        local_object.published_at,
+        post.published_at,
+        Utc::now(),
+    )?;
+    let post = db::post_status_set(
+        &client,
+        post.id,
+        local_object.status,
+        published_at,
+        Some(user.id),
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(post))
+}
+
+/// Status changes of posts, oldest first.
+///
+/// Meant for services reacting to publications: poll with the id of the last event seen as
+/// `after`. Open to editors, admins and signed service requests.
+#[utoipa::path(
+    context_path = "/posts",
+    responses(
+        (status = 200, description = "Post events", body = [PostEvent]),
+        (status = 403, description = "Editor role or a service signature required", body = ServiceError)
+    ),
+    params(PostEventQuery)
+)]
+#[get("/events")]
+pub async fn post_events(
+    service: Option<ServiceIdentity>,
+    user: Option<AuthUser>,
+    query: Query<PostEventQuery>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    if service.is_none() && !user.as_ref().is_some_and(is_editor) {
+        return Err(ServiceError::Forbidden(
+            "Editor role or a service signature required".into(),
+        ));
     }
+    let client: Client = db_pool.get().await?;
+
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_EVENT_LIMIT)
+        .clamp(1, MAX_EVENT_LIMIT);
+    let events = db::post_event_list(&client, query.after.unwrap_or(0), limit).await?;
+    Ok(HttpResponse::Ok().json(events))
+}
+
+/// Set the tags of a post.
+///
+/// Replaces all the tags of the post with those named, creating the tags that don't exist
+/// yet. Open to the author, editors and admins.
+#[utoipa::path(
+    context_path = "/posts",
+    request_body = SetPostTags,
+    responses(
+        (status = 200, description = "The post with its new tags", body = Post),
+        (status = 400, description = "Invalid tag names", body = ServiceError),
+        (status = 401, description = "Not signed in", body = ServiceError),
+        (status = 403, description = "Not the author of the post", body = ServiceError),
+        (status = 404, description = "Post not found by id", body = ServiceError)
+    ),
+    params(
+        ("id", description = "Unique Post Id")
+    )
+)]
+#[put("/{id}/tags")]
+pub async fn set_post_tags(
+    user: AuthUser,
+    id_posts: web::Path<(i32,)>,
+    local_object: web::Json<SetPostTags>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let names = tag_names(&local_object.tags)?;
+    let mut client: Client = db_pool.get().await?;
+
+    let post = editable_post(&client, id_posts.0, &user).await?;
+    posts_tags::db::post_tags_set(&mut client, post.id, &names).await?;
+
+    let post = db::post_id(&client, post.id).await?;
+    Ok(HttpResponse::Ok().json(post))
+}
+
+/// Set the categories of a post.
+///
+/// Files the post under exactly the categories given, which must exist. Open to the
+/// author, editors and admins.
+#[utoipa::path(
+    context_path = "/posts",
+    request_body = SetPostCategories,
+    responses(
+        (status = 200, description = "The post with its new categories", body = Post),
+        (status = 400, description = "Unknown category", body = ServiceError),
+        (status = 401, description = "Not signed in", body = ServiceError),
+        (status = 403, description = "Not the author of the post", body = ServiceError),
+        (status = 404, description = "Post not found by id", body = ServiceError)
+    ),
+    params(
+        ("id", description = "Unique Post Id")
+    )
+)]
+#[put("/{id}/categories")]
+pub async fn set_post_categories(
+    user: AuthUser,
+    id_posts: web::Path<(i32,)>,
+    local_object: web::Json<SetPostCategories>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let mut ids = local_object.categories.clone();
+    ids.sort_unstable();
+    ids.dedup();
+    let mut client: Client = db_pool.get().await?;
+
+    let post = editable_post(&client, id_posts.0, &user).await?;
+    category::db::post_categories_set(&mut client, post.id, &ids).await?;
+
+    let post = db::post_id(&client, post.id).await?;
+    Ok(HttpResponse::Ok().json(post))
+}
+
+/// Set the media attached to a post.
+///
+/// Attaches exactly the uploaded files given, in that order, which is the order posts
+/// list them in. Open to the author, editors and admins.
+#[utoipa::path(
+    context_path = "/posts",
+    request_body = SetPostMedia,
+    responses(
+        (status = 200, description = "The post with its new media", body = Post),
+        (status = 400, description = "Unknown media", body = ServiceError),
+        (status = 401, description = "Not signed in", body = ServiceError),
+        (status = 403, description = "Not the author of the post", body = ServiceError),
+        (status = 404, description = "Post not found by id", body = ServiceError)
+    ),
+    params(
+        ("id", description = "Unique Post Id")
+    )
+)]
+#[put("/{id}/media")]
+pub async fn set_post_media(
+    user: AuthUser,
+    id_posts: web::Path<(i32,)>,
+    local_object: web::Json<SetPostMedia>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    // The order matters here, only repeats are dropped.
+    let mut ids = local_object.media.clone();
+    let mut seen = std::collections::HashSet::new();
+    ids.retain(|id| seen.insert(*id));
+    let mut client: Client = db_pool.get().await?;
+
+    let post = editable_post(&client, id_posts.0, &user).await?;
+    media::db::post_media_set(&mut client, post.id, &ids).await?;
+
+    let post = db::post_id(&client, post.id).await?;
+    Ok(HttpResponse::Ok().json(post))
+}
+
+/// Revisions of a post, newest first.
+///
+/// Every revision is a full snapshot of the post after a change. Open to the author,
+/// editors and admins.
+#[utoipa::path(
+    context_path = "/posts",
+    responses(
+        (status = 200, description = "Revisions of the post", body = [PostRevision]),
+        (status = 401, description = "Not signed in", body = ServiceError),
+        (status = 403, description = "Not the author of the post", body = ServiceError),
+        (status = 404, description = "Post not found by id", body = ServiceError)
+    ),
+    params(
+        ("id", description = "Unique Post Id")
+    )
+)]
+#[get("/{id}/revisions")]
+pub async fn post_revisions(
+    user: AuthUser,
+    id_posts: web::Path<(i32,)>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let client: Client = db_pool.get().await?;
+
+    let post = editable_post(&client, id_posts.0, &user).await?;
+    let revisions = db::post_revision_list(&client, post.id).await?;
+    Ok(HttpResponse::Ok().json(revisions))
+}
+
+/// Unified diff between two revisions of a post.
+///
+/// Covers the title, slug, summary and format as well as the content.
+#[utoipa::path(
+    context_path = "/posts",
+    responses(
+        (status = 200, description = "Unified diff, empty when the revisions are the same", body = String, content_type = "text/x-diff"),
+        (status = 401, description = "Not signed in", body = ServiceError),
+        (status = 403, description = "Not the author of the post", body = ServiceError),
+        (status = 404, description = "Post or revision not found", body = ServiceError)
+    ),
+    params(
+        ("id", description = "Unique Post Id"),
+        RevisionDiffQuery
+    )
+)]
+#[get("/{id}/revisions/diff")]
+pub async fn post_revision_diff(
+    user: AuthUser,
+    id_posts: web::Path<(i32,)>,
+    query: Query<RevisionDiffQuery>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let client: Client = db_pool.get().await?;
+
+    let post = editable_post(&client, id_posts.0, &user).await?;
+    let from = db::post_revision_get(&client, post.id, query.from).await?;
+    let to = db::post_revision_get(&client, post.id, query.to).await?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/x-diff; charset=utf-8")
+        .body(from.diff(&to)))
+}
+
+/// Get one revision of a post.
+#[utoipa::path(
+    context_path = "/posts",
+    responses(
+        (status = 200, description = "The revision", body = PostRevision),
+        (status = 401, description = "Not signed in", body = ServiceError),
+        (status = 403, description = "Not the author of the post", body = ServiceError),
+        (status = 404, description = "Post or revision not found", body = ServiceError)
+    ),
+    params(
+        ("id", description = "Unique Post Id"),
+        ("revision", description = "Revision number")
+    )
+)]
+#[get("/{id}/revisions/{revision}")]
+pub async fn post_revision(
+    user: AuthUser,
+    path: web::Path<(i32, i32)>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let (id, revision) = path.into_inner();
+    let client: Client = db_pool.get().await?;
+
+    let post = editable_post(&client, id, &user).await?;
+    let revision = db::post_revision_get(&client, post.id, revision).await?;
+    Ok(HttpResponse::Ok().json(revision))
+}
+
+/// Restore an old revision of a post.
+///
+/// The post gets the title, slug, summary and content of that revision back, recorded as a
+/// new revision so the history is kept. The status is left as it is.
+#[utoipa::path(
+    context_path = "/posts",
+    responses(
+        (status = 200, description = "The restored post", body = Post),
+        (status = 401, description = "Not signed in", body = ServiceError),
+        (status = 403, description = "Not the author of the post", body = ServiceError),
+        (status = 404, description = "Post or revision not found", body = ServiceError)
+    ),
+    params(
+        ("id", description = "Unique Post Id"),
+        ("revision", description = "Revision number to restore")
+    )
+)]
+#[post("/{id}/revisions/{revision}/restore")]
+pub async fn restore_post_revision(
+    user: AuthUser,
+    path: web::Path<(i32, i32)>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let (id, revision) = path.into_inner();
+    let client: Client = db_pool.get().await?;
+
+    let post = editable_post(&client, id, &user).await?;
+    let revision = db::post_revision_get(&client, post.id, revision).await?;
+    // The slug comes back too, unless another post has taken it since.
+    let slug = match free_slug(&client, &SlugChoice::Given(revision.slug), Some(post.id)).await {
+        Ok(slug) => Some(slug),
+        Err(ServiceError::Conflict(_)) => None,
+        Err(e) => return Err(e),
+    };
+    let restored = CreatePost {
+        title: revision.title,
+        slug,
+        summary: revision.summary,
+        content: revision.content,
+        ..Default::default()
+    };
+    db::post_update(
+        &client,
+        post.id,
+        restored,
+        revision.content_format.parse()?,
+        Some(user.id),
+        Some(revision.revision),
+    )
+    .await?;
+
+    let post = db::post_id(&client, post.id).await?;
+    Ok(HttpResponse::Ok().json(post))
 }
 
 pub fn init_routes(cfg: &mut web::ServiceConfig) {
     cfg.service(posts);
     cfg.service(add_posts);
+    cfg.service(post_events);
+    cfg.service(search_posts);
+    cfg.service(get_post_by_slug);
+    cfg.service(change_post_status);
+    cfg.service(set_post_tags);
+    cfg.service(set_post_categories);
+    cfg.service(set_post_media);
+    cfg.service(post_revisions);
+    cfg.service(post_revision_diff);
+    cfg.service(post_revision);
+    cfg.service(restore_post_revision);
     cfg.service(update_posts);
     cfg.service(get_posts);
     cfg.service(delete_posts);
@@ -186,3 +743,48 @@ pub fn init_routes(cfg: &mut web::ServiceConfig) {
 //     println!("{:#?}", res);
 //     res
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(id: i32, role: Role) -> AuthUser {
+        AuthUser {
+            id,
+            email: format!("user{}@example.com", id),
+            username: None,
+            role,
+            session_id: Some(1),
+            impersonator_id: None,
+        }
+    }
+
+    #[test]
+    fn test_visible_post() {
+        let post = |status: &str| Post {
+            id: 7,
+            status: status.into(),
+            author_id: Some(1),
+            ..Default::default()
+        };
+        let author = user(1, Role::User);
+        let other = user(2, Role::User);
+        let editor = user(3, Role::Editor);
+
+        for viewer in [None, Some(&author), Some(&other), Some(&editor)] {
+            assert!(visible_post(post("published"), viewer).is_ok());
+        }
+        for status in ["draft", "scheduled", "archived"] {
+            assert!(matches!(
+                visible_post(post(status), None),
+                Err(ServiceError::NotFound(_))
+            ));
+            assert!(matches!(
+                visible_post(post(status), Some(&other)),
+                Err(ServiceError::NotFound(_))
+            ));
+            assert!(visible_post(post(status), Some(&author)).is_ok());
+            assert!(visible_post(post(status), Some(&editor)).is_ok());
+        }
+    }
+}
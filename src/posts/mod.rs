@@ -1,7 +1,12 @@
+pub mod content;
 pub mod db;
 pub mod handlers;
 pub mod models;
+pub mod scheduler;
+pub mod search;
+pub use crate::posts::content::*;
 pub use crate::posts::db::*; 
 pub use crate::posts::handlers::*;
 pub use crate::posts::models::*;
-
+pub use crate::posts::scheduler::*;
+pub use crate::posts::search::*;
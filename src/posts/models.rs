@@ -1,11 +1,25 @@
+use std::fmt;
+use std::str::FromStr;
+
 use serde::{Deserialize, Serialize};
+use similar::TextDiff;
+use tokio_pg_mapper::FromTokioPostgresRow;
 use tokio_pg_mapper_derive::PostgresMapper;
+use tokio_postgres::Row;
 
 // extern crate chrono;
 //use chrono::prelude::*;
 //use chrono::{DateTime, Duration, Utc};
-use chrono::Utc;
-use utoipa::ToSchema;
+use chrono::{DateTime, Utc};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::category::CategoryList;
+use crate::errors::ServiceError;
+use crate::media::MediaList;
+use crate::pagination::{SortField, SortOrder};
+use crate::posts::content::ContentFormat;
+use crate::posts::search::highlight;
+use crate::tags::TagList;
 //To be added based on special query
 
 #[derive(Serialize, Debug, Clone, Deserialize, ToSchema, PostgresMapper, Default)]
@@ -15,16 +29,421 @@ pub struct Post {
     pub title: String,
     pub slug: String,
     pub summary: String,
+    /// The source as written, in `content_format`.
     pub content: String,
+    /// One of `markdown`, `html` or `plain`.
+    pub content_format: String,
+    /// `content` rendered and sanitized, safe to embed in a page.
+    pub content_html: String,
     pub submitted_date: chrono::DateTime<Utc>,
     pub modified_date: chrono::DateTime<Utc>,
+    pub author_id: Option<i32>,
+    pub author_username: Option<String>,
+    /// One of `draft`, `scheduled`, `published` or `archived`.
+    pub status: String,
+    /// When the post went public, or will for scheduled posts.
+    pub published_at: Option<chrono::DateTime<Utc>>,
+    /// Number of the latest revision, see `GET /posts/{id}/revisions`.
+    pub revision: i32,
+    /// Text search configuration the post is indexed with.
+    pub language: String,
+    /// Set with `PUT /posts/{id}/tags`.
+    pub tags: TagList,
+    /// Set with `PUT /posts/{id}/categories`.
+    pub categories: CategoryList,
+    /// Set with `PUT /posts/{id}/media`.
+    pub media: MediaList,
+    /// Approved comments, see `GET /posts/{id}/comments`.
+    pub comment_count: i64,
 }
 
-#[derive(Serialize, Debug, Clone, Deserialize, ToSchema, PostgresMapper, Default)]
-#[pg_mapper(table = "posts")]
+#[derive(Serialize, Debug, Clone, Deserialize, ToSchema, Default)]
 pub struct CreatePost {
+    pub title: String,
+    /// Made from the title when left out, or kept as it is when updating. The slug a post
+    /// leaves keeps leading to it, see `GET /posts/by-slug/{slug}`.
+    #[serde(default)]
+    pub slug: Option<String>,
+    pub summary: String,
+    pub content: String,
+    /// Defaults to `markdown`, or the current format when updating.
+    #[serde(default)]
+    pub content_format: Option<ContentFormat>,
+    /// Defaults to `draft`. Ignored when updating, change it with `PATCH /posts/{id}/status`.
+    #[serde(default)]
+    pub status: Option<PostStatus>,
+    /// Required for `scheduled` posts.
+    #[serde(default)]
+    pub published_at: Option<chrono::DateTime<Utc>>,
+    /// Text search configuration to index the post with, such as `english` or `french`.
+    /// Defaults to the server's `search_language`, or the current one when updating.
+    #[serde(default)]
+    pub language: Option<String>,
+}
+
+/// Where a post is in its life, stored as text in the `status` column. Only `published`
+/// posts are public.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PostStatus {
+    #[default]
+    Draft,
+    Scheduled,
+    Published,
+    Archived,
+}
+
+impl PostStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PostStatus::Draft => "draft",
+            PostStatus::Scheduled => "scheduled",
+            PostStatus::Published => "published",
+            PostStatus::Archived => "archived",
+        }
+    }
+
+    /// The `published_at` a post moving to this status gets. `requested` is what the
+    /// caller asked for, `current` what the post has now.
+    pub fn published_at(
+        self,
+        requested: Option<DateTime<Utc>>,
+        current: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> Result<Option<DateTime<Utc>>, ServiceError> {
+        match self {
+            PostStatus::Draft => Ok(None),
+            PostStatus::Scheduled => match requested {
+                Some(at) if at > now => Ok(Some(at)),
+                Some(_) => Err(ServiceError::BadRequest(
+                    "Posts can only be scheduled for the future".into(),
+                )),
+                None => Err(ServiceError::BadRequest(
+                    "Scheduled posts need published_at".into(),
+                )),
+            },
+            PostStatus::Published => match requested {
+                Some(at) if at > now => Err(ServiceError::BadRequest(
+                    "Use the scheduled status to publish later".into(),
+                )),
+                Some(at) => Ok(Some(at)),
+                // Republishing keeps the original date, a pending schedule is dropped.
+                None => Ok(Some(current.filter(|at| *at <= now).unwrap_or(now))),
+            },
+            PostStatus::Archived => Ok(current.filter(|at| *at <= now)),
+        }
+    }
+}
+
+impl FromStr for PostStatus {
+    type Err = ServiceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "draft" => Ok(PostStatus::Draft),
+            "scheduled" => Ok(PostStatus::Scheduled),
+            "published" => Ok(PostStatus::Published),
+            "archived" => Ok(PostStatus::Archived),
+            other => Err(ServiceError::BadRequest(format!(
+                "Unknown post status: {}",
+                other
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for PostStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Deserialize, ToSchema)]
+#[schema(example = json!({"status": "scheduled", "published_at": "2030-01-01T09:00:00Z"}))]
+pub struct PostStatusChange {
+    pub status: PostStatus,
+    /// Required when scheduling. When publishing, a past date backdates the post.
+    #[serde(default)]
+    pub published_at: Option<chrono::DateTime<Utc>>,
+}
+
+/// What the post list can be sorted on.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PostSort {
+    /// Publication date, the submission date for posts not published yet.
+    #[default]
+    Published,
+    Submitted,
+    Modified,
+    Title,
+    Id,
+}
+
+impl PostSort {
+    pub fn field(&self) -> SortField {
+        match self {
+            PostSort::Published => SortField {
+                name: "published",
+                expr: "COALESCE(p.published_at, p.submitted_date)",
+                sql_type: "timestamptz",
+            },
+            PostSort::Submitted => SortField {
+                name: "submitted",
+                expr: "p.submitted_date",
+                sql_type: "timestamptz",
+            },
+            PostSort::Modified => SortField {
+                name: "modified",
+                expr: "p.modified_date",
+                sql_type: "timestamptz",
+            },
+            PostSort::Title => SortField {
+                name: "title",
+                expr: "p.title",
+                sql_type: "text",
+            },
+            PostSort::Id => SortField {
+                name: "id",
+                expr: "p.id",
+                sql_type: "integer",
+            },
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, IntoParams, Default)]
+#[into_params(parameter_in = Query)]
+pub struct PostListQuery {
+    /// Only posts with this status. Others than `published` only list the caller's own
+    /// posts, or every post for editors.
+    pub status: Option<PostStatus>,
+    /// Only posts with this tag.
+    pub tag: Option<i32>,
+    /// Only posts filed under this category or one of its subcategories.
+    pub category: Option<i32>,
+    /// Only posts by this user.
+    pub author: Option<i32>,
+    /// Only posts published (or, unpublished, submitted) at or after this time.
+    pub since: Option<chrono::DateTime<Utc>>,
+    /// Only posts published (or, unpublished, submitted) before this time.
+    pub until: Option<chrono::DateTime<Utc>>,
+    /// Field to sort on, `published` by default.
+    pub sort: Option<PostSort>,
+    /// `desc` by default.
+    pub order: Option<SortOrder>,
+    /// `next_cursor` or `prev_cursor` of the page before.
+    pub cursor: Option<String>,
+    /// At most this many posts, 20 by default and at most 100.
+    pub limit: Option<i64>,
+}
+
+/// A post changing status, for services reacting to publications.
+#[derive(Serialize, Debug, Clone, Deserialize, ToSchema, PostgresMapper)]
+#[pg_mapper(table = "post_events")]
+pub struct PostEvent {
+    pub id: i32,
+    pub post_id: i32,
+    /// The status the post moved to.
+    pub kind: String,
+    /// Who changed the status, `None` when the scheduler published the post.
+    pub actor_id: Option<i32>,
+    pub created_at: chrono::DateTime<Utc>,
+}
+
+#[derive(Deserialize, Debug, Clone, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PostEventQuery {
+    /// Only events after this id, pass the last id seen to poll for new ones.
+    pub after: Option<i32>,
+    /// At most this many events, 100 by default and at most 500.
+    pub limit: Option<i64>,
+}
+
+/// A post as it was after one change.
+#[derive(Serialize, Debug, Clone, Deserialize, ToSchema, PostgresMapper)]
+#[pg_mapper(table = "post_revisions")]
+pub struct PostRevision {
+    pub post_id: i32,
+    /// Counts up from 1 for each post.
+    pub revision: i32,
     pub title: String,
     pub slug: String,
     pub summary: String,
     pub content: String,
+    pub content_format: String,
+    /// Who made the change.
+    pub author_id: Option<i32>,
+    pub author_username: Option<String>,
+    /// The revision brought back, when the change was a restore.
+    pub restored_from: Option<i32>,
+    pub created_at: chrono::DateTime<Utc>,
+}
+
+impl PostRevision {
+    /// The revision as text, fields first and the content after a blank line.
+    fn snapshot(&self) -> String {
+        format!(
+            "title: {}\nslug: {}\nsummary: {}\nformat: {}\n\n{}\n",
+            self.title,
+            self.slug,
+            self.summary,
+            self.content_format,
+            self.content.trim_end_matches('\n')
+        )
+    }
+
+    /// The changes from `self` to `to` as a unified diff.
+    pub fn diff(&self, to: &PostRevision) -> String {
+        let (old, new) = (self.snapshot(), to.snapshot());
+        TextDiff::from_lines(&old, &new)
+            .unified_diff()
+            .context_radius(3)
+            .header(
+                &format!("posts/{}@{}", self.post_id, self.revision),
+                &format!("posts/{}@{}", to.post_id, to.revision),
+            )
+            .to_string()
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RevisionDiffQuery {
+    /// The older revision.
+    pub from: i32,
+    /// The newer revision.
+    pub to: i32,
+}
+
+#[derive(Deserialize, Debug, Clone, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PostSearchQuery {
+    /// Words to look for. `"quoted words"` match a phrase, `word*` a prefix, `-word`
+    /// excludes and `OR` allows either of two terms.
+    pub q: String,
+    /// Text search configuration to read `q` with, the server's `search_language` by default.
+    pub lang: Option<String>,
+    /// Only posts with this tag.
+    pub tag: Option<i32>,
+    /// Only posts filed under this category or one of its subcategories.
+    pub category: Option<i32>,
+    /// At most this many results, 20 by default and at most 100.
+    pub limit: Option<i64>,
+    /// Results to skip.
+    pub offset: Option<i64>,
+}
+
+/// A post matching a search.
+#[derive(Serialize, Debug, Clone, Deserialize, ToSchema)]
+pub struct PostSearchHit {
+    pub post: Post,
+    /// Relevance, higher is better. Title matches weigh more than summary matches, and
+    /// those more than content matches.
+    pub rank: f32,
+    /// Escaped HTML excerpts of the content with the matches in `<mark>`.
+    pub snippet: String,
+}
+
+impl PostSearchHit {
+    pub fn from_row(row: &Row) -> PostSearchHit {
+        PostSearchHit {
+            post: Post::from_row_ref(row).unwrap(),
+            rank: row.get("rank"),
+            snippet: highlight(row.get("snippet")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_published_at() {
+        let now = Utc::now();
+        let past = now - Duration::days(3);
+        let future = now + Duration::days(3);
+
+        assert_eq!(
+            PostStatus::Draft
+                .published_at(Some(past), Some(past), now)
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            PostStatus::Scheduled
+                .published_at(Some(future), None, now)
+                .unwrap(),
+            Some(future)
+        );
+        assert!(PostStatus::Scheduled
+            .published_at(Some(past), None, now)
+            .is_err());
+        assert!(PostStatus::Scheduled.published_at(None, None, now).is_err());
+
+        assert_eq!(
+            PostStatus::Published.published_at(None, None, now).unwrap(),
+            Some(now)
+        );
+        assert_eq!(
+            PostStatus::Published
+                .published_at(None, Some(past), now)
+                .unwrap(),
+            Some(past)
+        );
+        assert_eq!(
+            PostStatus::Published
+                .published_at(None, Some(future), now)
+                .unwrap(),
+            Some(now)
+        );
+        assert!(PostStatus::Published
+            .published_at(Some(future), None, now)
+            .is_err());
+
+        assert_eq!(
+            PostStatus::Archived
+                .published_at(None, Some(past), now)
+                .unwrap(),
+            Some(past)
+        );
+        assert_eq!(
+            PostStatus::Archived
+                .published_at(None, Some(future), now)
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_revision_diff() {
+        let first = PostRevision {
+            post_id: 7,
+            revision: 1,
+            title: "Hello".into(),
+            slug: "hello".into(),
+            summary: "".into(),
+            content: "one\ntwo\n".into(),
+            content_format: "markdown".into(),
+            author_id: None,
+            author_username: None,
+            restored_from: None,
+            created_at: Utc::now(),
+        };
+        let second = PostRevision {
+            revision: 2,
+            title: "Hello again".into(),
+            content: "one\nthree".into(),
+            ..first.clone()
+        };
+
+        assert_eq!(
+            first.diff(&second),
+            "--- posts/7@1\n+++ posts/7@2\n@@ -1,7 +1,7 @@\n-title: Hello\n+title: Hello again\n \
+             slug: hello\n summary: \n format: markdown\n \n one\n-two\n+three\n"
+        );
+        assert_eq!(first.diff(&first), "");
+    }
 }
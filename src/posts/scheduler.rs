@@ -0,0 +1,34 @@
+use std::time::Duration;
+
+use actix_web::rt::time;
+use deadpool_postgres::Pool;
+
+use crate::configs;
+use crate::posts::db;
+
+/// Publishes scheduled posts once their `published_at` has passed, checking every
+/// `post_scheduler_interval_secs`. Each publication is recorded as a post event.
+pub async fn run_scheduler(pool: Pool) {
+    let config = configs::Config::from_env().unwrap();
+    let mut interval = time::interval(Duration::from_secs(
+        config.srv_cnf.post_scheduler_interval_secs.max(1),
+    ));
+
+    loop {
+        interval.tick().await;
+        let client = match pool.get().await {
+            Ok(client) => client,
+            Err(e) => {
+                log::error!("post scheduler could not reach the database: {}", e);
+                continue;
+            }
+        };
+        match db::posts_publish_due(&client).await {
+            Ok(published) if !published.is_empty() => {
+                log::info!("published scheduled posts {:?}", published)
+            }
+            Ok(_) => {}
+            Err(e) => log::error!("post scheduler failed: {:?}", e),
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use crate::posts::content::escape_html;
+
+/// Marks a match in `ts_headline` output, replaced by `<mark>` once the snippet is escaped.
+pub const HIGHLIGHT_START: char = '\u{2}';
+pub const HIGHLIGHT_STOP: char = '\u{3}';
+
+/// The words of a search term. Punctuation splits words, so only letters and digits ever
+/// reach `to_tsquery`.
+fn words(term: &str) -> Vec<&str> {
+    term.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+/// One term or quoted phrase as tsquery text: its words must follow each other, and a
+/// trailing `*` makes the last one a prefix.
+fn term_query(term: &str, prefix: bool) -> Option<String> {
+    let words = words(term);
+    if words.is_empty() {
+        return None;
+    }
+    let mut query = words
+        .iter()
+        .map(|word| format!("'{}'", word))
+        .collect::<Vec<String>>()
+        .join(" <-> ");
+    if prefix {
+        query.push_str(":*");
+    }
+    Some(query)
+}
+
+/// Turns what a user typed into the text of a `to_tsquery`, or `None` when nothing is
+/// left to search for.
+///
+/// Words must all match, `"quoted words"` match as a phrase, `word*` matches words
+/// starting with `word`, `-word` excludes posts with it and `OR` between two terms lets
+/// either match.
+pub fn parse_search(input: &str) -> Option<String> {
+    let mut query = String::new();
+    let mut pending_or = false;
+    let mut rest = input.trim_start();
+
+    while !rest.is_empty() {
+        let negated = rest.starts_with('-');
+        if negated {
+            rest = &rest[1..];
+        }
+
+        let (term, prefix, quoted) = if let Some(phrase) = rest.strip_prefix('"') {
+            let end = phrase.find('"').unwrap_or(phrase.len());
+            let term = &phrase[..end];
+            rest = phrase.get(end + 1..).unwrap_or("");
+            (term, false, true)
+        } else {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let term = &rest[..end];
+            rest = &rest[end..];
+            match term.strip_suffix('*') {
+                Some(term) => (term, true, false),
+                None => (term, false, false),
+            }
+        };
+        rest = rest.trim_start();
+
+        if !negated && !quoted && term == "OR" {
+            pending_or = !query.is_empty();
+            continue;
+        }
+        let term = match term_query(term, prefix) {
+            Some(term) if negated => format!("!({})", term),
+            Some(term) => term,
+            None => continue,
+        };
+        if !query.is_empty() {
+            query.push_str(if pending_or { " | " } else { " & " });
+        }
+        query.push_str(&term);
+        pending_or = false;
+    }
+
+    if query.is_empty() {
+        None
+    } else {
+        Some(query)
+    }
+}
+
+/// `ts_headline` options, fragments of the content around the matches.
+pub fn headline_options() -> String {
+    format!(
+        "StartSel={}, StopSel={}, MaxFragments=2, MaxWords=30, MinWords=10, FragmentDelimiter= … ",
+        HIGHLIGHT_START, HIGHLIGHT_STOP
+    )
+}
+
+/// Escapes a `ts_headline` snippet and marks the matches with `<mark>`.
+pub fn highlight(snippet: &str) -> String {
+    escape_html(snippet)
+        .replace(HIGHLIGHT_START, "<mark>")
+        .replace(HIGHLIGHT_STOP, "</mark>")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_search() {
+        assert_eq!(parse_search("rust actix").unwrap(), "'rust' & 'actix'");
+        assert_eq!(
+            parse_search("\"full text\" search*").unwrap(),
+            "'full' <-> 'text' & 'search':*"
+        );
+        assert_eq!(
+            parse_search("postgres OR mysql -oracle").unwrap(),
+            "'postgres' | 'mysql' & !('oracle')"
+        );
+        assert_eq!(
+            parse_search("it's e-mail').*").unwrap(),
+            "'it' <-> 's' & 'e' <-> 'mail':*"
+        );
+        assert_eq!(parse_search("OR \"unclosed").unwrap(), "'unclosed'");
+        assert_eq!(parse_search("  -- ** \"\" OR "), None);
+    }
+
+    #[test]
+    fn test_highlight() {
+        assert_eq!(
+            highlight("a <b> \u{2}match\u{3} & more"),
+            "a &lt;b&gt; <mark>match</mark> &amp; more"
+        );
+    }
+}
@@ -1,54 +1,66 @@
+use crate::configs::db;
+use crate::errors::ServiceError;
 use crate::posts_tags::{CreatePostsTags, PostsTags};
+use crate::tags::Tags;
 use deadpool_postgres::Client;
-use std::io;
 use tokio_pg_mapper::FromTokioPostgresRow;
+use tokio_postgres::error::SqlState;
+
+// Turns constraint violations on posts_tags into the matching client errors.
+fn link_error(e: tokio_postgres::Error) -> ServiceError {
+    match e.code() {
+        Some(code) if *code == SqlState::UNIQUE_VIOLATION => {
+            ServiceError::Conflict("The post already has this tag".into())
+        }
+        Some(code) if *code == SqlState::FOREIGN_KEY_VIOLATION => {
+            ServiceError::NotFound("Post or tag not found".into())
+        }
+        _ => ServiceError::from(e),
+    }
+}
 
 // CORE CRUD
 
-//TODO configure .env for db shema name
-
-// Decide wether to return id or return all fields from insert sql query . if return ID, insert id in function argument.
-// shift id in db tables to the top so we can skip it when not needed
-
 pub async fn posts_tags_add(
     client: &Client,
     selfobj: CreatePostsTags,
-) -> Result<CreatePostsTags, io::Error> {
+) -> Result<PostsTags, ServiceError> {
     let statement = client
-        .prepare(
-            "INSERT INTO public.posts_tags
+        .prepare(&format!(
+            "INSERT INTO {posts_tags}
    (post_id, tag_id)
-    VALUES ($0, $1) RETURNING post_id, tag_id",
-        )
-        .await
-        .unwrap();
+    VALUES ($1, $2) RETURNING post_id, tag_id",
+            posts_tags = db().posts_tags
+        ))
+        .await?;
 
-    client
-        .query(&statement, &[&selfobj.post_id, &selfobj.tag_id])
+    let row = client
+        .query_one(&statement, &[&selfobj.post_id, &selfobj.tag_id])
         .await
-        .expect("Error creating posts_tags")
-        .iter()
-        .map(|row| CreatePostsTags::from_row_ref(row).unwrap())
-        .collect::<Vec<CreatePostsTags>>()
-        .pop()
-        .ok_or(io::Error::new(
-            io::ErrorKind::Other,
-            "Error creating posts_tags tables",
-        ))
+        .map_err(link_error)?;
+    Ok(PostsTags::from_row_ref(&row).unwrap())
 }
 
-// TODO populate fields
-
-pub async fn posts_tags_list(client: &Client) -> Result<Vec<PostsTags>, io::Error> {
+/// Links of the published posts, plus those of every post of `viewer_id` or, with
+/// `see_all`, of every post.
+pub async fn posts_tags_list(
+    client: &Client,
+    viewer_id: Option<i32>,
+    see_all: bool,
+) -> Result<Vec<PostsTags>, ServiceError> {
     let statement = client
-        .prepare("select * from public.posts_tags order by id desc")
-        .await
-        .unwrap();
+        .prepare(&format!(
+            "select pt.post_id, pt.tag_id from {posts_tags} pt JOIN {posts} p ON p.id = pt.post_id
+            where (p.status = 'published' OR p.author_id = $1 OR $2)
+            order by pt.post_id desc, pt.tag_id",
+            posts_tags = db().posts_tags,
+            posts = db().posts
+        ))
+        .await?;
 
     let posts_tags_list = client
-        .query(&statement, &[])
-        .await
-        .expect("Error getting author lists")
+        .query(&statement, &[&viewer_id, &see_all])
+        .await?
         .iter()
         .map(|row| PostsTags::from_row_ref(row).unwrap())
         .collect::<Vec<PostsTags>>();
@@ -56,55 +68,98 @@ pub async fn posts_tags_list(client: &Client) -> Result<Vec<PostsTags>, io::Erro
     Ok(posts_tags_list)
 }
 
-pub async fn posts_tags_id(client: &Client, id_posts_tags: i32) -> Result<PostsTags, io::Error> {
+pub async fn posts_tags_delete(
+    client: &Client,
+    post_id: i32,
+    tag_id: i32,
+) -> Result<(), ServiceError> {
     let statement = client
-        .prepare("select * from public.posts_tags where id = $1")
-        .await
-        .unwrap();
-
-    let maybe_posts_tags = client
-        .query_opt(&statement, &[&id_posts_tags])
-        .await
-        .expect("Error adding posts_tags ")
-        .map(|row| PostsTags::from_row_ref(&row).unwrap());
+        .prepare(&format!(
+            "DELETE FROM {posts_tags} WHERE post_id = $1 AND tag_id = $2",
+            posts_tags = db().posts_tags
+        ))
+        .await?;
 
-    match maybe_posts_tags {
-        Some(posts_tags) => Ok(posts_tags),
-        None => Err(io::Error::new(io::ErrorKind::NotFound, "Not found")),
+    match client.execute(&statement, &[&post_id, &tag_id]).await? {
+        1 => Ok(()),
+        _ => Err(ServiceError::NotFound(
+            "The post doesn't have this tag".into(),
+        )),
     }
 }
 
-//TODO take into account ID position
-
-pub async fn posts_tags_update(
-    client: &Client,
-    id: i32,
-    mdl: CreatePostsTags,
-) -> Result<(), io::Error> {
-    let statement = client
-        .prepare("update public.posts_tags set (post_id, tag_id) = ($0, $1) where id = $3")
-        .await
-        .unwrap();
-
-    let result = client
-        .execute(&statement, &[&mdl.post_id, &mdl.tag_id, &id])
-        .await
-        .expect("Error getting todo lists");
+// END OF CORE CRUD
 
-    match result {
-        ref updated if *updated == 1 => Ok(()),
-        _ => Err(io::Error::new(io::ErrorKind::Other, "Failed to check list")),
+/// Gives the post exactly the tags named, creating the missing ones, in one transaction.
+/// Returns the tags the post now has.
+pub async fn post_tags_set(
+    client: &mut Client,
+    post_id: i32,
+    names: &[String],
+) -> Result<Vec<Tags>, ServiceError> {
+    let transaction = client.transaction().await?;
+
+    // Serializes concurrent changes to the tags of the same post.
+    let statement = transaction
+        .prepare(&format!(
+            "SELECT id FROM {posts} WHERE id = $1 FOR UPDATE",
+            posts = db().posts
+        ))
+        .await?;
+    if transaction
+        .query_opt(&statement, &[&post_id])
+        .await?
+        .is_none()
+    {
+        return Err(ServiceError::NotFound("Post not found".into()));
     }
-}
 
-pub async fn posts_tags_delete(client: &Client, posts_tags_id: i32) -> Result<(), io::Error> {
-    let statement = client
-        .prepare("DELETE FROM public.posts_tags WHERE id = $1")
-        .await
-        .unwrap();
+    let statement = transaction
+        .prepare(&format!(
+            "INSERT INTO {tags} (name) SELECT unnest($1::text[])
+            ON CONFLICT ((lower(name))) DO NOTHING",
+            tags = db().tags
+        ))
+        .await?;
+    transaction.execute(&statement, &[&names]).await?;
+
+    let statement = transaction
+        .prepare(&format!(
+            "SELECT id, name FROM {tags}
+            WHERE lower(name) = ANY (SELECT lower(n) FROM unnest($1::text[]) n)
+            ORDER BY name",
+            tags = db().tags
+        ))
+        .await?;
+    let tags = transaction
+        .query(&statement, &[&names])
+        .await?
+        .iter()
+        .map(|row| Tags::from_row_ref(row).unwrap())
+        .collect::<Vec<Tags>>();
+    let tag_ids = tags.iter().map(|tag| tag.id).collect::<Vec<i32>>();
+
+    let statement = transaction
+        .prepare(&format!(
+            "DELETE FROM {posts_tags} WHERE post_id = $1 AND tag_id <> ALL ($2)",
+            posts_tags = db().posts_tags
+        ))
+        .await?;
+    transaction
+        .execute(&statement, &[&post_id, &tag_ids])
+        .await?;
+
+    let statement = transaction
+        .prepare(&format!(
+            "INSERT INTO {posts_tags} (post_id, tag_id) SELECT $1, unnest($2::integer[])
+            ON CONFLICT DO NOTHING",
+            posts_tags = db().posts_tags
+        ))
+        .await?;
+    transaction
+        .execute(&statement, &[&post_id, &tag_ids])
+        .await?;
 
-    client.execute(&statement, &[&posts_tags_id]).await.unwrap();
-    Ok(())
+    transaction.commit().await?;
+    Ok(tags)
 }
-
-// END OF CORE CRUD
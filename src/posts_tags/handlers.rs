@@ -1,113 +1,95 @@
+use crate::auth::AuthUser;
+use crate::errors::ServiceError;
+use crate::posts::handlers::{editable_post, is_editor};
 use crate::posts_tags::db;
 use crate::posts_tags::models::CreatePostsTags;
-use std::io;
 
-use actix_web::{delete, get, patch, post, web, HttpResponse, Responder};
+use actix_web::{delete, get, post, web, HttpResponse};
 use deadpool_postgres::{Client, Pool};
-use io::ErrorKind::NotFound;
 
+/// Every link between a post and a tag.
+///
+/// Only links of posts the caller may see: published ones, their own and, for editors,
+/// every post.
+#[utoipa::path(
+    context_path = "/posts_tags",
+    responses(
+        (status = 200, description = "Post tag links", body = [PostsTags]),
+    )
+)]
 #[get("/")]
-pub async fn posts_tags(db_pool: web::Data<Pool>) -> impl Responder {
-    let client: Client = db_pool
-        .get()
-        .await
-        .expect("Error connecting to the database");
-
-    let result = db::posts_tags_list(&client).await;
-
-    match result {
-        Ok(object) => HttpResponse::Ok().json(object),
-        Err(_) => HttpResponse::InternalServerError().into(),
-    }
+pub async fn posts_tags(
+    viewer: Option<AuthUser>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let client: Client = db_pool.get().await?;
+
+    let links = db::posts_tags_list(
+        &client,
+        viewer.as_ref().map(|user| user.id),
+        viewer.as_ref().is_some_and(is_editor),
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(links))
 }
 
+/// Tag a post.
+///
+/// Only the author of the post, editors and admins may change its tags.
+#[utoipa::path(
+    context_path = "/posts_tags",
+    request_body = CreatePostsTags,
+    responses(
+        (status = 201, description = "Post tagged", body = PostsTags),
+        (status = 401, description = "Not signed in", body = ServiceError),
+        (status = 403, description = "Not the author of the post", body = ServiceError),
+        (status = 404, description = "Post or tag not found", body = ServiceError),
+        (status = 409, description = "The post already has this tag", body = ServiceError)
+    )
+)]
 #[post("/")]
 pub async fn add_posts_tags(
+    user: AuthUser,
     local_object: web::Json<CreatePostsTags>,
     db_pool: web::Data<Pool>,
-) -> impl Responder {
-    let client: Client = db_pool
-        .get()
-        .await
-        .expect("Error connecting to the database");
-
-    let result = db::posts_tags_add(&client, local_object.clone()).await;
-
-    match result {
-        Ok(object) => HttpResponse::Ok().json(object),
-        Err(_) => HttpResponse::InternalServerError().into(),
-    }
-}
-
-#[get("/{id}")]
-pub async fn get_posts_tags(
-    id_posts_tags: web::Path<(i32,)>,
-    db_pool: web::Data<Pool>,
-) -> impl Responder {
-    let client: Client = db_pool
-        .get()
-        .await
-        .expect("Error connecting to the database");
-
-    let result = db::posts_tags_id(&client, id_posts_tags.0).await;
+) -> Result<HttpResponse, ServiceError> {
+    let client: Client = db_pool.get().await?;
 
-    match result {
-        Ok(object) => HttpResponse::Ok().json(object),
-        Err(ref e) if e.kind() == NotFound => HttpResponse::NotFound().into(),
-        Err(_) => HttpResponse::InternalServerError().into(),
-    }
+    editable_post(&client, local_object.post_id, &user).await?;
+    let link = db::posts_tags_add(&client, local_object.into_inner()).await?;
+    Ok(HttpResponse::Created().json(link))
 }
 
-#[delete("/{id}")]
+/// Remove a tag from a post.
+#[utoipa::path(
+    context_path = "/posts_tags",
+    responses(
+        (status = 200, description = "Tag removed"),
+        (status = 401, description = "Not signed in", body = ServiceError),
+        (status = 403, description = "Not the author of the post", body = ServiceError),
+        (status = 404, description = "The post doesn't have this tag", body = ServiceError)
+    ),
+    params(
+        ("post_id", description = "Unique Post Id"),
+        ("tag_id", description = "Unique Tag Id")
+    )
+)]
+#[delete("/{post_id}/{tag_id}")]
 pub async fn delete_posts_tags(
-    posts_tags_id: web::Path<(i32,)>,
+    user: AuthUser,
+    path: web::Path<(i32, i32)>,
     db_pool: web::Data<Pool>,
-) -> impl Responder {
-    let client: Client = db_pool
-        .get()
-        .await
-        .expect("Error connecting to the database");
+) -> Result<HttpResponse, ServiceError> {
+    let (post_id, tag_id) = path.into_inner();
+    let client: Client = db_pool.get().await?;
 
-    let result = db::posts_tags_delete(&client, posts_tags_id.0).await;
-
-    match result {
-        Ok(object) => HttpResponse::Ok().json(object),
-        Err(ref e) if e.kind() == NotFound => HttpResponse::NotFound().into(),
-        Err(_) => HttpResponse::InternalServerError().into(),
-    }
-}
-
-#[patch("/{id}")]
-pub async fn update_posts_tags(
-    id_posts_tags: web::Path<(i32,)>,
-    local_object: web::Json<CreatePostsTags>,
-    db_pool: web::Data<Pool>,
-) -> impl Responder {
-    let client: Client = db_pool
-        .get()
-        .await
-        .expect("Error connecting to the database");
-
-    let result = db::posts_tags_update(&client, id_posts_tags.0, local_object.clone()).await;
-
-    match result {
-        Ok(object) => HttpResponse::Ok().json(object),
-        Err(ref e) if e.kind() == NotFound => HttpResponse::NotFound().into(),
-        Err(_) => HttpResponse::InternalServerError().into(),
-    }
+    editable_post(&client, post_id, &user).await?;
+    db::posts_tags_delete(&client, post_id, tag_id).await?;
+    Ok(HttpResponse::Ok().finish())
 }
 
 pub fn init_routes(cfg: &mut web::ServiceConfig) {
     cfg.service(posts_tags);
     cfg.service(add_posts_tags);
-    cfg.service(update_posts_tags);
-    cfg.service(get_posts_tags);
     cfg.service(delete_posts_tags);
 }
-
-// #[delete("/{id}")]
-// pub async fn delete_author(id_author: web::Path<(i32,)>,  db_pool: web::Data<Pool>) -> impl Responder {
-//     let res = format!("{:?},", id_author.0);
-//     println!("{:#?}", res);
-//     res
-// }
@@ -1,21 +1,73 @@
 use serde::{Deserialize, Serialize};
 use tokio_pg_mapper_derive::PostgresMapper;
-extern crate chrono;
-use chrono::prelude::*;
-use chrono::{DateTime, Duration, Utc};
+use utoipa::ToSchema;
 
-//To be added based on special query
+use crate::errors::ServiceError;
 
-#[derive(Serialize, Clone, Deserialize, PostgresMapper)]
+/// Longest tag name accepted.
+pub const MAX_TAG_NAME: usize = 64;
+/// Most tags a post can have.
+pub const MAX_POST_TAGS: usize = 50;
+
+#[derive(Serialize, Debug, Clone, Deserialize, ToSchema, PostgresMapper)]
 #[pg_mapper(table = "posts_tags")]
 pub struct PostsTags {
     pub post_id: i32,
     pub tag_id: i32,
 }
 
-#[derive(Serialize, Clone, Deserialize, PostgresMapper)]
+#[derive(Serialize, Debug, Clone, Deserialize, ToSchema, PostgresMapper)]
 #[pg_mapper(table = "posts_tags")]
 pub struct CreatePostsTags {
     pub post_id: i32,
     pub tag_id: i32,
 }
+
+/// The complete set of tags a post should have, by name.
+#[derive(Serialize, Debug, Clone, Deserialize, ToSchema)]
+#[schema(example = json!({"tags": ["rust", "actix"]}))]
+pub struct SetPostTags {
+    /// Tags that don't exist yet are created. Names match regardless of case.
+    pub tags: Vec<String>,
+}
+
+/// Trims the names and drops repeats, keeping the first spelling of each.
+pub fn tag_names(names: &[String]) -> Result<Vec<String>, ServiceError> {
+    let mut unique: Vec<String> = Vec::with_capacity(names.len());
+    for name in names {
+        let name = name.trim();
+        if name.is_empty() || name.chars().count() > MAX_TAG_NAME {
+            return Err(ServiceError::BadRequest(format!(
+                "Tag names must have 1 to {} characters",
+                MAX_TAG_NAME
+            )));
+        }
+        if !unique
+            .iter()
+            .any(|seen| seen.to_lowercase() == name.to_lowercase())
+        {
+            unique.push(name.to_owned());
+        }
+    }
+    if unique.len() > MAX_POST_TAGS {
+        return Err(ServiceError::BadRequest(format!(
+            "A post can have at most {} tags",
+            MAX_POST_TAGS
+        )));
+    }
+    Ok(unique)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tag_names() {
+        let names = vec![" Rust ".to_owned(), "actix".to_owned(), "rust".to_owned()];
+        assert_eq!(tag_names(&names).unwrap(), vec!["Rust", "actix"]);
+        assert!(tag_names(&["  ".to_owned()]).is_err());
+        assert!(tag_names(&["x".repeat(MAX_TAG_NAME + 1)]).is_err());
+        assert!(tag_names(&[]).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,299 @@
+use deadpool_postgres::Client;
+use tokio_pg_mapper::FromTokioPostgresRow;
+use tokio_postgres::error::SqlState;
+use tokio_postgres::types::ToSql;
+
+use crate::auth::{add_user, delete_trusted_devices, delete_user_sessions, CreateUser, Role};
+use crate::configs::db;
+use crate::scim::filter::{CompareOp, Filter, Value};
+use crate::scim::{ScimError, ScimMember, ScimUserRow};
+
+const SCIM_USER_COLUMNS: &str = "id, email, external_id, display_name, role, disabled, created_at";
+
+fn invalid_filter(detail: impl Into<String>) -> ScimError {
+    ScimError::bad_request("invalidFilter", detail)
+}
+
+// Text attributes and whether they compare ignoring case, as the core schema defines.
+fn user_column(attr: &str) -> Option<(&'static str, bool)> {
+    match attr.to_ascii_lowercase().as_str() {
+        "username" | "emails" | "emails.value" => Some(("email", true)),
+        "externalid" => Some(("external_id", false)),
+        "displayname" => Some(("display_name", true)),
+        "id" => Some(("id::text", false)),
+        _ => None,
+    }
+}
+
+/// Translates a filter on users to a SQL condition. Values are bound as text parameters
+/// appended to `params`, only validated booleans end up in the SQL text.
+pub fn user_filter_sql(filter: &Filter, params: &mut Vec<String>) -> Result<String, ScimError> {
+    let (attr, op, value) = match filter {
+        Filter::And(left, right) => {
+            return Ok(format!(
+                "({} AND {})",
+                user_filter_sql(left, params)?,
+                user_filter_sql(right, params)?
+            ))
+        }
+        Filter::Or(left, right) => {
+            return Ok(format!(
+                "({} OR {})",
+                user_filter_sql(left, params)?,
+                user_filter_sql(right, params)?
+            ))
+        }
+        Filter::Not(inner) => return Ok(format!("NOT ({})", user_filter_sql(inner, params)?)),
+        Filter::Compare { attr, op, value } => (attr, *op, value),
+    };
+
+    if attr.eq_ignore_ascii_case("active") {
+        return match (op, value) {
+            (CompareOp::Pr, _) => Ok("TRUE".to_owned()),
+            (CompareOp::Eq, Value::Bool(active)) => Ok(format!("disabled = {}", !active)),
+            (CompareOp::Ne, Value::Bool(active)) => Ok(format!("disabled = {}", active)),
+            _ => Err(invalid_filter(
+                "active can only be compared to true or false",
+            )),
+        };
+    }
+
+    let (column, ignore_case) =
+        user_column(attr).ok_or_else(|| invalid_filter(format!("Unknown attribute {}", attr)))?;
+    let column = if ignore_case {
+        format!("lower({})", column)
+    } else {
+        column.to_owned()
+    };
+
+    let value = match (op, value) {
+        (CompareOp::Pr, _) => return Ok(format!("{} IS NOT NULL", column)),
+        (CompareOp::Eq, Value::Null) => return Ok(format!("{} IS NULL", column)),
+        (CompareOp::Ne, Value::Null) => return Ok(format!("{} IS NOT NULL", column)),
+        (_, Value::Str(value)) => value.clone(),
+        _ => return Err(invalid_filter(format!("Invalid value for {}", attr))),
+    };
+    params.push(value);
+    let param = if ignore_case {
+        format!("lower(${})", params.len())
+    } else {
+        format!("${}", params.len())
+    };
+
+    Ok(match op {
+        CompareOp::Eq => format!("{} = {}", column, param),
+        CompareOp::Ne => format!("({0} IS NULL OR {0} <> {1})", column, param),
+        CompareOp::Co => format!("strpos({}, {}) > 0", column, param),
+        CompareOp::Sw => format!("starts_with({}, {})", column, param),
+        CompareOp::Ew => format!("right({0}, length({1})) = {1}", column, param),
+        CompareOp::Pr => unreachable!("handled above"),
+    })
+}
+
+pub async fn scim_user_list(
+    client: &Client,
+    filter: Option<&Filter>,
+    limit: i64,
+    offset: i64,
+) -> Result<(Vec<ScimUserRow>, i64), ScimError> {
+    let mut params = Vec::new();
+    let condition = match filter {
+        Some(filter) => user_filter_sql(filter, &mut params)?,
+        None => "TRUE".to_owned(),
+    };
+    let mut args: Vec<&(dyn ToSql + Sync)> =
+        params.iter().map(|p| p as &(dyn ToSql + Sync)).collect();
+
+    let statement = client
+        .prepare(&format!(
+            "SELECT count(*) FROM {users} WHERE {}",
+            condition,
+            users = db().users
+        ))
+        .await?;
+    let total: i64 = client.query_one(&statement, &args).await?.get(0);
+
+    let statement = client
+        .prepare(&format!(
+            "SELECT {} FROM {users} WHERE {} ORDER BY id LIMIT ${} OFFSET ${}",
+            SCIM_USER_COLUMNS,
+            condition,
+            params.len() + 1,
+            params.len() + 2,
+            users = db().users
+        ))
+        .await?;
+    args.push(&limit);
+    args.push(&offset);
+    let users = client
+        .query(&statement, &args)
+        .await?
+        .iter()
+        .map(|row| ScimUserRow::from_row_ref(row).unwrap())
+        .collect::<Vec<ScimUserRow>>();
+
+    Ok((users, total))
+}
+
+pub async fn scim_user_get(client: &Client, id: i32) -> Result<ScimUserRow, ScimError> {
+    let statement = client
+        .prepare(&format!(
+            "SELECT {} FROM {users} WHERE id = $1",
+            SCIM_USER_COLUMNS,
+            users = db().users
+        ))
+        .await?;
+
+    client
+        .query_opt(&statement, &[&id])
+        .await?
+        .map(|row| ScimUserRow::from_row_ref(&row).unwrap())
+        .ok_or_else(|| ScimError::not_found("User not found"))
+}
+
+fn email_conflict(e: tokio_postgres::Error) -> ScimError {
+    match e.code() {
+        Some(code) if *code == SqlState::UNIQUE_VIOLATION => ScimError::new(
+            actix_web::http::StatusCode::CONFLICT,
+            Some("uniqueness"),
+            "userName is already in use",
+        ),
+        _ => ScimError::from(e),
+    }
+}
+
+/// Creates a user with the `user` role and a password nobody knows.
+pub async fn scim_user_create(
+    client: &mut Client,
+    email: &str,
+    hashed_password: String,
+    external_id: Option<&str>,
+    display_name: Option<&str>,
+    active: bool,
+) -> Result<ScimUserRow, ScimError> {
+    let transaction = client.transaction().await?;
+
+    let usr = CreateUser {
+        email: email.to_owned(),
+        hashed_password,
+        username: None,
+    };
+    let user = add_user(&transaction, usr, Role::User).await?;
+
+    let statement = transaction
+        .prepare(&format!(
+            "UPDATE {users} SET external_id = $1, display_name = $2, disabled = $3 WHERE id = $4
+            RETURNING {}",
+            SCIM_USER_COLUMNS,
+            users = db().users
+        ))
+        .await?;
+    let row = transaction
+        .query_one(
+            &statement,
+            &[&external_id, &display_name, &!active, &user.id],
+        )
+        .await?;
+
+    transaction.commit().await?;
+    Ok(ScimUserRow::from_row_ref(&row).unwrap())
+}
+
+/// Writes back a modified user. Deactivating signs the user out of every session and
+/// forgets their trusted devices.
+pub async fn scim_user_save(client: &mut Client, user: &ScimUserRow) -> Result<(), ScimError> {
+    let transaction = client.transaction().await?;
+
+    let statement = transaction
+        .prepare(&format!(
+            "UPDATE {users} SET email = $1, external_id = $2, display_name = $3, disabled = $4
+            WHERE id = $5",
+            users = db().users
+        ))
+        .await?;
+    let updated = transaction
+        .execute(
+            &statement,
+            &[
+                &user.email,
+                &user.external_id,
+                &user.display_name,
+                &user.disabled,
+                &user.id,
+            ],
+        )
+        .await
+        .map_err(email_conflict)?;
+    if updated != 1 {
+        return Err(ScimError::not_found("User not found"));
+    }
+    if user.disabled {
+        delete_user_sessions(&transaction, user.id, None).await?;
+        delete_trusted_devices(&transaction, user.id).await?;
+    }
+
+    transaction.commit().await?;
+    Ok(())
+}
+
+pub async fn scim_group_members(client: &Client, role: Role) -> Result<Vec<ScimMember>, ScimError> {
+    let statement = client
+        .prepare(&format!(
+            "SELECT id, email FROM {users} WHERE role = $1 ORDER BY id",
+            users = db().users
+        ))
+        .await?;
+
+    let members = client
+        .query(&statement, &[&role.as_str()])
+        .await?
+        .iter()
+        .map(|row| ScimMember {
+            value: row.get::<_, i32>(0).to_string(),
+            display: Some(row.get(1)),
+        })
+        .collect::<Vec<ScimMember>>();
+    Ok(members)
+}
+
+/// Moves everyone holding `role`, except the given users, back to the `user` role.
+pub async fn scim_role_demote(client: &Client, role: Role, keep: &[i32]) -> Result<u64, ScimError> {
+    let statement = client
+        .prepare(&format!(
+            "UPDATE {users} SET role = $1 WHERE role = $2 AND NOT (id = ANY($3))",
+            users = db().users
+        ))
+        .await?;
+
+    Ok(client
+        .execute(&statement, &[&Role::User.as_str(), &role.as_str(), &keep])
+        .await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scim::filter::parse;
+
+    #[test]
+    fn test_user_filter_sql() {
+        let mut params = Vec::new();
+        let filter = parse(r#"userName eq "Jane@Example.com" and active eq true"#).unwrap();
+        assert_eq!(
+            user_filter_sql(&filter, &mut params).unwrap(),
+            "(lower(email) = lower($1) AND disabled = false)"
+        );
+        assert_eq!(params, vec!["Jane@Example.com".to_owned()]);
+
+        let mut params = Vec::new();
+        let filter = parse(r#"externalId sw "ab" or not (displayName pr)"#).unwrap();
+        assert_eq!(
+            user_filter_sql(&filter, &mut params).unwrap(),
+            "(starts_with(external_id, $1) OR NOT (lower(display_name) IS NOT NULL))"
+        );
+
+        let mut params = Vec::new();
+        let filter = parse(r#"password eq "x""#).unwrap();
+        assert!(user_filter_sql(&filter, &mut params).is_err());
+    }
+}
@@ -0,0 +1,289 @@
+//! The subset of the SCIM filter grammar (RFC 7644 §3.4.2.2) identity providers send:
+//! attribute comparisons joined by `and`, `or` and `not`, with parentheses.
+
+use std::iter::Peekable;
+use std::str::Chars;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Co,
+    Sw,
+    Ew,
+    Pr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Str(String),
+    Bool(bool),
+    Null,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    Compare {
+        attr: String,
+        op: CompareOp,
+        value: Value,
+    },
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Str(String),
+    Open,
+    Close,
+}
+
+fn read_string(chars: &mut Peekable<Chars>) -> Result<String, String> {
+    let mut value = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(value),
+            Some('\\') => match chars.next() {
+                Some('n') => value.push('\n'),
+                Some('t') => value.push('\t'),
+                Some(c) => value.push(c),
+                None => break,
+            },
+            Some(c) => value.push(c),
+            None => break,
+        }
+    }
+    Err("Unterminated string in filter".into())
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            '"' => {
+                chars.next();
+                tokens.push(Token::Str(read_string(&mut chars)?));
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == '(' || c == ')' || c == '"' {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        matches!(self.tokens.get(self.position), Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Filter, String> {
+        let mut left = self.and()?;
+        while self.peek_keyword("or") {
+            self.position += 1;
+            left = Filter::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Filter, String> {
+        let mut left = self.unary()?;
+        while self.peek_keyword("and") {
+            self.position += 1;
+            left = Filter::And(Box::new(left), Box::new(self.unary()?));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Filter, String> {
+        if self.peek_keyword("not") {
+            self.position += 1;
+            return Ok(Filter::Not(Box::new(self.group()?)));
+        }
+        if self.tokens.get(self.position) == Some(&Token::Open) {
+            return self.group();
+        }
+        self.comparison()
+    }
+
+    fn group(&mut self) -> Result<Filter, String> {
+        if self.next() != Some(Token::Open) {
+            return Err("Expected '(' in filter".into());
+        }
+        let inner = self.or()?;
+        if self.next() != Some(Token::Close) {
+            return Err("Expected ')' in filter".into());
+        }
+        Ok(inner)
+    }
+
+    fn comparison(&mut self) -> Result<Filter, String> {
+        let attr = match self.next() {
+            Some(Token::Word(attr)) => attr,
+            _ => return Err("Expected an attribute in filter".into()),
+        };
+        let op = match self.next() {
+            Some(Token::Word(op)) => match op.to_ascii_lowercase().as_str() {
+                "eq" => CompareOp::Eq,
+                "ne" => CompareOp::Ne,
+                "co" => CompareOp::Co,
+                "sw" => CompareOp::Sw,
+                "ew" => CompareOp::Ew,
+                "pr" => CompareOp::Pr,
+                _ => return Err(format!("Unsupported filter operator {:?}", op)),
+            },
+            _ => return Err("Expected an operator in filter".into()),
+        };
+        if op == CompareOp::Pr {
+            return Ok(Filter::Compare {
+                attr,
+                op,
+                value: Value::Null,
+            });
+        }
+
+        let value = match self.next() {
+            Some(Token::Str(value)) => Value::Str(value),
+            Some(Token::Word(word)) => match word.to_ascii_lowercase().as_str() {
+                "true" => Value::Bool(true),
+                "false" => Value::Bool(false),
+                "null" => Value::Null,
+                // Bare numbers are compared as text.
+                _ if word.chars().all(|c| c.is_ascii_digit()) => Value::Str(word),
+                _ => return Err(format!("Unexpected {:?} in filter", word)),
+            },
+            _ => return Err("Expected a value in filter".into()),
+        };
+        Ok(Filter::Compare { attr, op, value })
+    }
+}
+
+pub fn parse(input: &str) -> Result<Filter, String> {
+    let mut parser = Parser {
+        tokens: tokenize(input)?,
+        position: 0,
+    };
+    let filter = parser.or()?;
+    if parser.position != parser.tokens.len() {
+        return Err("Unexpected trailing input in filter".into());
+    }
+    Ok(filter)
+}
+
+/// Evaluates a filter against an in-memory resource, comparing text ignoring case.
+/// `lookup` returns the value of an attribute, `None` when it is unset or unknown.
+pub fn matches(filter: &Filter, lookup: &dyn Fn(&str) -> Option<String>) -> bool {
+    match filter {
+        Filter::And(left, right) => matches(left, lookup) && matches(right, lookup),
+        Filter::Or(left, right) => matches(left, lookup) || matches(right, lookup),
+        Filter::Not(inner) => !matches(inner, lookup),
+        Filter::Compare { attr, op, value } => {
+            let actual = lookup(attr).map(|actual| actual.to_lowercase());
+            let expected = match value {
+                Value::Str(value) => Some(value.to_lowercase()),
+                Value::Bool(value) => Some(value.to_string()),
+                Value::Null => None,
+            };
+            match (op, actual, expected) {
+                (CompareOp::Pr, actual, _) => actual.is_some(),
+                (CompareOp::Eq, actual, expected) => actual == expected,
+                (CompareOp::Ne, actual, expected) => actual != expected,
+                (CompareOp::Co, Some(actual), Some(expected)) => actual.contains(&expected),
+                (CompareOp::Sw, Some(actual), Some(expected)) => actual.starts_with(&expected),
+                (CompareOp::Ew, Some(actual), Some(expected)) => actual.ends_with(&expected),
+                _ => false,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compare(attr: &str, op: CompareOp, value: Value) -> Filter {
+        Filter::Compare {
+            attr: attr.into(),
+            op,
+            value,
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse(r#"userName eq "bjensen@example.com""#),
+            Ok(compare(
+                "userName",
+                CompareOp::Eq,
+                Value::Str("bjensen@example.com".into())
+            ))
+        );
+        assert_eq!(
+            parse(r#"active EQ true and (externalId pr or not (displayName sw "B\"J"))"#),
+            Ok(Filter::And(
+                Box::new(compare("active", CompareOp::Eq, Value::Bool(true))),
+                Box::new(Filter::Or(
+                    Box::new(compare("externalId", CompareOp::Pr, Value::Null)),
+                    Box::new(Filter::Not(Box::new(compare(
+                        "displayName",
+                        CompareOp::Sw,
+                        Value::Str("B\"J".into())
+                    ))))
+                ))
+            ))
+        );
+        assert!(parse(r#"userName gt "a""#).is_err());
+        assert!(parse(r#"userName eq "a" extra"#).is_err());
+        assert!(parse(r#"(userName eq "a""#).is_err());
+    }
+
+    #[test]
+    fn test_matches() {
+        let lookup = |attr: &str| match attr {
+            "displayName" => Some("Editor".to_owned()),
+            _ => None,
+        };
+        let filter = parse(r#"displayName eq "editor" and not (id pr)"#).unwrap();
+        assert!(matches(&filter, &lookup));
+        let filter = parse(r#"displayName sw "adm" or displayName ew "tor""#).unwrap();
+        assert!(matches(&filter, &lookup));
+        let filter = parse(r#"displayName co "x""#).unwrap();
+        assert!(!matches(&filter, &lookup));
+    }
+}
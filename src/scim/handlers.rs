@@ -0,0 +1,794 @@
+use std::future::{ready, Ready};
+
+use actix_web::dev::Payload;
+use actix_web::http::{header, StatusCode};
+use actix_web::web::Query;
+use actix_web::{
+    delete, get, patch, post, put, web, FromRequest, HttpRequest, HttpResponse, HttpResponseBuilder,
+};
+use deadpool_postgres::{Client, Pool};
+use serde::Serialize;
+use serde_json::{json, Value as JsonValue};
+
+use crate::admin::{user_delete, user_set_role};
+use crate::auth::{constant_time_compare, encryption, send_password_reset, Role};
+use crate::configs;
+use crate::scim::db;
+use crate::scim::filter::{self, CompareOp, Filter, Value};
+use crate::scim::models::*;
+
+const DEFAULT_COUNT: i64 = 100;
+const MAX_COUNT: i64 = 200;
+
+/// Every role, in the order groups are listed.
+const ROLES: [Role; 3] = [Role::User, Role::Editor, Role::Admin];
+
+/// The identity provider, authenticated by the provisioning bearer token
+/// (`SCIM_TOKEN`). SCIM is disabled while no token is configured.
+pub struct ScimClient;
+
+fn authorize(req: &HttpRequest) -> Result<ScimClient, ScimError> {
+    let config = configs::Config::from_env().unwrap();
+    let expected = config
+        .srv_cnf
+        .scim_token
+        .filter(|token| !token.is_empty())
+        .ok_or_else(|| {
+            ScimError::new(
+                StatusCode::FORBIDDEN,
+                None,
+                "SCIM provisioning is not configured",
+            )
+        })?;
+
+    let sent = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim);
+    match sent {
+        Some(sent) if constant_time_compare(sent, &expected) => Ok(ScimClient),
+        _ => Err(ScimError::new(
+            StatusCode::UNAUTHORIZED,
+            None,
+            "Invalid provisioning token",
+        )),
+    }
+}
+
+impl FromRequest for ScimClient {
+    type Error = ScimError;
+    type Future = Ready<Result<ScimClient, ScimError>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(authorize(req))
+    }
+}
+
+fn base_url() -> String {
+    let config = configs::Config::from_env().unwrap();
+    format!("{}/scim/v2", config.srv_cnf.public_url())
+}
+
+fn scim_response(builder: &mut HttpResponseBuilder, body: impl Serialize) -> HttpResponse {
+    builder.content_type(SCIM_CONTENT_TYPE).json(body)
+}
+
+fn parse_filter(query: &ListQuery) -> Result<Option<Filter>, ScimError> {
+    query
+        .filter
+        .as_deref()
+        .map(|text| filter::parse(text).map_err(|e| ScimError::bad_request("invalidFilter", e)))
+        .transpose()
+}
+
+// 1-based start index and page size, within bounds.
+fn page(query: &ListQuery) -> (i64, i64) {
+    let start_index = query.start_index.unwrap_or(1).max(1);
+    let count = query.count.unwrap_or(DEFAULT_COUNT).clamp(0, MAX_COUNT);
+    (start_index, count)
+}
+
+fn parse_user_id(id: &str) -> Result<i32, ScimError> {
+    id.parse()
+        .map_err(|_| ScimError::not_found("User not found"))
+}
+
+fn parse_group_id(id: &str) -> Result<Role, ScimError> {
+    id.parse()
+        .map_err(|_| ScimError::not_found("Group not found"))
+}
+
+/// `userName` must be an email address. When it isn't, the primary email is used instead.
+fn input_email(input: &ScimUserInput) -> Result<String, ScimError> {
+    if input.user_name.contains('@') {
+        return Ok(input.user_name.trim().to_owned());
+    }
+    input
+        .emails
+        .iter()
+        .find(|email| email.primary == Some(true))
+        .or_else(|| input.emails.first())
+        .map(|email| email.value.trim().to_owned())
+        .ok_or_else(|| ScimError::bad_request("invalidValue", "userName must be an email address"))
+}
+
+fn string_value(path: &str, value: &JsonValue) -> Result<String, ScimError> {
+    value
+        .as_str()
+        .map(str::to_owned)
+        .ok_or_else(|| ScimError::bad_request("invalidValue", format!("{} must be a string", path)))
+}
+
+// Some providers send booleans as "True" / "False".
+fn bool_value(path: &str, value: &JsonValue) -> Result<bool, ScimError> {
+    match value {
+        JsonValue::Bool(value) => Ok(*value),
+        JsonValue::String(text) if text.eq_ignore_ascii_case("true") => Ok(true),
+        JsonValue::String(text) if text.eq_ignore_ascii_case("false") => Ok(false),
+        _ => Err(ScimError::bad_request(
+            "invalidValue",
+            format!("{} must be a boolean", path),
+        )),
+    }
+}
+
+fn set_user_attribute(
+    user: &mut ScimUserRow,
+    path: &str,
+    value: &JsonValue,
+) -> Result<(), ScimError> {
+    let attribute = path.to_ascii_lowercase();
+    match attribute.as_str() {
+        "active" => user.disabled = !bool_value(path, value)?,
+        "username" => user.email = string_value(path, value)?,
+        "externalid" => user.external_id = Some(string_value(path, value)?),
+        "displayname" => user.display_name = Some(string_value(path, value)?),
+        "emails" => {
+            let emails: Vec<ScimEmail> = serde_json::from_value(value.clone())
+                .map_err(|e| ScimError::bad_request("invalidValue", e.to_string()))?;
+            if let Some(email) = emails
+                .iter()
+                .find(|email| email.primary == Some(true))
+                .or_else(|| emails.first())
+            {
+                user.email = email.value.clone();
+            }
+        }
+        _ if attribute.starts_with("emails[") && attribute.ends_with("].value") => {
+            user.email = string_value(path, value)?
+        }
+        // Attributes without a column here, like name.givenName, are accepted and dropped
+        // so provisioning doesn't stall on them.
+        _ => log::debug!("ignoring SCIM attribute {}", path),
+    }
+    Ok(())
+}
+
+fn apply_user_operation(
+    user: &mut ScimUserRow,
+    operation: &PatchOperation,
+) -> Result<(), ScimError> {
+    match operation.op.to_ascii_lowercase().as_str() {
+        "add" | "replace" => {
+            let value = operation
+                .value
+                .as_ref()
+                .ok_or_else(|| ScimError::bad_request("invalidValue", "Missing value"))?;
+            match &operation.path {
+                Some(path) => set_user_attribute(user, path, value),
+                None => {
+                    let attributes = value.as_object().ok_or_else(|| {
+                        ScimError::bad_request("invalidValue", "Value must be an object")
+                    })?;
+                    for (path, value) in attributes {
+                        set_user_attribute(user, path, value)?;
+                    }
+                    Ok(())
+                }
+            }
+        }
+        "remove" => {
+            let path = operation
+                .path
+                .as_deref()
+                .ok_or_else(|| ScimError::bad_request("noTarget", "Missing path"))?;
+            match path.to_ascii_lowercase().as_str() {
+                "externalid" => user.external_id = None,
+                "displayname" => user.display_name = None,
+                _ => {
+                    return Err(ScimError::bad_request(
+                        "mutability",
+                        format!("{} can't be removed", path),
+                    ))
+                }
+            }
+            Ok(())
+        }
+        other => Err(ScimError::bad_request(
+            "invalidSyntax",
+            format!("Unknown operation {}", other),
+        )),
+    }
+}
+
+/// List Users | Top
+///
+/// SCIM user search with `filter`, `startIndex` and `count`.
+#[utoipa::path(
+    context_path = "/scim/v2",
+    params(
+        ListQuery
+    ),
+    responses(
+        (status = 200, description = "ListResponse of users"),
+        (status = 400, description = "Invalid filter", body = ScimError),
+        (status = 401, description = "Invalid provisioning token", body = ScimError)
+    )
+)]
+#[get("/Users")]
+pub async fn list_users(
+    _scim: ScimClient,
+    query: Query<ListQuery>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ScimError> {
+    let filter = parse_filter(&query)?;
+    let (start_index, count) = page(&query);
+    let client: Client = db_pool.get().await?;
+
+    let (users, total) =
+        db::scim_user_list(&client, filter.as_ref(), count, start_index - 1).await?;
+    let base_url = base_url();
+    let resources: Vec<ScimUser> = users
+        .into_iter()
+        .map(|user| ScimUser::from_row(user, &base_url))
+        .collect();
+
+    Ok(scim_response(
+        &mut HttpResponse::Ok(),
+        ListResponse {
+            schemas: vec![LIST_SCHEMA.to_owned()],
+            total_results: total,
+            start_index,
+            items_per_page: resources.len() as i64,
+            resources,
+        },
+    ))
+}
+
+/// Create User | Top
+///
+/// Provisions a user with the `user` role. Active users are emailed a link to choose a
+/// password.
+#[utoipa::path(
+    context_path = "/scim/v2",
+    request_body = ScimUserInput,
+    responses(
+        (status = 201, description = "User provisioned", body = ScimUser),
+        (status = 400, description = "Invalid user", body = ScimError),
+        (status = 409, description = "userName is already in use", body = ScimError)
+    )
+)]
+#[post("/Users")]
+pub async fn create_user(
+    _scim: ScimClient,
+    input: web::Json<ScimUserInput>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ScimError> {
+    let email = input_email(&input)?;
+    let config = configs::Config::from_env().unwrap();
+    let mut client: Client = db_pool.get().await?;
+
+    let hashed_password = encryption::password_hash(
+        &encryption::generate_token(),
+        config.srv_cnf.bcrypt_or_argon,
+    )
+    .await?;
+    let user = db::scim_user_create(
+        &mut client,
+        &email,
+        hashed_password,
+        input.external_id.as_deref(),
+        input.display_name.as_deref(),
+        input.active,
+    )
+    .await?;
+    log::info!("SCIM provisioned user {}", user.id);
+
+    if input.active {
+        send_password_reset(&client, user.id, &user.email).await?;
+    }
+
+    let user = ScimUser::from_row(user, &base_url());
+    Ok(scim_response(
+        HttpResponse::Created().insert_header((header::LOCATION, user.meta.location.clone())),
+        user,
+    ))
+}
+
+/// Get User | Top
+#[utoipa::path(
+    context_path = "/scim/v2",
+    responses(
+        (status = 200, description = "User", body = ScimUser),
+        (status = 404, description = "User not found", body = ScimError)
+    ),
+    params(
+        ("id", description = "User id")
+    )
+)]
+#[get("/Users/{id}")]
+pub async fn get_user(
+    _scim: ScimClient,
+    id: web::Path<String>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ScimError> {
+    let client: Client = db_pool.get().await?;
+
+    let user = db::scim_user_get(&client, parse_user_id(&id)?).await?;
+    Ok(scim_response(
+        &mut HttpResponse::Ok(),
+        ScimUser::from_row(user, &base_url()),
+    ))
+}
+
+/// Replace User | Top
+///
+/// Setting `active` to false deprovisions the user and revokes all their sessions.
+#[utoipa::path(
+    context_path = "/scim/v2",
+    request_body = ScimUserInput,
+    responses(
+        (status = 200, description = "User replaced", body = ScimUser),
+        (status = 404, description = "User not found", body = ScimError),
+        (status = 409, description = "userName is already in use", body = ScimError)
+    ),
+    params(
+        ("id", description = "User id")
+    )
+)]
+#[put("/Users/{id}")]
+pub async fn replace_user(
+    _scim: ScimClient,
+    id: web::Path<String>,
+    input: web::Json<ScimUserInput>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ScimError> {
+    let mut client: Client = db_pool.get().await?;
+
+    let mut user = db::scim_user_get(&client, parse_user_id(&id)?).await?;
+    user.email = input_email(&input)?;
+    user.external_id = input.external_id.clone();
+    user.display_name = input.display_name.clone();
+    user.disabled = !input.active;
+    db::scim_user_save(&mut client, &user).await?;
+    log::info!("SCIM replaced user {} (active: {})", user.id, input.active);
+
+    Ok(scim_response(
+        &mut HttpResponse::Ok(),
+        ScimUser::from_row(user, &base_url()),
+    ))
+}
+
+/// Patch User | Top
+///
+/// Applies `add`, `replace` and `remove` operations. Deactivating revokes all sessions.
+#[utoipa::path(
+    context_path = "/scim/v2",
+    request_body = PatchOp,
+    responses(
+        (status = 200, description = "User updated", body = ScimUser),
+        (status = 400, description = "Invalid operation", body = ScimError),
+        (status = 404, description = "User not found", body = ScimError)
+    ),
+    params(
+        ("id", description = "User id")
+    )
+)]
+#[patch("/Users/{id}")]
+pub async fn patch_user(
+    _scim: ScimClient,
+    id: web::Path<String>,
+    patch: web::Json<PatchOp>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ScimError> {
+    let mut client: Client = db_pool.get().await?;
+
+    let mut user = db::scim_user_get(&client, parse_user_id(&id)?).await?;
+    for operation in &patch.operations {
+        apply_user_operation(&mut user, operation)?;
+    }
+    db::scim_user_save(&mut client, &user).await?;
+    log::info!("SCIM patched user {} (active: {})", user.id, !user.disabled);
+
+    Ok(scim_response(
+        &mut HttpResponse::Ok(),
+        ScimUser::from_row(user, &base_url()),
+    ))
+}
+
+/// Delete User | Top
+///
+/// Deprovisions the user for good, their sessions are revoked.
+#[utoipa::path(
+    context_path = "/scim/v2",
+    responses(
+        (status = 204, description = "User deleted"),
+        (status = 404, description = "User not found", body = ScimError)
+    ),
+    params(
+        ("id", description = "User id")
+    )
+)]
+#[delete("/Users/{id}")]
+pub async fn delete_user(
+    _scim: ScimClient,
+    id: web::Path<String>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ScimError> {
+    let user_id = parse_user_id(&id)?;
+    let mut client: Client = db_pool.get().await?;
+
+    user_delete(&mut client, user_id).await?;
+    log::info!("SCIM deleted user {}", user_id);
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+async fn group(client: &Client, role: Role, base_url: &str) -> Result<ScimGroup, ScimError> {
+    Ok(ScimGroup {
+        schemas: vec![GROUP_SCHEMA.to_owned()],
+        id: role.to_string(),
+        display_name: role.to_string(),
+        members: db::scim_group_members(client, role).await?,
+        meta: ScimMeta {
+            resource_type: "Group".to_owned(),
+            created: None,
+            location: format!("{}/Groups/{}", base_url, role),
+        },
+    })
+}
+
+fn member_ids(value: Option<&JsonValue>) -> Result<Vec<i32>, ScimError> {
+    let members: Vec<ScimMember> = match value {
+        Some(value) => serde_json::from_value(value.clone())
+            .map_err(|e| ScimError::bad_request("invalidValue", e.to_string()))?,
+        None => Vec::new(),
+    };
+    members
+        .iter()
+        .map(|member| {
+            member.value.parse().map_err(|_| {
+                ScimError::bad_request("invalidValue", format!("Unknown member {}", member.value))
+            })
+        })
+        .collect()
+}
+
+// The ids selected by a `members[value eq "12"]` path.
+fn member_path_ids(path: &str) -> Result<Vec<i32>, ScimError> {
+    let inner = path
+        .strip_prefix("members[")
+        .and_then(|rest| rest.strip_suffix(']'))
+        .ok_or_else(|| {
+            ScimError::bad_request("invalidPath", format!("Unsupported path {}", path))
+        })?;
+
+    fn collect(filter: &Filter, ids: &mut Vec<i32>) -> bool {
+        match filter {
+            Filter::Or(left, right) => collect(left, ids) && collect(right, ids),
+            Filter::Compare {
+                attr,
+                op: CompareOp::Eq,
+                value: Value::Str(value),
+            } if attr.eq_ignore_ascii_case("value") => match value.parse() {
+                Ok(id) => {
+                    ids.push(id);
+                    true
+                }
+                Err(_) => false,
+            },
+            _ => false,
+        }
+    }
+
+    let filter = filter::parse(inner).map_err(|e| ScimError::bad_request("invalidFilter", e))?;
+    let mut ids = Vec::new();
+    if collect(&filter, &mut ids) {
+        Ok(ids)
+    } else {
+        Err(ScimError::bad_request(
+            "invalidFilter",
+            format!("Unsupported member filter {}", inner),
+        ))
+    }
+}
+
+async fn add_members(client: &Client, role: Role, ids: &[i32]) -> Result<(), ScimError> {
+    for id in ids {
+        user_set_role(client, *id, role).await?;
+    }
+    Ok(())
+}
+
+// Everybody holds at least the `user` role, leaving any other group falls back to it.
+async fn remove_members(client: &Client, role: Role, ids: &[i32]) -> Result<(), ScimError> {
+    if role == Role::User {
+        return Ok(());
+    }
+    for id in ids {
+        let user = db::scim_user_get(client, *id).await?;
+        if user.role == role.as_str() {
+            user_set_role(client, *id, Role::User).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn set_members(client: &Client, role: Role, ids: &[i32]) -> Result<(), ScimError> {
+    add_members(client, role, ids).await?;
+    if role != Role::User {
+        db::scim_role_demote(client, role, ids).await?;
+    }
+    Ok(())
+}
+
+fn check_display_name(role: Role, display_name: Option<&str>) -> Result<(), ScimError> {
+    match display_name {
+        Some(name) if name != role.as_str() => Err(ScimError::bad_request(
+            "mutability",
+            "Groups are roles and can't be renamed",
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// List Groups | Top
+///
+/// The roles, as groups whose members are the users holding them.
+#[utoipa::path(
+    context_path = "/scim/v2",
+    params(
+        ListQuery
+    ),
+    responses(
+        (status = 200, description = "ListResponse of groups"),
+        (status = 400, description = "Invalid filter", body = ScimError)
+    )
+)]
+#[get("/Groups")]
+pub async fn list_groups(
+    _scim: ScimClient,
+    query: Query<ListQuery>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ScimError> {
+    let filter = parse_filter(&query)?;
+    let (start_index, count) = page(&query);
+    let client: Client = db_pool.get().await?;
+
+    let roles: Vec<Role> = ROLES
+        .into_iter()
+        .filter(|role| match &filter {
+            Some(filter) => filter::matches(filter, &|attr: &str| match attr
+                .to_ascii_lowercase()
+                .as_str()
+            {
+                "id" | "displayname" => Some(role.to_string()),
+                _ => None,
+            }),
+            None => true,
+        })
+        .collect();
+    let total = roles.len() as i64;
+
+    let base_url = base_url();
+    let mut resources = Vec::new();
+    for role in roles
+        .into_iter()
+        .skip((start_index - 1) as usize)
+        .take(count as usize)
+    {
+        resources.push(group(&client, role, &base_url).await?);
+    }
+
+    Ok(scim_response(
+        &mut HttpResponse::Ok(),
+        ListResponse {
+            schemas: vec![LIST_SCHEMA.to_owned()],
+            total_results: total,
+            start_index,
+            items_per_page: resources.len() as i64,
+            resources,
+        },
+    ))
+}
+
+/// Get Group | Top
+#[utoipa::path(
+    context_path = "/scim/v2",
+    responses(
+        (status = 200, description = "Group", body = ScimGroup),
+        (status = 404, description = "Group not found", body = ScimError)
+    ),
+    params(
+        ("id", description = "Role name")
+    )
+)]
+#[get("/Groups/{id}")]
+pub async fn get_group(
+    _scim: ScimClient,
+    id: web::Path<String>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ScimError> {
+    let role = parse_group_id(&id)?;
+    let client: Client = db_pool.get().await?;
+
+    Ok(scim_response(
+        &mut HttpResponse::Ok(),
+        group(&client, role, &base_url()).await?,
+    ))
+}
+
+/// Replace Group | Top
+///
+/// Gives the role to exactly the listed members, others fall back to `user`.
+#[utoipa::path(
+    context_path = "/scim/v2",
+    request_body = ScimGroupInput,
+    responses(
+        (status = 200, description = "Group replaced", body = ScimGroup),
+        (status = 400, description = "Invalid group", body = ScimError),
+        (status = 404, description = "Group or member not found", body = ScimError)
+    ),
+    params(
+        ("id", description = "Role name")
+    )
+)]
+#[put("/Groups/{id}")]
+pub async fn replace_group(
+    _scim: ScimClient,
+    id: web::Path<String>,
+    input: web::Json<ScimGroupInput>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ScimError> {
+    let role = parse_group_id(&id)?;
+    check_display_name(role, input.display_name.as_deref())?;
+    let client: Client = db_pool.get().await?;
+
+    let ids = member_ids(Some(&json!(input.members)))?;
+    set_members(&client, role, &ids).await?;
+    log::info!("SCIM set the members of group {}", role);
+
+    Ok(scim_response(
+        &mut HttpResponse::Ok(),
+        group(&client, role, &base_url()).await?,
+    ))
+}
+
+/// Patch Group | Top
+///
+/// Adds or removes members of a role. Members removed from `editor` or `admin` fall back
+/// to `user`.
+#[utoipa::path(
+    context_path = "/scim/v2",
+    request_body = PatchOp,
+    responses(
+        (status = 200, description = "Group updated", body = ScimGroup),
+        (status = 400, description = "Invalid operation", body = ScimError),
+        (status = 404, description = "Group or member not found", body = ScimError)
+    ),
+    params(
+        ("id", description = "Role name")
+    )
+)]
+#[patch("/Groups/{id}")]
+pub async fn patch_group(
+    _scim: ScimClient,
+    id: web::Path<String>,
+    patch: web::Json<PatchOp>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ScimError> {
+    let role = parse_group_id(&id)?;
+    let client: Client = db_pool.get().await?;
+
+    for operation in &patch.operations {
+        let op = operation.op.to_ascii_lowercase();
+        let path = operation.path.as_deref().unwrap_or("");
+
+        match (op.as_str(), path) {
+            ("add", "members") => {
+                add_members(&client, role, &member_ids(operation.value.as_ref())?).await?
+            }
+            ("replace", "members") => {
+                set_members(&client, role, &member_ids(operation.value.as_ref())?).await?
+            }
+            ("remove", "members") => {
+                remove_members(&client, role, &member_ids(operation.value.as_ref())?).await?
+            }
+            ("remove", path) => remove_members(&client, role, &member_path_ids(path)?).await?,
+            ("replace", _) | ("add", _) => {
+                // A path-less replace carries the attributes as an object.
+                let value = operation.value.as_ref();
+                let display_name = match path {
+                    "displayName" => value.and_then(JsonValue::as_str),
+                    "" => value
+                        .and_then(|value| value.get("displayName"))
+                        .and_then(JsonValue::as_str),
+                    _ => {
+                        return Err(ScimError::bad_request(
+                            "invalidPath",
+                            format!("Unsupported path {}", path),
+                        ))
+                    }
+                };
+                check_display_name(role, display_name)?;
+                if let Some(members) = value.and_then(|value| value.get("members")) {
+                    let ids = member_ids(Some(members))?;
+                    if op == "add" {
+                        add_members(&client, role, &ids).await?;
+                    } else {
+                        set_members(&client, role, &ids).await?;
+                    }
+                }
+            }
+            (other, _) => {
+                return Err(ScimError::bad_request(
+                    "invalidSyntax",
+                    format!("Unknown operation {}", other),
+                ))
+            }
+        }
+    }
+    log::info!("SCIM patched the members of group {}", role);
+
+    Ok(scim_response(
+        &mut HttpResponse::Ok(),
+        group(&client, role, &base_url()).await?,
+    ))
+}
+
+/// Service Provider Config | Top
+///
+/// The SCIM features this service supports.
+#[utoipa::path(
+    context_path = "/scim/v2",
+    responses(
+        (status = 200, description = "Supported features")
+    )
+)]
+#[get("/ServiceProviderConfig")]
+pub async fn service_provider_config(_scim: ScimClient) -> HttpResponse {
+    scim_response(
+        &mut HttpResponse::Ok(),
+        json!({
+            "schemas": [SERVICE_PROVIDER_CONFIG_SCHEMA],
+            "patch": { "supported": true },
+            "bulk": { "supported": false, "maxOperations": 0, "maxPayloadSize": 0 },
+            "filter": { "supported": true, "maxResults": MAX_COUNT },
+            "changePassword": { "supported": false },
+            "sort": { "supported": false },
+            "etag": { "supported": false },
+            "authenticationSchemes": [{
+                "type": "oauthbearertoken",
+                "name": "Provisioning token",
+                "description": "Bearer token configured with SCIM_TOKEN",
+                "primary": true
+            }]
+        }),
+    )
+}
+
+pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_users);
+    cfg.service(create_user);
+    cfg.service(get_user);
+    cfg.service(replace_user);
+    cfg.service(patch_user);
+    cfg.service(delete_user);
+    cfg.service(list_groups);
+    cfg.service(get_group);
+    cfg.service(replace_group);
+    cfg.service(patch_group);
+    cfg.service(service_provider_config);
+}
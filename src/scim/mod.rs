@@ -0,0 +1,7 @@
+pub mod db;
+pub mod filter;
+pub mod handlers;
+pub mod models;
+pub use crate::scim::db::*;
+pub use crate::scim::handlers::*;
+pub use crate::scim::models::*;
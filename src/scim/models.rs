@@ -0,0 +1,262 @@
+use std::fmt;
+
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio_pg_mapper_derive::PostgresMapper;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::errors::ServiceError;
+
+pub const USER_SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:User";
+pub const GROUP_SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:Group";
+pub const LIST_SCHEMA: &str = "urn:ietf:params:scim:api:messages:2.0:ListResponse";
+pub const PATCH_SCHEMA: &str = "urn:ietf:params:scim:api:messages:2.0:PatchOp";
+pub const ERROR_SCHEMA: &str = "urn:ietf:params:scim:api:messages:2.0:Error";
+pub const SERVICE_PROVIDER_CONFIG_SCHEMA: &str =
+    "urn:ietf:params:scim:schemas:core:2.0:ServiceProviderConfig";
+
+pub const SCIM_CONTENT_TYPE: &str = "application/scim+json";
+
+/// The columns of `users` a SCIM user is built from.
+#[derive(Debug, Clone, Deserialize, PostgresMapper)]
+#[pg_mapper(table = "users")]
+pub struct ScimUserRow {
+    pub id: i32,
+    pub email: String,
+    pub external_id: Option<String>,
+    pub display_name: Option<String>,
+    pub role: String,
+    pub disabled: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct ScimEmail {
+    pub value: String,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub primary: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct ScimMember {
+    pub value: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimMeta {
+    pub resource_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created: Option<DateTime<Utc>>,
+    pub location: String,
+}
+
+/// A user as SCIM sees it: `userName` is the email address and the role is exposed as
+/// the user's only group.
+#[derive(Serialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimUser {
+    pub schemas: Vec<String>,
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external_id: Option<String>,
+    pub user_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    pub active: bool,
+    pub emails: Vec<ScimEmail>,
+    pub groups: Vec<ScimMember>,
+    pub meta: ScimMeta,
+}
+
+impl ScimUser {
+    pub fn from_row(row: ScimUserRow, base_url: &str) -> ScimUser {
+        ScimUser {
+            schemas: vec![USER_SCHEMA.to_owned()],
+            id: row.id.to_string(),
+            external_id: row.external_id,
+            user_name: row.email.clone(),
+            display_name: row.display_name,
+            active: !row.disabled,
+            emails: vec![ScimEmail {
+                value: row.email,
+                kind: Some("work".to_owned()),
+                primary: Some(true),
+            }],
+            groups: vec![ScimMember {
+                value: row.role.clone(),
+                display: Some(row.role),
+            }],
+            meta: ScimMeta {
+                resource_type: "User".to_owned(),
+                created: Some(row.created_at),
+                location: format!("{}/Users/{}", base_url, row.id),
+            },
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Body of `POST /Users` and `PUT /Users/{id}`.
+#[derive(Deserialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimUserInput {
+    pub user_name: String,
+    pub external_id: Option<String>,
+    pub display_name: Option<String>,
+    #[serde(default = "default_true")]
+    pub active: bool,
+    #[serde(default)]
+    pub emails: Vec<ScimEmail>,
+}
+
+/// A role, exposed as a group whose members are the users holding it.
+#[derive(Serialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimGroup {
+    pub schemas: Vec<String>,
+    pub id: String,
+    pub display_name: String,
+    pub members: Vec<ScimMember>,
+    pub meta: ScimMeta,
+}
+
+/// Body of `PUT /Groups/{id}`, only the members can change.
+#[derive(Deserialize, Debug, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimGroupInput {
+    pub display_name: Option<String>,
+    #[serde(default)]
+    pub members: Vec<ScimMember>,
+}
+
+#[derive(Deserialize, Debug, Clone, ToSchema)]
+pub struct PatchOperation {
+    /// `add`, `replace` or `remove`, any case.
+    pub op: String,
+    pub path: Option<String>,
+    #[schema(value_type = Object)]
+    pub value: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize, Debug, Clone, ToSchema)]
+pub struct PatchOp {
+    #[serde(default)]
+    pub schemas: Vec<String>,
+    #[serde(rename = "Operations")]
+    pub operations: Vec<PatchOperation>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ListResponse<T: Serialize> {
+    pub schemas: Vec<String>,
+    pub total_results: i64,
+    pub start_index: i64,
+    pub items_per_page: i64,
+    #[serde(rename = "Resources")]
+    pub resources: Vec<T>,
+}
+
+/// Filtering and pagination of list requests. `startIndex` is 1-based.
+#[derive(Deserialize, Debug, Clone, IntoParams)]
+#[serde(rename_all = "camelCase")]
+pub struct ListQuery {
+    pub filter: Option<String>,
+    pub start_index: Option<i64>,
+    pub count: Option<i64>,
+}
+
+/// Errors in the SCIM error format, which identity providers parse.
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimError {
+    pub schemas: Vec<String>,
+    pub status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scim_type: Option<String>,
+    pub detail: String,
+}
+
+impl ScimError {
+    pub fn new(status: StatusCode, scim_type: Option<&str>, detail: impl Into<String>) -> Self {
+        ScimError {
+            schemas: vec![ERROR_SCHEMA.to_owned()],
+            status: status.as_u16().to_string(),
+            scim_type: scim_type.map(str::to_owned),
+            detail: detail.into(),
+        }
+    }
+
+    pub fn bad_request(scim_type: &str, detail: impl Into<String>) -> Self {
+        ScimError::new(StatusCode::BAD_REQUEST, Some(scim_type), detail)
+    }
+
+    pub fn not_found(detail: impl Into<String>) -> Self {
+        ScimError::new(StatusCode::NOT_FOUND, None, detail)
+    }
+}
+
+impl fmt::Display for ScimError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.status, self.detail)
+    }
+}
+
+impl ResponseError for ScimError {
+    fn status_code(&self) -> StatusCode {
+        self.status
+            .parse()
+            .ok()
+            .and_then(|status| StatusCode::from_u16(status).ok())
+            .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code())
+            .content_type(SCIM_CONTENT_TYPE)
+            .json(self)
+    }
+}
+
+impl From<ServiceError> for ScimError {
+    fn from(error: ServiceError) -> ScimError {
+        match error {
+            ServiceError::NotFound(detail) => ScimError::not_found(detail),
+            ServiceError::BadId => ScimError::bad_request("invalidValue", "Invalid ID"),
+            ServiceError::BadRequest(detail) => ScimError::bad_request("invalidValue", detail),
+            ServiceError::Conflict(detail) => {
+                ScimError::new(StatusCode::CONFLICT, Some("uniqueness"), detail)
+            }
+            ServiceError::Unauthorized => {
+                ScimError::new(StatusCode::UNAUTHORIZED, None, "Unauthorized")
+            }
+            ServiceError::Forbidden(detail) => ScimError::new(StatusCode::FORBIDDEN, None, detail),
+            other => {
+                log::error!("SCIM request failed: {:?}", other);
+                ScimError::new(StatusCode::INTERNAL_SERVER_ERROR, None, "Internal error")
+            }
+        }
+    }
+}
+
+impl From<tokio_postgres::Error> for ScimError {
+    fn from(error: tokio_postgres::Error) -> ScimError {
+        ServiceError::from(error).into()
+    }
+}
+
+impl From<deadpool_postgres::PoolError> for ScimError {
+    fn from(error: deadpool_postgres::PoolError) -> ScimError {
+        ServiceError::from(error).into()
+    }
+}
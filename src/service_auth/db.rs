@@ -0,0 +1,114 @@
+use deadpool_postgres::Client;
+use tokio_pg_mapper::FromTokioPostgresRow;
+
+use crate::configs::db;
+use crate::errors::ServiceError;
+use crate::service_auth::ServiceKeyRow;
+
+const SERVICE_KEY_COLUMNS: &str =
+    "id, service, key_id, secret_encr, created_by, created_at, last_used_at, revoked_at";
+
+pub async fn service_key_add(
+    client: &Client,
+    service: &str,
+    key_id: &str,
+    secret_encr: &str,
+    created_by: i32,
+) -> Result<ServiceKeyRow, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "INSERT INTO {service_keys} (service, key_id, secret_encr, created_by)
+            VALUES ($1, $2, $3, $4) RETURNING {}",
+            SERVICE_KEY_COLUMNS,
+            service_keys = db().service_keys
+        ))
+        .await?;
+
+    let row = client
+        .query_one(&statement, &[&service, &key_id, &secret_encr, &created_by])
+        .await?;
+    Ok(ServiceKeyRow::from_row_ref(&row).unwrap())
+}
+
+pub async fn service_key_list(client: &Client) -> Result<Vec<ServiceKeyRow>, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "SELECT {} FROM {service_keys} ORDER BY id DESC",
+            SERVICE_KEY_COLUMNS,
+            service_keys = db().service_keys
+        ))
+        .await?;
+
+    let keys = client
+        .query(&statement, &[])
+        .await?
+        .iter()
+        .map(|row| ServiceKeyRow::from_row_ref(row).unwrap())
+        .collect::<Vec<ServiceKeyRow>>();
+    Ok(keys)
+}
+
+/// A key that hasn't been revoked.
+pub async fn service_key_find(
+    client: &Client,
+    key_id: &str,
+) -> Result<Option<ServiceKeyRow>, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "SELECT {} FROM {service_keys} WHERE key_id = $1 AND revoked_at IS NULL",
+            SERVICE_KEY_COLUMNS,
+            service_keys = db().service_keys
+        ))
+        .await?;
+
+    Ok(client
+        .query_opt(&statement, &[&key_id])
+        .await?
+        .map(|row| ServiceKeyRow::from_row_ref(&row).unwrap()))
+}
+
+/// The newest active key of a service.
+pub async fn service_key_find_by_service(
+    client: &Client,
+    service: &str,
+) -> Result<Option<ServiceKeyRow>, ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "SELECT {} FROM {service_keys} WHERE service = $1 AND revoked_at IS NULL
+            ORDER BY id DESC LIMIT 1",
+            SERVICE_KEY_COLUMNS,
+            service_keys = db().service_keys
+        ))
+        .await?;
+
+    Ok(client
+        .query_opt(&statement, &[&service])
+        .await?
+        .map(|row| ServiceKeyRow::from_row_ref(&row).unwrap()))
+}
+
+pub async fn service_key_touch(client: &Client, id: i32) -> Result<(), ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "UPDATE {service_keys} SET last_used_at = now() WHERE id = $1",
+            service_keys = db().service_keys
+        ))
+        .await?;
+
+    client.execute(&statement, &[&id]).await?;
+    Ok(())
+}
+
+pub async fn service_key_revoke(client: &Client, id: i32) -> Result<(), ServiceError> {
+    let statement = client
+        .prepare(&format!(
+            "UPDATE {service_keys} SET revoked_at = now() WHERE id = $1 AND revoked_at IS NULL",
+            service_keys = db().service_keys
+        ))
+        .await?;
+
+    match client.execute(&statement, &[&id]).await? {
+        1 => Ok(()),
+        _ => Err(ServiceError::NotFound("Service key not found".into())),
+    }
+}
@@ -0,0 +1,113 @@
+use crate::auth::{encryption, AdminUser};
+use crate::configs;
+use crate::errors::ServiceError;
+use crate::service_auth::db;
+use crate::service_auth::models::{CreateServiceKey, IssuedServiceKey, ServiceKey};
+
+use actix_web::{delete, get, post, web, HttpResponse};
+use deadpool_postgres::{Client, Pool};
+
+pub(crate) fn secret_key() -> Result<Vec<u8>, ServiceError> {
+    let config = configs::Config::from_env().unwrap();
+    encryption::hex_to_bytes(&config.srv_cnf.secret_key)
+        .map_err(|_| ServiceError::FaultySetup("SECRET_KEY could not parse".into()))
+}
+
+/// Issue a service key.
+///
+/// Returns the key id and the secret the service signs its requests with. The secret is
+/// only shown here.
+#[utoipa::path(
+    context_path = "/admin/services",
+    request_body = CreateServiceKey,
+    responses(
+        (status = 201, description = "Key issued", body = IssuedServiceKey),
+        (status = 400, description = "Missing service name", body = ServiceError),
+        (status = 403, description = "Admin role required", body = ServiceError)
+    )
+)]
+#[post("/keys")]
+pub async fn issue_service_key(
+    admin: AdminUser,
+    local_object: web::Json<CreateServiceKey>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let service = local_object.service.trim();
+    if service.is_empty() || service.len() > 100 {
+        return Err(ServiceError::BadRequest(
+            "Service name must be 1 to 100 characters".into(),
+        ));
+    }
+    let client: Client = db_pool.get().await?;
+
+    let key_id = format!("sk_{}", &encryption::generate_token()[..24]);
+    let secret = encryption::generate_token();
+    let secret_encr = encryption::encrypt(&secret, &key_id, &secret_key()?)?;
+    let key = db::service_key_add(&client, service, &key_id, &secret_encr, admin.0.id).await?;
+    log::info!(
+        "admin {} issued service key {} for {}",
+        admin.0.id,
+        key.key_id,
+        key.service
+    );
+
+    Ok(HttpResponse::Created().json(IssuedServiceKey {
+        key: key.into(),
+        secret,
+    }))
+}
+
+/// List service keys, revoked ones included.
+#[utoipa::path(
+    context_path = "/admin/services",
+    responses(
+        (status = 200, description = "Service keys, newest first", body = [ServiceKey]),
+        (status = 403, description = "Admin role required", body = ServiceError)
+    )
+)]
+#[get("/keys")]
+pub async fn list_service_keys(
+    _admin: AdminUser,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let client: Client = db_pool.get().await?;
+
+    let keys = db::service_key_list(&client)
+        .await?
+        .into_iter()
+        .map(ServiceKey::from)
+        .collect::<Vec<ServiceKey>>();
+    Ok(HttpResponse::Ok().json(keys))
+}
+
+/// Revoke a service key. Requests signed with it are refused from now on.
+#[utoipa::path(
+    context_path = "/admin/services",
+    responses(
+        (status = 200, description = "Key revoked"),
+        (status = 403, description = "Admin role required", body = ServiceError),
+        (status = 404, description = "Key not found or already revoked", body = ServiceError)
+    ),
+    params(
+        ("id", description = "Id of the service key")
+    )
+)]
+#[delete("/keys/{id}")]
+pub async fn revoke_service_key(
+    admin: AdminUser,
+    id: web::Path<i32>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let client: Client = db_pool.get().await?;
+
+    db::service_key_revoke(&client, *id).await?;
+    log::info!("admin {} revoked service key {}", admin.0.id, id);
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(issue_service_key);
+    cfg.service(list_service_keys);
+    cfg.service(revoke_service_key);
+}
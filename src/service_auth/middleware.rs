@@ -0,0 +1,206 @@
+use std::future::{self, Ready};
+use std::rc::Rc;
+
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::error::PayloadError;
+use actix_web::web::{Bytes, BytesMut};
+use actix_web::{web, HttpMessage, HttpResponse};
+use chrono::Utc;
+use deadpool_postgres::Pool;
+use futures::future::LocalBoxFuture;
+use futures::StreamExt;
+
+use crate::auth::encryption;
+use crate::configs;
+use crate::service_auth::db;
+use crate::service_auth::handlers::secret_key;
+use crate::service_auth::signature::{canonical_request, replay_cache, verify, within_window};
+use crate::service_auth::{ServiceIdentity, SignedRequest};
+
+/// Key id of the calling service.
+pub const SERVICE_KEY_HEADER: &str = "x-service-key";
+/// Unix time in seconds at which the request was signed.
+pub const SIGNATURE_TIMESTAMP_HEADER: &str = "x-signature-timestamp";
+/// Hex encoded HMAC-SHA256, see `signature::canonical_request`.
+pub const SIGNATURE_HEADER: &str = "x-signature";
+
+/// Largest body a signed request may carry, it is read in full to check the digest.
+const MAX_SIGNED_BODY: usize = 4 * 1024 * 1024;
+
+/// Checks requests carrying `x-service-key` and refuses them with 401 unless they are
+/// correctly signed, within the replay window and not seen before. The calling service is
+/// then available to handlers as `ServiceIdentity`. Unsigned requests pass through.
+///
+/// Signed requests skip the CSRF check, so it has to be registered after `CsrfGuard`. Its
+/// `ServiceIdentity` replaces one `ClientCertificateGuard` found for the connection.
+pub struct ServiceSignatureGuard;
+
+impl<S, B> Transform<S, ServiceRequest> for ServiceSignatureGuard
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = ServiceSignatureMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        future::ready(Ok(ServiceSignatureMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct ServiceSignatureMiddleware<S> {
+    service: Rc<S>,
+}
+
+fn header(req: &ServiceRequest, name: &str) -> Option<String> {
+    req.headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned)
+}
+
+async fn read_body(req: &mut ServiceRequest) -> Result<Bytes, &'static str> {
+    let mut payload = req.take_payload();
+    let mut body = BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(|_| "Could not read the request body")?;
+        if body.len() + chunk.len() > MAX_SIGNED_BODY {
+            return Err("Signed request body is too large");
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body.freeze())
+}
+
+/// The service whose signature `req` carries, or why it is refused. Leaves the body in
+/// place for the handler.
+async fn check_signature(req: &mut ServiceRequest) -> Result<ServiceIdentity, &'static str> {
+    let (key_id, timestamp, signature) = match (
+        header(req, SERVICE_KEY_HEADER),
+        header(req, SIGNATURE_TIMESTAMP_HEADER),
+        header(req, SIGNATURE_HEADER),
+    ) {
+        (Some(key_id), Some(timestamp), Some(signature)) => (key_id, timestamp, signature),
+        _ => return Err("Incomplete request signature"),
+    };
+
+    let config = configs::Config::from_env().unwrap();
+    let window = config.srv_cnf.signature_window_secs;
+    let now = Utc::now().timestamp();
+    let timestamp = timestamp
+        .parse::<i64>()
+        .map_err(|_| "Invalid signature timestamp")?;
+    if !within_window(timestamp, now, window) {
+        return Err("Request signature expired");
+    }
+
+    let pool = req
+        .app_data::<web::Data<Pool>>()
+        .cloned()
+        .ok_or("Service keys unavailable")?;
+    let client = pool.get().await.map_err(|e| {
+        log::error!("could not check a service signature: {}", e);
+        "Service keys unavailable"
+    })?;
+    let key = db::service_key_find(&client, &key_id)
+        .await
+        .map_err(|e| {
+            log::error!("could not look up service key {}: {:?}", key_id, e);
+            "Service keys unavailable"
+        })?
+        .ok_or("Unknown service key")?;
+    let secret = secret_key()
+        .and_then(|secret_key| encryption::decrypt(&key.secret_encr, &key.key_id, &secret_key))
+        .map_err(|e| {
+            log::error!("could not decrypt service key {}: {:?}", key.key_id, e);
+            "Service keys unavailable"
+        })?;
+
+    let body = read_body(req).await?;
+    let path = req
+        .uri()
+        .path_and_query()
+        .map(|path| path.as_str().to_owned())
+        .unwrap_or_else(|| req.path().to_owned());
+    let canonical = canonical_request(req.method().as_str(), &path, timestamp, &body);
+    req.set_payload(Payload::Stream {
+        payload: Box::pin(futures::stream::once(
+            async move { Ok::<_, PayloadError>(body) },
+        )),
+    });
+
+    if !verify(&secret, &canonical, &signature) {
+        log::warn!("bad signature for service key {}", key.key_id);
+        return Err("Invalid request signature");
+    }
+    if !replay_cache().first_use(&signature, timestamp, now, window) {
+        log::warn!("replayed request for service key {}", key.key_id);
+        return Err("Request signature already used");
+    }
+
+    if let Err(e) = db::service_key_touch(&client, key.id).await {
+        log::error!(
+            "could not record use of service key {}: {:?}",
+            key.key_id,
+            e
+        );
+    }
+    Ok(ServiceIdentity {
+        key_id: key.key_id,
+        service: key.service,
+    })
+}
+
+impl<S, B> Service<ServiceRequest> for ServiceSignatureMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, actix_web::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+
+        Box::pin(async move {
+            if !req.headers().contains_key(SERVICE_KEY_HEADER) {
+                return service
+                    .call(req)
+                    .await
+                    .map(ServiceResponse::map_into_left_body);
+            }
+
+            match check_signature(&mut req).await {
+                Ok(identity) => {
+                    log::info!(
+                        "service {} ({}): {} {}",
+                        identity.service,
+                        identity.key_id,
+                        req.method(),
+                        req.path()
+                    );
+                    req.extensions_mut().insert(identity);
+                    req.extensions_mut().insert(SignedRequest);
+                    service
+                        .call(req)
+                        .await
+                        .map(ServiceResponse::map_into_left_body)
+                }
+                Err(reason) => Ok(req
+                    .into_response(HttpResponse::Unauthorized().json(reason))
+                    .map_into_right_body()),
+            }
+        })
+    }
+}
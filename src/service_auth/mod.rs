@@ -0,0 +1,9 @@
+pub mod db;
+pub mod handlers;
+pub mod middleware;
+pub mod models;
+pub mod signature;
+pub use crate::service_auth::db::*;
+pub use crate::service_auth::handlers::*;
+pub use crate::service_auth::middleware::*;
+pub use crate::service_auth::models::*;
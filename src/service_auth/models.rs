@@ -0,0 +1,91 @@
+use std::future::{self, Ready};
+
+use actix_web::{FromRequest, HttpMessage, HttpRequest};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio_pg_mapper_derive::PostgresMapper;
+use utoipa::ToSchema;
+
+use crate::errors::ServiceError;
+
+#[derive(Debug, Clone, Deserialize, PostgresMapper)]
+#[pg_mapper(table = "service_keys")]
+pub struct ServiceKeyRow {
+    pub id: i32,
+    pub service: String,
+    pub key_id: String,
+    pub secret_encr: String,
+    pub created_by: Option<i32>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+/// A signing key as admins see it, without the secret.
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct ServiceKey {
+    pub id: i32,
+    pub service: String,
+    /// Sent in the `x-service-key` header.
+    pub key_id: String,
+    pub created_by: Option<i32>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl From<ServiceKeyRow> for ServiceKey {
+    fn from(row: ServiceKeyRow) -> ServiceKey {
+        ServiceKey {
+            id: row.id,
+            service: row.service,
+            key_id: row.key_id,
+            created_by: row.created_by,
+            created_at: row.created_at,
+            last_used_at: row.last_used_at,
+            revoked_at: row.revoked_at,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, ToSchema)]
+#[schema(example = json!({"service": "newsletter-cron"}))]
+pub struct CreateServiceKey {
+    /// Name of the calling service, shown to handlers and in logs.
+    pub service: String,
+}
+
+/// Returned once when a key is issued, the secret can't be read back later.
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct IssuedServiceKey {
+    pub key: ServiceKey,
+    pub secret: String,
+}
+
+/// Marks a request `ServiceSignatureGuard` found correctly signed. Unlike a
+/// `ServiceIdentity`, which a client certificate gives too, a browser can't make one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SignedRequest;
+
+/// The internal service that signed the request, set by `ServiceSignatureGuard`.
+/// Handlers take it like they take `Session`; extracting it fails with 401 on requests
+/// that weren't signed.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ServiceIdentity {
+    pub key_id: String,
+    pub service: String,
+}
+
+impl FromRequest for ServiceIdentity {
+    type Error = ServiceError;
+    type Future = Ready<Result<ServiceIdentity, ServiceError>>;
+
+    fn from_request(req: &HttpRequest, _: &mut actix_web::dev::Payload) -> Self::Future {
+        future::ready(
+            req.extensions()
+                .get::<ServiceIdentity>()
+                .cloned()
+                .ok_or(ServiceError::Unauthorized),
+        )
+    }
+}
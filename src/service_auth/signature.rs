@@ -0,0 +1,116 @@
+//! Request signatures of internal services. The signed string is
+//!
+//! ```text
+//! METHOD\nPATH?QUERY\nTIMESTAMP\nHEX(SHA256(BODY))
+//! ```
+//!
+//! and the signature is the hex encoded HMAC-SHA256 of it under the service's secret.
+
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub fn canonical_request(method: &str, path: &str, timestamp: i64, body: &[u8]) -> String {
+    format!(
+        "{}\n{}\n{}\n{}",
+        method.to_ascii_uppercase(),
+        path,
+        timestamp,
+        hex::encode(Sha256::digest(body))
+    )
+}
+
+fn mac(secret: &str, canonical: &str) -> HmacSha256 {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(canonical.as_bytes());
+    mac
+}
+
+pub fn sign(secret: &str, canonical: &str) -> String {
+    hex::encode(mac(secret, canonical).finalize().into_bytes())
+}
+
+/// Compares in constant time.
+pub fn verify(secret: &str, canonical: &str, signature: &str) -> bool {
+    match hex::decode(signature) {
+        Ok(signature) => mac(secret, canonical).verify_slice(&signature).is_ok(),
+        Err(_) => false,
+    }
+}
+
+pub fn within_window(timestamp: i64, now: i64, window: i64) -> bool {
+    (now - timestamp).abs() <= window
+}
+
+/// Signatures accepted within the replay window, so each signed request is served once.
+/// Kept in memory, so only replays to the same process are caught.
+#[derive(Default)]
+pub struct ReplayCache {
+    seen: Mutex<HashMap<String, i64>>,
+}
+
+impl ReplayCache {
+    /// Records the signature, false when it was already seen. `verify` takes hex digits in
+    /// either case, so a re-cased signature counts as the same one.
+    pub fn first_use(&self, signature: &str, timestamp: i64, now: i64, window: i64) -> bool {
+        let signature = signature.to_ascii_lowercase();
+        let mut seen = self.seen.lock().unwrap_or_else(|e| e.into_inner());
+        seen.retain(|_, seen_at| within_window(*seen_at, now, window));
+        if seen.contains_key(&signature) {
+            return false;
+        }
+        seen.insert(signature, timestamp);
+        true
+    }
+}
+
+pub fn replay_cache() -> &'static ReplayCache {
+    static CACHE: OnceLock<ReplayCache> = OnceLock::new();
+    CACHE.get_or_init(ReplayCache::default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signature() {
+        let canonical = canonical_request("post", "/posts/?draft=1", 1700000000, b"{}");
+        assert_eq!(
+            canonical,
+            "POST\n/posts/?draft=1\n1700000000\n\
+             44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a"
+        );
+        let signature = sign("secret", &canonical);
+        assert!(verify("secret", &canonical, &signature));
+        assert!(!verify("other", &canonical, &signature));
+        assert!(!verify("secret", &canonical, "not hex"));
+        assert!(verify(
+            "secret",
+            &canonical,
+            &signature.to_ascii_uppercase()
+        ));
+        let tampered = canonical_request("post", "/posts/?draft=1", 1700000000, b"{ }");
+        assert!(!verify("secret", &tampered, &signature));
+    }
+
+    #[test]
+    fn test_replay_cache() {
+        let cache = ReplayCache::default();
+        assert!(cache.first_use("a", 100, 100, 300));
+        assert!(!cache.first_use("a", 100, 200, 300));
+        assert!(cache.first_use("b", 200, 200, 300));
+        // The same signature in other case verifies too, it is no new request.
+        assert!(cache.first_use("c0ffee", 200, 200, 300));
+        assert!(!cache.first_use("C0FFEE", 200, 210, 300));
+        assert!(!cache.first_use("c0FfEe", 200, 220, 300));
+        // Out of the window the entry is dropped, the timestamp check refuses it anyway.
+        assert!(cache.first_use("a", 100, 500, 300));
+        assert!(!within_window(100, 500, 300));
+    }
+}
@@ -0,0 +1,169 @@
+//! Slugs of posts and categories, the readable names they go by in URLs.
+//!
+//! Clients may pick a slug or leave it to the server, which makes one from the title by
+//! transliterating it to ASCII. A slug can't be used twice: a picked slug already in use is
+//! refused, a made one gets the first free `-2`, `-3`... suffix instead.
+
+use crate::errors::ServiceError;
+
+/// Longest slug, in bytes. Slugs are ASCII, so also in characters.
+pub const MAX_SLUG_LEN: usize = 80;
+
+/// Room left for suffixes when looking up the slugs a made one could clash with.
+const SUFFIX_ROOM: usize = 10;
+
+/// `text` as a slug: transliterated to lowercase ASCII letters and digits, with single
+/// dashes between words. Empty when nothing of `text` survives.
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::with_capacity(text.len());
+    for c in deunicode::deunicode(text).chars() {
+        match c {
+            c if c.is_ascii_alphanumeric() => slug.push(c.to_ascii_lowercase()),
+            // Elisions such as "don't" read better as one word.
+            '\'' => {}
+            _ if !slug.is_empty() && !slug.ends_with('-') => slug.push('-'),
+            _ => {}
+        }
+    }
+    truncate(&slug, MAX_SLUG_LEN).to_string()
+}
+
+/// At most `len` bytes of `slug`, cut after a whole word when there is one to cut after.
+fn truncate(slug: &str, len: usize) -> &str {
+    if slug.len() <= len {
+        return slug.trim_end_matches('-');
+    }
+    let cut = &slug[..len];
+    match cut.rfind('-') {
+        Some(end) if !slug[len..].starts_with('-') && end > 0 => &cut[..end],
+        _ => cut.trim_end_matches('-'),
+    }
+}
+
+/// The slug a post or category asks for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SlugChoice {
+    /// Picked by the client, normalized with `slugify`.
+    Given(String),
+    /// Made from the title.
+    Generated(String),
+}
+
+impl SlugChoice {
+    /// `given` if there is one, else a slug made from `title`, or `fallback` when the title
+    /// has nothing to make one from.
+    pub fn new(
+        given: Option<&str>,
+        title: &str,
+        fallback: &str,
+    ) -> Result<SlugChoice, ServiceError> {
+        match given {
+            Some(given) => {
+                let slug = slugify(given);
+                if slug.is_empty() {
+                    return Err(ServiceError::BadRequest(
+                        "Slugs need at least one letter or digit".into(),
+                    ));
+                }
+                Ok(SlugChoice::Given(slug))
+            }
+            None => {
+                let slug = slugify(title);
+                Ok(SlugChoice::Generated(if slug.is_empty() {
+                    fallback.to_string()
+                } else {
+                    slug
+                }))
+            }
+        }
+    }
+
+    /// What every slug this choice can end up as starts with, for `LIKE prefix || '%'`.
+    /// Holds only letters, digits and dashes.
+    pub fn prefix(&self) -> &str {
+        match self {
+            SlugChoice::Given(slug) => slug,
+            SlugChoice::Generated(slug) => truncate(slug, MAX_SLUG_LEN - SUFFIX_ROOM),
+        }
+    }
+
+    /// The slug to use, given the slugs starting with `prefix` that are taken.
+    pub fn resolve(&self, taken: &[String]) -> Result<String, ServiceError> {
+        let is_free = |slug: &str| !taken.iter().any(|taken| taken == slug);
+        match self {
+            SlugChoice::Given(slug) if is_free(slug) => Ok(slug.clone()),
+            SlugChoice::Given(slug) => Err(ServiceError::Conflict(format!(
+                "The slug {} is already in use",
+                slug
+            ))),
+            SlugChoice::Generated(slug) if is_free(slug) => Ok(slug.clone()),
+            SlugChoice::Generated(slug) => (2..)
+                .map(|n| {
+                    let suffix = format!("-{}", n);
+                    format!("{}{}", truncate(slug, MAX_SLUG_LEN - suffix.len()), suffix)
+                })
+                .find(|candidate| is_free(candidate))
+                .ok_or_else(|| ServiceError::Conflict("No free slug left".into())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slugify() {
+        assert_eq!(slugify("Hello, World!"), "hello-world");
+        assert_eq!(
+            slugify("  Don't panic -- it's fine "),
+            "dont-panic-its-fine"
+        );
+        assert_eq!(slugify("Crème brûlée à Zürich"), "creme-brulee-a-zurich");
+        assert_eq!(slugify("Привет мир"), "privet-mir");
+        assert_eq!(slugify("北京"), "bei-jing");
+        assert_eq!(slugify("!!!"), "");
+
+        let long = slugify(&"word ".repeat(40));
+        assert!(long.len() <= MAX_SLUG_LEN);
+        assert!(long.ends_with("word"));
+    }
+
+    #[test]
+    fn test_slug_choice() {
+        assert_eq!(
+            SlugChoice::new(None, "My Post", "post").unwrap(),
+            SlugChoice::Generated("my-post".into())
+        );
+        assert_eq!(
+            SlugChoice::new(None, "???", "post").unwrap(),
+            SlugChoice::Generated("post".into())
+        );
+        assert_eq!(
+            SlugChoice::new(Some("Custom Slug"), "My Post", "post").unwrap(),
+            SlugChoice::Given("custom-slug".into())
+        );
+        assert!(SlugChoice::new(Some("--"), "My Post", "post").is_err());
+    }
+
+    #[test]
+    fn test_resolve() {
+        let taken = vec!["my-post".to_string(), "my-post-2".to_string()];
+        let generated = SlugChoice::Generated("my-post".into());
+        assert_eq!(generated.resolve(&[]).unwrap(), "my-post");
+        assert_eq!(generated.resolve(&taken).unwrap(), "my-post-3");
+
+        let given = SlugChoice::Given("my-post".into());
+        assert!(matches!(
+            given.resolve(&taken),
+            Err(ServiceError::Conflict(_))
+        ));
+
+        let long = SlugChoice::Generated("a".repeat(MAX_SLUG_LEN));
+        let taken = vec!["a".repeat(MAX_SLUG_LEN)];
+        let slug = long.resolve(&taken).unwrap();
+        assert_eq!(slug.len(), MAX_SLUG_LEN);
+        assert!(slug.ends_with("-2"));
+        assert!(slug.starts_with(long.prefix()));
+    }
+}
@@ -1,61 +1,83 @@
+use crate::configs::db;
+use crate::errors::ServiceError;
+use crate::pagination::{Keyed, Keyset};
 use crate::tags::{CreateTags, Tags};
 use deadpool_postgres::Client;
 use std::io;
 use tokio_pg_mapper::FromTokioPostgresRow;
+use tokio_postgres::error::SqlState;
 
 // CORE CRUD
 
-//TODO configure .env for db shema name
-
 // Decide wether to return id or return all fields from insert sql query . if return ID, insert id in function argument.
 // shift id in db tables to the top so we can skip it when not needed
 
-pub async fn tags_add(client: &Client, selfobj: CreateTags) -> Result<Tags, io::Error> {
+// Turns a unique violation on the tag name into a conflict.
+fn tag_conflict(e: tokio_postgres::Error) -> ServiceError {
+    match e.code() {
+        Some(code) if *code == SqlState::UNIQUE_VIOLATION => {
+            ServiceError::Conflict("A tag with this name already exists".into())
+        }
+        _ => ServiceError::from(e),
+    }
+}
+
+pub async fn tags_add(client: &Client, selfobj: CreateTags) -> Result<Tags, ServiceError> {
     let statement = client
-        .prepare(
-            "INSERT INTO public.tags
+        .prepare(&format!(
+            "INSERT INTO {tags}
    (name)
-    VALUES ($0 ) RETURNING id, name",
-        )
-        .await
-        .unwrap();
+    VALUES ($1) RETURNING id, name",
+            tags = db().tags
+        ))
+        .await?;
 
-    client
-        .query(&statement, &[&selfobj.name])
+    let row = client
+        .query_one(&statement, &[&selfobj.name])
         .await
-        .expect("Error creating tags")
-        .iter()
-        .map(|row| Tags::from_row_ref(row).unwrap())
-        .collect::<Vec<Tags>>()
-        .pop()
-        .ok_or(io::Error::new(
-            io::ErrorKind::Other,
-            "Error creating tags tables",
-        ))
+        .map_err(tag_conflict)?;
+    Ok(Tags::from_row_ref(&row).unwrap())
 }
 
 // TODO populate fields
 
-pub async fn tags_list(client: &Client) -> Result<Vec<Tags>, io::Error> {
+/// A page of the tags whose name contains `q`.
+pub async fn tags_list(
+    client: &Client,
+    q: Option<&str>,
+    keyset: &Keyset,
+) -> Result<Vec<Keyed<Tags>>, ServiceError> {
     let statement = client
-        .prepare("select * from public.tags order by id desc")
-        .await
-        .unwrap();
+        .prepare(&format!(
+            "SELECT t.*, {} FROM {tags} t
+            WHERE ($1::text IS NULL OR t.name ILIKE '%' || $1 || '%')
+            and {}
+            {}",
+            keyset.key_column(),
+            keyset.condition("t.id", 2, 3),
+            keyset.order_by("t.id"),
+            tags = db().tags
+        ))
+        .await?;
 
-    let tags_list = client
-        .query(&statement, &[])
-        .await
-        .expect("Error getting author lists")
+    let tags = client
+        .query(&statement, &[&q, &keyset.key(), &keyset.id()])
+        .await?
         .iter()
-        .map(|row| Tags::from_row_ref(row).unwrap())
-        .collect::<Vec<Tags>>();
-
-    Ok(tags_list)
+        .map(|row| {
+            let tag = Tags::from_row_ref(row).unwrap();
+            Keyed::new(row, tag.id, tag)
+        })
+        .collect::<Vec<Keyed<Tags>>>();
+    Ok(tags)
 }
 
 pub async fn tags_id(client: &Client, id_tags: i32) -> Result<Tags, io::Error> {
     let statement = client
-        .prepare("select * from public.tags where id = $1")
+        .prepare(&format!(
+            "select * from {tags} where id = $1",
+            tags = db().tags
+        ))
         .await
         .unwrap();
 
@@ -73,31 +95,36 @@ pub async fn tags_id(client: &Client, id_tags: i32) -> Result<Tags, io::Error> {
 
 //TODO take into account ID position
 
-pub async fn tags_update(client: &Client, id: i32, mdl: CreateTags) -> Result<(), io::Error> {
+pub async fn tags_update(client: &Client, id: i32, mdl: CreateTags) -> Result<(), ServiceError> {
     let statement = client
-        .prepare("update public.tags set (name) = ($0) where id = $1")
-        .await
-        .unwrap();
+        .prepare(&format!(
+            "update {tags} set name = $1 where id = $2",
+            tags = db().tags
+        ))
+        .await?;
 
-    let result = client
+    match client
         .execute(&statement, &[&mdl.name, &id])
         .await
-        .expect("Error getting todo lists");
-
-    match result {
-        ref updated if *updated == 1 => Ok(()),
-        _ => Err(io::Error::new(io::ErrorKind::Other, "Failed to check list")),
+        .map_err(tag_conflict)?
+    {
+        1 => Ok(()),
+        _ => Err(ServiceError::NotFound("Tag not found".into())),
     }
 }
 
-pub async fn tags_delete(client: &Client, tags_id: i32) -> Result<(), io::Error> {
+pub async fn tags_delete(client: &Client, tags_id: i32) -> Result<(), ServiceError> {
     let statement = client
-        .prepare("DELETE FROM public.tags WHERE id = $1")
-        .await
-        .unwrap();
+        .prepare(&format!(
+            "DELETE FROM {tags} WHERE id = $1",
+            tags = db().tags
+        ))
+        .await?;
 
-    client.execute(&statement, &[&tags_id]).await.unwrap();
-    Ok(())
+    match client.execute(&statement, &[&tags_id]).await? {
+        1 => Ok(()),
+        _ => Err(ServiceError::NotFound("Tag not found".into())),
+    }
 }
 
 // END OF CORE CRUD
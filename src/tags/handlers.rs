@@ -1,63 +1,80 @@
+use crate::auth::AuthUser;
+use crate::errors::ServiceError;
+use crate::pagination::{Keyset, SortOrder};
+use crate::posts::handlers::is_editor;
+use crate::posts::{self, PostListQuery};
+use crate::posts_tags::tag_names;
 use crate::tags::db;
-use crate::tags::models::CreateTags;
+use crate::tags::models::{CreateTags, TagListQuery};
 use std::io;
 
-use actix_web::{delete, get, patch, post, web, HttpResponse, Responder};
+use actix_web::web::Query;
+use actix_web::{delete, get, patch, post, web, HttpRequest, HttpResponse, Responder};
 use deadpool_postgres::{Client, Pool};
 use io::ErrorKind::NotFound;
 
+/// Tags show on every post and in the feeds, only editors and admins change them.
+fn tag_editor(user: &AuthUser) -> Result<(), ServiceError> {
+    if is_editor(user) {
+        Ok(())
+    } else {
+        Err(ServiceError::Forbidden(
+            "Only editors and admins change tags".into(),
+        ))
+    }
+}
+
+/// Get a page of tags, by name unless sorted otherwise.
 #[utoipa::path(
     responses(
-        (status = 200, description = "Category lists", body = [Tags]),
-    )
+        (status = 200, description = "Category lists", body = TagPage),
+        (status = 400, description = "Invalid cursor", body = ServiceError)
+    ),
+    params(TagListQuery)
 )]
 #[get("/")]
-pub async fn tags(db_pool: web::Data<Pool>) -> impl Responder {
-    let client: Client = db_pool
-        .get()
-        .await
-        .expect("Error connecting to the database");
-
-    let result = db::tags_list(&client).await;
-
-    match result {
-        Ok(object) => HttpResponse::Ok().json(object),
-        Err(_) => HttpResponse::InternalServerError().into(),
-    }
+pub async fn tags(
+    req: HttpRequest,
+    query: Query<TagListQuery>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let keyset = Keyset::new(
+        query.sort.unwrap_or_default().field(),
+        query.order.unwrap_or(SortOrder::Asc),
+        query.cursor.as_deref(),
+        query.limit,
+    )?;
+    let client: Client = db_pool.get().await?;
+
+    let tags = db::tags_list(&client, query.q.as_deref(), &keyset).await?;
+    Ok(HttpResponse::Ok().json(keyset.page(&req, tags)))
 }
 
-/// Create new Todo to shared in-memory storage.
-///
-/// Post a new `Todo` in request body as json to store it. Api will return
-/// created `Todo` on success or `ErrorResponse::Conflict` if todo with same id already exists.
+/// Create a tag.
 ///
-/// One could call the api with.
-/// ```text
-/// curl localhost:8080/todo -d '{"id": 1, "value": "Buy movie ticket", "checked": false}'
-/// ```
+/// Open to editors and admins.
 #[utoipa::path(
     request_body = CreateTags,
     responses(
-        (status = 201, description = "Category Successfully added", body = Tags),
-        (status = 409, description = "Category with id already exists", body = ServiceError)
+        (status = 201, description = "Tag created", body = Tags),
+        (status = 400, description = "Invalid tag name", body = ServiceError),
+        (status = 401, description = "Not signed in", body = ServiceError),
+        (status = 403, description = "Not an editor", body = ServiceError),
+        (status = 409, description = "A tag with this name already exists", body = ServiceError)
     )
 )]
 #[post("/")]
 pub async fn add_tags(
+    user: AuthUser,
     local_object: web::Json<CreateTags>,
     db_pool: web::Data<Pool>,
-) -> impl Responder {
-    let client: Client = db_pool
-        .get()
-        .await
-        .expect("Error connecting to the database");
-
-    let result = db::tags_add(&client, local_object.clone()).await;
+) -> Result<HttpResponse, ServiceError> {
+    tag_editor(&user)?;
+    let name = tag_names(std::slice::from_ref(&local_object.name))?.remove(0);
+    let client: Client = db_pool.get().await?;
 
-    match result {
-        Ok(object) => HttpResponse::Ok().json(object),
-        Err(_) => HttpResponse::InternalServerError().into(),
-    }
+    let tag = db::tags_add(&client, CreateTags { name }).await?;
+    Ok(HttpResponse::Created().json(tag))
 }
 
 /// Get Category by given todo id.
@@ -88,80 +105,112 @@ pub async fn get_tags(id_tags: web::Path<(i32,)>, db_pool: web::Data<Pool>) -> i
     }
 }
 
-/// Delete tag by given path variable id.
+/// Delete a tag, removing it from every post.
 ///
-/// This endpoint needs `api_key` authentication in order to call. Api key can be found from README.md.
-///
-/// Api will delete todo from shared in-memory storage by the provided id and return success 200.
-/// If storage does not contain `Todo` with given id 404 not found will be returned.
+/// Open to editors and admins.
 #[utoipa::path(
     responses(
-        (status = 200, description = "tag deleted successfully"),
-        (status = 401, description = "Unauthorized to delete tag", body = ServiceError),
-        (status = 404, description = "tag not found by id", body = ServiceError)
+        (status = 200, description = "Tag deleted"),
+        (status = 401, description = "Not signed in", body = ServiceError),
+        (status = 403, description = "Not an editor", body = ServiceError),
+        (status = 404, description = "Tag not found by id", body = ServiceError)
     ),
     params(
-        ("id", description = "Unique storage id of tag")
-    ),
-    security(
-        ("api_key" = [])
+        ("id", description = "Unique Tag Id")
     )
 )]
 #[delete("/{id}")]
-pub async fn delete_tags(tags_id: web::Path<(i32,)>, db_pool: web::Data<Pool>) -> impl Responder {
-    let client: Client = db_pool
-        .get()
-        .await
-        .expect("Error connecting to the database");
-
-    let result = db::tags_delete(&client, tags_id.0).await;
+pub async fn delete_tags(
+    user: AuthUser,
+    tags_id: web::Path<(i32,)>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    tag_editor(&user)?;
+    let client: Client = db_pool.get().await?;
 
-    match result {
-        Ok(object) => HttpResponse::Ok().json(object),
-        Err(ref e) if e.kind() == NotFound => HttpResponse::NotFound().into(),
-        Err(_) => HttpResponse::InternalServerError().into(),
-    }
+    db::tags_delete(&client, tags_id.0).await?;
+    Ok(HttpResponse::Ok().json(()))
 }
 
-/// Update Todo with given id.
-///
-/// This endpoint supports optional authentication.
+/// Rename a tag.
 ///
-/// Tries to update `Todo` by given id as path variable. If todo is found by id values are
-/// updated according `TodoUpdateRequest` and updated `Todo` is returned with status 200.
-/// If todo is not found then 404 not found is returned.
+/// Open to editors and admins.
 #[utoipa::path(
-    request_body = TodoUpdateRequest,
+    request_body = CreateTags,
     responses(
-        (status = 200, description = "Category updated successfully", body = CreateCategory),
-        (status = 404, description = "Category not found by id", body = ServiceError)
+        (status = 200, description = "Tag renamed"),
+        (status = 400, description = "Invalid tag name", body = ServiceError),
+        (status = 401, description = "Not signed in", body = ServiceError),
+        (status = 403, description = "Not an editor", body = ServiceError),
+        (status = 404, description = "Tag not found by id", body = ServiceError),
+        (status = 409, description = "A tag with this name already exists", body = ServiceError)
     ),
     params(
-        ("id", description = "Unique storage id of Category")
-    ),
-    security(
-        (),
-        ("api_key" = [])
+        ("id", description = "Unique Tag Id")
     )
 )]
 #[patch("/{id}")]
 pub async fn update_tags(
+    user: AuthUser,
     id_tags: web::Path<(i32,)>,
     local_object: web::Json<CreateTags>,
     db_pool: web::Data<Pool>,
-) -> impl Responder {
-    let client: Client = db_pool
-        .get()
-        .await
-        .expect("Error connecting to the database");
+) -> Result<HttpResponse, ServiceError> {
+    tag_editor(&user)?;
+    let name = tag_names(std::slice::from_ref(&local_object.name))?.remove(0);
+    let client: Client = db_pool.get().await?;
 
-    let result = db::tags_update(&client, id_tags.0, local_object.clone()).await;
+    db::tags_update(&client, id_tags.0, CreateTags { name }).await?;
+    Ok(HttpResponse::Ok().json(()))
+}
 
-    match result {
-        Ok(object) => HttpResponse::Ok().json(object),
-        Err(ref e) if e.kind() == NotFound => HttpResponse::NotFound().into(),
-        Err(_) => HttpResponse::InternalServerError().into(),
-    }
+/// Posts with a tag.
+///
+/// Takes the same filters, sorting and cursors as `GET /posts`, and shows the same posts.
+#[utoipa::path(
+    context_path = "/tags",
+    responses(
+        (status = 200, description = "Posts with the tag", body = PostPage),
+        (status = 400, description = "Invalid cursor", body = ServiceError),
+        (status = 404, description = "Tag not found", body = ServiceError)
+    ),
+    params(
+        ("id", description = "Unique Tag Id"),
+        PostListQuery
+    )
+)]
+#[get("/{id}/posts")]
+pub async fn tag_posts(
+    req: HttpRequest,
+    viewer: Option<AuthUser>,
+    id_tags: web::Path<(i32,)>,
+    query: Query<PostListQuery>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, ServiceError> {
+    let keyset = Keyset::new(
+        query.sort.unwrap_or_default().field(),
+        query.order.unwrap_or_default(),
+        query.cursor.as_deref(),
+        query.limit,
+    )?;
+    let client: Client = db_pool.get().await?;
+
+    let tag = db::tags_id(&client, id_tags.0)
+        .await
+        .map_err(|_| ServiceError::NotFound("Tag not found".into()))?;
+    let query = PostListQuery {
+        tag: Some(tag.id),
+        ..query.into_inner()
+    };
+    let posts = posts::db::post_list(
+        &client,
+        viewer.as_ref().map(|user| user.id),
+        viewer.as_ref().is_some_and(is_editor),
+        &query,
+        &keyset,
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(keyset.page(&req, posts)))
 }
 
 pub fn init_routes(cfg: &mut web::ServiceConfig) {
@@ -169,6 +218,7 @@ pub fn init_routes(cfg: &mut web::ServiceConfig) {
     cfg.service(add_tags);
     cfg.service(update_tags);
     cfg.service(get_tags);
+    cfg.service(tag_posts);
     cfg.service(delete_tags);
 }
 
@@ -178,3 +228,27 @@ pub fn init_routes(cfg: &mut web::ServiceConfig) {
 //     println!("{:#?}", res);
 //     res
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Role;
+
+    #[test]
+    fn test_tag_editor() {
+        let user = |role: Role| AuthUser {
+            id: 1,
+            email: "jane@example.com".into(),
+            username: None,
+            role,
+            session_id: Some(1),
+            impersonator_id: None,
+        };
+        assert!(tag_editor(&user(Role::Admin)).is_ok());
+        assert!(tag_editor(&user(Role::Editor)).is_ok());
+        assert!(matches!(
+            tag_editor(&user(Role::User)),
+            Err(ServiceError::Forbidden(_))
+        ));
+    }
+}
@@ -5,10 +5,26 @@ use chrono::prelude::*;
 use chrono::{DateTime, Duration, Utc};
 use utoipa::{IntoParams, ToResponse, ToSchema};
 
+use std::error::Error;
+
+use tokio_postgres::types::{FromSql, Type};
+
+use crate::pagination::{SortField, SortOrder};
+
 //To be added based on special query
 //To be added based on special query
 #[derive(
-    Serialize, Debug, ToSchema, Clone, ToResponse, IntoParams, Deserialize, PostgresMapper, Default,
+    Serialize,
+    Debug,
+    ToSchema,
+    Clone,
+    ToResponse,
+    IntoParams,
+    Deserialize,
+    PostgresMapper,
+    Default,
+    PartialEq,
+    Eq,
 )]
 #[schema(example = json!({"class": "post inline"}))]
 #[response(description = "Category Lists")]
@@ -18,6 +34,22 @@ pub struct Tags {
     pub name: String,
 }
 
+/// The tags of a post, ordered by name. Read from a column holding them as a JSON array.
+#[derive(Serialize, Debug, ToSchema, Clone, Deserialize, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct TagList(pub Vec<Tags>);
+
+impl<'a> FromSql<'a> for TagList {
+    fn from_sql(ty: &Type, raw: &'a [u8]) -> Result<TagList, Box<dyn Error + Sync + Send>> {
+        let json = <&str as FromSql>::from_sql(ty, raw)?;
+        Ok(TagList(serde_json::from_str(json)?))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        <&str as FromSql>::accepts(ty)
+    }
+}
+
 #[derive(
     Serialize, Debug, ToSchema, Clone, ToResponse, IntoParams, Deserialize, PostgresMapper, Default,
 )]
@@ -27,3 +59,62 @@ pub struct Tags {
 pub struct CreateTags {
     pub name: String,
 }
+
+/// What the tag list can be sorted on.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TagSort {
+    #[default]
+    Name,
+    Id,
+}
+
+impl TagSort {
+    pub fn field(&self) -> SortField {
+        match self {
+            TagSort::Name => SortField {
+                name: "name",
+                expr: "t.name",
+                sql_type: "text",
+            },
+            TagSort::Id => SortField {
+                name: "id",
+                expr: "t.id",
+                sql_type: "integer",
+            },
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TagListQuery {
+    /// Only tags whose name contains this, ignoring case.
+    pub q: Option<String>,
+    /// Field to sort on, `name` by default.
+    pub sort: Option<TagSort>,
+    /// `asc` by default.
+    pub order: Option<SortOrder>,
+    /// `next_cursor` or `prev_cursor` of the page before.
+    pub cursor: Option<String>,
+    /// At most this many tags, 20 by default and at most 100.
+    pub limit: Option<i64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tag_list_from_sql() {
+        let tags = TagList::from_sql(&Type::TEXT, br#"[{"id": 3, "name": "rust"}]"#).unwrap();
+        assert_eq!(
+            tags.0,
+            vec![Tags {
+                id: 3,
+                name: "rust".into()
+            }]
+        );
+        assert!(TagList::from_sql(&Type::TEXT, b"not json").is_err());
+    }
+}
@@ -0,0 +1,143 @@
+use std::any::Any;
+
+use actix_tls::accept::rustls_0_20::TlsStream;
+use actix_web::dev::Extensions;
+use actix_web::rt::net::TcpStream;
+use x509_parser::prelude::{FromDer, GeneralName, X509Certificate};
+
+/// What the server needs of a verified client certificate, kept with the connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientCertificate {
+    pub subject: String,
+    pub common_name: Option<String>,
+    pub emails: Vec<String>,
+    pub dns_names: Vec<String>,
+}
+
+/// Who a client certificate stands for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CertificateSubject {
+    /// The user with this email address.
+    User(String),
+    /// The service of this name.
+    Service(String),
+}
+
+impl ClientCertificate {
+    pub fn from_der(der: &[u8]) -> Result<ClientCertificate, String> {
+        let (_, certificate) = X509Certificate::from_der(der).map_err(|e| e.to_string())?;
+
+        let mut parsed = ClientCertificate {
+            subject: certificate.subject().to_string(),
+            common_name: certificate
+                .subject()
+                .iter_common_name()
+                .next()
+                .and_then(|cn| cn.as_str().ok())
+                .map(str::to_owned),
+            emails: Vec::new(),
+            dns_names: Vec::new(),
+        };
+        if let Some(names) = certificate
+            .subject_alternative_name()
+            .map_err(|e| e.to_string())?
+        {
+            for name in &names.value.general_names {
+                match name {
+                    GeneralName::RFC822Name(email) => parsed.emails.push((*email).to_owned()),
+                    GeneralName::DNSName(dns) => parsed.dns_names.push((*dns).to_owned()),
+                    _ => {}
+                }
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// An email address, from the alternative names or a common name that looks like one,
+    /// makes it a user certificate. Otherwise the first DNS name, or the common name,
+    /// names a service.
+    pub fn identity(&self) -> Option<CertificateSubject> {
+        let common_name = self.common_name.as_deref().map(str::trim);
+        if let Some(email) = self
+            .emails
+            .first()
+            .map(String::as_str)
+            .or(common_name.filter(|cn| cn.contains('@')))
+        {
+            return Some(CertificateSubject::User(email.to_owned()));
+        }
+        self.dns_names
+            .first()
+            .map(String::as_str)
+            .or(common_name)
+            .filter(|name| !name.is_empty())
+            .map(|name| CertificateSubject::Service(name.to_owned()))
+    }
+}
+
+/// `HttpServer::on_connect` callback keeping the verified client certificate of a TLS
+/// connection, readable with `conn_data::<ClientCertificate>()`.
+pub fn on_connect(connection: &dyn Any, data: &mut Extensions) {
+    let tls = match connection.downcast_ref::<TlsStream<TcpStream>>() {
+        Some(tls) => tls,
+        None => return,
+    };
+    let (_, session) = tls.get_ref();
+    let der = match session.peer_certificates().and_then(|chain| chain.first()) {
+        Some(certificate) => &certificate.0,
+        None => return,
+    };
+    match ClientCertificate::from_der(der) {
+        Ok(certificate) => {
+            data.insert(certificate);
+        }
+        Err(e) => log::warn!("could not read a client certificate: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{Certificate, CertificateParams, DistinguishedName, DnType, SanType};
+
+    fn certificate(common_name: &str, alt_names: Vec<SanType>) -> ClientCertificate {
+        let mut params = CertificateParams::default();
+        params.distinguished_name = DistinguishedName::new();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, common_name);
+        params.subject_alt_names = alt_names;
+        let der = Certificate::from_params(params)
+            .unwrap()
+            .serialize_der()
+            .unwrap();
+        ClientCertificate::from_der(&der).unwrap()
+    }
+
+    #[test]
+    fn test_certificate_identity() {
+        let user = certificate(
+            "Jane Doe",
+            vec![SanType::Rfc822Name("jane@example.com".into())],
+        );
+        assert_eq!(user.common_name.as_deref(), Some("Jane Doe"));
+        assert_eq!(
+            user.identity(),
+            Some(CertificateSubject::User("jane@example.com".into()))
+        );
+
+        let service = certificate("cron", vec![SanType::DnsName("newsletter-cron".into())]);
+        assert_eq!(
+            service.identity(),
+            Some(CertificateSubject::Service("newsletter-cron".into()))
+        );
+        assert_eq!(
+            certificate("ops@example.com", vec![]).identity(),
+            Some(CertificateSubject::User("ops@example.com".into()))
+        );
+        assert_eq!(
+            certificate("backup-job", vec![]).identity(),
+            Some(CertificateSubject::Service("backup-job".into()))
+        );
+    }
+}
@@ -0,0 +1,84 @@
+use std::fs::File;
+use std::io::{self, BufReader};
+
+use rustls::server::{AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient};
+use rustls::{Certificate, PrivateKey, RootCertStore, ServerConfig};
+use rustls_pemfile::Item;
+
+use crate::configs::SrvConfig;
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn read_pem(path: &str) -> io::Result<Vec<Item>> {
+    let file = File::open(path).map_err(|e| invalid(format!("{}: {}", path, e)))?;
+    rustls_pemfile::read_all(&mut BufReader::new(file))
+}
+
+pub fn load_certificates(path: &str) -> io::Result<Vec<Certificate>> {
+    let certificates: Vec<Certificate> = read_pem(path)?
+        .into_iter()
+        .filter_map(|item| match item {
+            Item::X509Certificate(der) => Some(Certificate(der)),
+            _ => None,
+        })
+        .collect();
+    if certificates.is_empty() {
+        return Err(invalid(format!("{} holds no certificate", path)));
+    }
+    Ok(certificates)
+}
+
+pub fn load_private_key(path: &str) -> io::Result<PrivateKey> {
+    read_pem(path)?
+        .into_iter()
+        .find_map(|item| match item {
+            Item::PKCS8Key(der) | Item::RSAKey(der) | Item::ECKey(der) => Some(PrivateKey(der)),
+            _ => None,
+        })
+        .ok_or_else(|| invalid(format!("{} holds no private key", path)))
+}
+
+/// TLS settings for `bind_rustls`, `None` when no certificate is configured and the
+/// server binds plain HTTP.
+pub fn server_config(cnf: &SrvConfig) -> io::Result<Option<ServerConfig>> {
+    let (cert_file, key_file) = match (&cnf.tls_cert_file, &cnf.tls_key_file) {
+        (Some(cert_file), Some(key_file)) => (cert_file, key_file),
+        (None, None) => return Ok(None),
+        _ => {
+            return Err(invalid(
+                "tls_cert_file and tls_key_file must be set together".into(),
+            ))
+        }
+    };
+
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let builder = match &cnf.tls_client_ca_file {
+        Some(ca_file) => {
+            let mut roots = RootCertStore::empty();
+            for certificate in load_certificates(ca_file)? {
+                roots
+                    .add(&certificate)
+                    .map_err(|e| invalid(format!("{}: {}", ca_file, e)))?;
+            }
+            if cnf.tls_client_cert_required {
+                builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots))
+            } else {
+                builder
+                    .with_client_cert_verifier(AllowAnyAnonymousOrAuthenticatedClient::new(roots))
+            }
+        }
+        None if cnf.tls_client_cert_required => {
+            return Err(invalid(
+                "tls_client_cert_required needs tls_client_ca_file".into(),
+            ))
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let config = builder
+        .with_single_cert(load_certificates(cert_file)?, load_private_key(key_file)?)
+        .map_err(|e| invalid(format!("{}: {}", key_file, e)))?;
+    Ok(Some(config))
+}
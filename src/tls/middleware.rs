@@ -0,0 +1,139 @@
+use std::future::{self, Ready};
+use std::rc::Rc;
+
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{web, HttpMessage};
+use deadpool_postgres::Pool;
+use futures::future::LocalBoxFuture;
+
+use crate::auth::{find_user_by_mail, FindUser};
+use crate::service_auth::{service_key_find_by_service, ServiceIdentity};
+use crate::tls::{CertificateSubject, ClientCertificate};
+
+/// The user a client certificate signed in, read by `AuthUser` when there is no session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CertificateUser(pub i32);
+
+/// Maps the client certificate of the connection to a user or service: the user with the
+/// certificate's email address becomes a `CertificateUser` if their password would sign
+/// them in, a service holding an active service key becomes the `ServiceIdentity`. Unknown
+/// subjects are only logged, handlers then refuse the request like any unauthenticated one.
+///
+/// Registered after `ServiceSignatureGuard`, so it runs first: when a request is also
+/// signed, the signature's `ServiceIdentity` replaces the certificate's, as the signature
+/// names the key it was made with.
+pub struct ClientCertificateGuard;
+
+impl<S, B> Transform<S, ServiceRequest> for ClientCertificateGuard
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = ClientCertificateMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        future::ready(Ok(ClientCertificateMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct ClientCertificateMiddleware<S> {
+    service: Rc<S>,
+}
+
+/// A certificate signs a user in only when their password would: the account is enabled
+/// and no password reset is pending.
+fn may_sign_in(user: &FindUser) -> bool {
+    !user.disabled && !user.password_reset_required
+}
+
+async fn resolve(req: &ServiceRequest, pool: &Pool, subject: CertificateSubject) {
+    let client = match pool.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            log::error!("could not map a client certificate: {}", e);
+            return;
+        }
+    };
+
+    match subject {
+        CertificateSubject::User(email) => match find_user_by_mail(&client, email.clone()).await {
+            Ok(user) if may_sign_in(&user) => {
+                req.extensions_mut().insert(CertificateUser(user.id));
+            }
+            Ok(user) if user.password_reset_required && !user.disabled => log::warn!(
+                "client certificate of {} refused, the user has to reset their password",
+                email
+            ),
+            _ => log::warn!("client certificate of {} matches no active user", email),
+        },
+        CertificateSubject::Service(service) => {
+            match service_key_find_by_service(&client, &service).await {
+                Ok(Some(key)) => {
+                    req.extensions_mut().insert(ServiceIdentity {
+                        key_id: key.key_id,
+                        service: key.service,
+                    });
+                }
+                Ok(None) => log::warn!(
+                    "client certificate of {} matches no service with an active key",
+                    service
+                ),
+                Err(e) => log::error!("could not look up service {}: {:?}", service, e),
+            }
+        }
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for ClientCertificateMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, actix_web::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let subject = req
+            .conn_data::<ClientCertificate>()
+            .and_then(ClientCertificate::identity);
+        let pool = req.app_data::<web::Data<Pool>>().cloned();
+
+        Box::pin(async move {
+            if let (Some(subject), Some(pool)) = (subject, pool) {
+                resolve(&req, &pool, subject).await;
+            }
+            service.call(req).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_may_sign_in() {
+        let user = |disabled, password_reset_required| FindUser {
+            id: 1,
+            hashed_password: String::new(),
+            disabled,
+            password_reset_required,
+        };
+        assert!(may_sign_in(&user(false, false)));
+        assert!(!may_sign_in(&user(true, false)));
+        assert!(!may_sign_in(&user(false, true)));
+        assert!(!may_sign_in(&user(true, true)));
+    }
+}
@@ -0,0 +1,6 @@
+pub mod certificate;
+pub mod config;
+pub mod middleware;
+pub use crate::tls::certificate::*;
+pub use crate::tls::config::*;
+pub use crate::tls::middleware::*;